@@ -0,0 +1,103 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use kube::api::DynamicObject;
+use kube::ResourceExt;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+const LABEL_VALUE_MAX_LEN: usize = 63;
+
+/// The origin of an applied object, recorded in its labels and annotations,
+/// so that objects left over in a shared cluster can be attributed to the test that created them.
+pub struct Audit {
+    pub run_id: String,
+    pub test: String,
+    pub step: String,
+    pub spec: PathBuf,
+}
+
+impl Audit {
+    pub fn labels(&self) -> BTreeMap<String, String> {
+        BTreeMap::from([
+            ("blackjack.io/run-id".to_string(), label_value(&self.run_id)),
+            ("blackjack.io/test".to_string(), label_value(&self.test)),
+        ])
+    }
+
+    pub fn annotations(&self) -> BTreeMap<String, String> {
+        BTreeMap::from([
+            ("blackjack.io/test".to_string(), self.test.clone()),
+            ("blackjack.io/step".to_string(), self.step.clone()),
+            (
+                "blackjack.io/spec".to_string(),
+                self.spec.display().to_string(),
+            ),
+        ])
+    }
+
+    pub fn mark(&self, object: &mut DynamicObject) {
+        object.labels_mut().extend(self.labels());
+        object.annotations_mut().extend(self.annotations());
+    }
+}
+
+/// Turns `value` into a valid label value by replacing invalid characters and truncating it.
+fn label_value(value: &str) -> String {
+    let value: String = value
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '-',
+        })
+        .take(LABEL_VALUE_MAX_LEN)
+        .collect();
+    value
+        .trim_matches(|c: char| !c.is_ascii_alphanumeric())
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("my-test", "my-test")]
+    #[case("suite/my test", "suite-my-test")]
+    #[case("-leading and trailing/", "leading-and-trailing")]
+    #[case(&"a".repeat(70), &"a".repeat(63))]
+    #[case("", "")]
+    fn test_label_value(#[case] value: &str, #[case] expected: &str) {
+        assert_eq!(label_value(value), expected);
+    }
+
+    #[test]
+    fn test_mark() {
+        let mut object: DynamicObject = serde_json::from_value(serde_json::json!({
+            "apiVersion": "v1",
+            "kind": "ConfigMap",
+            "metadata": {"name": "config", "labels": {"app": "web"}},
+        }))
+        .unwrap();
+        let audit = Audit {
+            run_id: "20240101-120000-abc123".to_string(),
+            test: "suite/config".to_string(),
+            step: "apply".to_string(),
+            spec: PathBuf::from("test/config/test.yaml"),
+        };
+        audit.mark(&mut object);
+        assert_eq!(object.labels()["app"], "web");
+        assert_eq!(object.labels()["blackjack.io/test"], "suite-config");
+        assert_eq!(
+            object.labels()["blackjack.io/run-id"],
+            "20240101-120000-abc123"
+        );
+        assert_eq!(object.annotations()["blackjack.io/test"], "suite/config");
+        assert_eq!(object.annotations()["blackjack.io/step"], "apply");
+        assert_eq!(
+            object.annotations()["blackjack.io/spec"],
+            "test/config/test.yaml"
+        );
+    }
+}
@@ -3,15 +3,40 @@
 
 use blackjack::config::{Config};
 use blackjack::error::Result;
+use blackjack::events::read_events;
+use blackjack::format::format_test_suite;
+use blackjack::import::import_kuttl;
+use blackjack::init::init_test;
+use blackjack::list::{list_tests, render_listing};
+use blackjack::log_prefix;
+use blackjack::naming::Naming;
+use blackjack::progress::LogWriter;
+use blackjack::report::{
+    diff_reports, read_summaries, render_diff_markdown, render_html, render_json, render_junit,
+    render_markdown, render_template, SuiteReport,
+};
 use blackjack::run_test::run_test_suite;
+use blackjack::schedule::{configured_durations, Schedule};
+use blackjack::schema::{
+    editor_schema, schema, vscode_snippets, yaml_language_server_header, DEFAULT_SCHEMA_URL,
+};
+use blackjack::script::Shell;
+use blackjack::test_filter::TestFilter;
+use blackjack::test_spec::ApplyRateSpec;
+use blackjack::validate::validate_test_suite;
+use blackjack::warnings::WarningPattern;
 use env_logger;
-use env_logger::{Builder, Env};
+use env_logger::{Builder, Env, Target};
 use std::path::Path;
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     #[arg(short, long)]
     config: Option<String>,
 
@@ -30,27 +55,464 @@ struct Cli {
     #[arg(long)]
     timeout_scaling: Option<f32>,
 
+    /// Path of the kubeconfig file, instead of the default one
+    #[arg(long)]
+    kubeconfig: Option<String>,
+
+    /// Context of the kubeconfig to use, instead of the current one
+    #[arg(long)]
+    context: Option<String>,
+
+    /// Write events of the test run to this file, one JSON object per line
+    #[arg(long)]
+    events: Option<String>,
+
+    /// Publish the results into this namespace, as ConfigMap `blackjack-results` and Events
+    #[arg(long)]
+    results_namespace: Option<String>,
+
+    /// Dump buckets, applied manifests and the namespace of failed tests into this directory
+    #[arg(long)]
+    artifacts_dir: Option<String>,
+
+    /// Trace method, path, status and latency of the requests of every test to the Kubernetes API
+    /// into `http-trace.log` in the artifacts dir
+    #[arg(long)]
+    http_trace: bool,
+
+    /// Shell that scripts are sourced by, instead of `sh` (`powershell` on Windows)
+    #[arg(long, value_enum)]
+    shell: Option<Shell>,
+
+    /// Directory to prepend to the PATH of scripts, may be given multiple times
+    #[arg(long)]
+    script_path: Vec<String>,
+
+    /// Order in which tests of the same type are started
+    #[arg(long, value_enum)]
+    schedule: Option<Schedule>,
+
+    /// JSON report or events of a previous run, for the durations of tests with `--schedule lpt`
+    #[arg(long)]
+    history: Option<String>,
+
+    /// Neither force server-side applies nor force namespace deletion,
+    /// so that ownership conflicts and stuck finalizers fail the test
+    #[arg(long)]
+    strict_cluster: bool,
+
+    /// Show running tests, their current step and pending waits, refreshed in place below the log
+    #[arg(long)]
+    progress: bool,
+
+    /// Write the requests denied by RBAC during the run to this file, as rules per identity
+    #[arg(long)]
+    rbac_report: Option<String>,
+
+    /// Share one cluster-wide watch between all watches of the run on the same resource with the
+    /// same selectors, instead of a watch per test
+    #[arg(long)]
+    share_watches: bool,
+
+    /// Fail the step applying a manifest if the API server returns a warning matching this regex,
+    /// e.g. of a deprecated API version; can be given several times
+    #[arg(long)]
+    fail_on_warning: Vec<WarningPattern>,
+
+    /// Also dump the changes of every bucket of a failed test, in the order observed,
+    /// into the artifacts dir
+    #[arg(long)]
+    bucket_events: bool,
+
+    /// How the random suffixes of the namespaces of tests and other generated names are made
+    #[arg(long, value_enum)]
+    naming: Option<Naming>,
+
+    /// Apply objects at no more than this many per second in each namespace,
+    /// unless overridden by `apply-rate` of a test
+    #[arg(long)]
+    apply_rate: Option<f64>,
+
+    /// Objects applied at once in each namespace before `--apply-rate` paces them
+    #[arg(long, default_value_t = 1)]
+    apply_burst: u32,
+
+    /// Show the data of Secrets and the managed fields of resources in failure diagnostics
+    #[arg(long)]
+    no_redact: bool,
+
+    /// Truncate resources in failure diagnostics to this many bytes of JSON, 0 for no limit
+    #[arg(long)]
+    max_object_size: Option<usize>,
+
+    /// Seconds given to teardown and cleanup after SIGINT or SIGTERM before exiting without them
+    #[arg(long)]
+    grace_period: Option<u16>,
+
+    /// Stop starting tests after the first failed test, reporting the rest as not executed
+    #[arg(long, conflicts_with = "max_failures")]
+    fail_fast: bool,
+
+    /// Stop starting tests after this many failed tests, reporting the rest as not executed
+    #[arg(long)]
+    max_failures: Option<usize>,
+
+    /// Only run the tests that failed when they were last run, as recorded in `.blackjack-state.json`
+    #[arg(long)]
+    rerun_failed: bool,
+
+    /// Skip the conditions on buckets of optional APIs the cluster does not serve,
+    /// e.g. of metrics-server, instead of failing the test
+    #[arg(long)]
+    skip_unavailable_apis: bool,
+
+    /// Only run tests whose name matches this glob, or this regex if enclosed in slashes
+    #[arg(long)]
+    filter: Option<String>,
+
+    /// Only run tests with at least one of these tags
+    #[arg(long, value_delimiter = ',')]
+    tags: Vec<String>,
+
+    /// Skip tests with any of these tags
+    #[arg(long, value_delimiter = ',')]
+    skip_tags: Vec<String>,
+
+    /// Only validate test specs without touching the cluster
+    #[arg(long)]
+    validate: bool,
+
+    #[arg(required = true)]
+    test_dir: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the schema of test specs, or editor support generated from it
+    Schema(SchemaArgs),
+    /// Rewrite test specs into canonical form
+    Fmt(FmtArgs),
+    /// Draft a test from the live resources of a namespace
+    Init(InitArgs),
+    /// Convert kuttl tests into Blackjack tests
+    ImportKuttl(ImportKuttlArgs),
+    /// Generate a report from the events of a previous test run
+    Report(ReportArgs),
+    /// List the discovered tests with their type, ordering, tags and steps without running them
+    List(ListArgs),
+}
+
+#[derive(Args)]
+struct ListArgs {
+    /// Only list tests whose name matches this glob, or this regex if enclosed in slashes
+    #[arg(long)]
+    filter: Option<String>,
+
+    /// Only list tests with at least one of these tags
+    #[arg(long, value_delimiter = ',')]
+    tags: Vec<String>,
+
+    /// Skip tests with any of these tags
+    #[arg(long, value_delimiter = ',')]
+    skip_tags: Vec<String>,
+
+    #[arg(long, value_enum, default_value_t = ListFormat::Text)]
+    format: ListFormat,
+
+    /// Config file whose `schedule` and `history` order the tests
+    #[arg(short, long)]
+    config: Option<String>,
+
+    /// Order in which tests of the same type would be started
+    #[arg(long, value_enum)]
+    schedule: Option<Schedule>,
+
+    /// JSON report or events of a previous run, for the durations of tests with `--schedule lpt`
+    #[arg(long)]
+    history: Option<String>,
+
     #[arg()]
     test_dir: String,
 }
 
+#[derive(Clone, ValueEnum)]
+enum ListFormat {
+    Text,
+    Json,
+}
+
+async fn print_list(args: ListArgs) -> Result<()> {
+    let filter = TestFilter::new(args.filter.as_deref(), args.tags, args.skip_tags)?;
+    let config = Config::new(args.config)
+        .await?
+        .with_schedule(args.schedule)
+        .with_history(args.history);
+    let durations = configured_durations(&config);
+    let listings = list_tests(
+        Path::new(&args.test_dir),
+        &filter,
+        config.schedule,
+        &durations,
+    )
+    .await?;
+    let output = match args.format {
+        ListFormat::Text => render_listing(&listings),
+        ListFormat::Json => serde_json::to_string_pretty(&listings)? + "\n",
+    };
+    print!("{output}");
+    Ok(())
+}
+
+#[derive(Args)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct ReportArgs {
+    #[command(subcommand)]
+    command: Option<ReportCommand>,
+
+    /// Events written by a test run with `--events`
+    #[arg(long, required = true)]
+    from: Option<String>,
+
+    #[arg(long, value_enum, default_value_t = ReportFormat::Junit)]
+    format: ReportFormat,
+
+    /// Render the report with this Handlebars template instead of a built-in format
+    #[arg(long, conflicts_with = "format")]
+    template: Option<String>,
+
+    /// Write the report to this file instead of stdout
+    #[arg(short, long)]
+    output: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum ReportCommand {
+    /// Compare two runs: newly failing tests, fixed tests and tests that got slower
+    Diff(DiffArgs),
+}
+
+#[derive(Args)]
+struct DiffArgs {
+    /// JSON report or events of the earlier run
+    old: String,
+
+    /// JSON report or events of the later run
+    new: String,
+
+    /// How many percent longer a test must take to count as slower
+    #[arg(long, default_value_t = 50.0)]
+    slowdown: f64,
+
+    /// How many seconds longer a test must take to count as slower
+    #[arg(long, default_value_t = 10.0)]
+    min_seconds: f64,
+
+    #[arg(long, value_enum, default_value_t = DiffFormat::Md)]
+    format: DiffFormat,
+
+    /// Write the comparison to this file instead of stdout
+    #[arg(short, long)]
+    output: Option<String>,
+}
+
+#[derive(Clone, ValueEnum)]
+enum DiffFormat {
+    Json,
+    Md,
+}
+
+#[derive(Clone, ValueEnum)]
+enum ReportFormat {
+    Junit,
+    Html,
+    Json,
+    Md,
+}
+
+fn print_diff(args: DiffArgs) -> Result<()> {
+    let diff = diff_reports(
+        &read_summaries(Path::new(&args.old))?,
+        &read_summaries(Path::new(&args.new))?,
+        args.slowdown,
+        args.min_seconds,
+    );
+    let output = match args.format {
+        DiffFormat::Json => serde_json::to_string_pretty(&diff)?,
+        DiffFormat::Md => render_diff_markdown(&diff),
+    };
+    match args.output {
+        Some(path) => std::fs::write(path, output)?,
+        None => print!("{output}"),
+    }
+    Ok(())
+}
+
+fn print_report(args: ReportArgs) -> Result<()> {
+    if let Some(ReportCommand::Diff(diff_args)) = args.command {
+        return print_diff(diff_args);
+    }
+    let from = args.from.unwrap_or_default();
+    let report = SuiteReport::from_events(&read_events(Path::new(&from))?);
+    let output = match (args.template, args.format) {
+        (Some(template), _) => render_template(&report, Path::new(&template))?,
+        (None, ReportFormat::Junit) => render_junit(&report),
+        (None, ReportFormat::Html) => render_html(&report),
+        (None, ReportFormat::Json) => render_json(&report),
+        (None, ReportFormat::Md) => render_markdown(&report),
+    };
+    match args.output {
+        Some(path) => std::fs::write(path, output)?,
+        None => print!("{output}"),
+    }
+    Ok(())
+}
+
+#[derive(Args)]
+struct FmtArgs {
+    /// Only report test specs that are not formatted, without rewriting them
+    #[arg(long)]
+    check: bool,
+
+    #[arg()]
+    test_dir: String,
+}
+
+#[derive(Args)]
+struct InitArgs {
+    /// Resources to snapshot, e.g. `namespace/foo`
+    #[arg(long)]
+    from: String,
+
+    /// Only snapshot resources matching this label selector
+    #[arg(long)]
+    selector: Option<String>,
+
+    #[arg()]
+    test_dir: String,
+}
+
+#[derive(Args)]
+struct ImportKuttlArgs {
+    /// Directory of a kuttl test, or of a suite with a kuttl test in every subdirectory
+    #[arg()]
+    dir: String,
+}
+
+#[derive(Args)]
+struct SchemaArgs {
+    #[arg(long, value_enum, default_value_t = SchemaOutput::Json)]
+    output: SchemaOutput,
+
+    /// Location of the schema referenced by the header
+    #[arg(long, default_value = DEFAULT_SCHEMA_URL)]
+    schema_url: String,
+}
+
+#[derive(Clone, ValueEnum)]
+enum SchemaOutput {
+    /// JSON schema of test specs
+    Json,
+    /// JSON schema with completions for yaml-language-server
+    Editor,
+    /// VS Code snippets file
+    VscodeSnippets,
+    /// yaml-language-server header line for test.yaml files
+    Header,
+}
+
+fn print_schema(args: SchemaArgs) -> Result<()> {
+    let output = match args.output {
+        SchemaOutput::Json => serde_json::to_string_pretty(&schema())?,
+        SchemaOutput::Editor => serde_json::to_string_pretty(&editor_schema())?,
+        SchemaOutput::VscodeSnippets => serde_json::to_string_pretty(&vscode_snippets())?,
+        SchemaOutput::Header => yaml_language_server_header(&args.schema_url),
+    };
+    println!("{output}");
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Cli::parse();
-    Config::init(
-        Config::new(args.config)
-            .await?
-            .with_user_parallel(args.user_parallel)
-            .with_cluster_parallel(args.cluster_parallel)
-            .with_user_attempts(args.user_attempts)
-            .with_cluster_attempts(args.cluster_attempts)
-            .with_timeout_scaling(args.timeout_scaling),
-    );
+    match args.command {
+        Some(Command::Schema(schema_args)) => return print_schema(schema_args),
+        Some(Command::Report(report_args)) => return print_report(report_args),
+        Some(Command::List(list_args)) => return print_list(list_args).await,
+        _ => {}
+    }
+
+    let config = Config::new(args.config)
+        .await?
+        .with_user_parallel(args.user_parallel)
+        .with_cluster_parallel(args.cluster_parallel)
+        .with_user_attempts(args.user_attempts)
+        .with_cluster_attempts(args.cluster_attempts)
+        .with_timeout_scaling(args.timeout_scaling)
+        .with_filter(args.filter)
+        .with_tags(args.tags)
+        .with_skip_tags(args.skip_tags)
+        .with_kubeconfig(args.kubeconfig)
+        .with_context(args.context)
+        .with_events(args.events)
+        .with_results_namespace(args.results_namespace)
+        .with_artifacts_dir(args.artifacts_dir)
+        .with_http_trace(args.http_trace)
+        .with_shell(args.shell)
+        .with_script_path(args.script_path)
+        .with_schedule(args.schedule)
+        .with_history(args.history)
+        .with_strict_cluster(args.strict_cluster)
+        .with_progress(args.progress)
+        .with_rbac_report(args.rbac_report)
+        .with_share_watches(args.share_watches)
+        .with_fail_on_warning(args.fail_on_warning)
+        .with_bucket_events(args.bucket_events)
+        .with_naming(args.naming)
+        .with_apply_rate(args.apply_rate.map(|rate| ApplyRateSpec {
+            rate,
+            burst: args.apply_burst,
+        }))
+        .with_no_redact(args.no_redact)
+        .with_max_object_size(args.max_object_size)
+        .with_grace_period(args.grace_period)
+        .with_fail_fast(args.fail_fast)
+        .with_max_failures(args.max_failures)
+        .with_rerun_failed(args.rerun_failed)
+        .with_skip_unavailable_apis(args.skip_unavailable_apis);
+
+    let env = Env::default().filter_or("BLACKJACK_LOG_LEVEL", config.loglevel.clone());
+    let mut builder = Builder::from_env(env);
+    if config.progress {
+        builder.target(Target::Pipe(Box::new(LogWriter)));
+    }
+    builder.format(log_prefix::format).init();
 
-    let env = Env::default().filter_or("BLACKJACK_LOG_LEVEL", Config::get().loglevel.clone());
-    Builder::from_env(env).format_timestamp(None).init();
+    match args.command {
+        Some(Command::Fmt(fmt_args)) => {
+            return format_test_suite(Path::new(&fmt_args.test_dir), fmt_args.check).await
+        }
+        Some(Command::Init(init_args)) => {
+            let client = config.client().await?;
+            return init_test(
+                &client,
+                Path::new(&init_args.test_dir),
+                &init_args.from,
+                init_args.selector,
+            )
+            .await;
+        }
+        Some(Command::ImportKuttl(import_args)) => {
+            return import_kuttl(Path::new(&import_args.dir)).await;
+        }
+        _ => {}
+    }
 
-    let test_dir = Path::new(&args.test_dir);
+    let test_dir = args.test_dir.unwrap_or_default();
+    let test_dir = Path::new(&test_dir);
 
-    run_test_suite(test_dir).await
+    if args.validate {
+        validate_test_suite(&config, test_dir).await
+    } else {
+        run_test_suite(config, test_dir).await
+    }
 }
@@ -0,0 +1,335 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::check::{assert_expr, CheckOptions, History};
+use crate::config::Config;
+use crate::error::{AssertDiagnostic, Error, Result, TestFailure, TestFailures};
+use crate::test_spec::{CertificateSpec, WaitSpec};
+use crate::wait::wait_until;
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::core::v1::Secret;
+use kube::{Api, Client};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Duration, Instant};
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::crypto::{
+    ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider,
+};
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use tokio_rustls::rustls::{ClientConfig, DigitallySignedStruct, SignatureScheme};
+use tokio_rustls::TlsConnector;
+use x509_parser::extensions::GeneralName;
+use x509_parser::pem::Pem;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn certificate_error(err: impl std::fmt::Display) -> Error {
+    Error::CertificateError(err.to_string())
+}
+
+fn timestamp(time: i64) -> Option<String> {
+    DateTime::<Utc>::from_timestamp(time, 0).map(|time| time.to_rfc3339())
+}
+
+fn certificate_value(der: &[u8], now: i64) -> Result<Value> {
+    let (_, cert) = X509Certificate::from_der(der).map_err(certificate_error)?;
+    let mut dns_names = vec![];
+    let mut ip_addresses = vec![];
+    if let Ok(Some(san)) = cert.subject_alternative_name() {
+        for name in &san.value.general_names {
+            match name {
+                GeneralName::DNSName(name) => dns_names.push(name.to_string()),
+                GeneralName::IPAddress(bytes) => {
+                    if let Ok(ip) = <[u8; 4]>::try_from(*bytes) {
+                        ip_addresses.push(std::net::IpAddr::from(ip).to_string());
+                    } else if let Ok(ip) = <[u8; 16]>::try_from(*bytes) {
+                        ip_addresses.push(std::net::IpAddr::from(ip).to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    let validity = cert.validity();
+    Ok(json!({
+        "subject": cert.subject().to_string(),
+        "issuer": cert.issuer().to_string(),
+        "serialNumber": cert.raw_serial_as_string(),
+        "dnsNames": dns_names,
+        "ipAddresses": ip_addresses,
+        "notBefore": timestamp(validity.not_before.timestamp()),
+        "notAfter": timestamp(validity.not_after.timestamp()),
+        "validFor": validity.not_after.timestamp() - now,
+        "isCA": cert.is_ca(),
+    }))
+}
+
+fn parse_pem(bytes: &[u8]) -> Result<Vec<Vec<u8>>> {
+    Pem::iter_from_buffer(bytes)
+        .map(|pem| pem.map(|pem| pem.contents).map_err(certificate_error))
+        .collect()
+}
+
+async fn fetch_secret(client: Client, spec: &CertificateSpec, name: &str) -> Result<Vec<Vec<u8>>> {
+    let secret = Api::<Secret>::namespaced(client, &spec.namespace)
+        .get(name)
+        .await?;
+    let data = secret
+        .data
+        .and_then(|mut data| data.remove(&spec.key))
+        .ok_or_else(|| certificate_error(format!("secret '{name}' has no key '{}'", spec.key)))?;
+    parse_pem(&data.0)
+}
+
+/// Accepts any certificate, as the certificate is inspected instead of trusted.
+#[derive(Debug)]
+struct AcceptAnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Splits `host:port`, with port 443 by default.
+fn host_and_port(endpoint: &str) -> (String, u16) {
+    match endpoint.rsplit_once(':') {
+        Some((host, port)) if !host.ends_with(':') => match port.parse() {
+            Ok(port) => (host.trim_matches(['[', ']']).to_string(), port),
+            Err(_) => (endpoint.to_string(), 443),
+        },
+        _ => (endpoint.trim_matches(['[', ']']).to_string(), 443),
+    }
+}
+
+async fn fetch_endpoint(spec: &CertificateSpec, endpoint: &str) -> Result<Vec<Vec<u8>>> {
+    let (host, port) = host_and_port(endpoint);
+    let server_name = spec.server_name.clone().unwrap_or_else(|| host.clone());
+    let server_name = ServerName::try_from(server_name).map_err(certificate_error)?;
+    let provider = Arc::new(ring::default_provider());
+    let config = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(certificate_error)?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate(provider)))
+        .with_no_client_auth();
+    let stream = timeout(CONNECT_TIMEOUT, async {
+        let tcp = TcpStream::connect((host.as_str(), port)).await?;
+        TlsConnector::from(Arc::new(config))
+            .connect(server_name, tcp)
+            .await
+    })
+    .await
+    .map_err(|_| certificate_error(format!("timed out connecting to '{endpoint}'")))?
+    .map_err(certificate_error)?;
+    let certificates = stream.get_ref().1.peer_certificates().unwrap_or_default();
+    Ok(certificates.iter().map(|cert| cert.to_vec()).collect())
+}
+
+/// The certificates of the secret or endpoint, the leaf certificate first.
+async fn fetch_certificates(client: Client, spec: &CertificateSpec) -> Result<Vec<Value>> {
+    let ders = match (&spec.secret, &spec.endpoint) {
+        (Some(secret), _) => fetch_secret(client, spec, secret).await?,
+        (None, Some(endpoint)) => fetch_endpoint(spec, endpoint).await?,
+        (None, None) => return Err(certificate_error("needs either secret or endpoint")),
+    };
+    let now = Utc::now().timestamp();
+    ders.iter().map(|der| certificate_value(der, now)).collect()
+}
+
+/// Checks the validity window of the leaf certificate and the condition.
+fn check_certificates(
+    spec: &CertificateSpec,
+    certificates: &[Value],
+) -> std::result::Result<(), AssertDiagnostic> {
+    let valid_for = certificates
+        .first()
+        .and_then(|leaf| leaf["validFor"].as_i64());
+    let in_window = valid_for.is_some_and(|valid_for| {
+        spec.min_validity.is_none_or(|min| valid_for >= min as i64)
+            && spec.max_validity.is_none_or(|max| valid_for <= max as i64)
+    });
+    if !in_window {
+        return Err(AssertDiagnostic {
+            expr: spec.condition.clone(),
+            input: certificates.to_vec(),
+            diff: vec![],
+        });
+    }
+    let certificates: Vec<&Value> = certificates.iter().collect();
+    assert_expr(
+        &certificates,
+        &vec![],
+        &History::new(),
+        &spec.condition,
+        CheckOptions {
+            coerce: spec.coerce,
+        },
+    )
+}
+
+/// Fetches the certificates repeatedly until they fulfill the condition.
+/// Errors are retried as well, as the certificate may not be issued yet.
+async fn wait_for_certificate(
+    client: Client,
+    config: &Config,
+    spec: &CertificateSpec,
+) -> Result<std::result::Result<(), AssertDiagnostic>> {
+    let deadline = Instant::now() + config.scaled_timeout(spec.timeout);
+    wait_until(
+        deadline,
+        Duration::from_secs(1),
+        || fetch_certificates(client.clone(), spec),
+        |certificates| check_certificates(spec, certificates),
+    )
+    .await
+}
+
+/// Waits until the certificates of all secrets and endpoints fulfill their conditions.
+pub async fn wait_for_certificates(
+    client: Client,
+    config: &Config,
+    specs: Vec<CertificateSpec>,
+) -> Result<()> {
+    log::debug!("Waiting for {} certificates", specs.len());
+    let mut errors: Vec<TestFailure> = Vec::new();
+    for spec in specs {
+        if let Err(assert_diagnostic) = wait_for_certificate(client.clone(), config, &spec).await? {
+            let target = match (&spec.secret, &spec.endpoint) {
+                (Some(secret), _) => format!("certificate:{}/{secret}", spec.namespace),
+                (None, endpoint) => format!("certificate:{}", endpoint.as_deref().unwrap_or("")),
+            };
+            let spec = WaitSpec {
+                condition: spec.condition,
+                coerce: spec.coerce,
+                ..WaitSpec::synthetic(target, spec.timeout)
+            };
+            errors.push(TestFailure::new(spec, assert_diagnostic));
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::ConditionsFailed(TestFailures(errors)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    const CERTIFICATE: &str = "-----BEGIN CERTIFICATE-----
+MIIB2DCCAX6gAwIBAgICEjQwCgYIKoZIzj0EAwIwLjEYMBYGA1UEAwwPYXBwLmV4
+YW1wbGUuY29tMRIwEAYDVQQKDAlCbGFja2phY2swIBcNMjYxMDE1MTM0ODQ0WhgP
+MjEyNjA5MjExMzQ4NDRaMC4xGDAWBgNVBAMMD2FwcC5leGFtcGxlLmNvbTESMBAG
+A1UECgwJQmxhY2tqYWNrMFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEilbfBljB
+40MocdH6RbPi9rua86prkBWJL+umAmDFD2D8pmHZhH1d+dhXRDcg+Re/+qsq86I0
+/6d0YcUcLd8MJ6OBiTCBhjAdBgNVHQ4EFgQUfl+ru+B/yi4T2X/Ht2ZlwYz8X98w
+HwYDVR0jBBgwFoAUfl+ru+B/yi4T2X/Ht2ZlwYz8X98wDwYDVR0TAQH/BAUwAwEB
+/zAzBgNVHREELDAqgg9hcHAuZXhhbXBsZS5jb22CESouYXBwLmV4YW1wbGUuY29t
+hwQKAAABMAoGCCqGSM49BAMCA0gAMEUCIHdweKah5yhK9lZeiVJBiZfrI0mXURsX
+apjJUn/lRijEAiEAwQdc8Zz/r2vtUt6mWNIfkWMgZWZaOzyqLZkU3ht3E20=
+-----END CERTIFICATE-----
+";
+    const NOT_BEFORE: i64 = 1792072124;
+
+    #[test]
+    fn test_certificate_value() {
+        let ders = parse_pem(CERTIFICATE.as_bytes()).unwrap();
+        assert_eq!(ders.len(), 1);
+        assert_eq!(
+            certificate_value(&ders[0], NOT_BEFORE).unwrap(),
+            json!({
+                "subject": "CN=app.example.com, O=Blackjack",
+                "issuer": "CN=app.example.com, O=Blackjack",
+                "serialNumber": "12:34",
+                "dnsNames": ["app.example.com", "*.app.example.com"],
+                "ipAddresses": ["10.0.0.1"],
+                "notBefore": "2026-10-15T13:48:44+00:00",
+                "notAfter": "2126-09-21T13:48:44+00:00",
+                "validFor": 3153600000_i64,
+                "isCA": true,
+            })
+        );
+    }
+
+    #[rstest]
+    #[case("127.0.0.1:8443", ("127.0.0.1", 8443))]
+    #[case("app.example.com", ("app.example.com", 443))]
+    #[case("[::1]:8443", ("::1", 8443))]
+    #[case("::1", ("::1", 443))]
+    fn test_host_and_port(#[case] endpoint: &str, #[case] expected: (&str, u16)) {
+        assert_eq!(
+            host_and_port(endpoint),
+            (expected.0.to_string(), expected.1)
+        );
+    }
+
+    #[rstest]
+    #[case(None, None, true)]
+    #[case(Some(86400), None, true)]
+    #[case(Some(86401), None, false)]
+    #[case(None, Some(3600), false)]
+    #[case(Some(3600), Some(86400), true)]
+    fn test_check_certificates(
+        #[case] min_validity: Option<u64>,
+        #[case] max_validity: Option<u64>,
+        #[case] expected: bool,
+    ) {
+        let spec: CertificateSpec = serde_json::from_value(json!({
+            "secret": "tls",
+            "min-validity": min_validity,
+            "max-validity": max_validity,
+            "condition": {"one": {"dnsNames": ["app.example.com"]}},
+            "timeout": 0,
+        }))
+        .unwrap();
+        let certificates = vec![json!({"dnsNames": ["app.example.com"], "validFor": 86400})];
+        assert_eq!(check_certificates(&spec, &certificates).is_ok(), expected);
+    }
+}
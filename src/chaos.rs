@@ -0,0 +1,114 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::error::{Error, Result};
+use crate::test_spec::{ChaosAction, ChaosSpec};
+use k8s_openapi::api::core::v1::Pod;
+use kube::api::{Api, DeleteParams, EvictParams, ListParams};
+use kube::{Client, ResourceExt};
+use rand::seq::SliceRandom;
+
+fn label_selector(spec: &ChaosSpec) -> String {
+    spec.labels
+        .iter()
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Chooses `count` random pods among those not already terminating.
+fn choose_pods(pods: Vec<Pod>, count: usize) -> Result<Vec<String>> {
+    let names: Vec<String> = pods
+        .into_iter()
+        .filter(|pod| pod.metadata.deletion_timestamp.is_none())
+        .map(|pod| pod.name_any())
+        .collect();
+    if names.len() < count {
+        return Err(Error::ChaosError(format!(
+            "found {} pods, but {count} are required",
+            names.len()
+        )));
+    }
+    Ok(names
+        .choose_multiple(&mut rand::thread_rng(), count)
+        .cloned()
+        .collect())
+}
+
+async fn run_chaos(client: Client, spec: &ChaosSpec) -> Result<()> {
+    let api: Api<Pod> = Api::namespaced(client, &spec.namespace);
+    let pods = api
+        .list(&ListParams::default().labels(&label_selector(spec)))
+        .await?;
+    let delete_params = DeleteParams {
+        grace_period_seconds: spec.grace_period,
+        ..DeleteParams::default()
+    };
+    for name in choose_pods(pods.items, spec.count)? {
+        match spec.action {
+            ChaosAction::Delete => {
+                log::info!("Chaos: deleting pod {}/{}", spec.namespace, name);
+                api.delete(&name, &delete_params).await?;
+            }
+            ChaosAction::Evict => {
+                log::info!("Chaos: evicting pod {}/{}", spec.namespace, name);
+                let evict_params = EvictParams {
+                    delete_options: Some(delete_params.clone()),
+                    ..EvictParams::default()
+                };
+                api.evict(&name, &evict_params)
+                    .await
+                    .map_err(|err| Error::ChaosError(format!("unable to evict {name}: {err}")))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub async fn run_all_chaos(client: Client, specs: Vec<ChaosSpec>) -> Result<()> {
+    for spec in specs {
+        run_chaos(client.clone(), &spec).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serde_json::json;
+
+    fn pod(name: &str, terminating: bool) -> Pod {
+        let mut pod = json!({"metadata": {"name": name}});
+        if terminating {
+            pod["metadata"]["deletionTimestamp"] = json!("2024-01-01T00:00:00Z");
+        }
+        serde_json::from_value(pod).unwrap()
+    }
+
+    #[rstest]
+    #[case(vec![("a", false), ("b", false), ("c", false)], 2, Some(vec!["a", "b", "c"]))]
+    #[case(vec![("a", false), ("b", true)], 1, Some(vec!["a"]))]
+    #[case(vec![("a", false), ("b", true)], 2, None)]
+    #[case(vec![], 1, None)]
+    fn test_choose_pods(
+        #[case] pods: Vec<(&str, bool)>,
+        #[case] count: usize,
+        #[case] candidates: Option<Vec<&str>>,
+    ) {
+        let pods = pods
+            .into_iter()
+            .map(|(name, terminating)| pod(name, terminating))
+            .collect();
+        match (choose_pods(pods, count), candidates) {
+            (Ok(chosen), Some(candidates)) => {
+                assert_eq!(chosen.len(), count);
+                assert!(chosen
+                    .iter()
+                    .all(|name| candidates.contains(&name.as_str())));
+            }
+            (Err(_), None) => {}
+            (result, _) => panic!("unexpected result: {:?}", result.map_err(|e| e.to_string())),
+        }
+    }
+}
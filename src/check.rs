@@ -1,29 +1,393 @@
 // Copyright 2024 Ole Kliemann
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::error::AssertDiagnostic;
-use crate::match_object;
-use crate::test_spec::Expr;
+use crate::error::{AssertDiagnostic, ResourceDiff};
+use crate::json_path;
+use crate::match_object::{self, Difference};
+use crate::test_spec::{
+    CompiledPath, EventCondition, Expr, JobCompleteCondition, PathCondition, Quantifier,
+    RestartCondition,
+};
 use serde_json;
+use std::collections::{BTreeMap, HashMap};
 
-pub fn assert_expr(input: &Vec<&serde_json::Value>, expr: &Expr) -> Result<(), AssertDiagnostic> {
+/// Observed versions of resources, by UID, oldest first.
+pub type History = HashMap<String, Vec<serde_json::Value>>;
+
+fn values_equal(value: &serde_json::Value, compare: &serde_json::Value, coerce: bool) -> bool {
+    if coerce {
+        match_object::coerced_eq(value, compare)
+    } else {
+        value == compare
+    }
+}
+
+fn pattern_matches(item: &serde_json::Value, pattern: &serde_json::Value, coerce: bool) -> bool {
+    if coerce {
+        match_object::contains_coerced(item, pattern)
+    } else {
+        match_object::contains(item, pattern)
+    }
+}
+
+fn pattern_diff(
+    item: &serde_json::Value,
+    pattern: &serde_json::Value,
+    coerce: bool,
+) -> Vec<Difference> {
+    if coerce {
+        match_object::diff_coerced(item, pattern)
+    } else {
+        match_object::diff(item, pattern)
+    }
+}
+
+/// Namespace and name of the resource, or its position in the bucket if it has no name.
+fn resource_name(index: usize, item: &serde_json::Value) -> String {
+    let namespace = item.pointer("/metadata/namespace").and_then(|n| n.as_str());
+    let name = item.pointer("/metadata/name").and_then(|n| n.as_str());
+    match (namespace, name) {
+        (Some(namespace), Some(name)) => format!("{namespace}/{name}"),
+        (None, Some(name)) => name.to_string(),
+        _ => format!("item {}", index + 1),
+    }
+}
+
+/// The fields of the item that differ from the pattern, if it does not match.
+fn resource_diff(
+    index: usize,
+    item: &serde_json::Value,
+    pattern: &serde_json::Value,
+    coerce: bool,
+) -> Option<ResourceDiff> {
+    let differences = pattern_diff(item, pattern, coerce);
+    (!differences.is_empty()).then(|| ResourceDiff {
+        resource: resource_name(index, item),
+        kind: item["kind"].as_str().map(str::to_string),
+        differences,
+    })
+}
+
+/// The differences of the item closest to the pattern, i.e. with the fewest differing fields.
+fn closest_diff(
+    input: &[&serde_json::Value],
+    pattern: &serde_json::Value,
+    coerce: bool,
+) -> Option<ResourceDiff> {
+    input
+        .iter()
+        .enumerate()
+        .filter_map(|(i, item)| resource_diff(i, item, pattern, coerce))
+        .min_by_key(|resource_diff| resource_diff.differences.len())
+}
+
+/// The path conditions in `expr`, to reject those with an invalid path or regular expression
+/// instead of never fulfilling them.
+pub fn path_conditions(expr: &Expr) -> Vec<&PathCondition> {
+    match expr {
+        Expr::AndExpr { and: exprs } | Expr::OrExpr { or: exprs } => {
+            exprs.iter().flat_map(path_conditions).collect()
+        }
+        Expr::NotExpr { not: expr } | Expr::DeletedExpr { deleted: expr } => path_conditions(expr),
+        Expr::PathExpr(condition) => vec![condition],
+        _ => vec![],
+    }
+}
+
+/// Whether the value at the path within `item` fulfills all checks of the condition.
+fn path_matches(
+    item: &serde_json::Value,
+    condition: &PathCondition,
+    compiled: &CompiledPath,
+    coerce: bool,
+) -> bool {
+    let Some(value) = json_path::lookup(item, &compiled.segments) else {
+        return condition.exists == Some(false);
+    };
+    let text = match value {
+        serde_json::Value::String(s) => s.clone(),
+        value => value.to_string(),
+    };
+    condition.exists != Some(false)
+        && condition
+            .equals
+            .as_ref()
+            .is_none_or(|equals| values_equal(value, equals, coerce))
+        && condition
+            .not_equals
+            .as_ref()
+            .is_none_or(|not_equals| !values_equal(value, not_equals, coerce))
+        && compiled
+            .regex
+            .as_ref()
+            .is_none_or(|regex| regex.is_match(&text))
+}
+
+fn event_matches(event: &serde_json::Value, condition: &EventCondition) -> bool {
+    let involved = event
+        .get("involvedObject")
+        .or_else(|| event.get("regarding"));
+    let field_matches = |value: Option<&serde_json::Value>, expected: &Option<String>| {
+        expected
+            .as_ref()
+            .is_none_or(|expected| value.and_then(|v| v.as_str()) == Some(expected))
+    };
+    field_matches(event.get("reason"), &condition.reason)
+        && field_matches(event.get("type"), &condition.event_type)
+        && field_matches(
+            involved.and_then(|i| i.get("kind")),
+            &condition.involved_kind,
+        )
+        && field_matches(
+            involved.and_then(|i| i.get("name")),
+            &condition.involved_name,
+        )
+}
+
+fn event_count(event: &serde_json::Value) -> u64 {
+    event
+        .pointer("/series/count")
+        .or_else(|| event.get("count"))
+        .or_else(|| event.get("deprecatedCount"))
+        .and_then(|c| c.as_u64())
+        .unwrap_or(1)
+}
+
+pub fn count_events(input: &[&serde_json::Value], condition: &EventCondition) -> u64 {
+    input
+        .iter()
+        .filter(|event| event_matches(event, condition))
+        .map(|event| event_count(event))
+        .sum()
+}
+
+fn count_restarts(pod: &serde_json::Value, condition: &RestartCondition) -> u64 {
+    ["/status/containerStatuses", "/status/initContainerStatuses"]
+        .iter()
+        .filter_map(|pointer| pod.pointer(pointer).and_then(|s| s.as_array()))
+        .flatten()
+        .filter(|status| {
+            condition
+                .container
+                .as_ref()
+                .is_none_or(|name| status.get("name").and_then(|n| n.as_str()) == Some(name))
+        })
+        .filter_map(|status| status.get("restartCount").and_then(|c| c.as_u64()))
+        .sum()
+}
+
+fn has_labels(item: &serde_json::Value, selector: &Option<BTreeMap<String, String>>) -> bool {
+    selector.as_ref().is_none_or(|selector| {
+        selector.iter().all(|(k, v)| {
+            item.pointer("/metadata/labels")
+                .and_then(|labels| labels.get(k))
+                .and_then(|l| l.as_str())
+                == Some(v)
+        })
+    })
+}
+
+fn restarts_since_baseline(
+    input: &[&serde_json::Value],
+    history: &History,
+    condition: &RestartCondition,
+) -> Vec<(String, u64)> {
+    input
+        .iter()
+        .filter(|pod| has_labels(pod, &condition.selector))
+        .map(|pod| {
+            let uid = pod.pointer("/metadata/uid").and_then(|u| u.as_str());
+            let initial = uid
+                .and_then(|uid| history.get(uid))
+                .and_then(|revisions| revisions.first())
+                .map_or(0, |initial| count_restarts(initial, condition));
+            let name = pod
+                .pointer("/metadata/name")
+                .and_then(|n| n.as_str())
+                .unwrap_or_default()
+                .to_string();
+            (name, count_restarts(pod, condition).saturating_sub(initial))
+        })
+        .collect()
+}
+
+/// Conditions of the resource of one of the types with status `True`.
+fn true_conditions<'a>(item: &'a serde_json::Value, types: &[&str]) -> Vec<&'a serde_json::Value> {
+    item.pointer("/status/conditions")
+        .and_then(|c| c.as_array())
+        .into_iter()
+        .flatten()
+        .filter(|c| c["status"] == "True")
+        .filter(|c| c["type"].as_str().is_some_and(|t| types.contains(&t)))
+        .collect()
+}
+
+/// Whether each selected Job has completed, with its progress and the reasons it failed.
+fn job_states(
+    input: &[&serde_json::Value],
+    condition: &JobCompleteCondition,
+) -> Vec<(bool, serde_json::Value)> {
+    input
+        .iter()
+        .filter(|job| has_labels(job, &condition.selector))
+        .map(|job| {
+            let count = |pointer| job.pointer(pointer).and_then(|n| n.as_u64());
+            let succeeded = count("/status/succeeded").unwrap_or(0);
+            let completions = count("/spec/completions").unwrap_or(1);
+            let complete =
+                succeeded >= completions || !true_conditions(job, &["Complete"]).is_empty();
+            let failures: Vec<serde_json::Value> =
+                true_conditions(job, &["Failed", "FailureTarget"])
+                    .into_iter()
+                    .map(|c| serde_json::json!({"reason": c["reason"], "message": c["message"]}))
+                    .collect();
+            let state = serde_json::json!({
+                "name": job.pointer("/metadata/name"),
+                "namespace": job.pointer("/metadata/namespace"),
+                "succeeded": succeeded,
+                "completions": completions,
+                "failed": count("/status/failed").unwrap_or(0),
+                "failures": failures,
+            });
+            (complete, state)
+        })
+        .collect()
+}
+
+/// Reasons the containers of the pods have terminated with other than success or are waiting,
+/// e.g. `Error` or `ImagePullBackOff`.
+pub fn pod_reasons(pods: &[serde_json::Value]) -> Vec<serde_json::Value> {
+    let mut reasons = vec![];
+    for pod in pods {
+        let statuses = ["initContainerStatuses", "containerStatuses"]
+            .into_iter()
+            .filter_map(|field| pod["status"][field].as_array())
+            .flatten();
+        for status in statuses {
+            let terminated = &status["state"]["terminated"];
+            let waiting = &status["state"]["waiting"];
+            let state = match (terminated["reason"].as_str(), waiting["reason"].as_str()) {
+                (Some("Completed"), _) => continue,
+                (Some(_), _) => terminated,
+                (None, Some(_)) => waiting,
+                (None, None) => continue,
+            };
+            reasons.push(serde_json::json!({
+                "pod": pod.pointer("/metadata/name"),
+                "container": status["name"],
+                "reason": state["reason"],
+                "message": state["message"],
+                "exitCode": state["exitCode"],
+            }));
+        }
+    }
+    reasons
+}
+
+/// Whether the condition can no longer be fulfilled, as a Job it requires to complete has
+/// a `Failed` condition.
+pub fn unfulfillable(input: &[&serde_json::Value], expr: &Expr) -> bool {
+    match expr {
+        Expr::JobCompleteExpr { job_complete } => input
+            .iter()
+            .filter(|job| has_labels(job, &job_complete.selector))
+            .any(|job| !true_conditions(job, &["Failed"]).is_empty()),
+        Expr::AndExpr { and } => and.iter().any(|e| unfulfillable(input, e)),
+        Expr::OrExpr { or } => !or.is_empty() && or.iter().all(|e| unfulfillable(input, e)),
+        _ => false,
+    }
+}
+
+/// Recorded revisions of a resource, ending with its current version.
+fn revisions<'a>(item: &'a serde_json::Value, history: &'a History) -> Vec<&'a serde_json::Value> {
+    let mut revisions: Vec<&serde_json::Value> = item
+        .pointer("/metadata/uid")
+        .and_then(|uid| uid.as_str())
+        .and_then(|uid| history.get(uid))
+        .map(|revisions| revisions.iter().collect())
+        .unwrap_or_default();
+    if revisions.last() != Some(&item) {
+        revisions.push(item);
+    }
+    revisions
+}
+
+/// Whether the patterns match distinct revisions in the given order.
+fn matches_sequence(
+    revisions: &[&serde_json::Value],
+    sequence: &[serde_json::Value],
+    coerce: bool,
+) -> bool {
+    let mut revisions = revisions.iter();
+    sequence
+        .iter()
+        .all(|pattern| revisions.any(|revision| pattern_matches(revision, pattern, coerce)))
+}
+
+fn sequence_diagnostic(
+    input: &[&serde_json::Value],
+    history: &History,
+    expr: &Expr,
+) -> AssertDiagnostic {
+    AssertDiagnostic {
+        input: input
+            .iter()
+            .map(|item| serde_json::json!(revisions(item, history)))
+            .collect(),
+        expr: expr.clone(),
+        diff: vec![],
+    }
+}
+
+fn restarts_diagnostic(restarts: Vec<(String, u64)>, expr: &Expr) -> AssertDiagnostic {
+    AssertDiagnostic {
+        input: restarts
+            .into_iter()
+            .map(|(name, restarts)| serde_json::json!({"name": name, "restarts": restarts}))
+            .collect(),
+        expr: expr.clone(),
+        diff: vec![],
+    }
+}
+
+/// How values are compared when checking a condition.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CheckOptions {
+    /// Compare numbers, booleans and strings holding them loosely.
+    pub coerce: bool,
+}
+
+pub fn assert_expr(
+    input: &Vec<&serde_json::Value>,
+    tombstones: &Vec<&serde_json::Value>,
+    history: &History,
+    expr: &Expr,
+    options: CheckOptions,
+) -> Result<(), AssertDiagnostic> {
     log::trace!("checking for condition {expr:?}");
+    let coerce = options.coerce;
     match expr {
         Expr::OneExpr { one } => input
             .iter()
-            .any(|item| match_object::contains(item, one))
+            .any(|item| pattern_matches(item, one, coerce))
             .then_some(())
             .ok_or_else(|| AssertDiagnostic {
                 input: input.iter().cloned().cloned().collect(),
                 expr: expr.clone(),
+                diff: closest_diff(input, one, coerce).into_iter().collect(),
             }),
         Expr::AllExpr { all } => input
             .iter()
-            .all(|item| match_object::contains(item, all))
+            .all(|item| pattern_matches(item, all, coerce))
             .then_some(())
             .ok_or_else(|| AssertDiagnostic {
                 input: input.iter().cloned().cloned().collect(),
                 expr: expr.clone(),
+                diff: input
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, item)| resource_diff(i, item, all, coerce))
+                    .collect(),
             }),
         Expr::SizeExpr { size } => {
             (input.len() == *size)
@@ -31,27 +395,132 @@ pub fn assert_expr(input: &Vec<&serde_json::Value>, expr: &Expr) -> Result<(), A
                 .ok_or_else(|| AssertDiagnostic {
                     input: vec![serde_json::json!(input.len())],
                     expr: expr.clone(),
+                    diff: vec![],
                 })
         }
         Expr::AndExpr { and } => and
             .iter()
-            .map(|e| assert_expr(input, e))
+            .map(|e| assert_expr(input, tombstones, history, e, options))
             .collect::<Result<Vec<()>, AssertDiagnostic>>()
             .map(|_| ()),
         Expr::OrExpr { or } => or
             .iter()
-            .any(|e| assert_expr(input, e).is_ok())
+            .any(|e| assert_expr(input, tombstones, history, e, options).is_ok())
             .then_some(())
             .ok_or_else(|| AssertDiagnostic {
                 input: input.iter().cloned().cloned().collect(),
                 expr: expr.clone(),
+                diff: vec![],
             }),
-        Expr::NotExpr { not } => assert_expr(input, not)
+        Expr::EventExpr { event } => {
+            let count = count_events(input, event);
+            let matches = match &event.count {
+                Some(comparison) => comparison.matches(count as f64),
+                None => count >= 1,
+            };
+            matches.then_some(()).ok_or_else(|| AssertDiagnostic {
+                input: input
+                    .iter()
+                    .filter(|item| event_matches(item, event))
+                    .cloned()
+                    .cloned()
+                    .collect(),
+                expr: expr.clone(),
+                diff: vec![],
+            })
+        }
+        Expr::NoRestartsExpr { no_restarts } => {
+            let restarts = restarts_since_baseline(input, history, no_restarts);
+            restarts
+                .iter()
+                .all(|(_, n)| *n == 0)
+                .then_some(())
+                .ok_or_else(|| restarts_diagnostic(restarts, expr))
+        }
+        Expr::RestartedExpr { restarted } => {
+            let restarts = restarts_since_baseline(input, history, restarted);
+            restarts
+                .iter()
+                .any(|(_, n)| *n > 0)
+                .then_some(())
+                .ok_or_else(|| restarts_diagnostic(restarts, expr))
+        }
+        Expr::JobCompleteExpr { job_complete } => {
+            let jobs = job_states(input, job_complete);
+            let complete = !jobs.is_empty() && jobs.iter().all(|(complete, _)| *complete);
+            complete.then_some(()).ok_or_else(|| AssertDiagnostic {
+                input: jobs
+                    .into_iter()
+                    .filter(|(complete, _)| !complete)
+                    .map(|(_, state)| state)
+                    .collect(),
+                expr: expr.clone(),
+                diff: vec![],
+            })
+        }
+        Expr::ReadyExpr { ready } => {
+            let condition_type = ready.condition_type();
+            let not_ready: Vec<serde_json::Value> = input
+                .iter()
+                .filter(|item| true_conditions(item, &[condition_type]).is_empty())
+                .map(|item| {
+                    serde_json::json!({
+                        "name": item.pointer("/metadata/name"),
+                        "conditions": item.pointer("/status/conditions"),
+                    })
+                })
+                .collect();
+            (!input.is_empty() && not_ready.is_empty())
+                .then_some(())
+                .ok_or_else(|| AssertDiagnostic {
+                    input: not_ready,
+                    expr: expr.clone(),
+                    diff: vec![],
+                })
+        }
+        Expr::DeletedExpr { deleted } => {
+            assert_expr(tombstones, &vec![], history, deleted, options).map_err(|diagnostic| {
+                AssertDiagnostic {
+                    input: diagnostic.input,
+                    expr: expr.clone(),
+                    diff: diagnostic.diff,
+                }
+            })
+        }
+        Expr::SequenceExpr { sequence } => input
+            .iter()
+            .any(|item| matches_sequence(&revisions(item, history), sequence, coerce))
+            .then_some(())
+            .ok_or_else(|| sequence_diagnostic(input, history, expr)),
+        Expr::AllSequenceExpr { all_sequence } => input
+            .iter()
+            .all(|item| matches_sequence(&revisions(item, history), all_sequence, coerce))
+            .then_some(())
+            .ok_or_else(|| sequence_diagnostic(input, history, expr)),
+        Expr::PathExpr(condition) => {
+            // Invalid conditions are rejected before waiting, see `path_conditions`.
+            let fulfilled = match (condition.compiled(), &condition.quantifier) {
+                (Err(_), _) => false,
+                (Ok(compiled), Quantifier::One) => input
+                    .iter()
+                    .any(|item| path_matches(item, condition, compiled, coerce)),
+                (Ok(compiled), Quantifier::All) => input
+                    .iter()
+                    .all(|item| path_matches(item, condition, compiled, coerce)),
+            };
+            fulfilled.then_some(()).ok_or_else(|| AssertDiagnostic {
+                input: input.iter().cloned().cloned().collect(),
+                expr: expr.clone(),
+                diff: vec![],
+            })
+        }
+        Expr::NotExpr { not } => assert_expr(input, tombstones, history, not, options)
             .is_err()
             .then_some(())
             .ok_or_else(|| AssertDiagnostic {
                 input: input.iter().cloned().cloned().collect(),
                 expr: expr.clone(),
+                diff: vec![],
             }),
     }
 }
@@ -59,6 +528,7 @@ pub fn assert_expr(input: &Vec<&serde_json::Value>, expr: &Expr) -> Result<(), A
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_spec::{Comparison, ReadyCondition};
     use rstest::rstest;
     use serde_json::json;
 
@@ -118,13 +588,353 @@ mod tests {
         ] },
         false
     )]
+    #[case(
+        vec![json!({"metadata": {"uid": "a", "labels": {"app": "nginx"}}, "status": {"containerStatuses": [{"name": "nginx", "restartCount": 2}]}})],
+        Expr::NoRestartsExpr { no_restarts: RestartCondition::default() },
+        false
+    )]
+    #[case(
+        vec![json!({"metadata": {"uid": "a", "labels": {"app": "nginx"}}, "status": {"containerStatuses": [{"name": "nginx", "restartCount": 2}]}})],
+        Expr::NoRestartsExpr { no_restarts: RestartCondition {
+            selector: Some([("app".to_string(), "other".to_string())].into()),
+            ..Default::default()
+        } },
+        true
+    )]
+    #[case(
+        vec![json!({"metadata": {"uid": "a"}, "status": {"containerStatuses": [{"name": "nginx", "restartCount": 0}]}})],
+        Expr::RestartedExpr { restarted: RestartCondition::default() },
+        false
+    )]
+    #[case(
+        vec![
+            json!({"reason": "FailedScheduling", "count": 3, "involvedObject": {"kind": "Pod"}}),
+            json!({"reason": "Scheduled", "count": 1, "involvedObject": {"kind": "Pod"}}),
+        ],
+        Expr::EventExpr { event: Box::new(EventCondition {
+            reason: Some("FailedScheduling".to_string()),
+            involved_kind: Some("Pod".to_string()),
+            count: Some(Comparison { gte: Some(3.0), ..Default::default() }),
+            ..Default::default()
+        }) },
+        true
+    )]
+    #[case(
+        vec![
+            json!({"reason": "FailedScheduling", "series": {"count": 2}, "regarding": {"kind": "Pod"}}),
+            json!({"reason": "FailedScheduling", "deprecatedCount": 1, "regarding": {"kind": "Pod"}}),
+        ],
+        Expr::EventExpr { event: Box::new(EventCondition {
+            reason: Some("FailedScheduling".to_string()),
+            count: Some(Comparison { eq: Some(3.0), ..Default::default() }),
+            ..Default::default()
+        }) },
+        true
+    )]
+    #[case(
+        vec![json!({"reason": "FailedScheduling", "involvedObject": {"kind": "Deployment"}})],
+        Expr::EventExpr { event: Box::new(EventCondition {
+            reason: Some("FailedScheduling".to_string()),
+            involved_kind: Some("Pod".to_string()),
+            ..Default::default()
+        }) },
+        false
+    )]
+    #[case(
+        vec![json!({"metadata": {"name": "migrate"}, "spec": {"completions": 2}, "status": {"succeeded": 2}})],
+        Expr::JobCompleteExpr { job_complete: JobCompleteCondition::default() },
+        true
+    )]
+    #[case(
+        vec![json!({"metadata": {"name": "migrate"}, "status": {"conditions": [{"type": "Complete", "status": "True"}]}})],
+        Expr::JobCompleteExpr { job_complete: JobCompleteCondition::default() },
+        true
+    )]
+    #[case(
+        vec![
+            json!({"metadata": {"name": "migrate"}, "status": {"succeeded": 1}}),
+            json!({"metadata": {"name": "seed"}, "status": {"active": 1}}),
+        ],
+        Expr::JobCompleteExpr { job_complete: JobCompleteCondition::default() },
+        false
+    )]
+    #[case(
+        vec![
+            json!({"metadata": {"name": "migrate", "labels": {"app": "db"}}, "status": {"succeeded": 1}}),
+            json!({"metadata": {"name": "seed"}, "status": {"active": 1}}),
+        ],
+        Expr::JobCompleteExpr { job_complete: JobCompleteCondition {
+            selector: Some([("app".to_string(), "db".to_string())].into()),
+        } },
+        true
+    )]
+    #[case(
+        vec![],
+        Expr::JobCompleteExpr { job_complete: JobCompleteCondition::default() },
+        false
+    )]
+    #[case(
+        vec![
+            json!({"status": {"conditions": [{"type": "Ready", "status": "True"}]}}),
+            json!({"status": {"conditions": [{"type": "Synced", "status": "False"}, {"type": "Ready", "status": "True"}]}}),
+        ],
+        Expr::ReadyExpr { ready: ReadyCondition::default() },
+        true
+    )]
+    #[case(
+        vec![
+            json!({"status": {"conditions": [{"type": "Ready", "status": "True"}]}}),
+            json!({"status": {"conditions": [{"type": "Ready", "status": "False"}]}}),
+        ],
+        Expr::ReadyExpr { ready: ReadyCondition::default() },
+        false
+    )]
+    #[case(
+        vec![json!({"status": {"conditions": [{"type": "Available", "status": "True"}]}})],
+        Expr::ReadyExpr { ready: ReadyCondition { condition_type: Some("Available".to_string()) } },
+        true
+    )]
+    #[case(vec![json!({"status": {}})], Expr::ReadyExpr { ready: ReadyCondition::default() }, false)]
+    #[case(vec![], Expr::ReadyExpr { ready: ReadyCondition::default() }, false)]
     fn test_assert_expr(
         #[case] input: Vec<serde_json::Value>,
         #[case] expr: Expr,
         #[case] expected: bool,
     ) {
         let v = input.iter().collect::<Vec<&serde_json::Value>>();
-        let result = assert_expr(&v, &expr);
+        let result = assert_expr(&v, &vec![], &History::new(), &expr, CheckOptions::default());
         assert_eq!(result.is_ok(), expected);
     }
+
+    #[test]
+    fn test_job_states() {
+        let job = json!({
+            "metadata": {"name": "migrate"},
+            "spec": {"completions": 1},
+            "status": {"failed": 3, "conditions": [
+                {"type": "FailureTarget", "status": "True", "reason": "BackoffLimitExceeded",
+                 "message": "Job has reached the specified backoff limit"},
+                {"type": "Failed", "status": "False", "reason": "BackoffLimitExceeded"},
+            ]},
+        });
+        assert_eq!(
+            job_states(&[&job], &JobCompleteCondition::default()),
+            vec![(
+                false,
+                json!({
+                    "name": "migrate",
+                    "namespace": null,
+                    "succeeded": 0,
+                    "completions": 1,
+                    "failed": 3,
+                    "failures": [{
+                        "reason": "BackoffLimitExceeded",
+                        "message": "Job has reached the specified backoff limit",
+                    }],
+                })
+            )]
+        );
+    }
+
+    #[test]
+    fn test_pod_reasons() {
+        let pod = json!({
+            "metadata": {"name": "migrate-x7k2p"},
+            "status": {
+                "initContainerStatuses": [
+                    {"name": "init", "state": {"terminated": {"reason": "Completed", "exitCode": 0}}},
+                ],
+                "containerStatuses": [
+                    {"name": "migrate", "state": {"terminated": {"reason": "Error", "exitCode": 1}}},
+                    {"name": "proxy", "state": {"waiting": {"reason": "ImagePullBackOff",
+                        "message": "Back-off pulling image"}}},
+                    {"name": "sidecar", "state": {"running": {}}},
+                ],
+            },
+        });
+        assert_eq!(
+            pod_reasons(&[pod]),
+            vec![
+                json!({"pod": "migrate-x7k2p", "container": "migrate", "reason": "Error",
+                    "message": null, "exitCode": 1}),
+                json!({"pod": "migrate-x7k2p", "container": "proxy", "reason": "ImagePullBackOff",
+                    "message": "Back-off pulling image", "exitCode": null}),
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case(json!({"conditions": [{"type": "Failed", "status": "True"}]}), true)]
+    #[case(json!({"conditions": [{"type": "FailureTarget", "status": "True"}]}), false)]
+    #[case(json!({"active": 1}), false)]
+    fn test_unfulfillable(#[case] status: serde_json::Value, #[case] expected: bool) {
+        let job = json!({"metadata": {"name": "migrate"}, "status": status});
+        let job_complete = Expr::JobCompleteExpr {
+            job_complete: JobCompleteCondition::default(),
+        };
+        assert_eq!(unfulfillable(&[&job], &job_complete), expected);
+        let and = Expr::AndExpr {
+            and: vec![Expr::default(), job_complete.clone()],
+        };
+        assert_eq!(unfulfillable(&[&job], &and), expected);
+        let or = Expr::OrExpr {
+            or: vec![Expr::default(), job_complete],
+        };
+        assert!(!unfulfillable(&[&job], &or));
+    }
+
+    #[test]
+    fn test_restarts_since_baseline() {
+        let initial = json!({"metadata": {"uid": "a"}, "status": {"containerStatuses": [{"name": "nginx", "restartCount": 2}]}});
+        let current = json!({"metadata": {"uid": "a"}, "status": {"containerStatuses": [{"name": "nginx", "restartCount": 2}]}});
+        let history = History::from([("a".to_string(), vec![initial])]);
+        let input = vec![&current];
+        let no_restarts = Expr::NoRestartsExpr {
+            no_restarts: RestartCondition::default(),
+        };
+        assert!(assert_expr(
+            &input,
+            &vec![],
+            &history,
+            &no_restarts,
+            CheckOptions::default()
+        )
+        .is_ok());
+        let restarted = json!({"metadata": {"uid": "a"}, "status": {"containerStatuses": [{"name": "nginx", "restartCount": 3}]}});
+        let input = vec![&restarted];
+        assert!(assert_expr(
+            &input,
+            &vec![],
+            &history,
+            &no_restarts,
+            CheckOptions::default()
+        )
+        .is_err());
+    }
+
+    #[rstest]
+    #[case(vec![json!({"status": "Pending"}), json!({"status": "Running"})], true)]
+    #[case(vec![json!({"status": "Running"}), json!({"status": "Pending"})], false)]
+    #[case(vec![json!({"status": "Pending"})], false)]
+    #[case(
+        vec![
+            json!({"status": "Pending"}),
+            json!({"status": "Pending", "ready": false}),
+            json!({"status": "Running"}),
+        ],
+        true
+    )]
+    fn test_sequence(#[case] revisions: Vec<serde_json::Value>, #[case] expected: bool) {
+        let current = revisions
+            .last()
+            .unwrap()
+            .clone()
+            .as_object()
+            .unwrap()
+            .clone()
+            .into_iter()
+            .chain([("metadata".to_string(), json!({"uid": "a"}))])
+            .collect::<serde_json::Value>();
+        let history = History::from([("a".to_string(), revisions)]);
+        let input = vec![&current];
+        let sequence = vec![json!({"status": "Pending"}), json!({"status": "Running"})];
+        let expr = Expr::SequenceExpr {
+            sequence: sequence.clone(),
+        };
+        assert_eq!(
+            assert_expr(&input, &vec![], &history, &expr, CheckOptions::default()).is_ok(),
+            expected
+        );
+        let expr = Expr::AllSequenceExpr {
+            all_sequence: sequence,
+        };
+        assert_eq!(
+            assert_expr(&input, &vec![], &history, &expr, CheckOptions::default()).is_ok(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case(Expr::SizeExpr { size: 1 }, true)]
+    #[case(Expr::SizeExpr { size: 0 }, false)]
+    #[case(Expr::OneExpr { one: json!({"metadata": {"name": "b"}}) }, true)]
+    #[case(Expr::OneExpr { one: json!({"metadata": {"name": "a"}}) }, false)]
+    fn test_deleted(#[case] inner: Expr, #[case] expected: bool) {
+        let current = json!({"metadata": {"name": "a"}});
+        let deleted =
+            json!({"metadata": {"name": "b", "deletionTimestamp": "2024-01-01T00:00:00Z"}});
+        let expr = Expr::DeletedExpr {
+            deleted: Box::new(inner),
+        };
+        let result = assert_expr(
+            &vec![&current],
+            &vec![&deleted],
+            &History::new(),
+            &expr,
+            CheckOptions::default(),
+        );
+        assert_eq!(result.is_ok(), expected);
+    }
+
+    #[rstest]
+    #[case(json!({"path": ".status.phase", "equals": "Running"}), false, true)]
+    #[case(json!({"path": ".status.phase", "equals": "Running", "for": "all"}), false, false)]
+    #[case(json!({"path": ".status.phase", "notEquals": "Failed", "for": "all"}), false, true)]
+    #[case(json!({"path": "{.spec.ports[0].port}", "equals": 8080}), false, true)]
+    #[case(json!({"path": ".spec.ports[0].port", "equals": "8080"}), false, false)]
+    #[case(json!({"path": ".spec.ports[0].port", "equals": "8080"}), true, true)]
+    #[case(json!({"path": ".spec.ports[0].port", "notEquals": "8080"}), true, false)]
+    #[case(json!({"path": ".status.podIP", "matches": "^10\\.", "for": "all"}), false, false)]
+    #[case(json!({"path": ".status.podIP", "exists": false}), false, true)]
+    #[case(json!({"path": ".status.podIP", "exists": true, "for": "all"}), false, false)]
+    #[case(json!({"path": ".spec.ports[*]", "exists": true}), false, false)]
+    fn test_path(#[case] expr: serde_json::Value, #[case] coerce: bool, #[case] expected: bool) {
+        let running = json!({
+            "spec": {"ports": [{"port": 8080}]},
+            "status": {"phase": "Running", "podIP": "10.0.0.1"},
+        });
+        let pending = json!({"spec": {"ports": [{"port": 8080}]}, "status": {"phase": "Pending"}});
+        let expr: Expr = serde_json::from_value(expr).unwrap();
+        assert!(matches!(expr, Expr::PathExpr(_)));
+        let result = assert_expr(
+            &vec![&running, &pending],
+            &vec![],
+            &History::new(),
+            &expr,
+            CheckOptions { coerce },
+        );
+        assert_eq!(result.is_ok(), expected);
+    }
+
+    #[rstest]
+    #[case(json!({"one": {"metadata": {"name": "b"}, "status": {"phase": "Running"}}}), vec![("ns/b", 1)])]
+    #[case(json!({"all": {"status": {"ready": true}}}), vec![("ns/a", 1), ("ns/b", 1)])]
+    #[case(json!({"all": {"status": {"phase": "Pending"}}}), vec![("ns/a", 1)])]
+    #[case(json!({"size": 3}), vec![])]
+    fn test_diagnostic_diff(#[case] expr: serde_json::Value, #[case] expected: Vec<(&str, usize)>) {
+        let a =
+            json!({"metadata": {"namespace": "ns", "name": "a"}, "status": {"phase": "Failed"}});
+        let b =
+            json!({"metadata": {"namespace": "ns", "name": "b"}, "status": {"phase": "Pending"}});
+        let expr: Expr = serde_json::from_value(expr).unwrap();
+        let diagnostic = assert_expr(
+            &vec![&a, &b],
+            &vec![],
+            &History::new(),
+            &expr,
+            CheckOptions::default(),
+        )
+        .unwrap_err();
+        let diff: Vec<(&str, usize)> = diagnostic
+            .diff
+            .iter()
+            .map(|resource_diff| {
+                (
+                    resource_diff.resource.as_str(),
+                    resource_diff.differences.len(),
+                )
+            })
+            .collect();
+        assert_eq!(diff, expected);
+    }
 }
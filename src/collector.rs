@@ -2,10 +2,15 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
+    config::default_manage_finalizers,
     error::Error,
     error::Result,
-    test_spec::{BucketOperation, WatchSpec},
+    events::{emit, BucketChange, EventKind},
+    match_object::get_path,
+    run,
+    test_spec::{BucketOperation, ListStrategy, WatchNamespace, WatchSpec},
 };
+use futures::stream::BoxStream;
 use futures::StreamExt;
 use kube::{
     api::{DynamicObject, Patch, PatchParams},
@@ -16,18 +21,30 @@ use kube::{
 };
 use serde_json;
 use serde_json::json;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, Mutex};
 use tokio::task::JoinSet;
-use tokio::time::{sleep, Duration};
+use tokio::time::{sleep, Duration, Instant};
 use tokio_util::sync::CancellationToken;
 
 const FINALIZER_NAME: &str = "blackjack.io/finalizer";
 
+/// How often a shared watch checks whether it still has subscribers.
+const SHARED_WATCH_IDLE_CHECK: Duration = Duration::from_secs(10);
+
 pub struct Bucket {
     pub allowed_operations: HashSet<BucketOperation>,
     pub data: HashMap<String, serde_json::Value>,
+    /// Observed versions of each resource in `data`.
+    /// Holds only the first observed version, unless `keep_history` is set.
+    pub history: HashMap<String, Vec<serde_json::Value>>,
+    pub keep_history: bool,
+    /// Last observed state of deleted resources, if `Tombstone` is set.
+    pub tombstones: HashMap<String, serde_json::Value>,
+    /// When a resource was last stored in or removed from `data`,
+    /// or when the bucket was created if that never happened.
+    pub changed: Instant,
 }
 
 impl Default for Bucket {
@@ -39,6 +56,10 @@ impl Default for Bucket {
                 BucketOperation::Delete,
             ]),
             data: HashMap::new(),
+            history: HashMap::new(),
+            keep_history: false,
+            tombstones: HashMap::new(),
+            changed: Instant::now(),
         }
     }
 }
@@ -48,6 +69,10 @@ impl Bucket {
         Bucket {
             allowed_operations,
             data: HashMap::new(),
+            history: HashMap::new(),
+            keep_history: false,
+            tombstones: HashMap::new(),
+            changed: Instant::now(),
         }
     }
 }
@@ -56,81 +81,290 @@ pub type Buckets = HashMap<String, Bucket>;
 
 pub struct CollectedData {
     pub buckets: Buckets,
+    /// Buckets not watched as their API is not served, with the reason.
+    pub unavailable: HashMap<String, String>,
+    /// Resources carrying the finalizer of Blackjack by UID, from which it is removed on cleanup,
+    /// regardless of which watch added it.
+    pub finalized: HashMap<String, serde_json::Value>,
 }
 pub type CollectedDataContainer = Arc<Mutex<CollectedData>>;
 
+/// Whether `key` holds the resource with `uid` in a bucket.
+/// Resources watched in several versions are held once per version, as `<uid>@<version>`.
+fn is_key_of(key: &str, uid: &str) -> bool {
+    key.strip_prefix(uid)
+        .is_some_and(|version| version.is_empty() || version.starts_with('@'))
+}
+
 impl CollectedData {
     pub fn new() -> Self {
         CollectedData {
             buckets: HashMap::new(),
+            unavailable: HashMap::new(),
+            finalized: HashMap::new(),
         }
     }
 
     pub fn contains(&self, uid: &str) -> bool {
         for (_, bucket) in &self.buckets {
-            if bucket.data.contains_key(uid) {
+            if bucket.data.keys().any(|key| is_key_of(key, uid)) {
                 return true;
             }
         }
         false
     }
 
-    pub async fn cleanup(&self, client: Client) -> Result<()> {
-        let uids: Vec<String> = {
-            self.buckets
-                .iter()
-                .flat_map(|(_, bucket)| bucket.data.iter())
-                .map(|(uid, _)| uid.clone())
-                .collect()
-        };
+    /// Stores a value received from other sources than watches, like a message,
+    /// if the bucket reflects creations. Returns whether it was stored.
+    pub fn insert_received(&mut self, bucket: &str, key: String, value: serde_json::Value) -> bool {
+        let bucket = self.buckets.entry(bucket.to_string()).or_default();
+        if !bucket.allowed_operations.contains(&BucketOperation::Create) {
+            return false;
+        }
+        bucket.history.insert(key.clone(), vec![value.clone()]);
+        bucket.changed = Instant::now();
+        bucket.data.insert(key, value);
+        true
+    }
 
-        for uid in uids {
+    pub async fn cleanup(&self, client: Client) -> Result<()> {
+        for (uid, resource_value) in &self.finalized {
             log::debug!("Removing finalizer for {uid}");
-            let resource = {
-                self.buckets
-                    .values()
-                    .flat_map(|bucket| bucket.data.get(&uid))
-                    .next()
-                    .cloned()
-            };
+            let obj: DynamicObject = serde_json::from_value(resource_value.clone())?;
+            let name = obj.name_any();
+            let namespace = obj.namespace().unwrap_or_default();
+            let api: Api<DynamicObject> = Api::namespaced_with(
+                client.clone(),
+                &namespace,
+                &ApiResource::from_gvk(&GroupVersionKind::try_from(
+                    &obj.types.unwrap_or_default(),
+                )?),
+            );
 
-            if let Some(resource_value) = resource {
-                let obj: DynamicObject = serde_json::from_value(resource_value)?;
-                let name = obj.name_any();
-                let namespace = obj.namespace().unwrap_or_default();
-                let api: Api<DynamicObject> = Api::namespaced_with(
-                    client.clone(),
-                    &namespace,
-                    &ApiResource::from_gvk(&GroupVersionKind::try_from(
-                        &obj.types.unwrap_or_default(),
-                    )?),
-                );
+            let patch = json!({
+                "metadata": {
+                    "finalizers": null
+                }
+            });
+            let patch_params = PatchParams::default();
+            log::debug!("calling API");
+            match api.patch(&name, &patch_params, &Patch::Merge(&patch)).await {
+                Ok(_) => log::debug!("Removed finalizer from '{}'", name),
+                Err(e) => log::warn!("Failed to remove finalizer from '{}': {}", name, e),
+            }
+        }
 
-                let patch = json!({
-                    "metadata": {
-                        "finalizers": null
+        Ok(())
+    }
+}
+
+/// The resources matched by the watch, one per version.
+/// Without a version, the preferred version of the group is resolved via discovery.
+async fn api_resources(client: &Client, spec: &WatchSpec) -> Result<Vec<ApiResource>> {
+    let versions = match (spec.versions.is_empty(), spec.version.is_empty()) {
+        (false, _) => spec.versions.clone(),
+        (true, false) => vec![spec.version.clone()],
+        (true, true) => {
+            let group = kube::discovery::group(client, &spec.group).await?;
+            let (api_resource, _) = group.recommended_kind(&spec.kind).ok_or_else(|| {
+                Error::DiscoveryError(Box::new(GroupVersionKind::gvk(&spec.group, "", &spec.kind)))
+            })?;
+            log::debug!(
+                "Resolved watch '{}' to version '{}'",
+                spec.name,
+                api_resource.version
+            );
+            return Ok(vec![api_resource]);
+        }
+    };
+    Ok(versions
+        .iter()
+        .map(|version| {
+            ApiResource::from_gvk(&GroupVersionKind::gvk(&spec.group, version, &spec.kind))
+        })
+        .collect())
+}
+
+/// Identifies watches that can share one stream: the same resource with the same selectors.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub(crate) struct SharedWatchKey {
+    group: String,
+    version: String,
+    plural: String,
+    label_selector: Option<String>,
+    field_selector: Option<String>,
+    streaming_list: bool,
+    page_size: Option<u32>,
+}
+
+impl SharedWatchKey {
+    fn new(api_resource: &ApiResource, config: &watcher::Config) -> Self {
+        SharedWatchKey {
+            group: api_resource.group.clone(),
+            version: api_resource.version.clone(),
+            plural: api_resource.plural.clone(),
+            label_selector: config.label_selector.clone(),
+            field_selector: config.field_selector.clone(),
+            streaming_list: config.initial_list_strategy == InitialListStrategy::StreamingList,
+            page_size: config.page_size,
+        }
+    }
+}
+
+struct Subscriber {
+    namespace: WatchNamespace,
+    sender: mpsc::UnboundedSender<Event<DynamicObject>>,
+}
+
+/// A cluster-wide watch shared by all watches of the run with the same key.
+/// Keeps the current objects, so that watches subscribing later start with them,
+/// and routes the events of objects to the subscribers watching their namespace.
+#[derive(Default)]
+pub(crate) struct SharedWatch {
+    objects: BTreeMap<String, DynamicObject>,
+    /// Objects listed since the watch started to list again, e.g. after it was interrupted.
+    relisted: Option<BTreeMap<String, DynamicObject>>,
+    subscribers: Vec<Subscriber>,
+}
+
+/// The shared watches of a run, by key.
+pub(crate) type SharedWatches = HashMap<SharedWatchKey, Arc<std::sync::Mutex<SharedWatch>>>;
+
+fn object_key(obj: &DynamicObject) -> String {
+    format!("{}/{}", obj.namespace().unwrap_or_default(), obj.name_any())
+}
+
+impl SharedWatch {
+    fn subscribe(&mut self, subscriber: Subscriber) {
+        for obj in self.objects.values() {
+            if subscriber
+                .namespace
+                .matches(&obj.namespace().unwrap_or_default())
+            {
+                let _ = subscriber.sender.send(Event::InitApply(obj.clone()));
+            }
+        }
+        self.subscribers.push(subscriber);
+    }
+
+    fn send(&self, event: Event<DynamicObject>, obj: &DynamicObject) {
+        let namespace = obj.namespace().unwrap_or_default();
+        for subscriber in &self.subscribers {
+            if subscriber.namespace.matches(&namespace) {
+                let _ = subscriber.sender.send(event.clone());
+            }
+        }
+    }
+
+    fn dispatch(&mut self, event: Event<DynamicObject>) {
+        match &event {
+            Event::Init => self.relisted = Some(BTreeMap::new()),
+            Event::InitApply(obj) | Event::Apply(obj) => {
+                if let (Event::InitApply(_), Some(relisted)) = (&event, &mut self.relisted) {
+                    relisted.insert(object_key(obj), obj.clone());
+                }
+                self.objects.insert(object_key(obj), obj.clone());
+                self.send(event.clone(), obj);
+            }
+            Event::Delete(obj) => {
+                self.objects.remove(&object_key(obj));
+                self.send(event.clone(), obj);
+            }
+            Event::InitDone => {
+                // Objects deleted while the watch was interrupted are not listed again.
+                let Some(relisted) = self.relisted.take() else {
+                    return;
+                };
+                let objects = std::mem::replace(&mut self.objects, relisted);
+                for (key, obj) in objects {
+                    if !self.objects.contains_key(&key) {
+                        self.send(Event::Delete(obj.clone()), &obj);
                     }
-                });
-                let patch_params = PatchParams::default();
-                log::debug!("calling API");
-                match api.patch(&name, &patch_params, &Patch::Merge(&patch)).await {
-                    Ok(_) => log::debug!("Removed finalizer from '{}'", name),
-                    Err(e) => log::warn!("Failed to remove finalizer from '{}': {}", name, e),
                 }
-            } else {
-                log::warn!("Resource with UID '{}' not found in collected_data", uid);
             }
         }
+    }
 
-        Ok(())
+    fn has_subscribers(&mut self) -> bool {
+        self.subscribers
+            .retain(|subscriber| !subscriber.sender.is_closed());
+        !self.subscribers.is_empty()
+    }
+}
+
+/// Subscribes to the shared watch of the resource in the current run, starting it if there is none.
+/// The watch ends once all of its subscribers are gone.
+fn subscribe(
+    client: Client,
+    api_resource: &ApiResource,
+    config: watcher::Config,
+    namespace: WatchNamespace,
+) -> BoxStream<'static, std::result::Result<Event<DynamicObject>, watcher::Error>> {
+    let key = SharedWatchKey::new(api_resource, &config);
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let subscriber = Subscriber { namespace, sender };
+    let registry = run::current()
+        .map(|run| run.shared_watches.clone())
+        .unwrap_or_default();
+    let mut watches = registry.lock().unwrap();
+    match watches.get(&key) {
+        Some(shared) => shared.lock().unwrap().subscribe(subscriber),
+        None => {
+            log::debug!(
+                "Starting shared watch of {}/{}",
+                api_resource.api_version,
+                api_resource.plural
+            );
+            let shared = Arc::new(std::sync::Mutex::new(SharedWatch::default()));
+            shared.lock().unwrap().subscribe(subscriber);
+            watches.insert(key.clone(), shared.clone());
+            let api = Api::all_with(client, api_resource);
+            let stream = watcher(api, config);
+            tokio::spawn(run_shared_watch(registry.clone(), key, shared, stream));
+        }
+    }
+    futures::stream::unfold(receiver, |mut receiver| async move {
+        let event = receiver.recv().await?;
+        Some((Ok(event), receiver))
+    })
+    .boxed()
+}
+
+async fn run_shared_watch(
+    registry: Arc<std::sync::Mutex<SharedWatches>>,
+    key: SharedWatchKey,
+    shared: Arc<std::sync::Mutex<SharedWatch>>,
+    stream: impl futures::Stream<Item = std::result::Result<Event<DynamicObject>, watcher::Error>>,
+) {
+    let mut stream = std::pin::pin!(stream);
+    loop {
+        tokio::select! {
+            event = stream.next() => match event {
+                Some(Ok(event)) => shared.lock().unwrap().dispatch(event),
+                Some(Err(e)) => {
+                    log::warn!("Error in shared watch of {}: {}", key.plural, e);
+                    sleep(Duration::from_secs(10)).await;
+                }
+                None => {}
+            },
+            _ = sleep(SHARED_WATCH_IDLE_CHECK) => {}
+        }
+        let mut watches = registry.lock().unwrap();
+        if !shared.lock().unwrap().has_subscribers() {
+            watches.remove(&key);
+            break;
+        }
     }
+    log::debug!("Shared watch of {} is terminating.", key.plural);
 }
 
 struct CollectorBrief {
     client: Client,
-    namespace: String,
+    test_name: String,
     api_resource: ApiResource,
     spec: WatchSpec,
+    versioned_keys: bool,
+    shared: bool,
     collected_data: CollectedDataContainer,
     token: CancellationToken,
 }
@@ -147,31 +381,41 @@ impl Collector {
 
     pub async fn new(
         client: Client,
+        test_name: &str,
         specs: Vec<WatchSpec>,
         collected_data: CollectedDataContainer,
+        shared: bool,
     ) -> Result<Self> {
         let token = CancellationToken::new();
         let mut tasks = JoinSet::new();
         for spec in specs {
-            let brief = CollectorBrief {
-                client: client.clone(),
-                namespace: spec.namespace.clone(),
-                collected_data: collected_data.clone(),
-                token: token.clone(),
-                api_resource: ApiResource::from_gvk(&GroupVersionKind::gvk(
-                    &spec.group,
-                    &spec.version,
-                    &spec.kind,
-                )),
-                spec,
-            };
+            let api_resources = api_resources(&client, &spec).await?;
+            let versioned_keys = api_resources.len() > 1;
+            for api_resource in api_resources {
+                let brief = CollectorBrief {
+                    client: client.clone(),
+                    test_name: test_name.to_string(),
+                    collected_data: collected_data.clone(),
+                    token: token.clone(),
+                    api_resource,
+                    spec: spec.clone(),
+                    versioned_keys,
+                    shared,
+                };
 
-            tasks.spawn(async move { brief.start().await });
+                tasks.spawn(run::inherit(async move { brief.start().await }));
+            }
         }
 
         Ok(Collector { token, tasks })
     }
 
+    /// Wraps tasks that feed buckets from other sources than watches,
+    /// to be stopped along with the watches.
+    pub fn from_tasks(token: CancellationToken, tasks: JoinSet<Result<()>>) -> Self {
+        Collector { token, tasks }
+    }
+
     pub async fn stop(&mut self) -> Result<()> {
         assert!(!self.token.is_cancelled());
 
@@ -190,14 +434,54 @@ impl Collector {
 }
 
 impl CollectorBrief {
-    async fn handle_apply(&self, api: Api<DynamicObject>, obj: DynamicObject) -> Result<()> {
+    fn emit_change(&self, bucket: &str, change: BucketChange, obj: &DynamicObject) {
+        emit(EventKind::BucketChanged {
+            test: self.test_name.clone(),
+            bucket: bucket.to_string(),
+            change,
+            object: format!("{}/{}", self.spec.kind, obj.name_any()),
+            resource_version: obj.resource_version(),
+        });
+    }
+
+    fn is_relevant_change(&self, old: &serde_json::Value, new: &serde_json::Value) -> bool {
+        self.spec.projection.as_ref().is_none_or(|paths| {
+            paths
+                .iter()
+                .any(|path| get_path(old, path) != get_path(new, path))
+        })
+    }
+
+    fn key(&self, uid: &str) -> String {
+        match self.versioned_keys {
+            true => format!("{uid}@{}", self.api_resource.version),
+            false => uid.to_string(),
+        }
+    }
+
+    fn manage_finalizers(&self) -> bool {
+        self.spec
+            .manage_finalizers
+            .unwrap_or_else(default_manage_finalizers)
+    }
+
+    /// Reflects an observed resource in the bucket.
+    /// `is_deleted` is set for resources that are already gone,
+    /// which are only observed when finalizers are not managed.
+    async fn handle_apply(&self, obj: DynamicObject, is_deleted: bool) -> Result<()> {
         let name = obj.name_any();
+        let api: Api<DynamicObject> = Api::namespaced_with(
+            self.client.clone(),
+            &obj.namespace().unwrap_or_default(),
+            &self.api_resource,
+        );
         let uid = obj.metadata.uid.clone().unwrap();
+        let key = self.key(&uid);
         let mut data = self.collected_data.lock().await;
-        let is_marked_for_deletion = obj.metadata.deletion_timestamp.is_some();
+        let is_marked_for_deletion = is_deleted || obj.metadata.deletion_timestamp.is_some();
         let mut is_stored = (*data).contains(&uid);
         let mut has_finalizer = obj.finalizers().contains(&FINALIZER_NAME.to_string());
-        if !is_stored && !is_marked_for_deletion {
+        if !is_stored && !is_marked_for_deletion && self.manage_finalizers() {
             if !has_finalizer {
                 let patch = json!({
                     "metadata": {
@@ -216,18 +500,48 @@ impl CollectorBrief {
                 }
             }
         }
+        if has_finalizer && !is_marked_for_deletion {
+            let value = serde_json::to_value(&obj).unwrap_or_default();
+            data.finalized.insert(uid.clone(), value);
+        }
+        if is_deleted {
+            data.finalized.remove(&uid);
+        }
         if is_marked_for_deletion {
             if is_stored {
                 is_stored = false;
-                for (_, bucket) in &mut (*data).buckets {
+                for (bucket_name, bucket) in &mut (*data).buckets {
+                    let keys: Vec<String> = bucket
+                        .data
+                        .keys()
+                        .filter(|key| is_key_of(key, &uid))
+                        .cloned()
+                        .collect();
+                    if !keys.is_empty()
+                        && bucket
+                            .allowed_operations
+                            .contains(&BucketOperation::Tombstone)
+                    {
+                        bucket.tombstones.insert(
+                            uid.clone(),
+                            serde_json::to_value(&obj).unwrap_or(serde_json::Value::Null),
+                        );
+                    }
                     if bucket.allowed_operations.contains(&BucketOperation::Delete) {
-                        bucket.data.remove(&uid);
+                        for key in keys {
+                            if bucket.data.remove(&key).is_some() {
+                                bucket.changed = Instant::now();
+                                self.emit_change(bucket_name, BucketChange::Delete, &obj);
+                            }
+                            bucket.history.remove(&key);
+                        }
                     } else {
                         is_stored = true;
                     }
                 }
             }
-            if has_finalizer && !is_stored {
+            if has_finalizer && !is_stored && !is_deleted {
+                data.finalized.remove(&uid);
                 let patch = json!({
                     "metadata": {
                         "finalizers": null
@@ -245,20 +559,36 @@ impl CollectorBrief {
                 .buckets
                 .entry(self.spec.name.clone())
                 .or_insert_with(Default::default);
-            if (!bucket.data.contains_key(&uid)
+            bucket.keep_history |= self.spec.history;
+            if (!bucket.data.contains_key(&key)
                 && bucket.allowed_operations.contains(&BucketOperation::Create))
-                || (bucket.data.contains_key(&uid)
-                    && bucket.allowed_operations.contains(&BucketOperation::Patch))
+                || (bucket.data.contains_key(&key)
+                    && bucket.allowed_operations.contains(&BucketOperation::Patch)
+                    && self.is_relevant_change(&bucket.data[&key], &value))
             {
-                bucket.data.insert(uid, value);
+                let history = bucket.history.entry(key.clone()).or_default();
+                if history.is_empty() || bucket.keep_history {
+                    history.push(value.clone());
+                }
+                let change = match bucket.data.contains_key(&key) {
+                    true => BucketChange::Patch,
+                    false => BucketChange::Create,
+                };
+                bucket.changed = Instant::now();
+                bucket.data.insert(key, value);
+                self.emit_change(&self.spec.name, change, &obj);
             }
         }
         Ok(())
     }
 
     async fn start(&self) -> Result<()> {
-        let api: Api<DynamicObject> =
-            Api::namespaced_with(self.client.clone(), &self.namespace, &self.api_resource);
+        let api: Api<DynamicObject> = match self.spec.namespace.single() {
+            Some(namespace) => {
+                Api::namespaced_with(self.client.clone(), namespace, &self.api_resource)
+            }
+            None => Api::all_with(self.client.clone(), &self.api_resource),
+        };
         let label_selector = self
             .spec
             .labels
@@ -290,13 +620,27 @@ impl CollectorBrief {
             .or_else(|| Some(String::new()))
             .unwrap();
 
+        let initial_list_strategy = match self.spec.initial_list_strategy.unwrap_or_default() {
+            ListStrategy::ListWatch => InitialListStrategy::ListWatch,
+            ListStrategy::StreamingList => InitialListStrategy::StreamingList,
+        };
+        let default_config = watcher::Config::default();
         let config = watcher::Config {
             label_selector: Some(label_selector),
             field_selector: Some(field_selector),
-            initial_list_strategy: InitialListStrategy::ListWatch,
-            ..watcher::Config::default()
+            initial_list_strategy,
+            page_size: self.spec.page_size.or(default_config.page_size),
+            ..default_config
+        };
+        let mut stream = match self.shared {
+            true => subscribe(
+                self.client.clone(),
+                &self.api_resource,
+                config,
+                self.spec.namespace.clone(),
+            ),
+            false => watcher(api, config).boxed(),
         };
-        let mut stream = watcher(api.clone(), config).boxed();
 
         while let Some(event) = tokio::select! {
             biased;
@@ -307,12 +651,24 @@ impl CollectorBrief {
             event = stream.next() => event,
         } {
             let result = match event {
+                Ok(Event::Apply(obj)) | Ok(Event::InitApply(obj))
+                    if !self
+                        .spec
+                        .namespace
+                        .matches(&obj.namespace().unwrap_or_default()) =>
+                {
+                    Ok(())
+                }
                 Ok(Event::Apply(obj)) | Ok(Event::InitApply(obj)) => match obj.uid() {
-                    Some(_) => self.handle_apply(api.clone(), obj).await,
+                    Some(_) => self.handle_apply(obj, false).await,
+                    None => Err(Error::NoUidError),
+                },
+                Ok(Event::Delete(obj)) if !self.manage_finalizers() => match obj.uid() {
+                    Some(_) => self.handle_apply(obj, true).await,
                     None => Err(Error::NoUidError),
                 },
                 Ok(_) => Ok(()),
-                Err(e) => Err(Error::WatcherError(e)),
+                Err(e) => Err(e.into()),
             };
             match result {
                 Ok(_) => {}
@@ -333,3 +689,66 @@ impl CollectorBrief {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(namespace: &str, name: &str) -> DynamicObject {
+        serde_json::from_value(json!({
+            "apiVersion": "v1",
+            "kind": "ConfigMap",
+            "metadata": {"name": name, "namespace": namespace},
+        }))
+        .unwrap()
+    }
+
+    fn subscriber(namespace: &str) -> (Subscriber, mpsc::UnboundedReceiver<Event<DynamicObject>>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let namespace = WatchNamespace::One(namespace.to_string());
+        (Subscriber { namespace, sender }, receiver)
+    }
+
+    fn received(receiver: &mut mpsc::UnboundedReceiver<Event<DynamicObject>>) -> Vec<String> {
+        std::iter::from_fn(|| receiver.try_recv().ok())
+            .map(|event| match event {
+                Event::Apply(obj) => format!("apply {}", object_key(&obj)),
+                Event::InitApply(obj) => format!("init {}", object_key(&obj)),
+                Event::Delete(obj) => format!("delete {}", object_key(&obj)),
+                Event::Init => "init".to_string(),
+                Event::InitDone => "done".to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_shared_watch() {
+        let mut shared = SharedWatch::default();
+        let (first, mut first_events) = subscriber("a");
+        shared.subscribe(first);
+        shared.dispatch(Event::Init);
+        shared.dispatch(Event::InitApply(object("a", "x")));
+        shared.dispatch(Event::InitApply(object("b", "y")));
+        shared.dispatch(Event::InitDone);
+        assert_eq!(received(&mut first_events), vec!["init a/x"]);
+
+        let (second, mut second_events) = subscriber("b");
+        shared.subscribe(second);
+        assert_eq!(received(&mut second_events), vec!["init b/y"]);
+
+        shared.dispatch(Event::Apply(object("b", "z")));
+        shared.dispatch(Event::Init);
+        shared.dispatch(Event::InitApply(object("a", "x")));
+        shared.dispatch(Event::InitDone);
+        assert_eq!(received(&mut first_events), vec!["init a/x"]);
+        assert_eq!(
+            received(&mut second_events),
+            vec!["apply b/z", "delete b/y", "delete b/z"]
+        );
+
+        drop(first_events);
+        assert!(shared.has_subscribers());
+        drop(second_events);
+        assert!(!shared.has_subscribers());
+    }
+}
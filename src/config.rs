@@ -2,9 +2,25 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::error::Result;
-use once_cell::sync::OnceCell;
+use crate::faults::InjectFaults;
+use crate::http_trace::TraceHttp;
+use crate::naming::Naming;
+use crate::rbac::RecordForbidden;
+use crate::run;
+use crate::schedule::Schedule;
+use crate::script::Shell;
+use crate::test_spec::{ApplyRateSpec, ListStrategy, TestSpec, WatchSpec};
+use crate::warnings::WarningPattern;
+use kube::client::ClientBuilder;
+use kube::config::{KubeConfigOptions, Kubeconfig};
+use kube::Client;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use tempfile::NamedTempFile;
 use tokio::fs;
+use tokio::time::Duration;
+use tower::util::option_layer;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TestTypeConfig {
@@ -12,12 +28,129 @@ pub struct TestTypeConfig {
     pub attempts: u16,
 }
 
+/// Selectors merged into every watch on matching resources.
+/// Empty `group`, `version` or `kind` match any value,
+/// but note that the core group is the empty string, too.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DefaultSelector {
+    #[serde(default)]
+    pub group: String,
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub kind: String,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default)]
+    pub fields: BTreeMap<String, String>,
+}
+
+impl DefaultSelector {
+    fn matches(&self, spec: &WatchSpec) -> bool {
+        (self.group.is_empty() || self.group == spec.group)
+            && (self.version.is_empty() || self.version == spec.version)
+            && (self.kind.is_empty() || self.kind == spec.kind)
+    }
+}
+
+fn merge_selector(
+    defaults: &BTreeMap<String, String>,
+    selector: Option<BTreeMap<String, String>>,
+) -> Option<BTreeMap<String, String>> {
+    if defaults.is_empty() {
+        return selector;
+    }
+    let mut merged = defaults.clone();
+    merged.extend(selector.unwrap_or_default());
+    Some(merged)
+}
+
+pub(crate) fn default_manage_finalizers() -> bool {
+    true
+}
+
+fn default_redact() -> bool {
+    true
+}
+
+fn default_max_object_size() -> usize {
+    4096
+}
+
+fn default_grace_period() -> u16 {
+    60
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub timeout_scaling: f32,
     pub loglevel: String,
     pub cluster: TestTypeConfig,
     pub user: TestTypeConfig,
+    #[serde(default)]
+    pub initial_list_strategy: ListStrategy,
+    #[serde(default)]
+    pub page_size: Option<u32>,
+    #[serde(default = "default_manage_finalizers")]
+    pub manage_finalizers: bool,
+    #[serde(default)]
+    pub default_selectors: Vec<DefaultSelector>,
+    #[serde(default)]
+    pub filter: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub skip_tags: Vec<String>,
+    #[serde(default)]
+    pub kubeconfig: Option<String>,
+    #[serde(default)]
+    pub context: Option<String>,
+    #[serde(default)]
+    pub events: Option<String>,
+    #[serde(default)]
+    pub results_namespace: Option<String>,
+    #[serde(default)]
+    pub artifacts_dir: Option<String>,
+    #[serde(default)]
+    pub shell: Shell,
+    #[serde(default)]
+    pub script_path: Vec<String>,
+    #[serde(default)]
+    pub schedule: Schedule,
+    #[serde(default)]
+    pub history: Option<String>,
+    #[serde(default)]
+    pub strict_cluster: bool,
+    #[serde(default)]
+    pub progress: bool,
+    #[serde(default)]
+    pub rbac_report: Option<String>,
+    #[serde(default)]
+    pub http_trace: bool,
+    #[serde(default)]
+    pub share_watches: bool,
+    #[serde(default)]
+    pub fail_on_warning: Vec<WarningPattern>,
+    #[serde(default)]
+    pub bucket_events: bool,
+    #[serde(default)]
+    pub naming: Naming,
+    #[serde(default)]
+    pub apply_rate: Option<ApplyRateSpec>,
+    #[serde(default = "default_redact")]
+    pub redact: bool,
+    #[serde(default = "default_max_object_size")]
+    pub max_object_size: usize,
+    #[serde(default = "default_grace_period")]
+    pub grace_period: u16,
+    #[serde(default)]
+    pub fail_fast: bool,
+    #[serde(default)]
+    pub max_failures: Option<usize>,
+    #[serde(default)]
+    pub rerun_failed: bool,
+    #[serde(default)]
+    pub skip_unavailable_apis: bool,
 }
 
 impl Default for Config {
@@ -33,12 +166,42 @@ impl Default for Config {
                 parallel: 4,
                 attempts: 2,
             },
+            initial_list_strategy: ListStrategy::ListWatch,
+            page_size: None,
+            manage_finalizers: true,
+            default_selectors: vec![],
+            filter: None,
+            tags: vec![],
+            skip_tags: vec![],
+            kubeconfig: None,
+            context: None,
+            events: None,
+            results_namespace: None,
+            artifacts_dir: None,
+            shell: Shell::default(),
+            script_path: vec![],
+            schedule: Schedule::default(),
+            history: None,
+            strict_cluster: false,
+            progress: false,
+            rbac_report: None,
+            http_trace: false,
+            share_watches: false,
+            fail_on_warning: vec![],
+            bucket_events: false,
+            naming: Naming::default(),
+            apply_rate: None,
+            redact: true,
+            max_object_size: 4096,
+            grace_period: 60,
+            fail_fast: false,
+            max_failures: None,
+            rerun_failed: false,
+            skip_unavailable_apis: false,
         }
     }
 }
 
-static CONFIG: OnceCell<Config> = OnceCell::new();
-
 impl Config {
     pub async fn new(filename: Option<String>) -> Result<Self> {
         if let Some(path) = filename {
@@ -115,11 +278,491 @@ impl Config {
         }
     }
 
-    pub fn init(config: Config) {
-        CONFIG.set(config).unwrap();
+    pub fn with_filter(self, filter: Option<String>) -> Self {
+        if filter.is_some() {
+            Config { filter, ..self }
+        } else {
+            self
+        }
+    }
+
+    pub fn with_tags(self, tags: Vec<String>) -> Self {
+        if !tags.is_empty() {
+            Config { tags, ..self }
+        } else {
+            self
+        }
+    }
+
+    pub fn with_skip_tags(self, skip_tags: Vec<String>) -> Self {
+        if !skip_tags.is_empty() {
+            Config { skip_tags, ..self }
+        } else {
+            self
+        }
+    }
+
+    pub fn with_kubeconfig(self, kubeconfig: Option<String>) -> Self {
+        if kubeconfig.is_some() {
+            Config { kubeconfig, ..self }
+        } else {
+            self
+        }
+    }
+
+    pub fn with_context(self, context: Option<String>) -> Self {
+        if context.is_some() {
+            Config { context, ..self }
+        } else {
+            self
+        }
+    }
+
+    pub fn with_events(self, events: Option<String>) -> Self {
+        if events.is_some() {
+            Config { events, ..self }
+        } else {
+            self
+        }
+    }
+
+    pub fn with_results_namespace(self, results_namespace: Option<String>) -> Self {
+        if results_namespace.is_some() {
+            Config {
+                results_namespace,
+                ..self
+            }
+        } else {
+            self
+        }
+    }
+
+    pub fn with_artifacts_dir(self, artifacts_dir: Option<String>) -> Self {
+        if artifacts_dir.is_some() {
+            Config {
+                artifacts_dir,
+                ..self
+            }
+        } else {
+            self
+        }
+    }
+
+    pub fn with_shell(self, shell: Option<Shell>) -> Self {
+        if let Some(shell) = shell {
+            Config { shell, ..self }
+        } else {
+            self
+        }
+    }
+
+    pub fn with_script_path(self, script_path: Vec<String>) -> Self {
+        if !script_path.is_empty() {
+            Config {
+                script_path,
+                ..self
+            }
+        } else {
+            self
+        }
+    }
+
+    pub fn with_schedule(self, schedule: Option<Schedule>) -> Self {
+        if let Some(schedule) = schedule {
+            Config { schedule, ..self }
+        } else {
+            self
+        }
+    }
+
+    pub fn with_naming(self, naming: Option<Naming>) -> Self {
+        if let Some(naming) = naming {
+            Config { naming, ..self }
+        } else {
+            self
+        }
+    }
+
+    pub fn with_apply_rate(self, apply_rate: Option<ApplyRateSpec>) -> Self {
+        if apply_rate.is_some() {
+            Config { apply_rate, ..self }
+        } else {
+            self
+        }
+    }
+
+    pub fn with_no_redact(self, no_redact: bool) -> Self {
+        if no_redact {
+            Config {
+                redact: false,
+                ..self
+            }
+        } else {
+            self
+        }
+    }
+
+    pub fn with_max_object_size(self, max_object_size: Option<usize>) -> Self {
+        if let Some(max_object_size) = max_object_size {
+            Config {
+                max_object_size,
+                ..self
+            }
+        } else {
+            self
+        }
+    }
+
+    pub fn with_grace_period(self, grace_period: Option<u16>) -> Self {
+        if let Some(grace_period) = grace_period {
+            Config {
+                grace_period,
+                ..self
+            }
+        } else {
+            self
+        }
+    }
+
+    pub fn with_fail_fast(self, fail_fast: bool) -> Self {
+        if fail_fast {
+            Config { fail_fast, ..self }
+        } else {
+            self
+        }
+    }
+
+    pub fn with_max_failures(self, max_failures: Option<usize>) -> Self {
+        if max_failures.is_some() {
+            Config {
+                max_failures,
+                ..self
+            }
+        } else {
+            self
+        }
+    }
+
+    pub fn with_rerun_failed(self, rerun_failed: bool) -> Self {
+        if rerun_failed {
+            Config {
+                rerun_failed,
+                ..self
+            }
+        } else {
+            self
+        }
+    }
+
+    pub fn with_skip_unavailable_apis(self, skip_unavailable_apis: bool) -> Self {
+        if skip_unavailable_apis {
+            Config {
+                skip_unavailable_apis,
+                ..self
+            }
+        } else {
+            self
+        }
+    }
+
+    /// Number of failed tests after which no further tests are started, if any.
+    pub fn failure_limit(&self) -> Option<usize> {
+        match self.fail_fast {
+            true => Some(1),
+            false => self.max_failures,
+        }
+    }
+
+    pub fn with_history(self, history: Option<String>) -> Self {
+        if history.is_some() {
+            Config { history, ..self }
+        } else {
+            self
+        }
+    }
+
+    pub fn with_strict_cluster(self, strict_cluster: bool) -> Self {
+        if strict_cluster {
+            Config {
+                strict_cluster,
+                ..self
+            }
+        } else {
+            self
+        }
+    }
+
+    pub fn with_progress(self, progress: bool) -> Self {
+        if progress {
+            Config { progress, ..self }
+        } else {
+            self
+        }
+    }
+
+    pub fn with_rbac_report(self, rbac_report: Option<String>) -> Self {
+        if rbac_report.is_some() {
+            Config {
+                rbac_report,
+                ..self
+            }
+        } else {
+            self
+        }
+    }
+
+    pub fn with_http_trace(self, http_trace: bool) -> Self {
+        if http_trace {
+            Config { http_trace, ..self }
+        } else {
+            self
+        }
+    }
+
+    pub fn with_share_watches(self, share_watches: bool) -> Self {
+        if share_watches {
+            Config {
+                share_watches,
+                ..self
+            }
+        } else {
+            self
+        }
+    }
+
+    pub fn with_fail_on_warning(self, fail_on_warning: Vec<WarningPattern>) -> Self {
+        if !fail_on_warning.is_empty() {
+            Config {
+                fail_on_warning,
+                ..self
+            }
+        } else {
+            self
+        }
+    }
+
+    pub fn with_bucket_events(self, bucket_events: bool) -> Self {
+        if bucket_events {
+            Config {
+                bucket_events,
+                ..self
+            }
+        } else {
+            self
+        }
+    }
+
+    /// Creates a client for the cluster selected by `kubeconfig` and `context`,
+    /// or for the default cluster if neither is set.
+    /// With `rbac_report`, the requests denied to the client are recorded for the current run.
+    pub async fn client(&self) -> Result<Client> {
+        self.build_client(None).await
+    }
+
+    /// Creates a client like `client` for the requests of `test_spec`, which injects
+    /// the faults of the test and traces its requests if `http_trace` and `artifacts_dir` are set.
+    pub async fn test_client(&self, test_spec: &TestSpec) -> Result<Client> {
+        self.build_client(Some(test_spec)).await
+    }
+
+    /// Writes the kubeconfig with its current context set to `context` to a temporary file,
+    /// so that scripts use the same cluster as the tests. `None` without `context`.
+    pub(crate) fn pinned_kubeconfig(&self) -> Result<Option<NamedTempFile>> {
+        let Some(context) = &self.context else {
+            return Ok(None);
+        };
+        let mut kubeconfig = match &self.kubeconfig {
+            Some(path) => Kubeconfig::read_from(path)?,
+            None => Kubeconfig::read()?,
+        };
+        kubeconfig.current_context = Some(context.clone());
+        let mut file = tempfile::Builder::new()
+            .prefix("kubeconfig-")
+            .suffix(".yaml")
+            .tempfile()?;
+        serde_yaml::to_writer(&mut file, &kubeconfig)?;
+        Ok(Some(file))
+    }
+
+    async fn build_client(&self, test_spec: Option<&TestSpec>) -> Result<Client> {
+        let options = KubeConfigOptions {
+            context: self.context.clone(),
+            ..Default::default()
+        };
+        let config = match (&self.kubeconfig, &self.context) {
+            (None, None) => kube::Config::infer().await?,
+            (Some(path), _) => {
+                kube::Config::from_custom_kubeconfig(Kubeconfig::read_from(path)?, &options).await?
+            }
+            (None, Some(_)) => kube::Config::from_kubeconfig(&options).await?,
+        };
+        let trace = match (test_spec, &self.artifacts_dir) {
+            (Some(test_spec), Some(dir)) if self.http_trace => {
+                Some(TraceHttp::new(&test_spec.name, Path::new(dir))?)
+            }
+            _ => None,
+        };
+        let faults = test_spec
+            .and_then(|test_spec| test_spec.faults.as_ref())
+            .map(InjectFaults::new);
+        let record_forbidden = match (&self.rbac_report, run::current()) {
+            (Some(_), Some(run)) => Some(RecordForbidden::new(&run)),
+            _ => None,
+        };
+        Ok(ClientBuilder::try_from(config)?
+            .with_layer(&option_layer(faults))
+            .with_layer(&option_layer(record_forbidden))
+            .with_layer(&option_layer(trace))
+            .build())
+    }
+
+    /// Merges all matching default selectors into the selectors of `spec`.
+    /// Selectors set on the watch itself take precedence.
+    pub fn apply_default_selectors(&self, spec: WatchSpec) -> WatchSpec {
+        let defaults: Vec<&DefaultSelector> = self
+            .default_selectors
+            .iter()
+            .filter(|default| default.matches(&spec))
+            .collect();
+        defaults.into_iter().fold(spec, |spec, default| WatchSpec {
+            labels: merge_selector(&default.labels, spec.labels),
+            fields: merge_selector(&default.fields, spec.fields),
+            ..spec
+        })
+    }
+
+    /// Fills the options of `spec` that are not set with the defaults of the config.
+    pub fn apply_watch_defaults(&self, spec: WatchSpec) -> WatchSpec {
+        let spec = self.apply_default_selectors(spec);
+        WatchSpec {
+            initial_list_strategy: spec
+                .initial_list_strategy
+                .or(Some(self.initial_list_strategy)),
+            page_size: spec.page_size.or(self.page_size),
+            manage_finalizers: spec.manage_finalizers.or(Some(self.manage_finalizers)),
+            ..spec
+        }
+    }
+
+    pub fn scaled_timeout(&self, seconds: u16) -> Duration {
+        Duration::from_secs((seconds * self.timeout_scaling.ceil() as u16).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn selector(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[rstest]
+    #[case("Pod", None, Some(selector(&[("managed-by", "op")])))]
+    #[case("Service", None, None)]
+    #[case(
+        "Pod",
+        Some(selector(&[("app", "nginx")])),
+        Some(selector(&[("app", "nginx"), ("managed-by", "op")]))
+    )]
+    #[case(
+        "Pod",
+        Some(selector(&[("managed-by", "other")])),
+        Some(selector(&[("managed-by", "other")]))
+    )]
+    fn test_apply_default_selectors(
+        #[case] kind: &str,
+        #[case] labels: Option<BTreeMap<String, String>>,
+        #[case] expected: Option<BTreeMap<String, String>>,
+    ) {
+        let config = Config {
+            default_selectors: vec![DefaultSelector {
+                version: "v1".to_string(),
+                kind: "Pod".to_string(),
+                labels: selector(&[("managed-by", "op")]),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let spec = WatchSpec {
+            version: "v1".to_string(),
+            kind: kind.to_string(),
+            labels,
+            ..Default::default()
+        };
+        assert_eq!(config.apply_default_selectors(spec).labels, expected);
+    }
+
+    #[rstest]
+    #[case(None, None, Some(100), Some(false))]
+    #[case(Some(10), Some(true), Some(10), Some(true))]
+    fn test_apply_watch_defaults(
+        #[case] page_size: Option<u32>,
+        #[case] manage_finalizers: Option<bool>,
+        #[case] expected_page_size: Option<u32>,
+        #[case] expected_manage_finalizers: Option<bool>,
+    ) {
+        let config = Config {
+            page_size: Some(100),
+            manage_finalizers: false,
+            ..Default::default()
+        };
+        let spec = WatchSpec {
+            page_size,
+            manage_finalizers,
+            ..Default::default()
+        };
+        let spec = config.apply_watch_defaults(spec);
+        assert_eq!(spec.page_size, expected_page_size);
+        assert_eq!(spec.manage_finalizers, expected_manage_finalizers);
+        assert!(spec.initial_list_strategy == Some(ListStrategy::ListWatch));
+    }
+
+    #[rstest]
+    #[case(false, None, None)]
+    #[case(false, Some(3), Some(3))]
+    #[case(true, None, Some(1))]
+    #[case(true, Some(3), Some(1))]
+    fn test_failure_limit(
+        #[case] fail_fast: bool,
+        #[case] max_failures: Option<usize>,
+        #[case] expected: Option<usize>,
+    ) {
+        let config = Config::default()
+            .with_fail_fast(fail_fast)
+            .with_max_failures(max_failures);
+        assert_eq!(config.failure_limit(), expected);
     }
 
-    pub fn get() -> &'static Self {
-        CONFIG.get().unwrap()
+    #[test]
+    fn test_pinned_kubeconfig() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config");
+        std::fs::write(
+            &path,
+            "apiVersion: v1\n\
+             kind: Config\n\
+             current-context: one\n\
+             clusters:\n\
+             - name: one\n  cluster: {server: 'https://one:6443'}\n\
+             - name: two\n  cluster: {server: 'https://two:6443'}\n\
+             contexts:\n\
+             - name: one\n  context: {cluster: one, user: user}\n\
+             - name: two\n  context: {cluster: two, user: user}\n\
+             users:\n\
+             - name: user\n  user: {token: secret}\n",
+        )
+        .unwrap();
+        let config = Config::default().with_kubeconfig(Some(path.display().to_string()));
+        assert!(config.pinned_kubeconfig().unwrap().is_none());
+        let config = config.with_context(Some("two".to_string()));
+        let pinned = config.pinned_kubeconfig().unwrap().unwrap();
+        let pinned = Kubeconfig::read_from(pinned.path()).unwrap();
+        assert_eq!(pinned.current_context.as_deref(), Some("two"));
+        assert_eq!(pinned.contexts.len(), 2);
     }
 }
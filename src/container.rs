@@ -0,0 +1,532 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::naming::Naming;
+use crate::script::{parse_env, read_lines, run_command};
+use crate::test_spec::{ContainerRuntime, ScriptContainerSpec};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use k8s_openapi::api::core::v1::{ConfigMap, Pod};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Status;
+use kube::api::{AttachParams, DeleteParams, PostParams};
+use kube::runtime::wait::{await_condition, conditions::is_pod_running};
+use kube::{Api, Client};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use tempfile::NamedTempFile;
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+
+const POD_READY_TIMEOUT: u16 = 120;
+/// Where the files of the test directory are mounted in the pod.
+const POD_TEST_DIR: &str = "/blackjack/test";
+const POD_ENV_FILE: &str = "/tmp/blackjack-env";
+
+fn container_error(err: impl std::fmt::Display) -> Error {
+    Error::ScriptContainerError(err.to_string())
+}
+
+#[cfg(unix)]
+fn exit_status(code: i32) -> ExitStatus {
+    std::os::unix::process::ExitStatusExt::from_raw(code << 8)
+}
+
+#[cfg(windows)]
+fn exit_status(code: i32) -> ExitStatus {
+    std::os::windows::process::ExitStatusExt::from_raw(code as u32)
+}
+
+/// Exit code of a command executed in a pod, from the status sent by the API server.
+fn exit_code(status: Option<&Status>) -> i32 {
+    let Some(status) = status else {
+        return 1;
+    };
+    if status.status.as_deref() == Some("Success") {
+        return 0;
+    }
+    status
+        .details
+        .as_ref()
+        .and_then(|details| details.causes.as_ref())
+        .and_then(|causes| {
+            causes
+                .iter()
+                .find(|cause| cause.reason.as_deref() == Some("ExitCode"))
+        })
+        .and_then(|cause| cause.message.as_ref())
+        .and_then(|message| message.parse().ok())
+        .unwrap_or(1)
+}
+
+/// The kubeconfig of the test, mounted into local containers, if it exists.
+fn kubeconfig(env: &HashMap<String, String>) -> Option<PathBuf> {
+    let path = env
+        .get("KUBECONFIG")
+        .cloned()
+        .or_else(|| std::env::var("KUBECONFIG").ok())
+        .and_then(|paths| std::env::split_paths(&paths).next())
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".kube/config")))?;
+    path.exists().then_some(path)
+}
+
+/// Arguments of `docker run` or `podman run` that source the script in the container.
+/// The test directory and the directory of the env file are mounted at the same paths,
+/// and the host network is used, so that port forwards and local clusters can be reached.
+fn run_args(
+    name: &str,
+    image: &str,
+    command_line: &str,
+    wd: &Path,
+    env_file: &Path,
+    env_names: &[&String],
+    kubeconfig: Option<&Path>,
+) -> Vec<String> {
+    let mut args = vec![
+        "run".to_string(),
+        "--rm".to_string(),
+        format!("--name={name}"),
+        "--network=host".to_string(),
+    ];
+    let mut mount = |path: &Path, options: &str| {
+        args.push("-v".to_string());
+        args.push(format!("{0}:{0}{options}", path.display()));
+    };
+    mount(wd, "");
+    if let Some(dir) = env_file.parent() {
+        mount(dir, "");
+    }
+    if let Some(kubeconfig) = kubeconfig {
+        mount(kubeconfig, ":ro");
+        args.push("-e".to_string());
+        args.push(format!("KUBECONFIG={}", kubeconfig.display()));
+    }
+    args.push("-w".to_string());
+    args.push(wd.display().to_string());
+    for name in env_names {
+        args.push("-e".to_string());
+        args.push(name.to_string());
+    }
+    args.extend([
+        "--entrypoint".to_string(),
+        "sh".to_string(),
+        image.to_string(),
+        "-c".to_string(),
+        format!(
+            ". {command_line} && env -0 > {}",
+            shell_words::quote(&env_file.display().to_string())
+        ),
+    ]);
+    args
+}
+
+/// Data of the ConfigMap holding the files of the test directory,
+/// with files that are not UTF-8 as binary data.
+fn config_map_data(files: Vec<(String, Vec<u8>)>) -> (Map<String, Value>, Map<String, Value>) {
+    let mut data = Map::new();
+    let mut binary_data = Map::new();
+    for (name, contents) in files {
+        match String::from_utf8(contents) {
+            Ok(text) => data.insert(name, Value::String(text)),
+            Err(err) => binary_data.insert(name, Value::String(STANDARD.encode(err.as_bytes()))),
+        };
+    }
+    (data, binary_data)
+}
+
+async fn read_test_dir(dir: &Path) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut files = vec![];
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.file_type().await?.is_file() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            files.push((name, fs::read(entry.path()).await?));
+        }
+    }
+    Ok(files)
+}
+
+/// Commands that export the env of the test in the pod.
+/// The kubeconfig of the host is left out, as the pod uses its service account.
+fn exports(env: &HashMap<String, String>) -> String {
+    let mut names: Vec<&String> = env
+        .keys()
+        .filter(|name| !matches!(name.as_str(), "KUBECONFIG" | "BLACKJACK_KUBECONFIG"))
+        .collect();
+    names.sort();
+    names
+        .into_iter()
+        .map(|name| format!("export {name}={}; ", shell_words::quote(&env[name])))
+        .collect()
+}
+
+/// A local container running a script, which is removed if the execution is dropped
+/// before the script finished, e.g. on a timeout, as killing `docker run` leaves it running.
+struct LocalRun {
+    program: &'static str,
+    name: String,
+    finished: bool,
+}
+
+impl Drop for LocalRun {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        log::debug!("Removing container '{}'", self.name);
+        let removal = Command::new(self.program)
+            .args(["rm", "-f", &self.name])
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn();
+        if let Err(err) = removal {
+            log::warn!("Unable to remove container '{}': {err}", self.name);
+        }
+    }
+}
+
+/// A pod that the scripts of a step are executed in.
+/// It is deleted by `stop`, or in the background if dropped before, e.g. on a timeout of the step.
+pub struct ScriptPod {
+    pods: Api<Pod>,
+    config_maps: Api<ConfigMap>,
+    name: String,
+    stopped: bool,
+}
+
+impl ScriptPod {
+    /// Creates the pod with the files of the test directory mounted, and waits until it runs.
+    async fn start(
+        client: Client,
+        config: &Config,
+        spec: &ScriptContainerSpec,
+        dir: &Path,
+    ) -> Result<Self> {
+        let name = format!("blackjack-script-{}", config.naming.suffix(10));
+        let pods: Api<Pod> = Api::namespaced(client.clone(), &spec.namespace);
+        let config_maps: Api<ConfigMap> = Api::namespaced(client, &spec.namespace);
+        let (data, binary_data) = config_map_data(read_test_dir(dir).await?);
+        let config_map: ConfigMap = serde_json::from_value(json!({
+            "metadata": {"name": name},
+            "data": data,
+            "binaryData": binary_data,
+        }))?;
+        config_maps
+            .create(&PostParams::default(), &config_map)
+            .await?;
+        let script_pod = ScriptPod {
+            pods,
+            config_maps,
+            name,
+            stopped: false,
+        };
+        let pod: Pod = serde_json::from_value(json!({
+            "metadata": {
+                "name": script_pod.name,
+                "labels": {"app.kubernetes.io/name": "blackjack-script"},
+            },
+            "spec": {
+                "serviceAccountName": spec.service_account,
+                "restartPolicy": "Never",
+                "terminationGracePeriodSeconds": 0,
+                "containers": [{
+                    "name": "script",
+                    "image": spec.image,
+                    "command": ["sh", "-c", "trap 'exit 0' TERM; while true; do sleep 1; done"],
+                    "workingDir": POD_TEST_DIR,
+                    "volumeMounts": [{"name": "test", "mountPath": POD_TEST_DIR}],
+                }],
+                "volumes": [{
+                    "name": "test",
+                    "configMap": {"name": script_pod.name, "defaultMode": 0o755},
+                }],
+            },
+        }))?;
+        if let Err(err) = script_pod.create(config, pod).await {
+            script_pod.stop().await?;
+            return Err(err);
+        }
+        Ok(script_pod)
+    }
+
+    async fn create(&self, config: &Config, pod: Pod) -> Result<()> {
+        self.pods.create(&PostParams::default(), &pod).await?;
+        tokio::time::timeout(
+            config.scaled_timeout(POD_READY_TIMEOUT),
+            await_condition(self.pods.clone(), &self.name, is_pod_running()),
+        )
+        .await
+        .map_err(|_| container_error(format!("pod '{}' did not start", self.name)))?
+        .map_err(container_error)?;
+        Ok(())
+    }
+
+    async fn exec(&self, command: String) -> Result<(ExitStatus, String, String)> {
+        let mut process = self
+            .pods
+            .exec(
+                &self.name,
+                ["sh", "-c", &command],
+                &AttachParams::default().container("script"),
+            )
+            .await?;
+        let stdout = process
+            .stdout()
+            .ok_or(Error::Other("unable to capture script stdout".to_string()))?;
+        let stderr = process
+            .stderr()
+            .ok_or(Error::Other("unable to capture script stderr".to_string()))?;
+        let status = process
+            .take_status()
+            .ok_or(Error::Other("unable to capture script status".to_string()))?;
+        let stdout_future = read_lines(stdout);
+        let stderr_future = read_lines(stderr);
+        let status = status.await;
+        let stdout_result = stdout_future.await??;
+        let stderr_result = stderr_future.await??;
+        process.join().await.map_err(container_error)?;
+        let code = exit_code(status.as_ref());
+        Ok((
+            exit_status(code),
+            stdout_result.concat(),
+            stderr_result.concat(),
+        ))
+    }
+
+    async fn read_env(&self) -> Result<String> {
+        let mut process = self
+            .pods
+            .exec(
+                &self.name,
+                ["cat", POD_ENV_FILE],
+                &AttachParams::default().container("script").stderr(false),
+            )
+            .await?;
+        let mut contents = String::new();
+        if let Some(mut stdout) = process.stdout() {
+            stdout.read_to_string(&mut contents).await?;
+        }
+        process.join().await.map_err(container_error)?;
+        Ok(contents)
+    }
+
+    async fn execute(
+        &self,
+        command_line: &str,
+        env: &mut HashMap<String, String>,
+    ) -> Result<(ExitStatus, String, String)> {
+        let command = format!(
+            "rm -f {POD_ENV_FILE}; {}. {command_line} && env -0 > {POD_ENV_FILE}",
+            exports(env)
+        );
+        let result = self.exec(command).await?;
+        if result.0.success() {
+            env.extend(parse_env(&self.read_env().await?));
+        }
+        Ok(result)
+    }
+
+    async fn stop(mut self) -> Result<()> {
+        self.stopped = true;
+        delete_pod(&self.pods, &self.config_maps, &self.name).await
+    }
+}
+
+impl Drop for ScriptPod {
+    fn drop(&mut self) {
+        if self.stopped {
+            return;
+        }
+        let (pods, config_maps) = (self.pods.clone(), self.config_maps.clone());
+        let name = self.name.clone();
+        tokio::spawn(async move {
+            if let Err(err) = delete_pod(&pods, &config_maps, &name).await {
+                log::warn!("Unable to delete script pod '{name}': {err}");
+            }
+        });
+    }
+}
+
+/// Deletes the script pod and the ConfigMap of its test directory.
+async fn delete_pod(pods: &Api<Pod>, config_maps: &Api<ConfigMap>, name: &str) -> Result<()> {
+    let params = DeleteParams::default().grace_period(0);
+    for result in [
+        pods.delete(name, &params).await.map(|_| ()),
+        config_maps.delete(name, &params).await.map(|_| ()),
+    ] {
+        match result {
+            Err(kube::Error::Api(response)) if response.code == 404 => {}
+            result => result?,
+        }
+    }
+    Ok(())
+}
+
+/// Runs the scripts of a step in a container instead of the local shell.
+pub enum ScriptContainer {
+    Local {
+        program: &'static str,
+        image: String,
+        wd: PathBuf,
+        naming: Naming,
+    },
+    Pod(Box<ScriptPod>),
+}
+
+impl ScriptContainer {
+    /// Prepares the container. For the `pod` runtime, the pod is started right away
+    /// and lasts until `stop`.
+    pub async fn start(
+        client: Client,
+        config: &Config,
+        spec: ScriptContainerSpec,
+        wd: PathBuf,
+    ) -> Result<Self> {
+        Ok(match spec.runtime {
+            ContainerRuntime::Docker | ContainerRuntime::Podman => ScriptContainer::Local {
+                program: match spec.runtime {
+                    ContainerRuntime::Podman => "podman",
+                    _ => "docker",
+                },
+                image: spec.image,
+                wd,
+                naming: config.naming,
+            },
+            ContainerRuntime::Pod => ScriptContainer::Pod(Box::new(
+                ScriptPod::start(client, config, &spec, &wd).await?,
+            )),
+        })
+    }
+
+    pub async fn execute(
+        &self,
+        command_line: &str,
+        env: &mut HashMap<String, String>,
+    ) -> Result<(ExitStatus, String, String)> {
+        match self {
+            ScriptContainer::Local {
+                program,
+                image,
+                wd,
+                naming,
+            } => {
+                let mut run = LocalRun {
+                    program,
+                    name: format!("blackjack-script-{}", naming.suffix(10)),
+                    finished: false,
+                };
+                let env_file = NamedTempFile::new()?;
+                let mut env_names: Vec<&String> =
+                    env.keys().filter(|name| *name != "KUBECONFIG").collect();
+                env_names.sort();
+                let kubeconfig = kubeconfig(env);
+                let mut command = Command::new(program);
+                command
+                    .args(run_args(
+                        &run.name,
+                        image,
+                        command_line,
+                        wd,
+                        env_file.path(),
+                        &env_names,
+                        kubeconfig.as_deref(),
+                    ))
+                    .envs(env.clone());
+                let result = run_command(command, env_file.path(), env).await;
+                run.finished = true;
+                result
+            }
+            ScriptContainer::Pod(pod) => pod.execute(command_line, env).await,
+        }
+    }
+
+    pub async fn stop(self) -> Result<()> {
+        match self {
+            ScriptContainer::Local { .. } => Ok(()),
+            ScriptContainer::Pod(pod) => pod.stop().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::{StatusCause, StatusDetails};
+    use rstest::rstest;
+
+    fn status(status: &str, exit_code: Option<&str>) -> Status {
+        Status {
+            status: Some(status.to_string()),
+            details: exit_code.map(|code| StatusDetails {
+                causes: Some(vec![StatusCause {
+                    reason: Some("ExitCode".to_string()),
+                    message: Some(code.to_string()),
+                    field: None,
+                }]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[rstest]
+    #[case(Some(status("Success", None)), 0)]
+    #[case(Some(status("Failure", Some("3"))), 3)]
+    #[case(Some(status("Failure", None)), 1)]
+    #[case(None, 1)]
+    fn test_exit_code(#[case] status: Option<Status>, #[case] expected: i32) {
+        assert_eq!(exit_code(status.as_ref()), expected);
+        assert_eq!(exit_status(expected).code(), Some(expected));
+    }
+
+    #[test]
+    fn test_run_args() {
+        let name = "BLACKJACK_NAMESPACE".to_string();
+        let args = run_args(
+            "blackjack-script-abc",
+            "alpine/k8s:1.31.0",
+            "check.sh",
+            Path::new("/tests/my-test"),
+            Path::new("/tmp/.tmpabc"),
+            &[&name],
+            Some(Path::new("/home/user/.kube/config")),
+        );
+        assert_eq!(
+            args.join(" "),
+            "run --rm --name=blackjack-script-abc --network=host -v /tests/my-test:/tests/my-test -v /tmp:/tmp \
+             -v /home/user/.kube/config:/home/user/.kube/config:ro \
+             -e KUBECONFIG=/home/user/.kube/config -w /tests/my-test -e BLACKJACK_NAMESPACE \
+             --entrypoint sh alpine/k8s:1.31.0 -c . check.sh && env -0 > /tmp/.tmpabc"
+        );
+    }
+
+    #[test]
+    fn test_config_map_data() {
+        let (data, binary_data) = config_map_data(vec![
+            ("check.sh".to_string(), b"echo ok\n".to_vec()),
+            ("blob.bin".to_string(), vec![0xff, 0x00]),
+        ]);
+        assert_eq!(Value::Object(data), json!({"check.sh": "echo ok\n"}));
+        assert_eq!(Value::Object(binary_data), json!({"blob.bin": "/wA="}));
+    }
+
+    #[test]
+    fn test_exports() {
+        let env = HashMap::from([
+            ("BLACKJACK_NAMESPACE".to_string(), "test-ns".to_string()),
+            ("BLACKJACK_GREETING".to_string(), "it's me".to_string()),
+            (
+                "KUBECONFIG".to_string(),
+                "/home/user/.kube/config".to_string(),
+            ),
+        ]);
+        assert_eq!(
+            exports(&env),
+            "export BLACKJACK_GREETING='it'\\''s me'; export BLACKJACK_NAMESPACE=test-ns; "
+        );
+    }
+}
@@ -0,0 +1,152 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::error::{Error, Result};
+use crate::test_spec::{ApplySpec, ConversionSpec, WaitSpec, WatchSpec};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::path::Path;
+use tokio::fs;
+
+fn conversion_error(spec: &ConversionSpec, message: impl std::fmt::Display) -> Error {
+    Error::ConversionError(format!("'{}': {message}", spec.name))
+}
+
+/// Expands the conversion check of the resource into a watch of the resource
+/// at the version it is written at and the version it is read back at,
+/// and a wait for it to read back as expected.
+fn expand(spec: &ConversionSpec, object: &Value) -> Result<(WatchSpec, WaitSpec)> {
+    let (Some(api_version), Some(kind), Some(name)) = (
+        object["apiVersion"].as_str(),
+        object["kind"].as_str(),
+        object["metadata"]["name"].as_str(),
+    ) else {
+        return Err(conversion_error(
+            spec,
+            "resource requires apiVersion, kind and metadata.name",
+        ));
+    };
+    let (group, written) = api_version.split_once('/').unwrap_or(("", api_version));
+    let mut versions = vec![written];
+    if spec.version != written {
+        versions.push(&spec.version);
+    }
+    let watch = serde_json::from_value(json!({
+        "name": spec.name,
+        "group": group,
+        "kind": kind,
+        "versions": versions,
+        "fields": {"metadata.name": name},
+    }))?;
+
+    let Value::Object(mut pattern) = spec.expect.clone() else {
+        return Err(conversion_error(spec, "expect must be an object"));
+    };
+    let read = match group {
+        "" => spec.version.clone(),
+        group => format!("{group}/{}", spec.version),
+    };
+    pattern.insert("apiVersion".to_string(), json!(read));
+    match pattern.get_mut("metadata") {
+        Some(Value::Object(metadata)) => {
+            metadata.insert("name".to_string(), json!(name));
+        }
+        _ => {
+            pattern.insert("metadata".to_string(), json!({"name": name}));
+        }
+    }
+    let wait = serde_json::from_value(json!({
+        "target": spec.name,
+        "condition": {"one": pattern},
+        "timeout": spec.timeout,
+    }))?;
+    Ok((watch, wait))
+}
+
+/// Reads the resource of the conversion check from its manifest below `dirname`,
+/// and expands the check into the watch, apply and wait of the step.
+pub async fn expand_conversion(
+    spec: ConversionSpec,
+    dirname: &Path,
+) -> Result<(WatchSpec, ApplySpec, WaitSpec)> {
+    let data = fs::read_to_string(dirname.join(&spec.path)).await?;
+    let mut objects = vec![];
+    for document in serde_yaml::Deserializer::from_str(&data) {
+        objects.push(Value::deserialize(document)?);
+    }
+    let [object] = objects.as_slice() else {
+        return Err(conversion_error(
+            &spec,
+            format!("{} must contain exactly one resource", spec.path),
+        ));
+    };
+    let (watch, wait) = expand(&spec, object)?;
+    let apply = serde_json::from_value(json!({"path": spec.path}))?;
+    Ok((watch, apply, wait))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_spec::Expr;
+    use rstest::rstest;
+
+    fn spec(version: &str, expect: Value) -> ConversionSpec {
+        serde_json::from_value(json!({
+            "name": "widgets",
+            "path": "widget.yaml",
+            "version": version,
+            "expect": expect,
+            "timeout": 30,
+        }))
+        .unwrap()
+    }
+
+    #[rstest]
+    #[case("v2", vec!["v1", "v2"])]
+    #[case("v1", vec!["v1"])]
+    fn test_expand(#[case] version: &str, #[case] versions: Vec<&str>) {
+        let object = json!({
+            "apiVersion": "example.com/v1",
+            "kind": "Widget",
+            "metadata": {"name": "small"},
+            "spec": {"size": 1},
+        });
+        let expect =
+            json!({"metadata": {"labels": {"a": "b"}}, "spec": {"dimensions": {"size": 1}}});
+        let (watch, wait) = expand(&spec(version, expect), &object).unwrap();
+        assert_eq!(watch.name, "widgets");
+        assert_eq!(watch.group, "example.com");
+        assert_eq!(watch.kind, "Widget");
+        assert_eq!(watch.versions, versions);
+        assert_eq!(
+            watch
+                .fields
+                .unwrap()
+                .get("metadata.name")
+                .map(String::as_str),
+            Some("small")
+        );
+        assert_eq!(wait.target, "widgets");
+        assert_eq!(wait.timeout, 30);
+        let Expr::OneExpr { one: pattern } = wait.condition else {
+            panic!("expected a one condition");
+        };
+        assert_eq!(
+            pattern,
+            json!({
+                "apiVersion": format!("example.com/{version}"),
+                "metadata": {"name": "small", "labels": {"a": "b"}},
+                "spec": {"dimensions": {"size": 1}},
+            })
+        );
+    }
+
+    #[rstest]
+    #[case(json!({"kind": "Widget", "metadata": {"name": "small"}}), json!({}))]
+    #[case(json!({"apiVersion": "example.com/v1", "kind": "Widget", "metadata": {}}), json!({}))]
+    #[case(json!({"apiVersion": "example.com/v1", "kind": "Widget", "metadata": {"name": "a"}}), json!([]))]
+    fn test_expand_invalid(#[case] object: Value, #[case] expect: Value) {
+        assert!(expand(&spec("v2", expect), &object).is_err());
+    }
+}
@@ -0,0 +1,349 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::error::Result;
+use crate::file::read_yaml_files;
+use crate::json_path::{self, Segment};
+use crate::match_object;
+use crate::test_spec::{Expr, TestSpec};
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
+use kube::api::{Api, ListParams};
+use kube::Client;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use tokio::fs;
+
+/// OpenAPI schemas of custom resources, by group, version and kind.
+pub type Schemas = HashMap<(String, String, String), Value>;
+
+/// Adds the schemas of all versions of the CRD, given as JSON, to `schemas`.
+/// Other objects are ignored.
+fn add_crd(schemas: &mut Schemas, crd: &Value) {
+    if crd["kind"] != "CustomResourceDefinition" {
+        return;
+    }
+    let spec = &crd["spec"];
+    let (Some(group), Some(kind)) = (spec["group"].as_str(), spec["names"]["kind"].as_str()) else {
+        return;
+    };
+    for version in spec["versions"].as_array().into_iter().flatten() {
+        let (Some(name), Some(schema)) = (
+            version["name"].as_str(),
+            version["schema"].get("openAPIV3Schema"),
+        ) else {
+            continue;
+        };
+        // The fields common to all objects are often left out of CRD schemas.
+        let mut schema = schema.clone();
+        if let Some(properties) = schema
+            .as_object_mut()
+            .map(|schema| schema.entry("properties").or_insert(json!({})))
+            .and_then(Value::as_object_mut)
+        {
+            for field in ["apiVersion", "kind", "metadata"] {
+                properties.entry(field).or_insert(json!({}));
+            }
+        }
+        schemas.insert(
+            (group.to_string(), name.to_string(), kind.to_string()),
+            schema,
+        );
+    }
+}
+
+/// Fetches the schemas of all CRDs installed in the cluster.
+pub async fn schemas_from_cluster(client: &Client) -> Result<Schemas> {
+    let crds: Api<CustomResourceDefinition> = Api::all(client.clone());
+    let mut schemas = Schemas::new();
+    for crd in crds.list(&ListParams::default()).await? {
+        add_crd(&mut schemas, &serde_json::to_value(crd)?);
+    }
+    Ok(schemas)
+}
+
+/// Reads the schemas of CRDs in the manifests applied by the test and its fixtures.
+/// Manifests that cannot be read are skipped, as they are reported elsewhere.
+pub async fn schemas_from_manifests(test_spec: &TestSpec) -> Schemas {
+    let paths = test_spec
+        .all_steps()
+        .flat_map(|step| step.apply.iter().map(|apply| &apply.path))
+        .chain(
+            test_spec
+                .fixtures
+                .iter()
+                .flat_map(|fixtures| &fixtures.apply),
+        )
+        .filter(|path| !path.contains('$'));
+    let mut schemas = Schemas::new();
+    for path in paths {
+        let path = test_spec.dir.join(path);
+        let data = if path.is_dir() {
+            read_yaml_files(path).await.ok()
+        } else {
+            fs::read_to_string(path).await.ok()
+        };
+        for document in serde_yaml::Deserializer::from_str(&data.unwrap_or_default()) {
+            if let Ok(value) = Value::deserialize(document) {
+                add_crd(&mut schemas, &value);
+            }
+        }
+    }
+    schemas
+}
+
+enum Field<'a> {
+    Defined(&'a Value),
+    Free,
+    Undefined,
+}
+
+/// Looks up the schema of the field `key` of objects described by `schema`.
+fn field<'a>(schema: &'a Value, key: &str) -> Field<'a> {
+    if let Some(field) = schema.get("properties").and_then(|p| p.get(key)) {
+        return Field::Defined(field);
+    }
+    match schema.get("additionalProperties") {
+        Some(field @ Value::Object(_)) => Field::Defined(field),
+        Some(Value::Bool(true)) => Field::Free,
+        _ if schema["x-kubernetes-preserve-unknown-fields"] == true => Field::Free,
+        _ if schema.get("properties").is_none() => Field::Free,
+        _ => Field::Undefined,
+    }
+}
+
+/// Collects the paths of fields in `pattern` which are not defined by `schema`.
+fn unknown_fields(schema: &Value, pattern: &Value, path: &str, unknown: &mut Vec<String>) {
+    if let Some(pattern) = match_object::exact(pattern) {
+        return unknown_fields(schema, pattern, path, unknown);
+    }
+    if let (Some(items), Some(schema)) = (match_object::in_order(pattern), schema.get("items")) {
+        for item in items {
+            unknown_fields(schema, item, &format!("{path}[]"), unknown);
+        }
+        return;
+    }
+    match pattern {
+        Value::Object(map) => {
+            for (key, value) in map {
+                let path = format!("{path}.{key}");
+                match field(schema, key) {
+                    Field::Defined(schema) => unknown_fields(schema, value, &path, unknown),
+                    Field::Free => {}
+                    Field::Undefined => unknown.push(path),
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(schema) = schema.get("items") {
+                for item in items {
+                    unknown_fields(schema, item, &format!("{path}[]"), unknown);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Returns the path if it leads to a field which is not defined by `schema`.
+fn unknown_path(schema: &Value, path: &str) -> Option<String> {
+    let segments = json_path::parse(path).ok()?;
+    let mut schema = schema;
+    for segment in segments {
+        schema = match segment {
+            Segment::Key(key) => match field(schema, &key) {
+                Field::Defined(schema) => schema,
+                Field::Free => return None,
+                Field::Undefined => return Some(path.to_string()),
+            },
+            Segment::Index(_) => schema.get("items")?,
+        };
+    }
+    None
+}
+
+/// Collects the object patterns and paths of the conditions in `expr`.
+fn collect<'a>(expr: &'a Expr, patterns: &mut Vec<&'a Value>, paths: &mut Vec<&'a str>) {
+    match expr {
+        Expr::AndExpr { and: exprs } | Expr::OrExpr { or: exprs } => {
+            for expr in exprs {
+                collect(expr, patterns, paths);
+            }
+        }
+        Expr::NotExpr { not: expr } | Expr::DeletedExpr { deleted: expr } => {
+            collect(expr, patterns, paths)
+        }
+        Expr::OneExpr { one: pattern } | Expr::AllExpr { all: pattern } => patterns.push(pattern),
+        Expr::SequenceExpr { sequence } => patterns.extend(sequence),
+        Expr::AllSequenceExpr { all_sequence } => patterns.extend(all_sequence),
+        Expr::PathExpr(condition) => paths.push(&condition.path),
+        Expr::SizeExpr { .. }
+        | Expr::EventExpr { .. }
+        | Expr::NoRestartsExpr { .. }
+        | Expr::RestartedExpr { .. }
+        | Expr::JobCompleteExpr { .. }
+        | Expr::ReadyExpr { .. } => {}
+    }
+}
+
+/// Checks the conditions of `wait` and `assert` against the schemas of the watched resources,
+/// e.g. to catch a misspelled `staus` before the wait times out.
+pub fn validate_conditions(test_spec: &TestSpec, schemas: &Schemas) -> Vec<String> {
+    let mut watches = HashMap::new();
+    for watch in test_spec.all_steps().flat_map(|step| &step.watch) {
+        watches.entry(&watch.name).or_insert((
+            watch.group.clone(),
+            watch.version.clone(),
+            watch.kind.clone(),
+        ));
+    }
+    let mut problems = vec![];
+    for step in test_spec.all_steps() {
+        let conditions = step
+            .wait
+            .iter()
+            .map(|wait| (&wait.target, &wait.condition))
+            .chain(step.assert.iter().map(|a| (&a.target, &a.condition)));
+        for (target, condition) in conditions {
+            let Some(schema) = watches.get(target).and_then(|gvk| schemas.get(gvk)) else {
+                continue;
+            };
+            let (mut patterns, mut paths) = (vec![], vec![]);
+            collect(condition, &mut patterns, &mut paths);
+            let mut unknown = vec![];
+            for pattern in patterns {
+                unknown_fields(schema, pattern, "", &mut unknown);
+            }
+            unknown.extend(
+                paths
+                    .into_iter()
+                    .filter(|path| !path.contains("${"))
+                    .filter_map(|path| unknown_path(schema, path)),
+            );
+            unknown.dedup();
+            for path in unknown {
+                problems.push(format!(
+                    "{}: condition on bucket '{target}' references '{path}', which is not in the schema of {}",
+                    step.name, watches[target].2
+                ));
+            }
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_spec::{StepSpec, WaitSpec, WatchSpec};
+    use rstest::rstest;
+
+    fn schemas() -> Schemas {
+        let mut schemas = Schemas::new();
+        add_crd(
+            &mut schemas,
+            &json!({
+                "apiVersion": "apiextensions.k8s.io/v1",
+                "kind": "CustomResourceDefinition",
+                "spec": {
+                    "group": "example.com",
+                    "names": {"kind": "Widget"},
+                    "versions": [{
+                        "name": "v1",
+                        "schema": {"openAPIV3Schema": {
+                            "type": "object",
+                            "properties": {
+                                "spec": {
+                                    "type": "object",
+                                    "properties": {
+                                        "size": {"type": "integer"},
+                                        "labels": {
+                                            "type": "object",
+                                            "additionalProperties": {"type": "string"},
+                                        },
+                                        "config": {
+                                            "type": "object",
+                                            "x-kubernetes-preserve-unknown-fields": true,
+                                        },
+                                    },
+                                },
+                                "status": {
+                                    "type": "object",
+                                    "properties": {
+                                        "conditions": {
+                                            "type": "array",
+                                            "items": {
+                                                "type": "object",
+                                                "properties": {
+                                                    "type": {"type": "string"},
+                                                    "status": {"type": "string"},
+                                                },
+                                            },
+                                        },
+                                    },
+                                },
+                            },
+                        }},
+                    }],
+                },
+            }),
+        );
+        schemas
+    }
+
+    #[rstest]
+    #[case(json!({"one": {"status": {"conditions": [{"type": "Ready"}]}}}), vec![])]
+    #[case(json!({"one": {"staus": {"conditions": []}}}), vec![".staus"])]
+    #[case(
+        json!({"all": {"status": {"conditions": [{"typ": "Ready", "status": "True"}]}}}),
+        vec![".status.conditions[].typ"]
+    )]
+    #[case(
+        json!({"one": {"status": {"conditions": {"$inOrder": [{"type": "A"}, {"typ": "B"}]}}}}),
+        vec![".status.conditions[].typ"]
+    )]
+    #[case(json!({"one": {"spec": {"$exact": {"size": 1, "lables": {}}}}}), vec![".spec.lables"])]
+    #[case(json!({"one": {"metadata": {"name": "a"}, "spec": {"labels": {"app": "x"}}}}), vec![])]
+    #[case(json!({"one": {"spec": {"config": {"anything": 1}}}}), vec![])]
+    #[case(json!({"not": {"one": {"spec": {"sise": 1}}}}), vec![".spec.sise"])]
+    #[case(json!({"path": ".status.conditions[0].status", "equals": "True"}), vec![])]
+    #[case(json!({"path": ".status.phase", "exists": true}), vec![".status.phase"])]
+    #[case(json!({"size": 1}), vec![])]
+    fn test_validate_conditions(#[case] condition: Value, #[case] expected: Vec<&str>) {
+        let test_spec = TestSpec {
+            steps: vec![StepSpec {
+                name: "step".to_string(),
+                watch: vec![WatchSpec {
+                    name: "widgets".to_string(),
+                    group: "example.com".to_string(),
+                    version: "v1".to_string(),
+                    kind: "Widget".to_string(),
+                    ..Default::default()
+                }],
+                wait: vec![WaitSpec {
+                    target: "widgets".to_string(),
+                    condition: serde_json::from_value(condition).unwrap(),
+                    assert_file: None,
+                    http: None,
+                    prometheus: None,
+                    metrics: None,
+                    coerce: false,
+                    never: false,
+                    timeout: 1,
+                }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let problems = validate_conditions(&test_spec, &schemas());
+        let expected: Vec<String> = expected
+            .into_iter()
+            .map(|path| {
+                format!(
+                    "step: condition on bucket 'widgets' references '{path}', which is not in the schema of Widget"
+                )
+            })
+            .collect();
+        assert_eq!(problems, expected);
+    }
+}
@@ -0,0 +1,173 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::error::Description;
+use k8s_openapi::api::core::v1::{Event, Pod};
+use kube::api::{Api, ListParams};
+use kube::Client;
+use serde_json::Value;
+
+/// How many of the most recent events of a resource are described.
+const MAX_EVENTS: usize = 10;
+
+/// Time an event was last seen, for sorting the events of a resource.
+fn last_seen(event: &Event) -> Option<chrono::DateTime<chrono::Utc>> {
+    event
+        .last_timestamp
+        .as_ref()
+        .map(|time| time.0)
+        .or_else(|| event.event_time.as_ref().map(|time| time.0))
+        .or_else(|| event.first_timestamp.as_ref().map(|time| time.0))
+}
+
+/// Formats an event like `kubectl describe` lists it,
+/// e.g. `Warning BackOff (x5): Back-off restarting failed container`.
+fn format_event(event: &Event) -> String {
+    let count = match event.count {
+        Some(count) if count > 1 => format!(" (x{count})"),
+        _ => String::new(),
+    };
+    format!(
+        "{} {}{count}: {}",
+        event.type_.as_deref().unwrap_or("Normal"),
+        event.reason.as_deref().unwrap_or_default(),
+        event.message.as_deref().unwrap_or_default().trim_end()
+    )
+}
+
+fn describe_object(object: &Value, events: &[Event]) -> Description {
+    let uid = object.pointer("/metadata/uid").and_then(Value::as_str);
+    let mut events: Vec<&Event> = events
+        .iter()
+        .filter(|event| uid.is_some() && event.involved_object.uid.as_deref() == uid)
+        .collect();
+    events.sort_by_key(|event| last_seen(event));
+    let skip = events.len().saturating_sub(MAX_EVENTS);
+    Description {
+        resource: format!(
+            "{}/{}",
+            object["kind"].as_str().unwrap_or("Object"),
+            object
+                .pointer("/metadata/name")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+        ),
+        spec: object.get("spec").cloned(),
+        status: object.get("status").cloned(),
+        events: events.into_iter().skip(skip).map(format_event).collect(),
+    }
+}
+
+/// Describes `objects` and the pods in `namespace` with their spec, status and recent events,
+/// approximating what `kubectl describe` would show after a wait failed.
+/// Pods and events that cannot be listed are left out.
+pub async fn describe(client: Client, namespace: &str, objects: Vec<Value>) -> Vec<Description> {
+    let mut objects = objects;
+    match Api::<Pod>::namespaced(client.clone(), namespace)
+        .list(&ListParams::default())
+        .await
+    {
+        Ok(pods) => {
+            for pod in pods.items {
+                let Ok(pod) = serde_json::to_value(pod) else {
+                    continue;
+                };
+                let uid = pod.pointer("/metadata/uid");
+                if !objects
+                    .iter()
+                    .any(|object| object.pointer("/metadata/uid") == uid)
+                {
+                    objects.push(pod);
+                }
+            }
+        }
+        Err(err) => log::debug!("Unable to list pods in namespace '{namespace}': {err}"),
+    }
+    let events = match Api::<Event>::namespaced(client, namespace)
+        .list(&ListParams::default())
+        .await
+    {
+        Ok(events) => events.items,
+        Err(err) => {
+            log::debug!("Unable to list events in namespace '{namespace}': {err}");
+            vec![]
+        }
+    };
+    objects
+        .iter()
+        .map(|object| describe_object(object, &events))
+        .collect()
+}
+
+/// The pods of the Job `job` in `namespace`, by the label Jobs set on them.
+/// Pods that cannot be listed are left out.
+pub async fn job_pods(client: Client, namespace: &str, job: &str) -> Vec<Value> {
+    let params = ListParams::default().labels(&format!("job-name={job}"));
+    match Api::<Pod>::namespaced(client, namespace)
+        .list(&params)
+        .await
+    {
+        Ok(pods) => pods
+            .items
+            .into_iter()
+            .filter_map(|pod| serde_json::to_value(pod).ok())
+            .collect(),
+        Err(err) => {
+            log::debug!("Unable to list pods of Job '{job}' in namespace '{namespace}': {err}");
+            vec![]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn event(uid: &str, reason: &str, count: i32, seconds: i64) -> Event {
+        serde_json::from_value(json!({
+            "metadata": {"name": format!("{reason}-{seconds}")},
+            "involvedObject": {"uid": uid},
+            "type": "Warning",
+            "reason": reason,
+            "message": format!("{reason} happened\n"),
+            "count": count,
+            "lastTimestamp": chrono::DateTime::from_timestamp(seconds, 0).unwrap(),
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_describe_object() {
+        let object = json!({
+            "kind": "Pod",
+            "metadata": {"name": "web-0", "uid": "abc"},
+            "spec": {"containers": []},
+            "status": {"phase": "Pending"},
+        });
+        let mut events: Vec<Event> = (0..12)
+            .map(|i| event("abc", &format!("Reason{i}"), 1, 100 - i))
+            .collect();
+        events.push(event("other", "Unrelated", 1, 200));
+        events.push(event("abc", "BackOff", 5, 300));
+        let description = describe_object(&object, &events);
+        assert_eq!(description.resource, "Pod/web-0");
+        assert_eq!(description.spec, Some(json!({"containers": []})));
+        assert_eq!(description.status, Some(json!({"phase": "Pending"})));
+        assert_eq!(description.events.len(), MAX_EVENTS);
+        assert_eq!(description.events[0], "Warning Reason8: Reason8 happened");
+        assert_eq!(
+            description.events.last().unwrap(),
+            "Warning BackOff (x5): BackOff happened"
+        );
+    }
+
+    #[test]
+    fn test_describe_object_without_uid() {
+        let object = json!({"kind": "ConfigMap", "metadata": {"name": "settings"}});
+        let description = describe_object(&object, &[event("abc", "Created", 1, 0)]);
+        assert_eq!(description.resource, "ConfigMap/settings");
+        assert_eq!(description.spec, None);
+        assert!(description.events.is_empty());
+    }
+}
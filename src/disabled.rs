@@ -0,0 +1,73 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+//! Stand-ins for the modules of cargo features that are disabled. Tests using them
+//! still parse and validate, but their steps fail.
+
+#[cfg(not(feature = "sql"))]
+pub mod sql {
+    use crate::config::Config;
+    use crate::error::{Error, Result};
+    use crate::test_spec::SqlSpec;
+
+    pub async fn wait_for_sql(_config: &Config, _specs: Vec<SqlSpec>) -> Result<()> {
+        Err(Error::FeatureDisabled("sql"))
+    }
+}
+
+#[cfg(not(feature = "s3"))]
+pub mod s3 {
+    use crate::config::Config;
+    use crate::error::{Error, Result};
+    use crate::test_spec::{Env, S3Spec};
+
+    pub async fn wait_for_s3(_config: &Config, _specs: Vec<S3Spec>, _env: &Env) -> Result<()> {
+        Err(Error::FeatureDisabled("s3"))
+    }
+}
+
+#[cfg(not(feature = "mqtt"))]
+pub mod mqtt {
+    use crate::collector::{CollectedDataContainer, Collector};
+    use crate::error::{Error, Result};
+    use crate::test_spec::{MqttPublishSpec, MqttSubscribeSpec};
+
+    pub async fn publish(_spec: MqttPublishSpec) -> Result<()> {
+        Err(Error::FeatureDisabled("mqtt"))
+    }
+
+    pub async fn subscribe_to_buckets(
+        _test_name: &str,
+        _specs: Vec<MqttSubscribeSpec>,
+        _collected_data: CollectedDataContainer,
+    ) -> Result<Collector> {
+        Err(Error::FeatureDisabled("mqtt"))
+    }
+}
+
+#[cfg(not(feature = "dns"))]
+pub mod dns {
+    use crate::config::Config;
+    use crate::error::{Error, Result};
+    use crate::test_spec::DnsSpec;
+
+    pub async fn wait_for_dns(_config: &Config, _specs: Vec<DnsSpec>) -> Result<()> {
+        Err(Error::FeatureDisabled("dns"))
+    }
+}
+
+#[cfg(not(feature = "certificate"))]
+pub mod certificate {
+    use crate::config::Config;
+    use crate::error::{Error, Result};
+    use crate::test_spec::CertificateSpec;
+    use kube::Client;
+
+    pub async fn wait_for_certificates(
+        _client: Client,
+        _config: &Config,
+        _specs: Vec<CertificateSpec>,
+    ) -> Result<()> {
+        Err(Error::FeatureDisabled("certificate"))
+    }
+}
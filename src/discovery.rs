@@ -0,0 +1,57 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::error::{Error, Result};
+use crate::run;
+use kube::core::discovery::{ApiCapabilities, ApiResource};
+use kube::core::GroupVersionKind;
+use kube::{Client, Discovery};
+use std::sync::Arc;
+
+/// Runs discovery of the API resources of the cluster.
+async fn run(client: &Client) -> Result<Arc<Discovery>> {
+    log::debug!("Running discovery");
+    Ok(Arc::new(Discovery::new(client.clone()).run().await?))
+}
+
+/// Returns the discovery cached for the current run, running discovery if there is none.
+/// Running discovery takes a request per API group, so it is shared by all tests of the run
+/// and only run again once invalidated, e.g. after CRDs have been applied.
+/// Concurrent callers wait for a single run. Outside of a run, discovery is not cached.
+pub async fn get(client: &Client) -> Result<Arc<Discovery>> {
+    let Some(current) = run::current() else {
+        return run(client).await;
+    };
+    let mut cache = current.discovery.lock().await;
+    if let Some(discovery) = cache.as_ref() {
+        return Ok(discovery.clone());
+    }
+    let discovery = run(client).await?;
+    *cache = Some(discovery.clone());
+    Ok(discovery)
+}
+
+/// Drops the cached discovery, so that the next lookup sees API resources
+/// added or removed since.
+pub async fn invalidate() {
+    if let Some(run) = run::current() {
+        log::debug!("Invalidating discovery");
+        *run.discovery.lock().await = None;
+    }
+}
+
+/// Resolves the kind via the cached discovery. A kind that is not found, e.g. of
+/// a CRD created by a script, is looked up again after running discovery anew.
+pub async fn resolve(
+    client: &Client,
+    gvk: &GroupVersionKind,
+) -> Result<(ApiResource, ApiCapabilities)> {
+    if let Some(resolved) = get(client).await?.resolve_gvk(gvk) {
+        return Ok(resolved);
+    }
+    invalidate().await;
+    get(client)
+        .await?
+        .resolve_gvk(gvk)
+        .ok_or_else(|| Error::DiscoveryError(Box::new(gvk.clone())))
+}
@@ -0,0 +1,164 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::check::{assert_expr, CheckOptions, History};
+use crate::config::Config;
+use crate::error::{AssertDiagnostic, Error, Result, TestFailure, TestFailures};
+use crate::test_spec::{DnsSpec, WaitSpec};
+use crate::wait::wait_until;
+use hickory_resolver::config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts};
+use hickory_resolver::error::ResolveErrorKind;
+use hickory_resolver::proto::rr::{Record, RecordType};
+use hickory_resolver::system_conf::read_system_conf;
+use hickory_resolver::TokioAsyncResolver;
+use serde_json::{json, Value};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use tokio::time::{Duration, Instant};
+
+fn dns_error(err: impl std::fmt::Display) -> Error {
+    Error::DnsError(err.to_string())
+}
+
+/// Parses `ip` or `ip:port`, with port 53 by default.
+fn resolver_address(resolver: &str) -> Result<SocketAddr> {
+    SocketAddr::from_str(resolver)
+        .or_else(|_| IpAddr::from_str(resolver).map(|ip| SocketAddr::new(ip, 53)))
+        .map_err(|_| dns_error(format!("invalid resolver address '{resolver}'")))
+}
+
+/// A resolver without cache, so that every attempt sees the current records.
+fn resolver(spec: &DnsSpec) -> Result<TokioAsyncResolver> {
+    let (config, mut options) = match &spec.resolver {
+        Some(resolver) => {
+            let protocol = if spec.tcp {
+                Protocol::Tcp
+            } else {
+                Protocol::Udp
+            };
+            let mut config = ResolverConfig::new();
+            config.add_name_server(NameServerConfig::new(resolver_address(resolver)?, protocol));
+            (config, ResolverOpts::default())
+        }
+        None => read_system_conf().map_err(dns_error)?,
+    };
+    options.cache_size = 0;
+    Ok(TokioAsyncResolver::tokio(config, options))
+}
+
+fn record_value(record: &Record) -> Value {
+    json!({
+        "name": record.name().to_string(),
+        "type": record.record_type().to_string(),
+        "ttl": record.ttl(),
+        "value": record.data().map(|data| data.to_string()),
+    })
+}
+
+async fn lookup(
+    resolver: &TokioAsyncResolver,
+    name: &str,
+    record_type: RecordType,
+) -> Result<Vec<Value>> {
+    match resolver.lookup(name, record_type).await {
+        Ok(lookup) => Ok(lookup.record_iter().map(record_value).collect()),
+        Err(err) if matches!(err.kind(), ResolveErrorKind::NoRecordsFound { .. }) => Ok(vec![]),
+        Err(err) => Err(dns_error(err)),
+    }
+}
+
+fn check_records(spec: &DnsSpec, records: &[Value]) -> std::result::Result<(), AssertDiagnostic> {
+    let records: Vec<&Value> = records.iter().collect();
+    assert_expr(
+        &records,
+        &vec![],
+        &History::new(),
+        &spec.condition,
+        CheckOptions {
+            coerce: spec.coerce,
+        },
+    )
+}
+
+/// Resolves the name repeatedly until the records fulfill the condition.
+/// Errors are retried as well, as the name server may still be starting.
+async fn wait_for_records(
+    config: &Config,
+    spec: &DnsSpec,
+) -> Result<std::result::Result<(), AssertDiagnostic>> {
+    let deadline = Instant::now() + config.scaled_timeout(spec.timeout);
+    let record_type = RecordType::from_str(&spec.record_type.to_uppercase())
+        .map_err(|_| dns_error(format!("unknown record type '{}'", spec.record_type)))?;
+    let resolver = resolver(spec)?;
+    wait_until(
+        deadline,
+        Duration::from_secs(1),
+        || lookup(&resolver, &spec.name, record_type),
+        |records| check_records(spec, records),
+    )
+    .await
+}
+
+/// Waits until the records of all names fulfill their conditions.
+pub async fn wait_for_dns(config: &Config, specs: Vec<DnsSpec>) -> Result<()> {
+    log::debug!("Waiting for {} DNS names", specs.len());
+    let mut errors: Vec<TestFailure> = Vec::new();
+    for spec in specs {
+        if let Err(assert_diagnostic) = wait_for_records(config, &spec).await? {
+            let target = format!("dns:{} {}", spec.record_type, spec.name);
+            let spec = WaitSpec {
+                condition: spec.condition,
+                coerce: spec.coerce,
+                ..WaitSpec::synthetic(target, spec.timeout)
+            };
+            errors.push(TestFailure::new(spec, assert_diagnostic));
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::ConditionsFailed(TestFailures(errors)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_resolver::proto::rr::rdata::{A, CNAME, TXT};
+    use hickory_resolver::proto::rr::{Name, RData};
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("10.96.0.10", Some("10.96.0.10:53"))]
+    #[case("127.0.0.1:5353", Some("127.0.0.1:5353"))]
+    #[case("[::1]:5353", Some("[::1]:5353"))]
+    #[case("kube-dns", None)]
+    fn test_resolver_address(#[case] resolver: &str, #[case] expected: Option<&str>) {
+        assert_eq!(
+            resolver_address(resolver).ok(),
+            expected.map(|address| address.parse().unwrap())
+        );
+    }
+
+    #[rstest]
+    #[case(RData::A(A::new(192, 0, 2, 1)), "A", "192.0.2.1")]
+    #[case(
+        RData::CNAME(CNAME(Name::from_str("lb.example.com.").unwrap())),
+        "CNAME",
+        "lb.example.com."
+    )]
+    #[case(RData::TXT(TXT::new(vec!["owner=blackjack".to_string()])), "TXT", "owner=blackjack")]
+    fn test_record_value(#[case] rdata: RData, #[case] record_type: &str, #[case] value: &str) {
+        let name = Name::from_str("app.example.com.").unwrap();
+        let record = Record::from_rdata(name, 300, rdata);
+        assert_eq!(
+            record_value(&record),
+            json!({
+                "name": "app.example.com.",
+                "type": record_type,
+                "ttl": 300,
+                "value": value,
+            })
+        );
+    }
+}
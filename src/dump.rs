@@ -0,0 +1,221 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::collector::CollectedData;
+use crate::error::{FailedTest, Result};
+use crate::events::{read_events, EventKind};
+use crate::manifest::ManifestHandle;
+use crate::redact::sanitize;
+use kube::api::{Api, DynamicObject, ListParams, TypeMeta};
+use kube::core::discovery::{verbs, Scope};
+use kube::{Client, Discovery};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// Serializes the values as YAML documents, separated by `---`.
+pub(crate) fn yaml_documents<T: Serialize>(values: impl IntoIterator<Item = T>) -> Result<String> {
+    let mut documents = vec![];
+    for value in values {
+        documents.push(serde_yaml::to_string(&value)?);
+    }
+    Ok(documents.join("---\n"))
+}
+
+/// The `bucket-changed` entries of the last attempt of `test` in the event log at `path`,
+/// as JSON lines by bucket.
+fn bucket_changes(path: &Path, test: &str) -> Result<HashMap<String, String>> {
+    let mut changes: HashMap<String, String> = HashMap::new();
+    for event in read_events(path)? {
+        match &event.kind {
+            EventKind::TestStarted { test: started, .. } if started == test => changes.clear(),
+            EventKind::BucketChanged {
+                test: changed,
+                bucket,
+                ..
+            } if changed == test => {
+                let lines = changes.entry(bucket.clone()).or_default();
+                lines.push_str(&serde_json::to_string(&event)?);
+                lines.push('\n');
+            }
+            _ => {}
+        }
+    }
+    Ok(changes)
+}
+
+async fn dump_buckets(
+    dir: &Path,
+    collected_data: &CollectedData,
+    changes: Option<&HashMap<String, String>>,
+) -> Result<()> {
+    let dir = dir.join("buckets");
+    fs::create_dir_all(&dir).await?;
+    for (name, bucket) in &collected_data.buckets {
+        if let Some(changes) = changes {
+            let path = dir.join(format!("{name}.events.jsonl"));
+            let lines = changes.get(name).map(String::as_str).unwrap_or_default();
+            fs::write(path, lines).await?;
+        }
+        let mut objects: Vec<(&String, &Value)> = bucket.data.iter().collect();
+        objects.sort_by_key(|(key, _)| *key);
+        let objects = objects
+            .into_iter()
+            .map(|(_, object)| sanitize(object.clone()));
+        fs::write(dir.join(format!("{name}.yaml")), yaml_documents(objects)?).await?;
+    }
+    Ok(())
+}
+
+async fn dump_manifests(dir: &Path, manifests: &[ManifestHandle]) -> Result<()> {
+    let objects = manifests
+        .iter()
+        .flat_map(|manifest| manifest.objects())
+        .map(|object| serde_json::to_value(object).map(sanitize))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    fs::write(dir.join("manifests.yaml"), yaml_documents(objects)?).await?;
+    Ok(())
+}
+
+/// Lists all objects of all namespaced resources in `namespace`,
+/// like `kubectl get all -o yaml` but including custom resources.
+/// Resources that cannot be listed are skipped.
+async fn dump_namespace(client: Client, dir: &Path, namespace: &str) -> Result<()> {
+    let discovery = Discovery::new(client.clone()).run().await?;
+    let mut objects = vec![];
+    for group in discovery.groups() {
+        for (ar, caps) in group.recommended_resources() {
+            if caps.scope != Scope::Namespaced || !caps.supports_operation(verbs::LIST) {
+                continue;
+            }
+            let api = Api::<DynamicObject>::namespaced_with(client.clone(), namespace, &ar);
+            match api.list(&ListParams::default()).await {
+                Ok(list) => {
+                    for mut object in list.items {
+                        object.types = Some(TypeMeta {
+                            api_version: ar.api_version.clone(),
+                            kind: ar.kind.clone(),
+                        });
+                        objects.push(sanitize(serde_json::to_value(object)?));
+                    }
+                }
+                Err(err) => log::debug!("Unable to list {}: {err}", ar.plural),
+            }
+        }
+    }
+    fs::write(dir.join("namespace.yaml"), yaml_documents(objects)?).await?;
+    Ok(())
+}
+
+/// Dumps the state of a failed test into `<artifacts_dir>/<test name>`:
+/// the reason of the failure, the contents of every bucket,
+/// the applied manifests and all objects in the namespace of the test.
+/// With `event_log`, the changes of every bucket recorded there as well.
+/// Replaces the dump of a previous attempt.
+pub async fn dump_failed_test(
+    client: Client,
+    artifacts_dir: &str,
+    event_log: Option<&Path>,
+    namespace: &str,
+    failed_test: &FailedTest,
+    collected_data: &CollectedData,
+    manifests: &[ManifestHandle],
+) -> Result<PathBuf> {
+    let dir = Path::new(artifacts_dir).join(&failed_test.test_name);
+    if dir.exists() {
+        fs::remove_dir_all(&dir).await?;
+    }
+    fs::create_dir_all(&dir).await?;
+    fs::write(
+        dir.join("failure.txt"),
+        format!(
+            "step: {}\nnamespace: {namespace}\n\n{}\n",
+            failed_test.step_name, failed_test.failure
+        ),
+    )
+    .await?;
+    let changes = match event_log {
+        Some(path) => Some(bucket_changes(path, &failed_test.test_name)?),
+        None => None,
+    };
+    dump_buckets(&dir, collected_data, changes.as_ref()).await?;
+    dump_manifests(&dir, manifests).await?;
+    dump_namespace(client, &dir, namespace).await?;
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collector::Bucket;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_dump_buckets() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut collected_data = CollectedData::new();
+        let mut bucket = Bucket::default();
+        bucket
+            .data
+            .insert("b".to_string(), json!({"metadata": {"name": "second"}}));
+        bucket
+            .data
+            .insert("a".to_string(), json!({"metadata": {"name": "first"}}));
+        collected_data.buckets.insert("pods".to_string(), bucket);
+        dump_buckets(dir.path(), &collected_data, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("buckets/pods.yaml")).unwrap(),
+            "metadata:\n  name: first\n---\nmetadata:\n  name: second\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dump_bucket_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("events.jsonl");
+        let changed = |test: &str, change: &str, rv: &str| {
+            json!({"time": "2024-01-01T00:00:01Z", "event": "bucket-changed", "test": test,
+                "bucket": "configmaps", "change": change, "object": "ConfigMap/settings",
+                "resourceVersion": rv})
+        };
+        let started = json!({"time": "2024-01-01T00:00:00Z", "event": "test-started",
+            "test": "a", "type": "user", "namespace": "a-1"});
+        let events = [
+            started.clone(),
+            changed("a", "create", "5"),
+            started,
+            changed("a", "create", "6"),
+            changed("b", "create", "1"),
+            changed("a", "patch", "7"),
+        ];
+        let log_content: String = events.iter().map(|event| format!("{event}\n")).collect();
+        std::fs::write(&log, log_content).unwrap();
+
+        let mut collected_data = CollectedData::new();
+        collected_data.insert_received("configmaps", "uid".to_string(), json!({}));
+        collected_data.insert_received("secrets", "uid".to_string(), json!({}));
+        let changes = bucket_changes(&log, "a").unwrap();
+        dump_buckets(dir.path(), &collected_data, Some(&changes))
+            .await
+            .unwrap();
+        let lines =
+            std::fs::read_to_string(dir.path().join("buckets/configmaps.events.jsonl")).unwrap();
+        let events: Vec<Value> = lines
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["change"], "create");
+        assert_eq!(events[0]["object"], "ConfigMap/settings");
+        assert_eq!(events[0]["resourceVersion"], "6");
+        assert_eq!(events[1]["resourceVersion"], "7");
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("buckets/secrets.events.jsonl")).unwrap(),
+            ""
+        );
+    }
+}
@@ -1,9 +1,11 @@
 // Copyright 2024 Ole Kliemann
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::match_object::Difference;
 use crate::test_spec::{Expr, WaitSpec};
-use display_json::{DebugAsJson, DisplayAsJsonPretty};
+use display_json::DebugAsJson;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -17,10 +19,10 @@ pub enum Error {
     PathError(std::path::PathBuf),
 
     #[error("Discovery error: {0:?}")]
-    DiscoveryError(kube::core::GroupVersionKind),
+    DiscoveryError(Box<kube::core::GroupVersionKind>),
 
     #[error("Watcher error: {0}")]
-    WatcherError(#[from] kube::runtime::watcher::Error),
+    WatcherError(#[source] Box<kube::runtime::watcher::Error>),
 
     #[error("Command line parse error: {0}")]
     CommandlineParseError(#[from] shell_words::ParseError),
@@ -29,7 +31,13 @@ pub enum Error {
     EnvSubstError(#[from] envsubst::Error),
 
     #[error("Kube error: {0}")]
-    KubeError(#[from] kube::Error),
+    KubeError(#[source] Box<kube::Error>),
+
+    #[error("Kubeconfig error: {0}")]
+    KubeconfigError(#[from] kube::config::KubeconfigError),
+
+    #[error("Kubeconfig error: {0}")]
+    InferConfigError(#[source] Box<kube::config::InferConfigError>),
 
     #[error("ParseGroupVersionError: {0}")]
     ParseGroupVersionError(#[from] kube::core::gvk::ParseGroupVersionError),
@@ -46,6 +54,9 @@ pub enum Error {
     #[error("NamespaceExists")]
     NamespaceExists,
 
+    #[error("Namespace '{0}' was not deleted within {1} seconds")]
+    NamespaceNotDeleted(String, u64),
+
     #[error("Conditions failed: {0}")]
     ConditionsFailed(TestFailures),
 
@@ -58,6 +69,9 @@ pub enum Error {
     #[error("Interrupted")]
     SIGINT,
 
+    #[error("Timed out after {0} seconds")]
+    TimeoutError(u16),
+
     #[error("Not executed")]
     NotExecuted,
 
@@ -67,20 +81,137 @@ pub enum Error {
     #[error("No UID?!")]
     NoUidError,
 
-    #[error("Script failed: {0} {1}")]
-    ScriptFailed(String, String),
+    #[error("Port forward error: {0}")]
+    PortForwardError(String),
+
+    #[error("Messaging error: {0}")]
+    MessagingError(String),
+
+    #[error("SQL error: {0}")]
+    SqlError(String),
+
+    #[error("S3 error: {0}")]
+    S3Error(String),
+
+    #[error("DNS error: {0}")]
+    DnsError(String),
+
+    #[error("Certificate error: {0}")]
+    CertificateError(String),
+
+    #[error("MQTT error: {0}")]
+    MqttError(String),
+
+    #[error("Webhook receiver error: {0}")]
+    WebhookError(String),
+
+    #[error("Patch error: {0}")]
+    PatchError(String),
+
+    #[error("Chaos error: {0}")]
+    ChaosError(String),
+
+    #[error("Fixture error: {0}")]
+    FixtureError(String),
+
+    #[error("Export error: {0}")]
+    ExportError(String),
+
+    #[error("Script container error: {0}")]
+    ScriptContainerError(String),
+
+    #[error("Init error: {0}")]
+    InitError(String),
+
+    #[error("Conversion error: {0}")]
+    ConversionError(String),
+
+    #[error("Import error: {0}")]
+    ImportError(String),
+
+    #[error("Logs error: {0}")]
+    LogsError(String),
+
+    #[error("Manifest error: {0}")]
+    ManifestError(String),
+
+    #[error("Condition error: {0}")]
+    ConditionError(String),
+
+    #[error("API warning: {0}")]
+    ApiWarning(String),
+
+    #[error("Script failed: {0}")]
+    ScriptFailed(Box<ScriptFailure>),
 
     #[error("Some tests failed")]
     SomeTestsFailedError,
 
+    #[error("Some tests are invalid")]
+    ValidationFailedError,
+
+    #[error("Some tests are not formatted")]
+    FormatFailedError,
+
+    #[error("Invalid test spec {0}: {1}")]
+    TestSpecError(std::path::PathBuf, serde_yaml::Error),
+
+    #[error("Invalid feature {0}, line {1}: {2}")]
+    FeatureError(std::path::PathBuf, usize, String),
+
+    #[error("Invalid include {0}: {1}")]
+    IncludeError(std::path::PathBuf, String),
+
+    #[error("Invalid report template {0}: {1}")]
+    ReportTemplateError(std::path::PathBuf, String),
+
+    #[error("blackjack was built without the `{0}` feature")]
+    FeatureDisabled(&'static str),
+
+    #[error("Test suite requires Mr. Blackjack {0}, but this is version {1}; please update")]
+    VersionError(String, String),
+
     #[error("Other error: {0}")]
     Other(String),
 }
 
-#[derive(Clone, Serialize, Deserialize, DisplayAsJsonPretty, DebugAsJson)]
+// The errors of kube are boxed to keep results carrying an `Error` small.
+impl From<kube::Error> for Error {
+    fn from(err: kube::Error) -> Self {
+        Error::KubeError(Box::new(err))
+    }
+}
+
+impl From<kube::runtime::watcher::Error> for Error {
+    fn from(err: kube::runtime::watcher::Error) -> Self {
+        Error::WatcherError(Box::new(err))
+    }
+}
+
+impl From<kube::config::InferConfigError> for Error {
+    fn from(err: kube::config::InferConfigError) -> Self {
+        Error::InferConfigError(Box::new(err))
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, DebugAsJson)]
 pub struct TestFailure {
     pub assert_diagnostic: AssertDiagnostic,
     pub spec: WaitSpec,
+    /// Spec, status and recent events of the resources in the bucket of a failed wait
+    /// and of the pods in the namespace of the test, like `kubectl describe` would show them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub describe: Vec<Description>,
+}
+
+impl TestFailure {
+    pub fn new(spec: WaitSpec, assert_diagnostic: AssertDiagnostic) -> Self {
+        TestFailure {
+            assert_diagnostic,
+            spec,
+            describe: vec![],
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -100,6 +231,35 @@ pub type TestResult = std::result::Result<SucceededTest, FailedTest>;
 pub struct AssertDiagnostic {
     pub expr: Expr,
     pub input: Vec<serde_json::Value>,
+    /// Fields of the resources closest to the patterns of a failed `one` or `all` condition
+    /// that differ from them. Shown instead of the whole input if there are any.
+    #[serde(default)]
+    pub diff: Vec<ResourceDiff>,
+}
+
+/// The fields in which a resource differs from the pattern of a condition.
+#[derive(Clone, DebugAsJson, Serialize, Deserialize)]
+pub struct ResourceDiff {
+    /// Name of the resource, or its position in the bucket if it has none.
+    pub resource: String,
+    /// Kind of the resource, if it has one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    pub differences: Vec<Difference>,
+}
+
+/// A resource as described in the diagnostic of a failed wait.
+#[derive(Clone, DebugAsJson, Serialize, Deserialize)]
+pub struct Description {
+    /// Kind and name of the resource, e.g. `Pod/web-0`.
+    pub resource: String,
+    #[serde(default)]
+    pub spec: Option<serde_json::Value>,
+    #[serde(default)]
+    pub status: Option<serde_json::Value>,
+    /// The most recent events of the resource, oldest first.
+    #[serde(default)]
+    pub events: Vec<String>,
 }
 
 impl std::fmt::Display for TestFailures {
@@ -112,10 +272,54 @@ impl std::fmt::Display for TestFailures {
     }
 }
 
+/// The failure as JSON, followed by the described resources.
+impl std::fmt::Display for TestFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let failure = TestFailure {
+            describe: vec![],
+            ..self.clone()
+        };
+        let json = serde_json::to_string_pretty(&failure).map_err(|_| std::fmt::Error)?;
+        write!(f, "{json}")?;
+        for description in &self.describe {
+            write!(f, "\n{description}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Description {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Describe {}:", self.resource)?;
+        if let Some(spec) = &self.spec {
+            writeln!(f, "  Spec: {spec}")?;
+        }
+        if let Some(status) = &self.status {
+            writeln!(f, "  Status: {status}")?;
+        }
+        if !self.events.is_empty() {
+            writeln!(f, "  Events:")?;
+            for event in &self.events {
+                writeln!(f, "    {event}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
 impl std::fmt::Display for AssertDiagnostic {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "🔴 **Assertion Failed**")?;
         writeln!(f, "Failed Expression: {}", self.expr)?;
+        if !self.diff.is_empty() {
+            for resource_diff in &self.diff {
+                writeln!(f, "Differences of {}:", resource_diff.resource)?;
+                for difference in &resource_diff.differences {
+                    writeln!(f, "  {difference}")?;
+                }
+            }
+            return Ok(());
+        }
         writeln!(f, "Input Data:")?;
         for (i, input) in self.input.iter().enumerate() {
             writeln!(f, "  {}. {}", i + 1, input)?;
@@ -124,4 +328,47 @@ impl std::fmt::Display for AssertDiagnostic {
     }
 }
 
+/// A script that exited non-zero, with the tail of its output
+/// and the environment it was run with, with secrets redacted.
+#[derive(Clone, Serialize, Deserialize, DebugAsJson)]
+pub struct ScriptFailure {
+    pub script: String,
+    pub exit_code: Option<i32>,
+    #[serde(default)]
+    pub expected_failure: bool,
+    pub stdout: Vec<String>,
+    pub stderr: Vec<String>,
+    pub env: BTreeMap<String, String>,
+}
+
+impl ScriptFailure {
+    /// Describes how the script exited.
+    pub fn exit(&self) -> String {
+        match self.exit_code {
+            Some(0) if self.expected_failure => "succeeded, but expected to fail".to_string(),
+            Some(code) => format!("exit code {code}"),
+            None => "terminated by signal".to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for ScriptFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} ({})", self.script, self.exit())?;
+        for (title, lines) in [("stdout", &self.stdout), ("stderr", &self.stderr)] {
+            if !lines.is_empty() {
+                writeln!(f, "Last lines of {title}:")?;
+                for line in lines {
+                    writeln!(f, "  {line}")?;
+                }
+            }
+        }
+        writeln!(f, "Environment:")?;
+        for (name, value) in &self.env {
+            writeln!(f, "  {name}={value}")?;
+        }
+        Ok(())
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
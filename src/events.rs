@@ -0,0 +1,191 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::error::{Error, FailedTest, Result, TestFailure, TestFailures, TestResult};
+use crate::progress;
+use crate::run;
+use crate::test_spec::TestType;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Failure of a test, as recorded in the event log.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Failure {
+    pub step: String,
+    pub error: String,
+    #[serde(default)]
+    pub failures: Vec<TestFailure>,
+}
+
+impl From<&FailedTest> for Failure {
+    fn from(failed_test: &FailedTest) -> Self {
+        Failure {
+            step: failed_test.step_name.clone(),
+            error: failed_test.failure.to_string(),
+            failures: match &failed_test.failure {
+                Error::ConditionsFailed(TestFailures(failures)) => failures.clone(),
+                _ => vec![],
+            },
+        }
+    }
+}
+
+/// Change of the contents of a bucket.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BucketChange {
+    Create,
+    Patch,
+    Delete,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum EventKind {
+    SuiteStarted,
+    SuiteFinished {
+        success: bool,
+    },
+    SuitePaused,
+    SuiteResumed,
+    TestSkipped {
+        test: String,
+    },
+    TestStarted {
+        test: String,
+        #[serde(rename = "type")]
+        test_type: TestType,
+        namespace: String,
+    },
+    TestFinished {
+        test: String,
+        #[serde(default)]
+        failure: Option<Failure>,
+    },
+    StepStarted {
+        test: String,
+        step: String,
+    },
+    StepFinished {
+        test: String,
+        step: String,
+        #[serde(default)]
+        error: Option<String>,
+    },
+    /// Targets of the waits of a step whose conditions are not fulfilled yet.
+    WaitPending {
+        test: String,
+        step: String,
+        targets: Vec<String>,
+    },
+    BucketChanged {
+        test: String,
+        bucket: String,
+        change: BucketChange,
+        object: String,
+        #[serde(
+            rename = "resourceVersion",
+            default,
+            skip_serializing_if = "Option::is_none"
+        )]
+        resource_version: Option<String>,
+    },
+    /// Warning returned by the API server on an apply.
+    ApiWarning {
+        test: String,
+        step: String,
+        object: String,
+        warning: String,
+    },
+    /// Condition not checked, as the API of the resources it is on is not served by the cluster.
+    ConditionSkipped {
+        test: String,
+        step: String,
+        target: String,
+        reason: String,
+    },
+}
+
+/// Entry of the event log, which is written as one JSON object per line.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Event {
+    pub time: DateTime<Utc>,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+/// File the events of a run are written to.
+pub(crate) struct EventLog {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl EventLog {
+    pub(crate) fn create(path: &Path) -> Result<Self> {
+        Ok(EventLog {
+            path: path.to_path_buf(),
+            file: Mutex::new(File::create(path)?),
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes the event as a whole line, so that the log can be read while it is written.
+    fn write(&self, event: &Event) -> Result<()> {
+        let line = serde_json::to_string(event)? + "\n";
+        let mut file = self.file.lock().unwrap_or_else(|err| err.into_inner());
+        Ok(file.write_all(line.as_bytes())?)
+    }
+}
+
+/// Records the event in the event log of the current run.
+/// Outside of a run, or if the run has no event log, it is discarded.
+pub fn emit(kind: EventKind) {
+    let Some(run) = run::current() else {
+        return;
+    };
+    if let Some(progress) = &run.progress {
+        progress::update(progress, &kind);
+    }
+    if let Some(events) = &run.events {
+        let event = Event {
+            time: Utc::now(),
+            kind,
+        };
+        if let Err(err) = events.write(&event) {
+            log::warn!("Unable to write event: {err}");
+        }
+    }
+}
+
+pub fn emit_test_finished(result: &TestResult) {
+    emit(match result {
+        Ok(test) => EventKind::TestFinished {
+            test: test.clone(),
+            failure: None,
+        },
+        Err(failed_test) => EventKind::TestFinished {
+            test: failed_test.test_name.clone(),
+            failure: Some(failed_test.into()),
+        },
+    });
+}
+
+/// Reads events from NDJSON, or from a JSON array of events.
+pub fn read_events(path: &Path) -> Result<Vec<Event>> {
+    let content = std::fs::read_to_string(path)?;
+    if content.trim_start().starts_with('[') {
+        return Ok(serde_json::from_str(&content)?);
+    }
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Ok(serde_json::from_str(line)?))
+        .collect()
+}
@@ -0,0 +1,262 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::collector::{CollectedData, CollectedDataContainer};
+use crate::config::Config;
+use crate::dump::yaml_documents;
+use crate::error::{Error, Result};
+use crate::init::clean;
+use crate::json_path;
+use crate::match_object::contains;
+use crate::test_spec::{ExportManifestsSpec, ExportSpec};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde_json::Value;
+use std::path::Path;
+use tokio::fs;
+use tokio::time::{sleep, Duration, Instant};
+
+fn export_error(spec: &ExportSpec, message: impl std::fmt::Display) -> Error {
+    Error::ExportError(format!("'{}': {message}", spec.name))
+}
+
+/// Strings are exported as they are, all other values as JSON.
+fn env_value(value: &Value, base64: bool) -> std::result::Result<String, String> {
+    let value = match value {
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    };
+    if base64 {
+        let decoded = STANDARD
+            .decode(value.trim())
+            .map_err(|err| format!("unable to decode base64: {err}"))?;
+        String::from_utf8(decoded).map_err(|_| "decoded value is not UTF-8".to_string())
+    } else {
+        Ok(value)
+    }
+}
+
+/// Extracts the value from the single resource in the bucket matching the spec.
+/// Returns `None` while there is no such resource or the path does not exist yet.
+fn extract(spec: &ExportSpec, collected_data: &CollectedData) -> Result<Option<String>> {
+    let segments = json_path::parse(&spec.path).map_err(|err| export_error(spec, err))?;
+    let Some(bucket) = collected_data.buckets.get(&spec.bucket) else {
+        return Ok(None);
+    };
+    let matching: Vec<&Value> = bucket
+        .data
+        .values()
+        .filter(|object| spec.matches.as_ref().is_none_or(|m| contains(object, m)))
+        .collect();
+    match matching.as_slice() {
+        [] => Ok(None),
+        [object] => json_path::lookup(object, &segments)
+            .map(|value| env_value(value, spec.base64).map_err(|err| export_error(spec, err)))
+            .transpose(),
+        objects => Err(export_error(
+            spec,
+            format!(
+                "{} resources in bucket '{}' match, expected exactly one",
+                objects.len(),
+                spec.bucket
+            ),
+        )),
+    }
+}
+
+/// Waits until the value can be extracted.
+async fn wait_for_value(
+    config: &Config,
+    spec: &ExportSpec,
+    collected_data: &CollectedDataContainer,
+) -> Result<String> {
+    let deadline = Instant::now() + config.scaled_timeout(spec.timeout);
+    loop {
+        if let Some(value) = extract(spec, &*collected_data.lock().await)? {
+            return Ok(value);
+        }
+        if Instant::now() >= deadline {
+            return Err(export_error(
+                spec,
+                format!(
+                    "no resource in bucket '{}' with a value at '{}'",
+                    spec.bucket, spec.path
+                ),
+            ));
+        }
+        sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// Extracts the values of all exports from their buckets,
+/// as env variables for the following steps.
+pub async fn export_values(
+    config: &Config,
+    specs: Vec<ExportSpec>,
+    collected_data: &CollectedDataContainer,
+) -> Result<Vec<(String, String)>> {
+    log::debug!("Exporting {} values", specs.len());
+    let mut env = vec![];
+    for spec in specs {
+        let value = wait_for_value(config, &spec, collected_data).await?;
+        log::debug!(
+            "Exporting '{}' from bucket '{}' as {}",
+            spec.path,
+            spec.bucket,
+            spec.env_name()
+        );
+        env.push((spec.env_name(), value));
+    }
+    Ok(env)
+}
+
+/// The resources in the bucket matching the spec, without the fields populated by the cluster,
+/// ordered by kind and name so that the manifests do not change between runs.
+fn manifests(spec: &ExportManifestsSpec, collected_data: &CollectedData) -> Result<Vec<Value>> {
+    let bucket = collected_data
+        .buckets
+        .get(&spec.bucket)
+        .ok_or_else(|| Error::ExportError(format!("bucket '{}' is not watched", spec.bucket)))?;
+    let mut objects: Vec<Value> = bucket
+        .data
+        .values()
+        .filter(|object| spec.matches.as_ref().is_none_or(|m| contains(object, m)))
+        .cloned()
+        .collect();
+    objects.iter_mut().for_each(clean);
+    objects.sort_by_key(|object| {
+        (
+            object["kind"].as_str().unwrap_or_default().to_string(),
+            object["metadata"]["name"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+        )
+    });
+    Ok(objects)
+}
+
+/// Writes the resources in the buckets as manifests to files below `dirname`.
+pub async fn export_manifests(
+    specs: Vec<ExportManifestsSpec>,
+    dirname: &Path,
+    collected_data: &CollectedDataContainer,
+) -> Result<()> {
+    for spec in specs {
+        let objects = manifests(&spec, &*collected_data.lock().await)?;
+        let path = dirname.join(&spec.path);
+        log::debug!(
+            "Writing {} manifests from bucket '{}' to {}",
+            objects.len(),
+            spec.bucket,
+            path.display()
+        );
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::write(&path, yaml_documents(objects)?).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collector::Bucket;
+    use rstest::rstest;
+    use serde_json::json;
+
+    #[rstest]
+    #[case(json!("10.96.0.1"), false, Ok("10.96.0.1"))]
+    #[case(json!(8080), false, Ok("8080"))]
+    #[case(json!({"a": 1}), false, Ok("{\"a\":1}"))]
+    #[case(json!("c2VjcmV0"), true, Ok("secret"))]
+    #[case(json!("not base64!"), true, Err(()))]
+    fn test_env_value(
+        #[case] value: Value,
+        #[case] base64: bool,
+        #[case] expected: std::result::Result<&str, ()>,
+    ) {
+        assert_eq!(
+            env_value(&value, base64).map_err(|_| ()),
+            expected.map(String::from)
+        );
+    }
+
+    fn spec(matches: Option<Value>, path: &str) -> ExportSpec {
+        serde_json::from_value(json!({
+            "name": "password",
+            "bucket": "secrets",
+            "match": matches,
+            "path": path,
+            "base64": true,
+        }))
+        .unwrap()
+    }
+
+    #[rstest]
+    #[case(Some(json!({"metadata": {"name": "db"}})), ".data.password", Ok(Some("secret")))]
+    #[case(Some(json!({"metadata": {"name": "db"}})), ".data.username", Ok(None))]
+    #[case(Some(json!({"metadata": {"name": "other"}})), ".data.password", Ok(None))]
+    #[case(None, ".data.password", Err(()))]
+    fn test_extract(
+        #[case] matches: Option<Value>,
+        #[case] path: &str,
+        #[case] expected: std::result::Result<Option<&str>, ()>,
+    ) {
+        let mut collected_data = CollectedData::new();
+        let mut bucket = Bucket::default();
+        bucket.data.insert(
+            "1".to_string(),
+            json!({"metadata": {"name": "db"}, "data": {"password": "c2VjcmV0"}}),
+        );
+        bucket.data.insert(
+            "2".to_string(),
+            json!({"metadata": {"name": "cache"}, "data": {"password": "b3RoZXI="}}),
+        );
+        collected_data.buckets.insert("secrets".to_string(), bucket);
+        assert_eq!(
+            extract(&spec(matches, path), &collected_data).map_err(|_| ()),
+            expected.map(|value| value.map(String::from))
+        );
+    }
+
+    #[rstest]
+    #[case(None, vec!["api", "db"])]
+    #[case(Some(json!({"metadata": {"labels": {"tier": "backend"}}})), vec!["db"])]
+    #[case(Some(json!({"metadata": {"name": "other"}})), vec![])]
+    fn test_manifests(#[case] matches: Option<Value>, #[case] expected: Vec<&str>) {
+        let mut collected_data = CollectedData::new();
+        let mut bucket = Bucket::default();
+        bucket.data.insert(
+            "1".to_string(),
+            json!({
+                "kind": "Service",
+                "metadata": {"name": "db", "namespace": "test", "uid": "1", "labels": {"tier": "backend"}},
+                "status": {},
+            }),
+        );
+        bucket.data.insert(
+            "2".to_string(),
+            json!({"kind": "Service", "metadata": {"name": "api", "namespace": "test", "uid": "2"}}),
+        );
+        collected_data
+            .buckets
+            .insert("services".to_string(), bucket);
+        let spec: ExportManifestsSpec = serde_json::from_value(json!({
+            "bucket": "services",
+            "match": matches,
+            "path": "manifests/services.yaml",
+        }))
+        .unwrap();
+        let objects = manifests(&spec, &collected_data).unwrap();
+        let names: Vec<&str> = objects
+            .iter()
+            .map(|object| object["metadata"]["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, expected);
+        assert!(objects.iter().all(
+            |object| object.get("status").is_none() && object["metadata"].get("uid").is_none()
+        ));
+    }
+}
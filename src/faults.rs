@@ -0,0 +1,132 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::test_spec::FaultSpec;
+use futures::future::BoxFuture;
+use http::{Request, Response, StatusCode};
+use kube::client::{Body, DynBody};
+use rand::Rng;
+use serde_json::json;
+use std::task::{Context, Poll};
+use tokio::time::{sleep, Duration};
+use tower::{BoxError, Layer, Service};
+
+/// Body of an injected error, a `Status` like the API server would return.
+fn status_body(status: StatusCode) -> serde_json::Value {
+    json!({
+        "kind": "Status",
+        "apiVersion": "v1",
+        "metadata": {},
+        "status": "Failure",
+        "message": format!("fault injected by blackjack: {status}"),
+        "reason": status.canonical_reason().unwrap_or_default().replace(' ', ""),
+        "code": status.as_u16(),
+    })
+}
+
+/// Whether a request is answered with an injected error, given a uniform sample in `0..1`.
+fn is_failed(error_rate: f64, sample: f64) -> bool {
+    sample < error_rate
+}
+
+/// Layer of the Kubernetes client delaying requests and answering some of them
+/// with an error instead of sending them, to simulate a degraded control plane.
+#[derive(Clone)]
+pub struct InjectFaults {
+    latency: Duration,
+    error_rate: f64,
+    status: StatusCode,
+}
+
+impl InjectFaults {
+    pub fn new(spec: &FaultSpec) -> Self {
+        InjectFaults {
+            latency: Duration::from_millis(spec.latency),
+            error_rate: spec.error_rate,
+            status: StatusCode::from_u16(spec.status).unwrap_or(StatusCode::SERVICE_UNAVAILABLE),
+        }
+    }
+}
+
+impl<S> Layer<S> for InjectFaults {
+    type Service = InjectFaultsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        InjectFaultsService {
+            inner,
+            faults: self.clone(),
+        }
+    }
+}
+
+pub struct InjectFaultsService<S> {
+    inner: S,
+    faults: InjectFaults,
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for InjectFaultsService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<Box<DynBody>>, Error = BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, std::result::Result<S::Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::result::Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        let InjectFaults {
+            latency, status, ..
+        } = self.faults.clone();
+        if is_failed(self.faults.error_rate, rand::thread_rng().gen()) {
+            log::debug!(
+                "Injecting {status} into {} {}",
+                request.method(),
+                request.uri()
+            );
+            return Box::pin(async move {
+                sleep(latency).await;
+                let body = Body::from(status_body(status).to_string().into_bytes());
+                let body = http_body_util::BodyExt::map_err(body, BoxError::from);
+                let mut response = Response::new(Box::new(body) as Box<DynBody>);
+                *response.status_mut() = status;
+                Ok(response)
+            });
+        }
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let response = response.await;
+            sleep(latency).await;
+            response
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(0.0, 0.0, false)]
+    #[case(0.5, 0.49, true)]
+    #[case(0.5, 0.5, false)]
+    #[case(1.0, 0.99, true)]
+    fn test_is_failed(#[case] error_rate: f64, #[case] sample: f64, #[case] expected: bool) {
+        assert_eq!(is_failed(error_rate, sample), expected);
+    }
+
+    #[rstest]
+    #[case(503, "ServiceUnavailable")]
+    #[case(429, "TooManyRequests")]
+    fn test_status_body(#[case] status: u16, #[case] reason: &str) {
+        let body = status_body(StatusCode::from_u16(status).unwrap());
+        assert_eq!(body["reason"], reason);
+        assert_eq!(body["code"], status);
+        let status: kube::core::Status = serde_json::from_value(body).unwrap();
+        assert!(status.is_failure());
+    }
+}
@@ -0,0 +1,196 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::audit::Audit;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::manifest::ManifestHandle;
+use crate::run;
+use crate::test_spec::{ApplySpec, FixtureSpec};
+use crate::warnings;
+use k8s_openapi::api::core::v1::Pod;
+use kube::api::{DeleteParams, PostParams};
+use kube::{Api, Client};
+use serde_json::json;
+use std::path::PathBuf;
+use tokio::time::{sleep, Duration, Instant};
+
+const IMAGE_PULL_TIMEOUT: u16 = 300;
+
+fn fixture_error(err: impl std::fmt::Display) -> Error {
+    Error::FixtureError(err.to_string())
+}
+
+/// Whether all images of the warm pod have been pulled.
+/// Fails if one of them cannot be pulled.
+fn images_pulled(pod: &Pod, count: usize) -> std::result::Result<bool, String> {
+    let statuses = pod
+        .status
+        .as_ref()
+        .and_then(|status| status.container_statuses.as_ref());
+    let Some(statuses) = statuses else {
+        return Ok(false);
+    };
+    let mut pulled = 0;
+    for status in statuses {
+        let waiting = status
+            .state
+            .as_ref()
+            .and_then(|state| state.waiting.as_ref())
+            .and_then(|waiting| waiting.reason.as_deref());
+        match waiting {
+            Some("ErrImagePull" | "ImagePullBackOff" | "InvalidImageName") => {
+                return Err(format!("unable to pull image {}", status.image));
+            }
+            Some("ContainerCreating") => {}
+            _ => pulled += 1,
+        }
+    }
+    Ok(pulled == count)
+}
+
+/// Pulls the images onto a node by starting a pod with a container for each of them.
+/// The containers need not run successfully, so images without `true` can be used as well.
+async fn pull_images(client: Client, config: &Config, spec: &FixtureSpec) -> Result<()> {
+    let name = format!("blackjack-warm-{}", config.naming.suffix(10));
+    let pods: Api<Pod> = Api::namespaced(client, &spec.namespace);
+    let containers: Vec<_> = spec
+        .images
+        .iter()
+        .enumerate()
+        .map(|(i, image)| {
+            json!({
+                "name": format!("image-{i}"),
+                "image": image,
+                "imagePullPolicy": "IfNotPresent",
+                "command": ["true"],
+            })
+        })
+        .collect();
+    let pod: Pod = serde_json::from_value(json!({
+        "metadata": {
+            "name": name,
+            "labels": {"app.kubernetes.io/name": "blackjack-warm"},
+        },
+        "spec": {
+            "restartPolicy": "Never",
+            "terminationGracePeriodSeconds": 0,
+            "containers": containers,
+        },
+    }))?;
+    log::info!("Pulling images {:?}", spec.images);
+    pods.create(&PostParams::default(), &pod).await?;
+    let deadline = Instant::now() + config.scaled_timeout(IMAGE_PULL_TIMEOUT);
+    let result = loop {
+        match pods.get(&name).await {
+            Ok(pod) => match images_pulled(&pod, spec.images.len()) {
+                Ok(true) => break Ok(()),
+                Ok(false) => {}
+                Err(err) => break Err(fixture_error(err)),
+            },
+            Err(err) => break Err(err.into()),
+        }
+        if Instant::now() >= deadline {
+            break Err(fixture_error("timed out pulling images"));
+        }
+        sleep(Duration::from_secs(1)).await;
+    };
+    match pods
+        .delete(&name, &DeleteParams::default().grace_period(0))
+        .await
+    {
+        Ok(_) | Err(kube::Error::Api(_)) => {}
+        Err(err) => log::warn!("Unable to delete pod '{name}': {err}"),
+    }
+    result
+}
+
+/// Resources of a test that are created before its first attempt
+/// and kept for all its attempts.
+pub struct Fixtures {
+    manifests: Vec<ManifestHandle>,
+}
+
+impl Fixtures {
+    pub async fn create(
+        client: Client,
+        config: &Config,
+        test_name: &str,
+        spec: &FixtureSpec,
+        dir: PathBuf,
+    ) -> Result<Self> {
+        if !spec.images.is_empty() {
+            pull_images(client.clone(), config, spec).await?;
+        }
+        let audit = Audit {
+            run_id: run::id(),
+            test: test_name.to_string(),
+            step: "fixtures".to_string(),
+            spec: dir.join("test.yaml"),
+        };
+        let mut fixtures = Fixtures { manifests: vec![] };
+        for path in &spec.apply {
+            let apply = ApplySpec {
+                path: path.clone(),
+                override_namespace: false,
+                namespace: String::new(),
+                preserve: false,
+                wait_established: false,
+            };
+            let handle = ManifestHandle::new(apply, dir.clone(), client.clone())
+                .await?
+                .with_audit(&audit)
+                .with_apply_rate(config.apply_rate.clone());
+            let applied = handle
+                .apply(!config.strict_cluster)
+                .await
+                .and_then(|warnings| warnings::check(config, test_name, "fixtures", warnings));
+            if let Err(err) = applied {
+                fixtures.manifests.push(handle);
+                fixtures.delete().await?;
+                return Err(err);
+            }
+            fixtures.manifests.push(handle);
+        }
+        Ok(fixtures)
+    }
+
+    pub async fn delete(self) -> Result<()> {
+        for manifest in self.manifests.into_iter().rev() {
+            manifest.delete().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serde_json::Value;
+
+    fn waiting(reason: &str) -> Value {
+        json!({"name": "c", "image": "i", "imageID": "", "ready": false, "restartCount": 0,
+               "state": {"waiting": {"reason": reason}}})
+    }
+
+    fn terminated() -> Value {
+        json!({"name": "c", "image": "i", "imageID": "sha", "ready": false, "restartCount": 0,
+               "state": {"terminated": {"exitCode": 0}}})
+    }
+
+    #[rstest]
+    #[case(json!({}), 1, Ok(false))]
+    #[case(json!({"containerStatuses": [waiting("ContainerCreating")]}), 1, Ok(false))]
+    #[case(json!({"containerStatuses": [terminated(), waiting("ContainerCreating")]}), 2, Ok(false))]
+    #[case(json!({"containerStatuses": [terminated(), waiting("CreateContainerError")]}), 2, Ok(true))]
+    #[case(json!({"containerStatuses": [terminated(), waiting("ImagePullBackOff")]}), 2, Err(()))]
+    fn test_images_pulled(
+        #[case] status: Value,
+        #[case] count: usize,
+        #[case] expected: std::result::Result<bool, ()>,
+    ) {
+        let pod: Pod = serde_json::from_value(json!({"status": status})).unwrap();
+        assert_eq!(images_pulled(&pod, count).map_err(|_| ()), expected);
+    }
+}
@@ -0,0 +1,491 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::error::{Error, Result};
+use crate::result_formatting::log_format;
+use crate::run_test::discover_tests;
+use crate::schema::schema;
+use crate::test_filter::TestFilter;
+use crate::test_spec::TestSpec;
+use serde_yaml::Value;
+use std::path::Path;
+use tokio::fs;
+
+fn resolve<'a>(node: &'a serde_json::Value, root: &'a serde_json::Value) -> &'a serde_json::Value {
+    node.get("$ref")
+        .or_else(|| node.pointer("/allOf/0/$ref"))
+        .and_then(|r| r.as_str())
+        .and_then(|r| r.strip_prefix('#'))
+        .and_then(|pointer| root.pointer(pointer))
+        .unwrap_or(node)
+}
+
+/// Finds the schema of an object among the variants of an untagged enum.
+fn variant<'a>(
+    node: &'a serde_json::Value,
+    root: &'a serde_json::Value,
+    value: &serde_yaml::Mapping,
+) -> &'a serde_json::Value {
+    node.get("anyOf")
+        .and_then(|variants| variants.as_array())
+        .and_then(|variants| {
+            variants.iter().map(|v| resolve(v, root)).find(|v| {
+                v.get("properties").is_some_and(|properties| {
+                    value
+                        .keys()
+                        .filter_map(|k| k.as_str())
+                        .all(|k| properties.get(k).is_some())
+                })
+            })
+        })
+        .unwrap_or(node)
+}
+
+/// Removes all fields that are set to their default value.
+fn prune_defaults(value: &mut Value, node: &serde_json::Value, root: &serde_json::Value) {
+    let node = resolve(node, root);
+    match value {
+        Value::Mapping(mapping) => {
+            let node = variant(node, root, mapping);
+            let properties = match node.get("properties") {
+                Some(properties) => properties,
+                None => return,
+            };
+            mapping.retain(|key, value| {
+                let property = match key.as_str().and_then(|key| properties.get(key)) {
+                    Some(property) => property,
+                    None => return true,
+                };
+                let is_default = property
+                    .get("default")
+                    .and_then(|default| serde_yaml::to_value(default).ok())
+                    .is_some_and(|default| default == *value);
+                if !is_default {
+                    prune_defaults(value, property, root);
+                }
+                !is_default
+            });
+        }
+        Value::Sequence(sequence) => {
+            if let Some(items) = node.get("items") {
+                for item in sequence {
+                    prune_defaults(item, items, root);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Indents sequences below their parent key, as is common in hand written YAML.
+fn indent_sequences(yaml: &str) -> String {
+    let mut sequences: Vec<usize> = vec![];
+    let mut block_scalar: Option<usize> = None;
+    let mut result = String::new();
+    for line in yaml.lines() {
+        let indent = line.len() - line.trim_start().len();
+        if block_scalar.is_some_and(|block| indent > block || line.trim().is_empty()) {
+            result.push_str(&" ".repeat(2 * sequences.len()));
+            result.push_str(line);
+            result.push('\n');
+            continue;
+        }
+        block_scalar = None;
+        let is_item = line.trim_start().starts_with("- ") || line.trim() == "-";
+        while sequences
+            .last()
+            .is_some_and(|&column| indent < column || (indent == column && !is_item))
+        {
+            sequences.pop();
+        }
+        if is_item && sequences.last() != Some(&indent) {
+            sequences.push(indent);
+        }
+        if starts_block_scalar(line.trim_end()) {
+            block_scalar = Some(indent);
+        }
+        result.push_str(&" ".repeat(2 * sequences.len()));
+        result.push_str(line);
+        result.push('\n');
+    }
+    result
+}
+
+/// Splits a line into content and trailing comment, ignoring `#` within quotes.
+fn split_comment(line: &str) -> (&str, Option<&str>) {
+    let mut quote: Option<char> = None;
+    let mut previous = ' ';
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, '#') if previous.is_whitespace() => {
+                return (line[..i].trim_end(), Some(&line[i..]));
+            }
+            _ => {}
+        }
+        previous = c;
+    }
+    (line, None)
+}
+
+fn starts_block_scalar(code: &str) -> bool {
+    code.rsplit(' ')
+        .next()
+        .is_some_and(|t| t.starts_with(['|', '>']) && t.len() <= 3)
+}
+
+/// Key of a `key: value` line, if any.
+fn key_of(code: &str) -> Option<&str> {
+    let (code, _) = split_comment(code);
+    let mut quote: Option<char> = None;
+    for (i, c) in code.char_indices() {
+        match (quote, c) {
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, ':') if code[i + 1..].is_empty() || code[i + 1..].starts_with(' ') => {
+                return Some(code[..i].trim_matches(['\'', '"']));
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Location of a line in the document, like `steps.0.wait`.
+/// `item` is the location of the sequence item started on the line, if any.
+#[derive(Clone)]
+struct LinePath {
+    item: Option<String>,
+    key: String,
+}
+
+impl LinePath {
+    /// Location that full line comments before the line refer to.
+    fn before(&self) -> &str {
+        self.item.as_deref().unwrap_or(&self.key)
+    }
+}
+
+enum Segment {
+    Key(String),
+    Item(usize),
+}
+
+/// Determines the location of every line of a YAML document in block style.
+/// Comments, blank lines and contents of block scalars have no location.
+fn line_paths(content: &str) -> Vec<Option<LinePath>> {
+    let mut stack: Vec<(usize, Segment)> = vec![];
+    let mut block_scalar: Option<usize> = None;
+    let path = |stack: &Vec<(usize, Segment)>| {
+        stack
+            .iter()
+            .map(|(_, segment)| match segment {
+                Segment::Key(key) => key.clone(),
+                Segment::Item(index) => index.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(".")
+    };
+    content
+        .lines()
+        .map(|line| {
+            let mut indent = line.len() - line.trim_start().len();
+            let trimmed = line.trim();
+            if block_scalar.is_some_and(|block| indent > block || trimmed.is_empty()) {
+                return None;
+            }
+            block_scalar = None;
+            if trimmed.is_empty() || trimmed.starts_with('#') || trimmed == "---" {
+                return None;
+            }
+            let mut code = trimmed;
+            let mut item = None;
+            if let Some(rest) = code.strip_prefix("- ").or((code == "-").then_some("")) {
+                let mut index = 0;
+                while let Some((column, segment)) = stack.last() {
+                    if *column > indent {
+                        stack.pop();
+                    } else if *column == indent {
+                        match segment {
+                            Segment::Item(previous) => {
+                                index = previous + 1;
+                                stack.pop();
+                            }
+                            Segment::Key(_) => break,
+                        }
+                    } else {
+                        break;
+                    }
+                }
+                stack.push((indent, Segment::Item(index)));
+                item = Some(path(&stack));
+                indent += 2;
+                code = rest.trim_start();
+            }
+            if let Some(key) = key_of(code) {
+                while stack.last().is_some_and(|(column, _)| *column >= indent) {
+                    stack.pop();
+                }
+                stack.push((indent, Segment::Key(key.to_string())));
+            }
+            if starts_block_scalar(split_comment(code).0) {
+                block_scalar = Some(line.len() - line.trim_start().len());
+            }
+            Some(LinePath {
+                item,
+                key: path(&stack),
+            })
+        })
+        .collect()
+}
+
+enum Comment {
+    /// Full line comments, placed before the line at the given location.
+    Before(String, Vec<String>),
+    /// Comment at the end of the line at the given location.
+    Trailing(String, String),
+}
+
+struct Comments {
+    header: Vec<String>,
+    comments: Vec<Comment>,
+    footer: Vec<String>,
+}
+
+fn collect_comments(content: &str) -> Comments {
+    let mut header: Vec<String> = vec![];
+    let mut comments: Vec<Comment> = vec![];
+    let mut pending: Vec<String> = vec![];
+    let mut seen_content = false;
+    for (line, path) in content.lines().zip(line_paths(content)) {
+        let trimmed = line.trim();
+        match path {
+            None if trimmed.starts_with('#') => pending.push(trimmed.to_string()),
+            None if trimmed.is_empty() && !seen_content => pending.push(String::new()),
+            None => {}
+            Some(path) => {
+                if !seen_content {
+                    header = std::mem::take(&mut pending);
+                    seen_content = true;
+                } else if !pending.is_empty() {
+                    comments.push(Comment::Before(
+                        path.before().to_string(),
+                        std::mem::take(&mut pending),
+                    ));
+                }
+                if let (_, Some(trailing)) = split_comment(trimmed) {
+                    comments.push(Comment::Trailing(path.key, trailing.to_string()));
+                }
+            }
+        }
+    }
+    while header.last().is_some_and(|l| l.is_empty()) {
+        header.pop();
+    }
+    Comments {
+        header,
+        comments,
+        footer: pending,
+    }
+}
+
+/// Puts comments back next to the lines at the same location in the document.
+/// Comments on fields that were removed go to the closest remaining parent.
+fn restore_comments(formatted: &str, comments: Comments) -> String {
+    let lines: Vec<&str> = formatted.lines().collect();
+    let paths = line_paths(formatted);
+    let find = |location: &str, before: bool| {
+        let mut location = location;
+        loop {
+            let found = paths.iter().position(|path| {
+                path.as_ref().is_some_and(|path| {
+                    if before {
+                        path.before() == location
+                    } else {
+                        path.key == location
+                    }
+                })
+            });
+            match (found, location.rsplit_once('.')) {
+                (Some(index), _) => return Some(index),
+                (None, Some((parent, _))) => location = parent,
+                (None, None) => return None,
+            }
+        }
+    };
+    let mut before: Vec<Vec<String>> = vec![vec![]; lines.len() + 1];
+    let mut trailing: Vec<Vec<String>> = vec![vec![]; lines.len()];
+    for comment in comments.comments {
+        match comment {
+            Comment::Before(location, block) => {
+                let index = find(&location, true).unwrap_or(lines.len());
+                before[index].extend(block);
+            }
+            Comment::Trailing(location, comment) => match find(&location, false) {
+                Some(index) if paths[index].as_ref().is_some_and(|p| p.key == location) => {
+                    trailing[index].push(comment)
+                }
+                Some(index) => before[index].push(comment),
+                None => before[lines.len()].push(comment),
+            },
+        }
+    }
+    let mut result: Vec<String> = comments.header;
+    for (index, line) in lines.iter().enumerate() {
+        let indent = &line[..line.len() - line.trim_start().len()];
+        result.extend(before[index].iter().map(|c| format!("{indent}{c}")));
+        result.push(
+            std::iter::once(line.to_string())
+                .chain(trailing[index].iter().cloned())
+                .collect::<Vec<_>>()
+                .join(" "),
+        );
+    }
+    result.extend(before[lines.len()].iter().cloned());
+    result.extend(comments.footer);
+    result.join("\n") + "\n"
+}
+
+/// Rewrites a test spec with fields in the order of the schema,
+/// without fields set to their default values, and with comments kept in place.
+pub fn format_test_spec(content: &str) -> std::result::Result<String, String> {
+    let test_spec: TestSpec = serde_yaml::from_str(content).map_err(|err| err.to_string())?;
+    let mut value = serde_yaml::to_value(&test_spec).map_err(|err| err.to_string())?;
+    let root = schema();
+    prune_defaults(&mut value, &root, &root);
+    let formatted = serde_yaml::to_string(&value).map_err(|err| err.to_string())?;
+    Ok(restore_comments(
+        &indent_sequences(&formatted),
+        collect_comments(content),
+    ))
+}
+
+/// Formats all test specs in the directory, or only reports the unformatted ones if `check` is set.
+pub async fn format_test_suite(dirname: &Path, check: bool) -> Result<()> {
+    let filter = TestFilter::new(None, vec![], vec![])?;
+    let test_specs = discover_tests(&dirname.to_path_buf(), &filter, &mut vec![]).await?;
+    if test_specs.is_empty() {
+        return Err(Error::NoTestsFoundError);
+    }
+
+    let mut success = true;
+    for test_spec in test_specs {
+        let path = test_spec.dir.join("test.yaml");
+        if !path.exists() {
+            log::debug!("Not formatting scenarios of test '{}'", test_spec.name);
+            continue;
+        }
+        let content = fs::read_to_string(&path).await?;
+        let result = format_test_spec(&content);
+        match &result {
+            Ok(formatted) if *formatted != content && !check => {
+                fs::write(&path, formatted).await?;
+            }
+            Ok(formatted) if *formatted != content => success = false,
+            Err(_) => success = false,
+            Ok(_) => {}
+        }
+        log_format(&path, check, result.map(|formatted| formatted != content));
+    }
+    success.then_some(()).ok_or(Error::FormatFailedError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(
+        "steps:\n- name: a\n  watch:\n  - name: b\n    kind: Pod\n",
+        "steps:\n  - name: a\n    watch:\n      - name: b\n        kind: Pod\n"
+    )]
+    #[case(
+        "script: |-\n  - not an item\n  echo\nsleep: 1\n",
+        "script: |-\n  - not an item\n  echo\nsleep: 1\n"
+    )]
+    fn test_indent_sequences(#[case] input: &str, #[case] expected: &str) {
+        assert_eq!(indent_sequences(input), expected);
+    }
+
+    #[rstest]
+    #[case("a: b # comment", ("a: b", Some("# comment")))]
+    #[case("a: 'b # no comment'", ("a: 'b # no comment'", None))]
+    #[case("a: b#c", ("a: b#c", None))]
+    fn test_split_comment(#[case] line: &str, #[case] expected: (&str, Option<&str>)) {
+        assert_eq!(split_comment(line), expected);
+    }
+
+    #[rstest]
+    #[case("steps:\n- name: a\n  wait:\n  - timeout: 1\n- name: b\n", vec![
+        (None, "steps"),
+        (Some("steps.0"), "steps.0.name"),
+        (None, "steps.0.wait"),
+        (Some("steps.0.wait.0"), "steps.0.wait.0.timeout"),
+        (Some("steps.1"), "steps.1.name"),
+    ])]
+    #[case("steps:\n  - name: a\n    script: |\n      - foo\n    sleep: 1\n", vec![
+        (None, "steps"),
+        (Some("steps.0"), "steps.0.name"),
+        (None, "steps.0.script"),
+        (None, "steps.0.sleep"),
+    ])]
+    fn test_line_paths(#[case] content: &str, #[case] expected: Vec<(Option<&str>, &str)>) {
+        let paths: Vec<(Option<String>, String)> = line_paths(content)
+            .into_iter()
+            .flatten()
+            .map(|path| (path.item, path.key))
+            .collect();
+        let expected: Vec<(Option<String>, String)> = expected
+            .into_iter()
+            .map(|(item, key)| (item.map(String::from), key.to_string()))
+            .collect();
+        assert_eq!(paths, expected);
+    }
+
+    #[test]
+    fn test_format_test_spec() {
+        let content = r#"# yaml-language-server: $schema=schema.json
+steps:
+  # deploy everything
+  - sleep: 0
+    wait:
+      - timeout: 10   # seconds
+        target: pods
+    name: deploy
+type: user
+name: my-test
+attempts: 2
+"#;
+        let expected = r#"# yaml-language-server: $schema=schema.json
+name: my-test
+steps:
+  # deploy everything
+  - name: deploy
+    wait:
+      - target: pods
+        timeout: 10 # seconds
+attempts: 2
+"#;
+        assert_eq!(format_test_spec(content).unwrap(), expected);
+        assert_eq!(format_test_spec(expected).unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_format_test_suite_skips_features() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, file, content) in [
+            ("a", "test.yaml", "name: a\n"),
+            (
+                "b",
+                "test.feature",
+                "Scenario: b\n  Given manifest \"b.yaml\" is applied\n",
+            ),
+        ] {
+            std::fs::create_dir(dir.path().join(name)).unwrap();
+            std::fs::write(dir.path().join(name).join(file), content).unwrap();
+        }
+        format_test_suite(dir.path(), true).await.unwrap();
+    }
+}
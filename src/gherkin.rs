@@ -0,0 +1,294 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::error::{Error, Result};
+use crate::import::watch;
+use crate::test_spec::{name_part, Expr, StepSpec, TestSpec, WaitSpec};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::path::Path;
+
+/// File of a test written as Given/When/Then scenarios instead of a `test.yaml`.
+pub const FEATURE_FILE: &str = "test.feature";
+
+/// Timeout of `Then` clauses without `within N seconds`.
+const DEFAULT_TIMEOUT: u16 = 30;
+
+/// `manifest "a.yaml" is applied`, `manifests "a.yaml", "b.yaml" are deleted`
+static MANIFESTS: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"^manifests? (".*") (?:is|are) (applied|deleted)$"#).unwrap());
+
+static QUOTED: Lazy<Regex> = Lazy::new(|| Regex::new(r#""([^"]+)""#).unwrap());
+
+/// `apps/v1 Deployment "web" <predicate>`
+static RESOURCE: Lazy<Regex> = Lazy::new(|| Regex::new(r#"^(\S+) (\w+) "([^"]+)" (.+)$"#).unwrap());
+
+static PATCHED: Lazy<Regex> = Lazy::new(|| Regex::new(r"^is patched with (.+)$").unwrap());
+
+static WITHIN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(.+?)(?: within (\d+) seconds?)?$").unwrap());
+
+static EQUAL: Lazy<Regex> = Lazy::new(|| Regex::new(r"^has (\S+) equal to (.+)$").unwrap());
+
+/// A resource referred to by a clause, as its manifest header.
+fn resource(api_version: &str, kind: &str, name: &str) -> Value {
+    json!({
+        "apiVersion": api_version,
+        "kind": kind,
+        "metadata": {"name": name},
+    })
+}
+
+/// The object with the value at a dotted path, e.g. `status.phase`.
+fn nested(path: &str, value: Value) -> Value {
+    path.rsplit('.')
+        .fold(value, |value, field| json!({ field: value }))
+}
+
+/// Adds the files of `manifest "a.yaml" is applied` or `... is deleted` to `step`.
+fn manifests(step: &mut StepSpec, clause: &str) -> Result<bool> {
+    let Some(captures) = MANIFESTS.captures(clause) else {
+        return Ok(false);
+    };
+    for path in QUOTED.captures_iter(&captures[1]) {
+        let apply = serde_json::from_value(json!({"path": &path[1]}))?;
+        match &captures[2] {
+            "applied" => step.apply.push(apply),
+            _ => step.delete.push(apply),
+        }
+    }
+    Ok(true)
+}
+
+/// Converts a `Given` or `When` clause into the actions of `step`.
+fn action(step: &mut StepSpec, clause: &str) -> Result<()> {
+    if manifests(step, clause)? {
+        return Ok(());
+    }
+    let patch = RESOURCE.captures(clause).and_then(|captures| {
+        let patch = PATCHED.captures(captures.get(4)?.as_str())?;
+        Some((captures, patch[1].to_string()))
+    });
+    let Some((captures, patch)) = patch else {
+        return Err(Error::Other(format!("unknown action '{clause}'")));
+    };
+    let patch: Value = serde_json::from_str(&patch)
+        .map_err(|err| Error::Other(format!("invalid patch '{patch}': {err}")))?;
+    let (group, version) = captures[1].split_once('/').unwrap_or(("", &captures[1]));
+    step.patch.push(serde_json::from_value(json!({
+        "group": group,
+        "version": version,
+        "kind": &captures[2],
+        "name": &captures[3],
+        "patch": patch,
+    }))?);
+    Ok(())
+}
+
+/// Converts a `Then` clause into a wait of `step`, watching the resource unless already watched.
+fn condition(step: &mut StepSpec, clause: &str, watched: &mut HashSet<String>) -> Result<()> {
+    let unknown = || Error::Other(format!("unknown condition '{clause}'"));
+    let captures = RESOURCE.captures(clause).ok_or_else(unknown)?;
+    let object = resource(&captures[1], &captures[2], &captures[3]);
+    let within = WITHIN
+        .captures(captures.get(4).unwrap().as_str())
+        .ok_or_else(unknown)?;
+    let timeout = match within.get(2) {
+        Some(seconds) => seconds.as_str().parse().map_err(|_| unknown())?,
+        None => DEFAULT_TIMEOUT,
+    };
+    let one = |object: Value| Expr::OneExpr { one: object };
+    let name = json!({"metadata": {"name": &captures[3]}});
+    let condition = match &within[1] {
+        "exists" => one(name),
+        "does not exist" => Expr::NotExpr {
+            not: Box::new(one(name)),
+        },
+        predicate => {
+            let equal = EQUAL.captures(predicate).ok_or_else(unknown)?;
+            // Values that are not JSON, e.g. `Running`, are compared as strings.
+            let value = serde_json::from_str(&equal[2])
+                .unwrap_or_else(|_| Value::String(equal[2].to_string()));
+            let mut expected = nested(&equal[1], value);
+            expected["metadata"] = name["metadata"].clone();
+            one(expected)
+        }
+    };
+    let watch = watch(&object)?;
+    let target = watch.name.clone();
+    if watched.insert(target.clone()) {
+        step.watch.push(watch);
+    }
+    step.wait.push(WaitSpec {
+        target,
+        condition,
+        assert_file: None,
+        http: None,
+        prometheus: None,
+        metrics: None,
+        coerce: false,
+        never: false,
+        timeout,
+    });
+    Ok(())
+}
+
+/// Converts a feature of Given/When/Then scenarios into a test spec named `name`,
+/// unless named by its `Feature:` line, which is made a valid part of a namespace name.
+/// Every run of `Given`, `When` or `Then` clauses of a scenario becomes a step.
+pub fn parse_feature(path: &Path, name: String, data: &str) -> Result<TestSpec> {
+    let mut test_spec = TestSpec {
+        name,
+        ..Default::default()
+    };
+    let mut scenario: Option<String> = None;
+    let mut keyword: Option<&str> = None;
+    let mut watched = HashSet::new();
+    for (index, line) in data.lines().enumerate() {
+        let feature_error =
+            |message: String| Error::FeatureError(path.to_path_buf(), index + 1, message);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (word, clause) = line.split_once(' ').unwrap_or((line, ""));
+        let clause = clause.trim();
+        match word {
+            "Feature:" => test_spec.name = name_part(clause).trim_matches('-').to_string(),
+            "Scenario:" => {
+                scenario = Some(clause.to_string());
+                keyword = None;
+            }
+            "Given" | "When" | "Then" | "And" => {
+                let Some(scenario) = &scenario else {
+                    return Err(feature_error(format!("'{word}' outside of a scenario")));
+                };
+                match (word, keyword) {
+                    ("And", None) => {
+                        return Err(feature_error(
+                            "'And' without a preceding clause".to_string(),
+                        ))
+                    }
+                    ("And", Some(_)) => {}
+                    (word, current) if current != Some(word) => {
+                        keyword = Some(word);
+                        let step_name = format!("{}-{}", name_part(scenario), word.to_lowercase());
+                        let count = test_spec
+                            .steps
+                            .iter()
+                            .filter(|step| step.name.starts_with(&step_name))
+                            .count();
+                        test_spec.steps.push(StepSpec {
+                            name: match count {
+                                0 => step_name,
+                                count => format!("{step_name}-{}", count + 1),
+                            },
+                            ..Default::default()
+                        });
+                    }
+                    _ => {}
+                }
+                let step = test_spec.steps.last_mut().unwrap();
+                match keyword {
+                    Some("Then") => condition(step, clause, &mut watched),
+                    _ => action(step, clause),
+                }
+                .map_err(|err| feature_error(err.to_string()))?;
+            }
+            _ => return Err(feature_error(format!("unknown keyword '{word}'"))),
+        }
+    }
+    if test_spec.steps.is_empty() {
+        return Err(Error::FeatureError(
+            path.to_path_buf(),
+            0,
+            "no scenarios found".to_string(),
+        ));
+    }
+    Ok(test_spec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    const FEATURE: &str = r#"
+Feature: web
+  # Stakeholders review this file, Blackjack runs it.
+  Scenario: Scale up
+    Given manifests "deployment.yaml", "service.yaml" are applied
+    Then apps/v1 Deployment "web" has status.readyReplicas equal to 1 within 60 seconds
+    When apps/v1 Deployment "web" is patched with {"spec": {"replicas": 3}}
+    Then apps/v1 Deployment "web" has status.readyReplicas equal to 3 within 120 seconds
+    And v1 Service "web" exists
+
+  Scenario: Tear down
+    When manifest "deployment.yaml" is deleted
+    Then apps/v1 Deployment "web" does not exist within 30 seconds
+"#;
+
+    #[test]
+    fn test_parse_feature() {
+        let test_spec = parse_feature(Path::new("test.feature"), "dir".into(), FEATURE).unwrap();
+        assert_eq!(test_spec.name, "web");
+        let names: Vec<&str> = test_spec.steps.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "scale-up-given",
+                "scale-up-then",
+                "scale-up-when",
+                "scale-up-then-2",
+                "tear-down-when",
+                "tear-down-then",
+            ]
+        );
+        let steps = &test_spec.steps;
+        let paths: Vec<&str> = steps[0].apply.iter().map(|a| a.path.as_str()).collect();
+        assert_eq!(paths, vec!["deployment.yaml", "service.yaml"]);
+
+        assert_eq!(steps[1].watch[0].name, "deployment-web");
+        assert_eq!(steps[1].watch[0].group, "apps");
+        assert_eq!(steps[1].wait[0].timeout, 60);
+        assert_eq!(
+            serde_json::to_value(&steps[1].wait[0].condition).unwrap(),
+            json!({"one": {"metadata": {"name": "web"}, "status": {"readyReplicas": 1}}})
+        );
+
+        assert_eq!(steps[2].patch[0].version, "v1");
+        assert_eq!(steps[2].patch[0].patch, json!({"spec": {"replicas": 3}}));
+
+        let watches: Vec<&str> = steps[3].watch.iter().map(|w| w.name.as_str()).collect();
+        assert_eq!(watches, vec!["service-web"]);
+        assert_eq!(steps[3].wait[1].timeout, DEFAULT_TIMEOUT);
+
+        assert_eq!(steps[4].delete[0].path, "deployment.yaml");
+        assert!(matches!(steps[5].wait[0].condition, Expr::NotExpr { .. }));
+    }
+
+    #[rstest]
+    #[case("Feature: web", "web")]
+    #[case("Feature: Scale the web deployment", "scale-the-web-deployment")]
+    #[case("Feature:  Roll out v2! ", "roll-out-v2")]
+    fn test_parse_feature_name(#[case] feature: &str, #[case] name: &str) {
+        let data = format!("{feature}\n  Scenario: a\n    Given manifest \"a.yaml\" is applied");
+        let test_spec = parse_feature(Path::new("test.feature"), "dir".into(), &data).unwrap();
+        assert_eq!(test_spec.name, name);
+    }
+
+    #[rstest]
+    #[case("Given manifest \"a.yaml\" is applied", 1)]
+    #[case("Scenario: a\n  And manifest \"a.yaml\" is applied", 2)]
+    #[case("Scenario: a\n  Given the cluster is ready", 2)]
+    #[case("Scenario: a\n  Then v1 Pod \"web\" is happy", 2)]
+    #[case("Scenario: a\n  When v1 Pod \"web\" is patched with {", 2)]
+    #[case("Scenario: a\n  Suppose nothing", 2)]
+    #[case("Feature: empty", 0)]
+    fn test_parse_feature_error(#[case] feature: &str, #[case] line: usize) {
+        let result = parse_feature(Path::new("test.feature"), "dir".into(), feature);
+        assert!(matches!(result, Err(Error::FeatureError(_, l, _)) if l == line));
+    }
+}
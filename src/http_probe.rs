@@ -0,0 +1,59 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::test_spec::HttpCondition;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::json;
+use tokio::time::Duration;
+
+pub(crate) static CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(5))
+        .build()
+        .expect("unable to create HTTP client")
+});
+
+static INSECURE_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(5))
+        .danger_accept_invalid_certs(true)
+        .build()
+        .expect("unable to create HTTP client")
+});
+
+/// Requests the endpoint once.
+/// On mismatch, returns a description of the response for diagnostics.
+pub async fn probe(condition: &HttpCondition) -> Result<(), serde_json::Value> {
+    let client = if condition.insecure {
+        &*INSECURE_CLIENT
+    } else {
+        &*CLIENT
+    };
+    let response = client
+        .request(
+            condition
+                .method
+                .parse()
+                .map_err(|_| json!({"url": condition.url, "error": "invalid method"}))?,
+            &condition.url,
+        )
+        .send()
+        .await
+        .map_err(|err| json!({"url": condition.url, "error": err.to_string()}))?;
+    let status = response.status().as_u16();
+    let body = response
+        .text()
+        .await
+        .map_err(|err| json!({"url": condition.url, "status": status, "error": err.to_string()}))?;
+    let body_matches = match &condition.body {
+        Some(pattern) => Regex::new(pattern)
+            .map_err(|err| json!({"url": condition.url, "error": err.to_string()}))?
+            .is_match(&body),
+        None => true,
+    };
+    log::trace!("HTTP probe {} returned {}", condition.url, status);
+    (status == condition.status && body_matches)
+        .then_some(())
+        .ok_or_else(|| json!({"url": condition.url, "status": status, "body": body}))
+}
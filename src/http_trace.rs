@@ -0,0 +1,153 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::error::Result;
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures::future::BoxFuture;
+use http::{Method, Request, Response, Uri};
+use std::fs::{create_dir_all, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::time::{Duration, Instant};
+use tower::{Layer, Service};
+
+/// Name of the trace file in the artifacts dir.
+pub const TRACE_FILE: &str = "http-trace.log";
+
+fn trace_line(
+    time: DateTime<Utc>,
+    test: &str,
+    method: &Method,
+    uri: &Uri,
+    status: Option<u16>,
+    latency: Duration,
+) -> String {
+    let path = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    let status = status.map_or("error".to_string(), |status| status.to_string());
+    format!(
+        "{} {test} {method} {path} {status} {}ms\n",
+        time.to_rfc3339_opts(SecondsFormat::Millis, true),
+        latency.as_millis()
+    )
+}
+
+/// Layer of the Kubernetes client appending the method, path, status and latency
+/// of every request of a test to the trace file.
+#[derive(Clone)]
+pub struct TraceHttp {
+    test: Arc<str>,
+    file: Arc<Mutex<File>>,
+}
+
+impl TraceHttp {
+    /// Traces the requests of the test `test` into the trace file in `artifacts_dir`.
+    pub fn new(test: &str, artifacts_dir: &Path) -> Result<Self> {
+        create_dir_all(artifacts_dir)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(artifacts_dir.join(TRACE_FILE))?;
+        Ok(TraceHttp {
+            test: test.into(),
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    fn write(&self, line: &str) {
+        let mut file = self.file.lock().unwrap_or_else(|err| err.into_inner());
+        if let Err(err) = file.write_all(line.as_bytes()) {
+            log::warn!("Unable to write HTTP trace: {err}");
+        }
+    }
+}
+
+impl<S> Layer<S> for TraceHttp {
+    type Service = TraceHttpService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TraceHttpService {
+            inner,
+            trace: self.clone(),
+        }
+    }
+}
+
+pub struct TraceHttpService<S> {
+    inner: S,
+    trace: TraceHttp,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for TraceHttpService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, std::result::Result<S::Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::result::Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        let (method, uri) = (request.method().clone(), request.uri().clone());
+        let (time, started) = (Utc::now(), Instant::now());
+        let response = self.inner.call(request);
+        let trace = self.trace.clone();
+        Box::pin(async move {
+            let response = response.await;
+            let status = response.as_ref().ok().map(|r| r.status().as_u16());
+            trace.write(&trace_line(
+                time,
+                &trace.test,
+                &method,
+                &uri,
+                status,
+                started.elapsed(),
+            ));
+            response
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(
+        "GET",
+        "https://10.0.0.1:6443/api/v1/namespaces/foo/pods?watch=true",
+        Some(200),
+        "2024-11-05T10:00:00.000Z web GET /api/v1/namespaces/foo/pods?watch=true 200 42ms\n"
+    )]
+    #[case(
+        "PATCH",
+        "/apis/apps/v1/namespaces/foo/deployments/web",
+        None,
+        "2024-11-05T10:00:00.000Z web PATCH /apis/apps/v1/namespaces/foo/deployments/web error 42ms\n"
+    )]
+    fn test_trace_line(
+        #[case] method: &str,
+        #[case] uri: &str,
+        #[case] status: Option<u16>,
+        #[case] expected: &str,
+    ) {
+        let time = DateTime::parse_from_rfc3339("2024-11-05T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let line = trace_line(
+            time,
+            "web",
+            &Method::from_bytes(method.as_bytes()).unwrap(),
+            &uri.parse().unwrap(),
+            status,
+            Duration::from_millis(42),
+        );
+        assert_eq!(line, expected);
+    }
+}
@@ -0,0 +1,483 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::error::{Error, Result};
+use crate::format::format_test_spec;
+use crate::test_spec::{
+    ApplySpec, Expr, ScriptOptionsSpec, ScriptSpec, StepSpec, TestSpec, WaitSpec, WatchSpec,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+use tokio::fs;
+
+/// Timeout of kuttl asserts if not set by a `TestAssert`.
+const DEFAULT_TIMEOUT: u16 = 30;
+
+/// YAML documents of the files of a kuttl test, by file name.
+type Files = BTreeMap<String, Vec<Value>>;
+
+/// Manifests written along with the test, by path relative to the test directory.
+type Manifests = BTreeMap<String, Vec<Value>>;
+
+/// Scripts written along with the test, by path relative to the test directory.
+type Scripts = BTreeMap<String, String>;
+
+fn import_error(message: impl std::fmt::Display) -> Error {
+    Error::ImportError(message.to_string())
+}
+
+/// Splits a kuttl step file name, e.g. `01-install.yaml`, into the index and name of the step.
+fn step_file(file_name: &str) -> Option<(u32, &str)> {
+    let stem = file_name
+        .strip_suffix(".yaml")
+        .or_else(|| file_name.strip_suffix(".yml"))?;
+    let (index, name) = stem.split_once('-')?;
+    Some((index.parse().ok()?, name))
+}
+
+fn is_kuttl(document: &Value, kind: &str) -> bool {
+    document["kind"] == kind
+        && document["apiVersion"]
+            .as_str()
+            .is_some_and(|api_version| api_version.starts_with("kuttl.dev/"))
+}
+
+/// Converts a kuttl command into a script to be written to `path`, with `$NAMESPACE` rewritten
+/// to the namespace created by Blackjack. With `retry`, the command is run again until it succeeds
+/// or `retry` seconds have passed, like the commands of a kuttl `TestAssert`.
+fn script(path: String, command: &Value, retry: Option<u16>) -> Option<(ScriptSpec, String)> {
+    let line = match (command["command"].as_str(), command["script"].as_str()) {
+        (Some(command), _) | (None, Some(command)) => command.trim(),
+        (None, None) => return None,
+    };
+    let line = line
+        .replace("${NAMESPACE}", "${BLACKJACK_NAMESPACE}")
+        .replace("$NAMESPACE", "$BLACKJACK_NAMESPACE");
+    let ignore_failure = command["ignoreFailure"].as_bool() == Some(true);
+    // Scripts are sourced, so commands that are retried or may fail run in a subshell,
+    // whose `exit` does not end the script.
+    let content = match (retry, ignore_failure) {
+        (Some(retry), _) => format!(
+            "deadline=$(($(date +%s) + {retry}))\n\
+             until (\n{line}\n); do\n\
+             \x20 if [ \"$(date +%s)\" -ge \"$deadline\" ]; then {}; fi\n\
+             \x20 sleep 1\n\
+             done\n",
+            if ignore_failure { "break" } else { "exit 1" }
+        ),
+        (None, true) => format!("(\n{line}\n) || true\n"),
+        (None, false) => format!("{line}\n"),
+    };
+    let timeout = command["timeout"].as_u64().map(|timeout| timeout as u16);
+    let spec = match timeout {
+        Some(timeout) => ScriptSpec::Options(ScriptOptionsSpec {
+            command: path,
+            timeout: Some(timeout),
+            expect_failure: false,
+            capture_stdout: None,
+            shell: None,
+        }),
+        None => ScriptSpec::Command(path),
+    };
+    Some((spec, content))
+}
+
+/// Adds the kuttl `commands` of `document` to `step` as scripts written to `scripts/`.
+fn scripts(step: &mut StepSpec, document: &Value, retry: Option<u16>, scripts: &mut Scripts) {
+    for command in document["commands"].as_array().into_iter().flatten() {
+        let path = format!("scripts/{}-{}.sh", step.name, step.script.len() + 1);
+        if let Some((spec, content)) = script(path.clone(), command, retry) {
+            step.script.push(spec);
+            scripts.insert(path, content);
+        }
+    }
+}
+
+/// The watch of the resources matching the kuttl assert of `object`,
+/// named by the kind and name of the object.
+pub(crate) fn watch(object: &Value) -> Result<WatchSpec> {
+    let (Some(api_version), Some(kind)) = (object["apiVersion"].as_str(), object["kind"].as_str())
+    else {
+        return Err(import_error("asserted objects require apiVersion and kind"));
+    };
+    let (group, version) = api_version.split_once('/').unwrap_or(("", api_version));
+    let name = object["metadata"]["name"].as_str();
+    let mut watch = json!({
+        "name": match name {
+            Some(name) => format!("{}-{name}", kind.to_lowercase()),
+            None => kind.to_lowercase(),
+        },
+        "group": group,
+        "version": version,
+        "kind": kind,
+    });
+    if let Some(name) = name {
+        watch["fields"] = json!({"metadata.name": name});
+    }
+    Ok(serde_json::from_value(watch)?)
+}
+
+/// The documents of a file referenced by a `TestStep`.
+fn referenced<'a>(files: &'a Files, path: &Value) -> Result<&'a Vec<Value>> {
+    let path = path.as_str().unwrap_or_default();
+    files
+        .get(path)
+        .ok_or_else(|| import_error(format!("file '{path}' referenced by TestStep not found")))
+}
+
+/// Converts the step of index `index` from the documents of its files.
+fn convert_step(
+    index: u32,
+    step_files: &[(&str, &str)],
+    files: &Files,
+    watched: &mut HashSet<String>,
+    manifests: &mut Manifests,
+    scripts: &mut Scripts,
+) -> Result<StepSpec> {
+    let name = step_files
+        .iter()
+        .map(|(_, name)| *name)
+        .find(|name| !name.starts_with("assert") && !name.starts_with("errors"))
+        .unwrap_or(step_files[0].1);
+    let name = format!("{index:02}-{name}");
+    let mut step = StepSpec {
+        name: name.clone(),
+        ..Default::default()
+    };
+    let (mut objects, mut asserts, mut errors, mut deletes) = (vec![], vec![], vec![], vec![]);
+    let mut timeout = DEFAULT_TIMEOUT;
+    for (file_name, file_step) in step_files {
+        let documents = &files[*file_name];
+        for document in documents {
+            if is_kuttl(document, "TestStep") {
+                for path in document["apply"].as_array().into_iter().flatten() {
+                    step.apply
+                        .push(serde_json::from_value(json!({"path": path}))?);
+                }
+                for path in document["assert"].as_array().into_iter().flatten() {
+                    asserts.extend(referenced(files, path)?.iter().cloned());
+                }
+                for path in document["error"].as_array().into_iter().flatten() {
+                    errors.extend(referenced(files, path)?.iter().cloned());
+                }
+                for reference in document["delete"].as_array().into_iter().flatten() {
+                    if reference["name"].is_null() {
+                        log::warn!("{name}: delete without name is not converted");
+                        continue;
+                    }
+                    deletes.push(json!({
+                        "apiVersion": reference["apiVersion"],
+                        "kind": reference["kind"],
+                        "metadata": {"name": reference["name"]},
+                    }));
+                }
+                self::scripts(&mut step, document, None, scripts);
+            } else if is_kuttl(document, "TestAssert") {
+                if let Some(seconds) = document["timeout"].as_u64() {
+                    timeout = seconds as u16;
+                }
+                self::scripts(&mut step, document, Some(timeout), scripts);
+            } else if document["apiVersion"]
+                .as_str()
+                .is_some_and(|api_version| api_version.starts_with("kuttl.dev/"))
+            {
+                log::warn!("{name}: {} is not converted", document["kind"]);
+            } else if file_step.starts_with("assert") {
+                asserts.push(document.clone());
+            } else if file_step.starts_with("errors") {
+                errors.push(document.clone());
+            } else {
+                objects.push(document.clone());
+            }
+        }
+    }
+
+    for (objects, suffix, target) in [
+        (objects, "", &mut step.apply),
+        (deletes, "-delete", &mut step.delete),
+    ] {
+        if !objects.is_empty() {
+            let path = format!("manifests/{name}{suffix}.yaml");
+            target.push(serde_json::from_value::<ApplySpec>(json!({"path": path}))?);
+            manifests.insert(path, objects);
+        }
+    }
+    for (object, exists) in asserts
+        .iter()
+        .map(|object| (object, true))
+        .chain(errors.iter().map(|object| (object, false)))
+    {
+        let watch = watch(object)?;
+        let target = watch.name.clone();
+        if watched.insert(target.clone()) {
+            step.watch.push(watch);
+        }
+        let one = Expr::OneExpr {
+            one: object.clone(),
+        };
+        step.wait.push(WaitSpec {
+            target,
+            condition: match exists {
+                true => one,
+                false => Expr::NotExpr { not: Box::new(one) },
+            },
+            assert_file: None,
+            http: None,
+            prometheus: None,
+            metrics: None,
+            coerce: false,
+            never: false,
+            timeout,
+        });
+    }
+    Ok(step)
+}
+
+/// Converts the files of a kuttl test into a test spec named `name`,
+/// the manifests of the objects applied by its steps and the scripts of its commands.
+fn convert(name: &str, files: &Files) -> Result<(TestSpec, Manifests, Scripts)> {
+    let mut steps: BTreeMap<u32, Vec<(&str, &str)>> = BTreeMap::new();
+    for file_name in files.keys() {
+        if let Some((index, step_name)) = step_file(file_name) {
+            steps
+                .entry(index)
+                .or_default()
+                .push((file_name.as_str(), step_name));
+        }
+    }
+    if steps.is_empty() {
+        return Err(import_error(format!(
+            "no kuttl steps found in test '{name}'"
+        )));
+    }
+    let mut watched = HashSet::new();
+    let mut manifests = Manifests::new();
+    let mut scripts = Scripts::new();
+    let steps = steps
+        .into_iter()
+        .map(|(index, step_files)| {
+            convert_step(
+                index,
+                &step_files,
+                files,
+                &mut watched,
+                &mut manifests,
+                &mut scripts,
+            )
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let test_spec = TestSpec {
+        name: name.to_string(),
+        steps,
+        ..Default::default()
+    };
+    Ok((test_spec, manifests, scripts))
+}
+
+async fn read_files(dir: &Path) -> Result<Files> {
+    let mut files = Files::new();
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if !entry.file_type().await?.is_file()
+            || !(file_name.ends_with(".yaml") || file_name.ends_with(".yml"))
+            || file_name == "test.yaml"
+        {
+            continue;
+        }
+        let data = fs::read_to_string(entry.path()).await?;
+        let mut documents = vec![];
+        for document in serde_yaml::Deserializer::from_str(&data) {
+            let document = Value::deserialize(document)?;
+            if !document.is_null() {
+                documents.push(document);
+            }
+        }
+        files.insert(file_name, documents);
+    }
+    Ok(files)
+}
+
+/// Converts the kuttl test in `dir` into a `test.yaml` in the same directory.
+async fn import_test(dir: &Path) -> Result<()> {
+    let path = dir.join("test.yaml");
+    if path.exists() {
+        return Err(import_error(format!("{} exists", path.display())));
+    }
+    let name = dir
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let (test_spec, manifests, scripts) = convert(&name, &read_files(dir).await?)?;
+    let mut contents: BTreeMap<String, String> = BTreeMap::new();
+    for (manifest, objects) in manifests {
+        let documents: Vec<String> = objects
+            .iter()
+            .map(serde_yaml::to_string)
+            .collect::<std::result::Result<_, _>>()?;
+        contents.insert(manifest, documents.join("---\n"));
+    }
+    for (path, content) in contents.into_iter().chain(scripts) {
+        let path = dir.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::write(&path, content).await?;
+        log::info!("Wrote {}", path.display());
+    }
+    // The directory of the test is implied by the location of `test.yaml`.
+    let mut test_spec = serde_json::to_value(&test_spec)?;
+    if let Some(test_spec) = test_spec.as_object_mut() {
+        test_spec.remove("dir");
+    }
+    let test_spec = serde_yaml::to_string(&test_spec)?;
+    let test_spec = format_test_spec(&test_spec).map_err(Error::ImportError)?;
+    fs::write(&path, test_spec).await?;
+    log::info!("Wrote {}", path.display());
+    Ok(())
+}
+
+/// Converts the kuttl tests in `dir` into Blackjack tests, writing a `test.yaml` into every test directory.
+/// `dir` is either a single kuttl test or a suite with a kuttl test in every subdirectory.
+pub async fn import_kuttl(dir: &Path) -> Result<()> {
+    let files = read_files(dir).await?;
+    if files.keys().any(|file_name| step_file(file_name).is_some()) {
+        return import_test(dir).await;
+    }
+    let mut tests = vec![];
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.file_type().await?.is_dir() {
+            tests.push(entry.path());
+        }
+    }
+    tests.sort();
+    if tests.is_empty() {
+        return Err(import_error(format!(
+            "no kuttl tests found in {}",
+            dir.display()
+        )));
+    }
+    for test in tests {
+        import_test(&test).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("00-install.yaml", Some((0, "install")))]
+    #[case("01-assert.yaml", Some((1, "assert")))]
+    #[case("12-errors.yml", Some((12, "errors")))]
+    #[case("kuttl-test.yaml", None)]
+    #[case("00-install.json", None)]
+    fn test_step_file(#[case] file_name: &str, #[case] expected: Option<(u32, &str)>) {
+        assert_eq!(step_file(file_name), expected);
+    }
+
+    #[test]
+    fn test_convert() {
+        let deployment = json!({
+            "apiVersion": "apps/v1",
+            "kind": "Deployment",
+            "metadata": {"name": "web"},
+            "spec": {"replicas": 2},
+        });
+        let files = Files::from([
+            ("00-install.yaml".to_string(), vec![deployment.clone()]),
+            (
+                "00-assert.yaml".to_string(),
+                vec![
+                    json!({
+                        "apiVersion": "kuttl.dev/v1beta1",
+                        "kind": "TestAssert",
+                        "timeout": 60,
+                        "commands": [{"script": "test -n \"$(kubectl get pods)\""}],
+                    }),
+                    json!({
+                        "apiVersion": "apps/v1",
+                        "kind": "Deployment",
+                        "metadata": {"name": "web"},
+                        "status": {"readyReplicas": 2},
+                    }),
+                ],
+            ),
+            (
+                "01-delete.yaml".to_string(),
+                vec![json!({
+                    "apiVersion": "kuttl.dev/v1beta1",
+                    "kind": "TestStep",
+                    "delete": [{"apiVersion": "apps/v1", "kind": "Deployment", "name": "web"}],
+                    "commands": [
+                        {"command": "kubectl get pods -n $NAMESPACE"},
+                        {"script": "exit 1", "ignoreFailure": true, "timeout": 10},
+                    ],
+                })],
+            ),
+            (
+                "01-errors.yaml".to_string(),
+                vec![
+                    json!({"apiVersion": "apps/v1", "kind": "Deployment", "metadata": {"name": "web"}}),
+                ],
+            ),
+        ]);
+        let (test_spec, manifests, scripts) = convert("web", &files).unwrap();
+        assert_eq!(test_spec.name, "web");
+        let names: Vec<&str> = test_spec.steps.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["00-install", "01-delete"]);
+
+        let install = &test_spec.steps[0];
+        assert_eq!(install.apply[0].path, "manifests/00-install.yaml");
+        assert_eq!(manifests["manifests/00-install.yaml"], vec![deployment]);
+        assert_eq!(install.watch[0].name, "deployment-web");
+        assert_eq!(install.watch[0].group, "apps");
+        assert_eq!(install.wait[0].target, "deployment-web");
+        assert_eq!(install.wait[0].timeout, 60);
+        assert!(matches!(install.wait[0].condition, Expr::OneExpr { .. }));
+        assert_eq!(install.script[0].command(), "scripts/00-install-1.sh");
+        let retried = &scripts["scripts/00-install-1.sh"];
+        assert!(retried.starts_with("deadline=$(($(date +%s) + 60))\nuntil (\n"));
+        assert!(retried.contains("test -n \"$(kubectl get pods)\"\n); do\n"));
+        assert!(retried.contains("then exit 1; fi"));
+
+        let delete = &test_spec.steps[1];
+        assert!(delete.watch.is_empty());
+        assert_eq!(delete.delete[0].path, "manifests/01-delete-delete.yaml");
+        assert_eq!(
+            manifests["manifests/01-delete-delete.yaml"],
+            vec![
+                json!({"apiVersion": "apps/v1", "kind": "Deployment", "metadata": {"name": "web"}})
+            ]
+        );
+        let specs: Vec<(&str, Option<u16>)> = delete
+            .script
+            .iter()
+            .map(|script| (script.command(), script.timeout()))
+            .collect();
+        assert_eq!(
+            specs,
+            vec![
+                ("scripts/01-delete-1.sh", None),
+                ("scripts/01-delete-2.sh", Some(10)),
+            ]
+        );
+        assert_eq!(
+            scripts["scripts/01-delete-1.sh"],
+            "kubectl get pods -n $BLACKJACK_NAMESPACE\n"
+        );
+        assert_eq!(scripts["scripts/01-delete-2.sh"], "(\nexit 1\n) || true\n");
+        assert_eq!(delete.wait[0].timeout, DEFAULT_TIMEOUT);
+        assert!(matches!(delete.wait[0].condition, Expr::NotExpr { .. }));
+    }
+
+    #[test]
+    fn test_convert_without_steps() {
+        assert!(convert("empty", &Files::new()).is_err());
+    }
+}
@@ -0,0 +1,277 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::error::{Error, Result};
+use crate::format::format_test_spec;
+use kube::api::{Api, ApiResource, DynamicObject, ListParams};
+use kube::core::GroupVersionKind;
+use kube::Client;
+use serde_json::{json, Value};
+use std::path::Path;
+use tokio::fs;
+
+/// Kinds of resources snapshotted from the namespace.
+const KINDS: [(&str, &str, &str); 5] = [
+    ("", "v1", "ConfigMap"),
+    ("", "v1", "Service"),
+    ("apps", "v1", "Deployment"),
+    ("apps", "v1", "StatefulSet"),
+    ("apps", "v1", "DaemonSet"),
+];
+
+/// Annotations set by the cluster or by kubectl, which are not part of the manifests.
+const GENERATED_ANNOTATIONS: [&str; 2] = [
+    "kubectl.kubernetes.io/last-applied-configuration",
+    "deployment.kubernetes.io/revision",
+];
+
+/// Parses the source of the snapshot, e.g. `namespace/foo`, into the name of the namespace.
+fn parse_source(from: &str) -> Result<String> {
+    match from.split_once('/') {
+        Some(("namespace" | "ns", namespace)) if !namespace.is_empty() => Ok(namespace.to_string()),
+        _ => Err(Error::InitError(format!(
+            "invalid source '{from}', expected namespace/NAME"
+        ))),
+    }
+}
+
+/// Whether the object is created by the cluster or a controller rather than applied.
+fn is_generated(object: &Value) -> bool {
+    let metadata = &object["metadata"];
+    metadata.get("ownerReferences").is_some()
+        || (object["kind"] == "ConfigMap" && metadata["name"] == "kube-root-ca.crt")
+        || (object["kind"] == "Service" && metadata["name"] == "kubernetes")
+}
+
+/// Removes the fields populated by the cluster, so the object can be applied to another namespace.
+pub(crate) fn clean(object: &mut Value) {
+    let is_service = kind(object) == "Service";
+    let Some(map) = object.as_object_mut() else {
+        return;
+    };
+    map.remove("status");
+    if let Some(metadata) = map.get_mut("metadata").and_then(Value::as_object_mut) {
+        metadata.retain(|key, _| matches!(key.as_str(), "name" | "labels" | "annotations"));
+        for field in ["labels", "annotations"] {
+            if let Some(values) = metadata.get_mut(field).and_then(Value::as_object_mut) {
+                // The labels and annotations of Blackjack are set again when the object is applied.
+                values.retain(|key, _| {
+                    !GENERATED_ANNOTATIONS.contains(&key.as_str())
+                        && !key.starts_with("blackjack.io/")
+                });
+                if values.is_empty() {
+                    metadata.remove(field);
+                }
+            }
+        }
+    }
+    if let Some(spec) = map.get_mut("spec").and_then(Value::as_object_mut) {
+        if is_service {
+            spec.remove("clusterIP");
+            spec.remove("clusterIPs");
+            spec.remove("healthCheckNodePort");
+            for port in spec
+                .get_mut("ports")
+                .and_then(Value::as_array_mut)
+                .into_iter()
+                .flatten()
+            {
+                port.as_object_mut().map(|port| port.remove("nodePort"));
+            }
+        }
+        if let Some(template) = spec
+            .get_mut("template")
+            .and_then(|template| template.get_mut("metadata"))
+            .and_then(Value::as_object_mut)
+        {
+            template.remove("creationTimestamp");
+        }
+    }
+}
+
+fn kind(object: &Value) -> &str {
+    object["kind"].as_str().unwrap_or_default()
+}
+
+/// The condition under which the workload is ready, or `None` for other objects.
+fn readiness(object: &Value) -> Option<Value> {
+    let replicas = object["spec"]["replicas"].as_u64().unwrap_or(1);
+    match kind(object) {
+        "Deployment" | "StatefulSet" if replicas > 0 => Some(json!({
+            "and": [
+                {"size": 1},
+                {"one": {"status": {"readyReplicas": replicas}}},
+            ],
+        })),
+        "DaemonSet" => Some(json!({
+            "and": [
+                {"path": ".status.desiredNumberScheduled", "exists": true},
+                {"path": ".status.numberUnavailable", "exists": false},
+            ],
+        })),
+        _ => None,
+    }
+}
+
+/// Drafts a test applying the snapshotted manifests and waiting for the workloads among them.
+fn draft_test_spec(objects: &[Value]) -> Value {
+    let mut watch = vec![];
+    let mut wait = vec![];
+    for object in objects {
+        let Some(condition) = readiness(object) else {
+            continue;
+        };
+        let workload = kind(object);
+        let name = object["metadata"]["name"].as_str().unwrap_or_default();
+        let bucket = format!("{}-{name}", workload.to_lowercase());
+        watch.push(json!({
+            "name": bucket,
+            "group": "apps",
+            "version": "v1",
+            "kind": workload,
+            "fields": {"metadata.name": name},
+        }));
+        wait.push(json!({
+            "target": bucket,
+            "timeout": 120,
+            "condition": condition,
+        }));
+    }
+    json!({
+        "steps": [{
+            "name": "apply",
+            "watch": watch,
+            "apply": [{"path": "manifests"}],
+            "wait": wait,
+        }],
+    })
+}
+
+fn manifest_name(object: &Value) -> String {
+    format!(
+        "{}-{}.yaml",
+        kind(object).to_lowercase(),
+        object["metadata"]["name"].as_str().unwrap_or_default()
+    )
+}
+
+/// Snapshots the resources of the namespace given by `from`, e.g. `namespace/foo`,
+/// into manifests below `dir`, and drafts a `test.yaml` applying them.
+pub async fn init_test(
+    client: &Client,
+    dir: &Path,
+    from: &str,
+    selector: Option<String>,
+) -> Result<()> {
+    let namespace = parse_source(from)?;
+    let path = dir.join("test.yaml");
+    if path.exists() {
+        return Err(Error::InitError(format!("{} exists", path.display())));
+    }
+    let params = match &selector {
+        Some(selector) => ListParams::default().labels(selector),
+        None => ListParams::default(),
+    };
+    let mut objects = vec![];
+    for (group, version, kind) in KINDS {
+        let resource = ApiResource::from_gvk(&GroupVersionKind::gvk(group, version, kind));
+        let api: Api<DynamicObject> = Api::namespaced_with(client.clone(), &namespace, &resource);
+        for object in api.list(&params).await? {
+            let mut object = serde_json::to_value(object)?;
+            if is_generated(&object) {
+                continue;
+            }
+            object["apiVersion"] = json!(resource.api_version);
+            object["kind"] = json!(kind);
+            clean(&mut object);
+            objects.push(object);
+        }
+    }
+    if objects.is_empty() {
+        return Err(Error::InitError(format!(
+            "no resources found in namespace {namespace}"
+        )));
+    }
+
+    let manifests = dir.join("manifests");
+    fs::create_dir_all(&manifests).await?;
+    for object in &objects {
+        let path = manifests.join(manifest_name(object));
+        fs::write(&path, serde_yaml::to_string(object)?).await?;
+        log::info!("Wrote {}", path.display());
+    }
+    let test_spec = serde_yaml::to_string(&draft_test_spec(&objects))?;
+    let test_spec = format_test_spec(&test_spec).map_err(Error::InitError)?;
+    fs::write(&path, test_spec).await?;
+    log::info!("Wrote {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_spec::TestSpec;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("namespace/foo", Some("foo"))]
+    #[case("ns/foo", Some("foo"))]
+    #[case("namespace/", None)]
+    #[case("foo", None)]
+    #[case("deployment/foo", None)]
+    fn test_parse_source(#[case] from: &str, #[case] expected: Option<&str>) {
+        assert_eq!(parse_source(from).ok().as_deref(), expected);
+    }
+
+    #[test]
+    fn test_clean() {
+        let mut object = json!({
+            "apiVersion": "v1",
+            "kind": "Service",
+            "metadata": {
+                "name": "web",
+                "namespace": "foo",
+                "uid": "1234",
+                "resourceVersion": "42",
+                "labels": {"app": "web", "blackjack.io/test": "web"},
+                "annotations": {
+                    "kubectl.kubernetes.io/last-applied-configuration": "{}",
+                },
+            },
+            "spec": {
+                "clusterIP": "10.0.0.1",
+                "clusterIPs": ["10.0.0.1"],
+                "ports": [{"port": 80, "nodePort": 30080}],
+                "selector": {"app": "web"},
+            },
+            "status": {"loadBalancer": {}},
+        });
+        clean(&mut object);
+        assert_eq!(
+            object,
+            json!({
+                "apiVersion": "v1",
+                "kind": "Service",
+                "metadata": {"name": "web", "labels": {"app": "web"}},
+                "spec": {"ports": [{"port": 80}], "selector": {"app": "web"}},
+            })
+        );
+    }
+
+    #[test]
+    fn test_draft_test_spec() {
+        let objects = vec![
+            json!({"kind": "ConfigMap", "metadata": {"name": "config"}}),
+            json!({"kind": "Deployment", "metadata": {"name": "web"}, "spec": {"replicas": 3}}),
+            json!({"kind": "StatefulSet", "metadata": {"name": "db"}, "spec": {"replicas": 0}}),
+            json!({"kind": "DaemonSet", "metadata": {"name": "agent"}, "spec": {}}),
+        ];
+        let test_spec: TestSpec = serde_json::from_value(draft_test_spec(&objects)).unwrap();
+        let step = &test_spec.steps[0];
+        assert_eq!(step.apply[0].path, "manifests");
+        let buckets: Vec<&str> = step.watch.iter().map(|w| w.name.as_str()).collect();
+        assert_eq!(buckets, vec!["deployment-web", "daemonset-agent"]);
+        let targets: Vec<&str> = step.wait.iter().map(|w| w.target.as_str()).collect();
+        assert_eq!(targets, buckets);
+    }
+}
@@ -0,0 +1,105 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use serde_json::Value;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// Parses a JSONPath like `{.data.password}`, `$.spec.ports[0].port`
+/// or `.metadata.annotations['example.com/owner']`.
+/// Only child and index selectors are supported, as a path selects a single value.
+pub fn parse(path: &str) -> Result<Vec<Segment>, String> {
+    let path = path.trim();
+    let path = path
+        .strip_prefix('{')
+        .and_then(|p| p.strip_suffix('}'))
+        .unwrap_or(path);
+    let mut rest = path.strip_prefix('$').unwrap_or(path);
+    let mut segments = vec![];
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('.') {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            if end == 0 {
+                return Err(format!("empty key in '{path}'"));
+            }
+            segments.push(Segment::Key(after[..end].to_string()));
+            rest = &after[end..];
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let end = after
+                .find(']')
+                .ok_or_else(|| format!("unclosed '[' in '{path}'"))?;
+            let selector = &after[..end];
+            let quoted = ['\'', '"'].into_iter().find_map(|quote| {
+                selector
+                    .strip_prefix(quote)
+                    .and_then(|s| s.strip_suffix(quote))
+            });
+            segments.push(match quoted {
+                Some(key) => Segment::Key(key.to_string()),
+                None => Segment::Index(
+                    selector
+                        .parse()
+                        .map_err(|_| format!("unsupported selector '[{selector}]' in '{path}'"))?,
+                ),
+            });
+            rest = &after[end + 1..];
+        } else {
+            return Err(format!("expected '.' or '[' at '{rest}' in '{path}'"));
+        }
+    }
+    Ok(segments)
+}
+
+pub fn lookup<'a>(value: &'a Value, segments: &[Segment]) -> Option<&'a Value> {
+    segments
+        .iter()
+        .try_fold(value, |value, segment| match segment {
+            Segment::Key(key) => value.get(key),
+            Segment::Index(index) => value.get(index),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serde_json::json;
+
+    fn key(key: &str) -> Segment {
+        Segment::Key(key.to_string())
+    }
+
+    #[rstest]
+    #[case("{.spec.clusterIP}", Ok(vec![key("spec"), key("clusterIP")]))]
+    #[case("$.spec.ports[0].port", Ok(vec![key("spec"), key("ports"), Segment::Index(0), key("port")]))]
+    #[case(
+        ".metadata.annotations['example.com/owner']",
+        Ok(vec![key("metadata"), key("annotations"), key("example.com/owner")])
+    )]
+    #[case(".data[\"tls.crt\"]", Ok(vec![key("data"), key("tls.crt")]))]
+    #[case("$", Ok(vec![]))]
+    #[case(".spec..clusterIP", Err(()))]
+    #[case(".spec.ports[*]", Err(()))]
+    #[case(".spec.ports[0", Err(()))]
+    #[case("spec.clusterIP", Err(()))]
+    fn test_parse(#[case] path: &str, #[case] expected: Result<Vec<Segment>, ()>) {
+        assert_eq!(parse(path).map_err(|_| ()), expected);
+    }
+
+    #[rstest]
+    #[case(".spec.ports[1].port", Some(json!(443)))]
+    #[case(".spec.ports[2].port", None)]
+    #[case(".metadata.labels['app.kubernetes.io/name']", Some(json!("web")))]
+    #[case(".spec.ports.port", None)]
+    fn test_lookup(#[case] path: &str, #[case] expected: Option<Value>) {
+        let object = json!({
+            "metadata": {"labels": {"app.kubernetes.io/name": "web"}},
+            "spec": {"ports": [{"port": 80}, {"port": 443}]},
+        });
+        assert_eq!(lookup(&object, &parse(path).unwrap()), expected.as_ref());
+    }
+}
@@ -1,16 +1,85 @@
 // Copyright 2024 Ole Kliemann
 // SPDX-License-Identifier: Apache-2.0
 
+pub mod audit;
+#[cfg(feature = "certificate")]
+pub mod certificate;
+pub mod chaos;
 pub mod check;
 pub mod collector;
 pub mod config;
+pub mod container;
+pub mod conversion;
+pub mod crd_schema;
+pub mod describe;
+mod disabled;
+pub mod discovery;
+#[cfg(feature = "dns")]
+pub mod dns;
+pub mod dump;
 pub mod error;
+pub mod events;
+pub mod export;
+pub mod faults;
 pub mod file;
+pub mod fixtures;
+pub mod format;
+pub mod gherkin;
+pub mod http_probe;
+pub mod http_trace;
+pub mod import;
+pub mod init;
+pub mod json_path;
+pub mod list;
+pub mod locks;
+pub mod log_prefix;
+pub mod logs;
 pub mod manifest;
 pub mod match_object;
+pub mod messages;
+pub mod metrics;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
 pub mod namespace;
+pub mod naming;
+pub mod pacing;
+pub mod patch;
+pub mod pause;
+pub mod port_forward;
+pub mod preflight;
+pub mod progress;
+pub mod prometheus;
+pub mod rbac;
+pub mod redact;
+pub mod report;
 pub mod result_formatting;
+pub mod results;
+pub mod run;
 pub mod run_test;
+pub mod runner;
+#[cfg(feature = "s3")]
+pub mod s3;
+pub mod schedule;
+pub mod schema;
 pub mod script;
+pub mod shutdown;
+#[cfg(feature = "sql")]
+pub mod sql;
+pub mod state;
+pub mod test_filter;
 pub mod test_spec;
+pub mod validate;
 pub mod wait;
+pub mod warnings;
+pub mod webhook;
+
+#[cfg(not(feature = "certificate"))]
+pub use disabled::certificate;
+#[cfg(not(feature = "dns"))]
+pub use disabled::dns;
+#[cfg(not(feature = "mqtt"))]
+pub use disabled::mqtt;
+#[cfg(not(feature = "s3"))]
+pub use disabled::s3;
+#[cfg(not(feature = "sql"))]
+pub use disabled::sql;
@@ -0,0 +1,126 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::error::Result;
+use crate::run_test::discover_tests;
+use crate::schedule::{sort_tests, Schedule};
+use crate::test_filter::TestFilter;
+use crate::test_spec::{TestSpec, TestType};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+/// A discovered test, as it would be run.
+#[derive(Clone, Serialize)]
+pub struct TestListing {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub test_type: TestType,
+    pub ordering: Option<String>,
+    pub tags: Vec<String>,
+    pub dir: PathBuf,
+    pub steps: Vec<String>,
+}
+
+impl From<&TestSpec> for TestListing {
+    fn from(test_spec: &TestSpec) -> Self {
+        TestListing {
+            name: test_spec.name.clone(),
+            test_type: test_spec.test_type.clone(),
+            ordering: test_spec.ordering.clone(),
+            tags: test_spec.tags.clone(),
+            dir: test_spec.dir.clone(),
+            steps: test_spec
+                .all_steps()
+                .map(|step| step.name.clone())
+                .collect(),
+        }
+    }
+}
+
+/// The selected tests of the suite in `dirname` with their matrices expanded,
+/// in the order they would be started: cluster tests first, then as sorted by `schedule`
+/// with the `durations` of the previous run.
+pub async fn list_tests(
+    dirname: &Path,
+    filter: &TestFilter,
+    schedule: Schedule,
+    durations: &HashMap<String, f64>,
+) -> Result<Vec<TestListing>> {
+    let mut skipped = vec![];
+    let test_specs = discover_tests(&dirname.to_path_buf(), filter, &mut skipped).await?;
+    let mut listings = vec![];
+    for test_type in [TestType::Cluster, TestType::User] {
+        let mut tests: Vec<TestSpec> = test_specs
+            .iter()
+            .filter(|test_spec| test_spec.test_type == test_type)
+            .cloned()
+            .flat_map(TestSpec::expand_matrix)
+            .collect();
+        sort_tests(&mut tests, schedule, durations);
+        listings.extend(tests.iter().map(TestListing::from));
+    }
+    Ok(listings)
+}
+
+/// One line per test with its type, ordering, tags and directory, followed by its steps.
+pub fn render_listing(listings: &[TestListing]) -> String {
+    let mut out = String::new();
+    for listing in listings {
+        let test_type = match listing.test_type {
+            TestType::Cluster => "cluster",
+            TestType::User => "user",
+        };
+        let _ = write!(out, "{} ({test_type}", listing.name);
+        if let Some(ordering) = &listing.ordering {
+            let _ = write!(out, ", ordering {ordering}");
+        }
+        if !listing.tags.is_empty() {
+            let _ = write!(out, ", tags {}", listing.tags.join(", "));
+        }
+        let _ = writeln!(out, ") {}", listing.dir.display());
+        for step in &listing.steps {
+            let _ = writeln!(out, "  - {step}");
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_list_tests() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, spec) in [
+            ("b", "ordering: '1'\nsteps:\n  - name: one\n  - name: two\n"),
+            ("a", "ordering: '2'\ntags: [slow]\n"),
+            (
+                "c",
+                "type: cluster\nmatrix:\n  V: ['1', '2']\nsteps:\n  - name: install\n",
+            ),
+        ] {
+            std::fs::create_dir(dir.path().join(name)).unwrap();
+            let spec = format!("name: {name}\n{spec}");
+            std::fs::write(dir.path().join(name).join("test.yaml"), spec).unwrap();
+        }
+        let filter = TestFilter::new(None, vec![], vec![]).unwrap();
+        let listings = list_tests(dir.path(), &filter, Schedule::Naive, &HashMap::new())
+            .await
+            .unwrap();
+        let names: Vec<&str> = listings.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, vec!["c-1", "c-2", "b", "a"]);
+        assert_eq!(listings[2].steps, vec!["one", "two"]);
+        let text = render_listing(&listings);
+        assert!(text.contains("a (user, ordering 2, tags slow)"));
+        assert!(text.contains("  - install\n"));
+
+        let durations = HashMap::from([("c-1".to_string(), 1.0), ("c-2".to_string(), 2.0)]);
+        let listings = list_tests(dir.path(), &filter, Schedule::Lpt, &durations)
+            .await
+            .unwrap();
+        assert_eq!(listings[0].name, "c-2");
+    }
+}
@@ -0,0 +1,94 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::test_spec::{TestSpec, TestType};
+use std::collections::HashMap;
+
+/// Locks required by a test, or `None` if it must run exclusively.
+/// Cluster tests without locks run exclusively,
+/// as they might touch any cluster scoped resource.
+fn required(test_spec: &TestSpec) -> Option<&[String]> {
+    match test_spec.test_type {
+        TestType::Cluster if test_spec.locks.is_empty() => None,
+        _ => Some(&test_spec.locks),
+    }
+}
+
+/// Named locks held by running tests, so that tests sharing a lock are not run concurrently.
+#[derive(Default)]
+pub struct Locks {
+    held: HashMap<String, Option<Vec<String>>>,
+}
+
+impl Locks {
+    pub fn is_free(&self, test_spec: &TestSpec) -> bool {
+        match required(test_spec) {
+            None => self.held.is_empty(),
+            Some(locks) => self.held.values().all(|held| {
+                held.as_ref()
+                    .is_some_and(|held| !held.iter().any(|lock| locks.contains(lock)))
+            }),
+        }
+    }
+
+    pub fn acquire(&mut self, test_spec: &TestSpec) {
+        self.held.insert(
+            test_spec.name.clone(),
+            required(test_spec).map(|locks| locks.to_vec()),
+        );
+    }
+
+    pub fn release(&mut self, test_name: &str) {
+        self.held.remove(test_name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn test_spec(name: &str, test_type: TestType, locks: &[&str]) -> TestSpec {
+        TestSpec {
+            name: name.to_string(),
+            test_type,
+            locks: locks.iter().map(|lock| lock.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[rstest]
+    #[case(vec![], vec!["crd:a"], true)]
+    #[case(vec![vec!["crd:a"]], vec!["crd:b"], true)]
+    #[case(vec![vec!["crd:a"]], vec!["crd:b", "crd:a"], false)]
+    #[case(vec![vec!["crd:a"], vec!["crd:b"]], vec!["crd:c"], true)]
+    #[case(vec![vec!["crd:a"]], vec![], false)]
+    #[case(vec![], vec![], true)]
+    #[case(vec![vec![]], vec!["crd:a"], false)]
+    fn test_cluster_locks(
+        #[case] running: Vec<Vec<&str>>,
+        #[case] locks: Vec<&str>,
+        #[case] expected: bool,
+    ) {
+        let mut held = Locks::default();
+        for (i, running) in running.into_iter().enumerate() {
+            let name = format!("running-{i}");
+            held.acquire(&test_spec(&name, TestType::Cluster, &running));
+        }
+        let candidate = test_spec("candidate", TestType::Cluster, &locks);
+        assert_eq!(held.is_free(&candidate), expected);
+    }
+
+    #[test]
+    fn test_release() {
+        let mut held = Locks::default();
+        let exclusive = test_spec("exclusive", TestType::Cluster, &[]);
+        let user = test_spec("user", TestType::User, &[]);
+        held.acquire(&exclusive);
+        assert!(!held.is_free(&user));
+        held.release("exclusive");
+        assert!(held.is_free(&user));
+        held.acquire(&user);
+        assert!(held.is_free(&test_spec("other", TestType::User, &[])));
+    }
+}
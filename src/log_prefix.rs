@@ -0,0 +1,76 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use env_logger::fmt::Formatter;
+use log::Record;
+use std::future::Future;
+use std::io::Write;
+use tokio::task::JoinHandle;
+
+tokio::task_local! {
+    static PREFIX: String;
+}
+
+/// Runs the future with log lines emitted by it tagged with `prefix`,
+/// e.g. `test/step`, so the output of parallel tests can be told apart.
+pub(crate) async fn scope<F: Future>(prefix: String, future: F) -> F::Output {
+    PREFIX.scope(prefix, future).await
+}
+
+/// Spawns the future as a task which keeps the prefix of the current task.
+pub(crate) fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    match current() {
+        Some(prefix) => tokio::spawn(PREFIX.scope(prefix, future)),
+        None => tokio::spawn(future),
+    }
+}
+
+/// The prefix of log lines emitted by the current task, if any.
+pub fn current() -> Option<String> {
+    PREFIX.try_with(Clone::clone).ok()
+}
+
+/// Formats log lines like the default format of `env_logger`,
+/// with the message tagged as `[test/step]` if emitted inside a test.
+pub fn format(buf: &mut Formatter, record: &Record) -> std::io::Result<()> {
+    let level = record.level();
+    let style = buf.default_level_style(level);
+    write!(buf, "[{style}{level:<5}{style:#}")?;
+    if let Some(module_path) = record.module_path() {
+        write!(buf, " {module_path}")?;
+    }
+    write!(buf, "] ")?;
+    if let Some(prefix) = current() {
+        write!(buf, "[{prefix}] ")?;
+    }
+    writeln!(buf, "{}", record.args())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_current() {
+        assert_eq!(current(), None);
+        let prefixes = scope("test".to_string(), async {
+            let outer = current();
+            let inner = scope("test/step".to_string(), async { current() }).await;
+            let spawned = spawn(async { current() }).await.unwrap();
+            (outer, inner, spawned)
+        })
+        .await;
+        assert_eq!(
+            prefixes,
+            (
+                Some("test".to_string()),
+                Some("test/step".to_string()),
+                Some("test".to_string())
+            )
+        );
+    }
+}
@@ -0,0 +1,201 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::config::Config;
+use crate::error::{AssertDiagnostic, Error, Result, TestFailure, TestFailures};
+use crate::test_spec::{Expr, LogsSpec, WaitSpec};
+use chrono::{DateTime, Utc};
+use futures::{AsyncBufReadExt, TryStreamExt};
+use k8s_openapi::api::core::v1::Pod;
+use kube::api::{Api, ListParams, LogParams};
+use kube::{Client, ResourceExt};
+use regex::Regex;
+use serde_json::json;
+use std::collections::BTreeSet;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio::time::{sleep, Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+fn logs_error(err: impl std::fmt::Display) -> Error {
+    Error::LogsError(err.to_string())
+}
+
+fn label_selector(spec: &LogsSpec) -> String {
+    spec.labels
+        .iter()
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Containers of the pod whose logs can be followed, i.e. that have started.
+fn started_containers(pod: &Pod, container: &Option<String>) -> Vec<String> {
+    let statuses = pod
+        .status
+        .as_ref()
+        .and_then(|status| status.container_statuses.as_ref());
+    statuses
+        .into_iter()
+        .flatten()
+        .filter(|status| container.as_ref().is_none_or(|c| *c == status.name))
+        .filter(|status| {
+            status
+                .state
+                .as_ref()
+                .is_some_and(|state| state.running.is_some() || state.terminated.is_some())
+        })
+        .map(|status| status.name.clone())
+        .collect()
+}
+
+/// Follows the log of a container, sending the first line matching `regex`.
+async fn follow(
+    api: Api<Pod>,
+    pod: String,
+    params: LogParams,
+    regex: Regex,
+    sender: mpsc::Sender<String>,
+) -> Result<()> {
+    let mut lines = api.log_stream(&pod, &params).await?.lines();
+    while let Some(line) = lines.try_next().await.map_err(logs_error)? {
+        if regex.is_match(&line) {
+            let _ = sender.send(line).await;
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Follows the logs of all matching pods until a line matches or the timeout is reached.
+/// Pods are looked up repeatedly, so pods started while waiting are followed as well.
+async fn wait_for_line(
+    client: Client,
+    config: &Config,
+    spec: &LogsSpec,
+    since: DateTime<Utc>,
+) -> Result<std::result::Result<(), AssertDiagnostic>> {
+    let regex = Regex::new(&spec.regex).map_err(logs_error)?;
+    let api: Api<Pod> = Api::namespaced(client, &spec.namespace);
+    let list_params = ListParams::default().labels(&label_selector(spec));
+    let deadline = Instant::now() + config.scaled_timeout(spec.timeout);
+    let (sender, mut receiver) = mpsc::channel(1);
+    let mut followed: BTreeSet<(String, String)> = BTreeSet::new();
+    let mut tasks = JoinSet::new();
+    while Instant::now() < deadline {
+        // Failing to list the pods, e.g. on a transient API error, is retried until the deadline.
+        let pods = match api.list(&list_params).await {
+            Ok(pods) => pods.items,
+            Err(err) => {
+                log::debug!("Unable to list pods in '{}': {err}", spec.namespace);
+                vec![]
+            }
+        };
+        for pod in pods {
+            for container in started_containers(&pod, &spec.container) {
+                if !followed.insert((pod.name_any(), container.clone())) {
+                    continue;
+                }
+                log::debug!("Following logs of {}/{container}", pod.name_any());
+                let params = LogParams {
+                    container: Some(container.clone()),
+                    follow: true,
+                    since_time: Some(since),
+                    ..Default::default()
+                };
+                let follow = follow(
+                    api.clone(),
+                    pod.name_any(),
+                    params,
+                    regex.clone(),
+                    sender.clone(),
+                );
+                let key = (pod.name_any(), container);
+                tasks.spawn(async move { (key, follow.await) });
+            }
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        tokio::select! {
+            Some(line) = receiver.recv() => {
+                log::debug!("Log line matched: {line}");
+                return Ok(Ok(()));
+            }
+            _ = sleep(POLL_INTERVAL.min(remaining)) => {}
+        }
+        // Logs that ended, e.g. because the container restarted, are followed again.
+        while let Some(Ok((key, result))) = tasks.try_join_next() {
+            if let Err(err) = result {
+                log::debug!("Error following logs of {}/{}: {err}", key.0, key.1);
+            }
+            followed.remove(&key);
+        }
+    }
+    let containers: Vec<String> = followed
+        .iter()
+        .map(|(pod, container)| format!("{pod}/{container}"))
+        .collect();
+    Ok(Err(AssertDiagnostic {
+        expr: Expr::AndExpr { and: vec![] },
+        input: vec![json!({
+            "namespace": spec.namespace,
+            "labels": spec.labels,
+            "regex": spec.regex,
+            "containers": containers,
+        })],
+        diff: vec![],
+    }))
+}
+
+/// Waits for a line of the logs to match for every spec, considering only lines
+/// logged since `since`, usually the start of the step.
+pub async fn wait_for_logs(
+    client: Client,
+    config: &Config,
+    specs: Vec<LogsSpec>,
+    since: DateTime<Utc>,
+) -> Result<()> {
+    log::debug!("Waiting for {} log lines", specs.len());
+    let mut errors: Vec<TestFailure> = Vec::new();
+    for spec in specs {
+        if let Err(assert_diagnostic) = wait_for_line(client.clone(), config, &spec, since).await? {
+            let target = format!("logs:{}/{}", spec.namespace, label_selector(&spec));
+            let spec = WaitSpec::synthetic(target, spec.timeout);
+            errors.push(TestFailure::new(spec, assert_diagnostic));
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::ConditionsFailed(TestFailures(errors)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(None, vec!["manager", "done"])]
+    #[case(Some("manager"), vec!["manager"])]
+    #[case(Some("sidecar"), vec![])]
+    fn test_started_containers(#[case] container: Option<&str>, #[case] expected: Vec<&str>) {
+        let pod: Pod = serde_json::from_value(json!({
+            "metadata": {"name": "operator"},
+            "status": {"containerStatuses": [
+                {"name": "manager", "image": "operator", "imageID": "", "ready": true,
+                 "restartCount": 0, "state": {"running": {}}},
+                {"name": "sidecar", "image": "proxy", "imageID": "", "ready": false,
+                 "restartCount": 0, "state": {"waiting": {"reason": "ContainerCreating"}}},
+                {"name": "done", "image": "init", "imageID": "", "ready": false,
+                 "restartCount": 0, "state": {"terminated": {"exitCode": 0}}},
+            ]},
+        }))
+        .unwrap();
+        assert_eq!(
+            started_containers(&pod, &container.map(str::to_string)),
+            expected
+        );
+    }
+}
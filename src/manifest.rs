@@ -1,34 +1,133 @@
 // Copyright 2024 Ole Kliemann
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::audit::Audit;
+use crate::config::Config;
+use crate::discovery;
 use crate::error::{Error, Result};
 use crate::file::read_yaml_files;
-use crate::test_spec::ApplySpec;
+use crate::pacing;
+use crate::test_spec::{ApplyRateSpec, ApplySpec};
+use crate::warnings::{self, ApiWarning};
+use http_body_util::BodyExt;
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
 use kube::api::{Api, DeleteParams, DynamicObject, Patch, PatchParams};
-use kube::core::discovery::Scope;
-use kube::{core::GroupVersionKind, Client, ResourceExt};
+use kube::client::Body;
+use kube::core::discovery::{ApiResource, Scope};
+use kube::core::ErrorResponse;
+use kube::runtime::wait::{await_condition, conditions::is_crd_established};
+use kube::{core::GroupVersionKind, Client, Discovery, ResourceExt};
 use serde::Deserialize;
 use serde_yaml::Value;
 use std::path::PathBuf;
 use tokio::fs;
-use kube::Discovery;
+use tokio::time::{sleep, Duration};
 
+const ESTABLISHED_TIMEOUT: u16 = 60;
+
+fn manifest_error(err: impl std::fmt::Display) -> Error {
+    Error::ManifestError(err.to_string())
+}
+
+fn is_crd(dynamic_obj: &DynamicObject) -> bool {
+    dynamic_obj.types.as_ref().is_some_and(|types| {
+        types.kind == "CustomResourceDefinition"
+            && types.api_version.starts_with("apiextensions.k8s.io/")
+    })
+}
+
+/// Applies the object like `Api::patch`, additionally returning the texts of the
+/// `Warning` headers of the response, which `Api` does not expose.
+async fn apply_object(
+    api: &Api<DynamicObject>,
+    dynamic_obj: &DynamicObject,
+    patch_params: &PatchParams,
+) -> kube::Result<Vec<String>> {
+    let request = kube::core::Request::new(api.resource_url())
+        .patch(
+            &dynamic_obj.name_any(),
+            patch_params,
+            &Patch::Apply(dynamic_obj),
+        )
+        .map_err(kube::Error::BuildRequest)?;
+    let response = Client::from(api.clone())
+        .send(request.map(Body::from))
+        .await?;
+    let warnings = response
+        .headers()
+        .get_all(http::header::WARNING)
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .map(warnings::parse_header)
+        .collect();
+    let status = response.status();
+    if status.is_client_error() || status.is_server_error() {
+        let body = response.into_body().collect().await?.to_bytes();
+        let text = String::from_utf8_lossy(&body);
+        let error = serde_json::from_str(&text).unwrap_or_else(|_| ErrorResponse {
+            status: status.to_string(),
+            message: text.to_string(),
+            reason: "Failed to parse error data".to_string(),
+            code: status.as_u16(),
+        });
+        return Err(kube::Error::Api(error));
+    }
+    Ok(warnings)
+}
+
+/// Kinds of the versions a CRD serves.
+fn served_kinds(crd: &CustomResourceDefinition) -> Vec<GroupVersionKind> {
+    crd.spec
+        .versions
+        .iter()
+        .filter(|version| version.served)
+        .map(|version| GroupVersionKind::gvk(&crd.spec.group, &version.name, &crd.spec.names.kind))
+        .collect()
+}
+
+/// An object of a manifest and where it is applied.
 #[derive(Debug)]
+struct Resource {
+    api_resource: ApiResource,
+    /// Namespace the object is applied in, `None` if it is cluster-scoped.
+    namespace: Option<String>,
+    object: DynamicObject,
+}
+
+impl Resource {
+    fn api(&self, client: Client) -> Api<DynamicObject> {
+        match &self.namespace {
+            Some(namespace) => Api::namespaced_with(client, namespace, &self.api_resource),
+            None => Api::all_with(client, &self.api_resource),
+        }
+    }
+}
+
 pub struct ManifestHandle {
-    resources: Vec<(Api<DynamicObject>, DynamicObject)>,
+    client: Client,
+    cleanup_client: Client,
+    resources: Vec<Resource>,
+    preserve: bool,
+    wait_established: bool,
+    apply_rate: Option<ApplyRateSpec>,
 }
 
 impl ManifestHandle {
     pub async fn new(spec: ApplySpec, wd: PathBuf, client: Client) -> Result<Self> {
         let path = wd.join(spec.path);
         let namespace = spec.override_namespace.then_some(spec.namespace);
-        if path.is_file() {
+        let handle = if path.is_file() {
             ManifestHandle::new_from_file(client, path, namespace).await
         } else if path.is_dir() {
             ManifestHandle::new_from_dir(client, path, namespace).await
         } else {
             Err(Error::PathError(path))
-        }
+        }?;
+        Ok(ManifestHandle {
+            preserve: spec.preserve,
+            wait_established: spec.wait_established,
+            ..handle
+        })
     }
 
     async fn new_from_data(
@@ -37,45 +136,46 @@ impl ManifestHandle {
         namespace_override: Option<String>,
     ) -> Result<Self> {
         let mut resources = Vec::new();
-        let discovery = Discovery::new(client.clone()).run().await?;
-        for document in serde_yaml::Deserializer::from_str(&yaml_str) {
-            let yaml_value: Value = Value::deserialize(document)?;
-            let mut dynamic_obj: DynamicObject = serde_yaml::from_value(yaml_value)?;
+        let dynamic_objs = serde_yaml::Deserializer::from_str(&yaml_str)
+            .map(|document| Ok(serde_yaml::from_value(Value::deserialize(document)?)?))
+            .collect::<Result<Vec<DynamicObject>>>()?;
+        for mut dynamic_obj in dynamic_objs {
             let gvk = GroupVersionKind::try_from(dynamic_obj.types.clone().unwrap_or_default())?;
 
             if namespace_override.is_some() && gvk.kind == "Namespace" {
                 continue;
             }
 
-            let (ar, caps) = discovery
-                .resolve_gvk(&gvk)
-                .ok_or_else(|| Error::DiscoveryError(gvk))?;
+            let (ar, caps) = discovery::resolve(&client, &gvk).await?;
 
-            resources.push(match caps.scope {
+            let namespace = match caps.scope {
                 Scope::Namespaced => {
                     if let Some(ref ns) = namespace_override {
                         dynamic_obj.metadata.namespace = Some(ns.clone());
                     }
-                    let namespace = dynamic_obj
+                    dynamic_obj
                         .metadata
                         .namespace
                         .clone()
                         .or_else(|| Some("default".to_string()))
-                        .unwrap();
-
-                    (
-                        Api::<DynamicObject>::namespaced_with(client.clone(), &namespace, &ar),
-                        dynamic_obj,
-                    )
                 }
-                Scope::Cluster => (
-                    Api::<DynamicObject>::all_with(client.clone(), &ar),
-                    dynamic_obj,
-                ),
+                Scope::Cluster => None,
+            };
+            resources.push(Resource {
+                api_resource: ar,
+                namespace,
+                object: dynamic_obj,
             });
         }
 
-        Ok(ManifestHandle { resources })
+        Ok(ManifestHandle {
+            client: client.clone(),
+            cleanup_client: client,
+            resources,
+            preserve: false,
+            wait_established: false,
+            apply_rate: None,
+        })
     }
 
     async fn new_from_file(
@@ -103,8 +203,43 @@ impl ManifestHandle {
         ManifestHandle::new_from_data(client, manifest_data, namespace_override).await
     }
 
-    pub async fn apply(&self) -> Result<()> {
-        for (api, dynamic_obj) in &self.resources {
+    /// Records the origin of the objects in their labels and annotations.
+    pub fn with_audit(mut self, audit: &Audit) -> Self {
+        for resource in &mut self.resources {
+            audit.mark(&mut resource.object);
+        }
+        self
+    }
+
+    /// Paces the objects applied per namespace.
+    pub fn with_apply_rate(self, apply_rate: Option<ApplyRateSpec>) -> Self {
+        ManifestHandle { apply_rate, ..self }
+    }
+
+    /// Deletes the resources with `client` instead of the client they are applied with,
+    /// e.g. without the faults injected into the requests of the steps of a test.
+    pub fn with_cleanup_client(self, cleanup_client: Client) -> Self {
+        ManifestHandle {
+            cleanup_client,
+            ..self
+        }
+    }
+
+    /// Whether the resources are kept when the test is cleaned up.
+    pub fn preserve(&self) -> bool {
+        self.preserve
+    }
+
+    pub fn objects(&self) -> impl Iterator<Item = &DynamicObject> {
+        self.resources.iter().map(|resource| &resource.object)
+    }
+
+    /// Applies the resources, with `force` taking over fields owned by other field managers.
+    /// Returns the warnings of the API server.
+    pub async fn apply(&self, force: bool) -> Result<Vec<ApiWarning>> {
+        let mut warnings = vec![];
+        for resource in &self.resources {
+            let dynamic_obj = &resource.object;
             log::debug!("applying: {dynamic_obj:?}");
             let kind = dynamic_obj.types.clone().unwrap_or_default().kind;
             let name = dynamic_obj.name_any();
@@ -117,23 +252,81 @@ impl ManifestHandle {
                 namespace
             );
 
-            let patch_params = PatchParams::apply("blackjack").force();
-            let patch = Patch::Apply(dynamic_obj);
-            let result = api
-                .patch(&dynamic_obj.name_any(), &patch_params, &patch)
-                .await;
+            let patch_params = if force {
+                PatchParams::apply("blackjack").force()
+            } else {
+                PatchParams::apply("blackjack")
+            };
+            if let Some(apply_rate) = &self.apply_rate {
+                pacing::acquire(&namespace, apply_rate).await;
+            }
+            let api = resource.api(self.client.clone());
+            let result = apply_object(&api, dynamic_obj, &patch_params).await;
             if result.is_err() {
                 log::error!("{result:?}");
-                return Err(Error::KubeError(result.unwrap_err()));
+                return Err(result.unwrap_err().into());
             }
+            warnings.extend(result.unwrap().into_iter().map(|text| ApiWarning {
+                object: format!("{kind}/{name}"),
+                text,
+            }));
         }
+        self.invalidate_discovery().await;
+
+        Ok(warnings)
+    }
 
+    /// Invalidates the shared discovery if the manifests hold CRDs, whose kinds
+    /// are added or removed by applying or deleting them.
+    async fn invalidate_discovery(&self) {
+        if self.objects().any(is_crd) {
+            discovery::invalidate().await;
+        }
+    }
+
+    /// With `wait-established`, waits for the applied CRDs to be established and
+    /// their kinds to be resolved by discovery of their group, then invalidates the shared
+    /// discovery, which later manifests are created with.
+    pub async fn wait_established(&self, client: Client, config: &Config) -> Result<()> {
+        if !self.wait_established {
+            return Ok(());
+        }
+        let crds: Api<CustomResourceDefinition> = Api::all(client.clone());
+        let names = self
+            .objects()
+            .filter(|obj| is_crd(obj))
+            .map(|obj| obj.name_any());
+        for name in names {
+            log::debug!("Waiting for CRD {name} to be established");
+            let wait = async {
+                await_condition(crds.clone(), &name, is_crd_established())
+                    .await
+                    .map_err(manifest_error)?;
+                let crd = crds.get(&name).await?;
+                let kinds = served_kinds(&crd);
+                loop {
+                    let discovery = Discovery::new(client.clone())
+                        .filter(&[&crd.spec.group])
+                        .run()
+                        .await?;
+                    if kinds.iter().all(|gvk| discovery.resolve_gvk(gvk).is_some()) {
+                        return Result::Ok(());
+                    }
+                    sleep(Duration::from_secs(1)).await;
+                }
+            };
+            tokio::time::timeout(config.scaled_timeout(ESTABLISHED_TIMEOUT), wait)
+                .await
+                .map_err(|_| manifest_error(format!("CRD '{name}' was not established")))??;
+        }
+        discovery::invalidate().await;
         Ok(())
     }
 
     pub async fn delete(&self) -> Result<()> {
         log::debug!("manifest.delete");
-        for (api, dynamic_obj) in &self.resources {
+        for resource in &self.resources {
+            let dynamic_obj = &resource.object;
             let kind = dynamic_obj.types.clone().unwrap_or_default().kind;
             let name = dynamic_obj.name_any();
             let namespace = dynamic_obj.namespace().unwrap_or_default();
@@ -146,13 +339,61 @@ impl ManifestHandle {
             );
 
             let delete_params = DeleteParams::default();
+            let api = resource.api(self.cleanup_client.clone());
             match api.delete(&dynamic_obj.name_any(), &delete_params).await {
                 Ok(_) => {}
                 Err(kube::Error::Api(ae)) if ae.code == 404 => {}
                 Err(e) => return Err(Error::from(e)),
             }
         }
+        self.invalidate_discovery().await;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serde_json::json;
+
+    #[rstest]
+    #[case("apiextensions.k8s.io/v1", "CustomResourceDefinition", true)]
+    #[case("example.com/v1", "CustomResourceDefinition", false)]
+    #[case("apiextensions.k8s.io/v1", "Widget", false)]
+    fn test_is_crd(#[case] api_version: &str, #[case] kind: &str, #[case] expected: bool) {
+        let dynamic_obj: DynamicObject = serde_json::from_value(json!({
+            "apiVersion": api_version,
+            "kind": kind,
+            "metadata": {"name": "widgets.example.com"},
+        }))
+        .unwrap();
+        assert_eq!(is_crd(&dynamic_obj), expected);
+    }
+
+    #[test]
+    fn test_served_kinds() {
+        let crd: CustomResourceDefinition = serde_json::from_value(json!({
+            "metadata": {"name": "widgets.example.com"},
+            "spec": {
+                "group": "example.com",
+                "names": {"kind": "Widget", "plural": "widgets"},
+                "scope": "Namespaced",
+                "versions": [
+                    {"name": "v1", "served": true, "storage": true},
+                    {"name": "v1alpha1", "served": false, "storage": false},
+                    {"name": "v1beta1", "served": true, "storage": false},
+                ],
+            },
+        }))
+        .unwrap();
+        assert_eq!(
+            served_kinds(&crd),
+            vec![
+                GroupVersionKind::gvk("example.com", "v1", "Widget"),
+                GroupVersionKind::gvk("example.com", "v1beta1", "Widget"),
+            ]
+        );
+    }
+}
@@ -1,15 +1,106 @@
 // Copyright 2024 Ole Kliemann
 // SPDX-License-Identifier: Apache-2.0
 
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+/// Resolves a dotted path like `.status.conditions.0.type` within `input`.
+pub fn get_path<'a>(input: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .filter(|segment| !segment.is_empty())
+        .try_fold(input, |value, segment| match value {
+            Value::Array(arr) => segment.parse::<usize>().ok().and_then(|i| arr.get(i)),
+            _ => value.get(segment),
+        })
+}
+
 pub fn contains(input: &Value, compare: &Value) -> bool {
+    contains_with(input, compare, &|input, compare| input == compare)
+}
+
+/// Like `contains`, but compares scalars with `coerced_eq`.
+pub fn contains_coerced(input: &Value, compare: &Value) -> bool {
+    contains_with(input, compare, &coerced_eq)
+}
+
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn as_bool(value: &Value) -> Option<bool> {
+    match value {
+        Value::Bool(b) => Some(*b),
+        Value::String(s) if s.eq_ignore_ascii_case("true") => Some(true),
+        Value::String(s) if s.eq_ignore_ascii_case("false") => Some(false),
+        _ => None,
+    }
+}
+
+/// Compares scalars by their meaning rather than their JSON type,
+/// e.g. `"3"` equals `3` and `"True"` equals `true` and `"true"`.
+pub fn coerced_eq(input: &Value, compare: &Value) -> bool {
+    if input == compare {
+        return true;
+    }
+    match (as_number(input), as_number(compare)) {
+        (Some(input), Some(compare)) => input == compare,
+        _ => matches!((as_bool(input), as_bool(compare)), (Some(a), Some(b)) if a == b),
+    }
+}
+
+/// The elements of an `$inOrder` pattern, `{"$inOrder": [...]}`, which need to be
+/// contained in distinct elements of an array in the given order.
+pub fn in_order(compare: &Value) -> Option<&Vec<Value>> {
+    match compare.as_object() {
+        Some(map) if map.len() == 1 => map.get("$inOrder").and_then(Value::as_array),
+        _ => None,
+    }
+}
+
+/// The value of an `$exact` pattern, `{"$exact": ...}`, which needs to equal the
+/// compared value without further fields or elements.
+pub fn exact(compare: &Value) -> Option<&Value> {
+    match compare.as_object() {
+        Some(map) if map.len() == 1 => map.get("$exact"),
+        _ => None,
+    }
+}
+
+fn equals_with(input: &Value, compare: &Value, eq: &dyn Fn(&Value, &Value) -> bool) -> bool {
+    match (input, compare) {
+        (Value::Object(map_input), Value::Object(map_compare)) => {
+            map_input.len() == map_compare.len()
+                && map_compare.iter().all(|(key, val_compare)| {
+                    map_input
+                        .get(key)
+                        .is_some_and(|val_input| equals_with(val_input, val_compare, eq))
+                })
+        }
+        (Value::Array(arr_input), Value::Array(arr_compare)) => {
+            arr_input.len() == arr_compare.len()
+                && arr_input
+                    .iter()
+                    .zip(arr_compare)
+                    .all(|(val_input, val_compare)| equals_with(val_input, val_compare, eq))
+        }
+        _ => eq(input, compare),
+    }
+}
+
+fn contains_with(input: &Value, compare: &Value, eq: &dyn Fn(&Value, &Value) -> bool) -> bool {
+    if let Some(compare) = exact(compare) {
+        return equals_with(input, compare, eq);
+    }
     match (input, compare) {
         (Value::Object(map_input), Value::Object(map_compare)) => {
             for (key, val_compare) in map_compare {
                 match map_input.get(key) {
                     Some(val_input) => {
-                        if !contains(val_input, val_compare) {
+                        if !contains_with(val_input, val_compare, eq) {
                             return false;
                         }
                     }
@@ -22,7 +113,7 @@ pub fn contains(input: &Value, compare: &Value) -> bool {
             for val_compare in arr_compare {
                 let mut found = false;
                 for val_input in arr_input {
-                    if contains(val_input, val_compare) {
+                    if contains_with(val_input, val_compare, eq) {
                         found = true;
                         break;
                     }
@@ -33,7 +124,123 @@ pub fn contains(input: &Value, compare: &Value) -> bool {
             }
             true
         }
-        _ => input == compare,
+        (Value::Array(arr_input), Value::Object(_)) => match in_order(compare) {
+            Some(arr_compare) => {
+                let mut arr_input = arr_input.iter();
+                arr_compare.iter().all(|val_compare| {
+                    arr_input.any(|val_input| contains_with(val_input, val_compare, eq))
+                })
+            }
+            None => false,
+        },
+        _ => eq(input, compare),
+    }
+}
+
+/// A field in which a value differs from a pattern, at a dotted path like `.status.phase`.
+/// `actual` is `None` if the field is missing.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Difference {
+    pub path: String,
+    pub expected: Value,
+    pub actual: Option<Value>,
+}
+
+impl std::fmt::Display for Difference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.actual {
+            Some(actual) => write!(
+                f,
+                "{}: expected {}, found {actual}",
+                self.path, self.expected
+            ),
+            None => write!(f, "{}: expected {}, missing", self.path, self.expected),
+        }
+    }
+}
+
+/// The fields in which `input` does not contain `compare`, empty if it does.
+pub fn diff(input: &Value, compare: &Value) -> Vec<Difference> {
+    let mut differences = vec![];
+    diff_with(
+        input,
+        compare,
+        &|input, compare| input == compare,
+        "",
+        &mut differences,
+    );
+    differences
+}
+
+/// Like `diff`, but compares scalars with `coerced_eq`.
+pub fn diff_coerced(input: &Value, compare: &Value) -> Vec<Difference> {
+    let mut differences = vec![];
+    diff_with(input, compare, &coerced_eq, "", &mut differences);
+    differences
+}
+
+fn diff_with(
+    input: &Value,
+    compare: &Value,
+    eq: &dyn Fn(&Value, &Value) -> bool,
+    path: &str,
+    differences: &mut Vec<Difference>,
+) {
+    if contains_with(input, compare, eq) {
+        return;
+    }
+    match (input, compare) {
+        (Value::Object(map_input), Value::Object(map_compare)) if exact(compare).is_none() => {
+            for (key, val_compare) in map_compare {
+                let path = format!("{path}.{key}");
+                match map_input.get(key) {
+                    Some(val_input) => diff_with(val_input, val_compare, eq, &path, differences),
+                    None => differences.push(Difference {
+                        path,
+                        expected: val_compare.clone(),
+                        actual: None,
+                    }),
+                }
+            }
+        }
+        (Value::Array(arr_input), Value::Array(arr_compare)) => {
+            for val_compare in arr_compare {
+                if arr_input
+                    .iter()
+                    .any(|val_input| contains_with(val_input, val_compare, eq))
+                {
+                    continue;
+                }
+                // Objects are compared to the closest element, anything else to the whole array.
+                let closest = arr_input
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, val_input)| val_input.is_object() && val_compare.is_object())
+                    .map(|(i, val_input)| {
+                        let mut element_differences = vec![];
+                        let path = format!("{path}.{i}");
+                        diff_with(val_input, val_compare, eq, &path, &mut element_differences);
+                        element_differences
+                    })
+                    .min_by_key(Vec::len);
+                match closest {
+                    Some(element_differences) => differences.extend(element_differences),
+                    None => differences.push(Difference {
+                        path: path.to_string(),
+                        expected: val_compare.clone(),
+                        actual: Some(input.clone()),
+                    }),
+                }
+            }
+        }
+        _ => differences.push(Difference {
+            path: match path {
+                "" => ".".to_string(),
+                path => path.to_string(),
+            },
+            expected: compare.clone(),
+            actual: Some(input.clone()),
+        }),
     }
 }
 
@@ -270,4 +477,135 @@ mod tests {
         let result = contains(&input, &compare);
         assert_eq!(result, expected);
     }
+
+    #[rstest]
+    #[case(json!({"$inOrder": [{"name": "a"}, {"name": "c"}]}), true)]
+    #[case(json!({"$inOrder": [{"name": "c"}, {"name": "a"}]}), false)]
+    #[case(json!({"$inOrder": [{"name": "a"}, {"name": "a"}]}), false)]
+    #[case(json!({"$inOrder": ["b", {"name": "c"}]}), false)]
+    #[case(json!({"$inOrder": []}), true)]
+    #[case(json!({"$inOrder": [{"name": "a"}], "other": []}), false)]
+    fn test_in_order(#[case] compare: Value, #[case] expected: bool) {
+        let input =
+            json!({"spec": {"initContainers": [{"name": "a"}, {"name": "b"}, {"name": "c"}]}});
+        let compare = json!({"spec": {"initContainers": compare}});
+        assert_eq!(contains(&input, &compare), expected);
+    }
+
+    #[rstest]
+    #[case(json!({"$exact": {"app": "web", "tier": "frontend"}}), true)]
+    #[case(json!({"$exact": {"app": "web"}}), false)]
+    #[case(json!({"$exact": {"app": "web", "tier": "frontend", "env": "prod"}}), false)]
+    #[case(json!({"app": {"$exact": "web"}}), true)]
+    #[case(json!({"$exact": {"app": "web", "tier": {"$exact": "frontend"}}}), false)]
+    fn test_exact_object(#[case] compare: Value, #[case] expected: bool) {
+        let input = json!({"metadata": {"labels": {"app": "web", "tier": "frontend"}}});
+        let compare = json!({"metadata": {"labels": compare}});
+        assert_eq!(contains(&input, &compare), expected);
+    }
+
+    #[rstest]
+    #[case(json!({"$exact": ["a", "b"]}), true)]
+    #[case(json!({"$exact": ["b", "a"]}), false)]
+    #[case(json!({"$exact": ["a"]}), false)]
+    #[case(json!(["a"]), true)]
+    fn test_exact_array(#[case] compare: Value, #[case] expected: bool) {
+        let input = json!({"spec": {"finalizers": ["a", "b"]}});
+        let compare = json!({"spec": {"finalizers": compare}});
+        assert_eq!(contains(&input, &compare), expected);
+    }
+
+    #[rstest]
+    #[case(json!({"exact": true}), true)]
+    #[case(json!({"inOrder": ["a"]}), true)]
+    #[case(json!({"exact": false}), false)]
+    fn test_operator_names_as_fields(#[case] compare: Value, #[case] expected: bool) {
+        let input = json!({"spec": {"exact": true, "inOrder": ["b", "a"]}});
+        let compare = json!({"spec": compare});
+        assert_eq!(contains(&input, &compare), expected);
+    }
+
+    #[rstest]
+    #[case(json!("3"), json!(3), true)]
+    #[case(json!(3.0), json!(" 3 "), true)]
+    #[case(json!("3"), json!(4), false)]
+    #[case(json!("True"), json!(true), true)]
+    #[case(json!("true"), json!("TRUE"), true)]
+    #[case(json!(false), json!("true"), false)]
+    #[case(json!(1), json!(true), false)]
+    #[case(json!(null), json!("null"), false)]
+    fn test_coerced_eq(#[case] input: Value, #[case] compare: Value, #[case] expected: bool) {
+        assert_eq!(coerced_eq(&input, &compare), expected);
+    }
+
+    #[rstest]
+    #[case(json!({"spec": {"replicas": 3, "ready": "True"}}), json!({"spec": {"replicas": "3", "ready": true}}), true)]
+    #[case(json!({"spec": {"ports": [80, 443]}}), json!({"spec": {"ports": ["443"]}}), true)]
+    #[case(json!({"spec": {"replicas": 3}}), json!({"spec": {"replicas": "4"}}), false)]
+    fn test_contains_coerced(#[case] input: Value, #[case] compare: Value, #[case] expected: bool) {
+        assert_eq!(contains_coerced(&input, &compare), expected);
+        assert!(!contains(&input, &compare));
+    }
+
+    #[rstest]
+    #[case(json!({"status": {"phase": "Running"}}), ".status.phase", Some(json!("Running")))]
+    #[case(json!({"status": {"phase": "Running"}}), "status", Some(json!({"phase": "Running"})))]
+    #[case(json!({"a": [{"b": 1}, {"b": 2}]}), ".a.1.b", Some(json!(2)))]
+    #[case(json!({"a": [{"b": 1}]}), ".a.1.b", None)]
+    #[case(json!({"a": 1}), ".b", None)]
+    #[case(json!({"a": 1}), ".", Some(json!({"a": 1})))]
+    fn test_get_path(#[case] input: Value, #[case] path: &str, #[case] expected: Option<Value>) {
+        assert_eq!(get_path(&input, path), expected.as_ref());
+    }
+
+    #[rstest]
+    #[case(json!({"a": 1, "b": 2}), json!({"a": 1}), vec![])]
+    #[case(
+        json!({"status": {"phase": "Pending"}}),
+        json!({"status": {"phase": "Running"}}),
+        vec![(".status.phase", json!("Running"), Some(json!("Pending")))]
+    )]
+    #[case(
+        json!({"metadata": {"labels": {}}}),
+        json!({"metadata": {"labels": {"app": "web"}}}),
+        vec![(".metadata.labels.app", json!("web"), None)]
+    )]
+    #[case(
+        json!({"spec": {"containers": [{"name": "a", "image": "x:1"}, {"name": "b", "image": "y:1"}]}}),
+        json!({"spec": {"containers": [{"name": "b", "image": "y:2"}]}}),
+        vec![(".spec.containers.1.image", json!("y:2"), Some(json!("y:1")))]
+    )]
+    #[case(
+        json!({"ports": [80]}),
+        json!({"ports": [443]}),
+        vec![(".ports", json!(443), Some(json!([80])))]
+    )]
+    #[case(
+        json!({"a": {"b": 1, "c": 2}}),
+        json!({"a": {"$exact": {"b": 1}}}),
+        vec![(".a", json!({"$exact": {"b": 1}}), Some(json!({"b": 1, "c": 2})))]
+    )]
+    #[case(json!(1), json!(2), vec![(".", json!(2), Some(json!(1)))])]
+    fn test_diff(
+        #[case] input: Value,
+        #[case] compare: Value,
+        #[case] expected: Vec<(&str, Value, Option<Value>)>,
+    ) {
+        let expected: Vec<Difference> = expected
+            .into_iter()
+            .map(|(path, expected, actual)| Difference {
+                path: path.to_string(),
+                expected,
+                actual,
+            })
+            .collect();
+        assert_eq!(diff(&input, &compare), expected);
+    }
+
+    #[test]
+    fn test_diff_coerced() {
+        let input = json!({"spec": {"replicas": "3"}});
+        assert!(diff_coerced(&input, &json!({"spec": {"replicas": 3}})).is_empty());
+        assert_eq!(diff(&input, &json!({"spec": {"replicas": 3}})).len(), 1);
+    }
 }
@@ -0,0 +1,296 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::check::{assert_expr, CheckOptions, History};
+use crate::config::Config;
+use crate::error::{AssertDiagnostic, Error, Result, TestFailure, TestFailures};
+use crate::test_spec::{subst_process_env, MessageSpec, WaitSpec};
+#[cfg(feature = "nats")]
+use futures::StreamExt;
+#[cfg(feature = "kafka")]
+use rskafka::client::partition::{OffsetAt, UnknownTopicHandling};
+#[cfg(feature = "kafka")]
+use rskafka::client::ClientBuilder;
+use serde_json::Value;
+#[cfg(any(feature = "kafka", feature = "nats"))]
+use serde_json::{json, Map};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::task::JoinSet;
+use tokio::time::{sleep, Duration, Instant};
+
+type Messages = Arc<Mutex<Vec<Value>>>;
+
+/// Messages received for a `MessageSpec`, collected in the background until dropped.
+pub struct Subscription {
+    spec: MessageSpec,
+    messages: Messages,
+    _tasks: JoinSet<()>,
+}
+
+fn messaging_error(err: impl std::fmt::Display) -> Error {
+    Error::MessagingError(err.to_string())
+}
+
+/// The payload as JSON if it can be parsed, or as string otherwise.
+pub(crate) fn payload(bytes: &[u8]) -> Value {
+    serde_json::from_slice(bytes)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(bytes).into_owned()))
+}
+
+/// Description of the source of the messages, used as target in diagnostics.
+fn source(spec: &MessageSpec) -> String {
+    match (&spec.kafka, &spec.nats) {
+        (Some(kafka), _) => format!("kafka:{}", kafka.topic),
+        (_, Some(nats)) => format!("nats:{}", nats.subject),
+        _ => String::new(),
+    }
+}
+
+#[cfg(feature = "kafka")]
+async fn subscribe_kafka(
+    brokers: String,
+    topic: String,
+    messages: Messages,
+    tasks: &mut JoinSet<()>,
+) -> Result<()> {
+    let brokers = brokers.split(',').map(|b| b.trim().to_string()).collect();
+    let client = ClientBuilder::new(brokers)
+        .build()
+        .await
+        .map_err(messaging_error)?;
+    let partitions = client
+        .list_topics()
+        .await
+        .map_err(messaging_error)?
+        .into_iter()
+        .find(|t| t.name == topic)
+        .ok_or_else(|| messaging_error(format!("unknown topic '{topic}'")))?
+        .partitions;
+    for partition in partitions {
+        let partition_client = client
+            .partition_client(topic.clone(), partition, UnknownTopicHandling::Retry)
+            .await
+            .map_err(messaging_error)?;
+        let mut offset = partition_client
+            .get_offset(OffsetAt::Latest)
+            .await
+            .map_err(messaging_error)?;
+        let (topic, messages) = (topic.clone(), messages.clone());
+        tasks.spawn(async move {
+            loop {
+                match partition_client
+                    .fetch_records(offset, 1..1_000_000, 500)
+                    .await
+                {
+                    Ok((records, _)) => {
+                        let mut messages = messages.lock().await;
+                        for record in records {
+                            offset = record.offset + 1;
+                            let headers: Map<String, Value> = record
+                                .record
+                                .headers
+                                .iter()
+                                .map(|(k, v)| (k.clone(), String::from_utf8_lossy(v).into()))
+                                .collect();
+                            messages.push(json!({
+                                "topic": topic,
+                                "partition": partition,
+                                "offset": record.offset,
+                                "key": record.record.key.as_deref().map(String::from_utf8_lossy),
+                                "headers": headers,
+                                "payload": payload(record.record.value.as_deref().unwrap_or_default()),
+                            }));
+                        }
+                    }
+                    Err(err) => {
+                        log::warn!("Error fetching from topic '{topic}': {err}");
+                        sleep(Duration::from_secs(1)).await;
+                    }
+                }
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "kafka"))]
+async fn subscribe_kafka(
+    _brokers: String,
+    _topic: String,
+    _messages: Messages,
+    _tasks: &mut JoinSet<()>,
+) -> Result<()> {
+    Err(Error::FeatureDisabled("kafka"))
+}
+
+#[cfg(feature = "nats")]
+async fn subscribe_nats(
+    url: String,
+    subject: String,
+    messages: Messages,
+    tasks: &mut JoinSet<()>,
+) -> Result<()> {
+    let client = async_nats::connect(url).await.map_err(messaging_error)?;
+    let mut subscriber = client.subscribe(subject).await.map_err(messaging_error)?;
+    tasks.spawn(async move {
+        // Keeps the connection open for as long as the subscription.
+        let _client = client;
+        while let Some(message) = subscriber.next().await {
+            let headers: Map<String, Value> = message
+                .headers
+                .iter()
+                .flat_map(|headers| headers.iter())
+                .filter_map(|(k, v)| Some((k.to_string(), v.last()?.to_string().into())))
+                .collect();
+            messages.lock().await.push(json!({
+                "subject": message.subject.to_string(),
+                "headers": headers,
+                "payload": payload(&message.payload),
+            }));
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(feature = "nats"))]
+async fn subscribe_nats(
+    _url: String,
+    _subject: String,
+    _messages: Messages,
+    _tasks: &mut JoinSet<()>,
+) -> Result<()> {
+    Err(Error::FeatureDisabled("nats"))
+}
+
+/// Starts receiving messages. Returns once subscribed,
+/// so that all messages published afterwards are received.
+pub async fn subscribe(spec: MessageSpec) -> Result<Subscription> {
+    let messages = Messages::default();
+    let mut tasks = JoinSet::new();
+    match (&spec.kafka, &spec.nats) {
+        (Some(kafka), None) => {
+            subscribe_kafka(
+                subst_process_env(kafka.brokers.clone()),
+                kafka.topic.clone(),
+                messages.clone(),
+                &mut tasks,
+            )
+            .await?
+        }
+        (None, Some(nats)) => {
+            subscribe_nats(
+                subst_process_env(nats.url.clone()),
+                nats.subject.clone(),
+                messages.clone(),
+                &mut tasks,
+            )
+            .await?
+        }
+        _ => return Err(messaging_error("exactly one of kafka and nats is required")),
+    }
+    log::debug!("Subscribed to {}", source(&spec));
+    Ok(Subscription {
+        spec,
+        messages,
+        _tasks: tasks,
+    })
+}
+
+fn check_messages(
+    spec: &MessageSpec,
+    messages: &[Value],
+) -> std::result::Result<(), AssertDiagnostic> {
+    let messages: Vec<&Value> = messages.iter().collect();
+    assert_expr(
+        &messages,
+        &vec![],
+        &History::new(),
+        &spec.condition,
+        CheckOptions {
+            coerce: spec.coerce,
+        },
+    )
+}
+
+impl Subscription {
+    async fn check(&self) -> std::result::Result<(), AssertDiagnostic> {
+        check_messages(&self.spec, &self.messages.lock().await)
+    }
+}
+
+/// Waits until the messages received by every subscription fulfill its condition,
+/// each within its own timeout.
+pub async fn wait_for_messages(config: &Config, subscriptions: Vec<Subscription>) -> Result<()> {
+    let start = Instant::now();
+    log::debug!(
+        "Waiting for messages of {} subscriptions",
+        subscriptions.len()
+    );
+    let mut pending = subscriptions;
+    let mut errors: Vec<TestFailure> = Vec::new();
+    while !pending.is_empty() {
+        let mut unfulfilled = vec![];
+        for subscription in pending {
+            let deadline = start + config.scaled_timeout(subscription.spec.timeout);
+            match subscription.check().await {
+                Ok(()) => {}
+                Err(_) if Instant::now() < deadline => unfulfilled.push(subscription),
+                Err(assert_diagnostic) => {
+                    let spec = &subscription.spec;
+                    let spec = WaitSpec {
+                        condition: spec.condition.clone(),
+                        coerce: spec.coerce,
+                        ..WaitSpec::synthetic(source(spec), spec.timeout)
+                    };
+                    errors.push(TestFailure::new(spec, assert_diagnostic))
+                }
+            }
+        }
+        pending = unfulfilled;
+        if !pending.is_empty() {
+            sleep(Duration::from_millis(100)).await;
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::ConditionsFailed(TestFailures(errors)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serde_json::json;
+
+    #[rstest]
+    #[case(b"{\"status\": \"created\"}", json!({"status": "created"}))]
+    #[case(b"created", json!("created"))]
+    #[case(b"", json!(""))]
+    fn test_payload(#[case] bytes: &[u8], #[case] expected: Value) {
+        assert_eq!(payload(bytes), expected);
+    }
+
+    #[rstest]
+    #[case(json!({"size": 2}), false, true)]
+    #[case(json!({"one": {"payload": {"status": "created"}}}), false, true)]
+    #[case(json!({"all": {"subject": "orders.created"}}), false, false)]
+    #[case(json!({"one": {"payload": {"count": 2}}}), false, false)]
+    #[case(json!({"one": {"payload": {"count": 2}}}), true, true)]
+    fn test_check_messages(#[case] condition: Value, #[case] coerce: bool, #[case] expected: bool) {
+        let spec: MessageSpec = serde_json::from_value(json!({
+            "nats": {"subject": "orders.>"},
+            "condition": condition,
+            "coerce": coerce,
+            "timeout": 1,
+        }))
+        .unwrap();
+        let messages = vec![
+            json!({"subject": "orders.created", "payload": {"status": "created", "count": "2"}}),
+            json!({"subject": "orders.deleted", "payload": "deleted"}),
+        ];
+        assert_eq!(check_messages(&spec, &messages).is_ok(), expected);
+    }
+}
@@ -0,0 +1,233 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::http_probe::CLIENT;
+use crate::test_spec::MetricsCondition;
+use kube::Client;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+/// A sample of a metric in the Prometheus text format.
+#[derive(Debug, PartialEq)]
+struct Sample {
+    labels: BTreeMap<String, String>,
+    value: f64,
+}
+
+/// Parses the labels of a sample, e.g. `controller="widget",result="error"`.
+fn parse_labels(text: &str) -> Option<BTreeMap<String, String>> {
+    let mut labels = BTreeMap::new();
+    let mut chars = text.chars().peekable();
+    loop {
+        while chars.next_if(|c| *c == ',' || c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            return Some(labels);
+        }
+        let name: String = std::iter::from_fn(|| chars.next_if(|c| *c != '=')).collect();
+        chars.next()?;
+        if chars.next()? != '"' {
+            return None;
+        }
+        let mut value = String::new();
+        loop {
+            match chars.next()? {
+                '"' => break,
+                '\\' => match chars.next()? {
+                    'n' => value.push('\n'),
+                    c => value.push(c),
+                },
+                c => value.push(c),
+            }
+        }
+        labels.insert(name.trim().to_string(), value);
+    }
+}
+
+/// Parses the line of a sample, e.g. `reconcile_errors_total{controller="widget"} 0`,
+/// into the name of the metric and the sample.
+fn parse_line(line: &str) -> Option<(&str, Sample)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let name_end = line.find(|c: char| c == '{' || c.is_whitespace())?;
+    let name = &line[..name_end];
+    let (labels, rest) = match line[name_end..].strip_prefix('{') {
+        Some(rest) => {
+            let end = label_end(rest)?;
+            (parse_labels(&rest[..end])?, &rest[end + 1..])
+        }
+        None => (BTreeMap::new(), &line[name_end..]),
+    };
+    let value = rest.split_whitespace().next()?.parse().ok()?;
+    Some((name, Sample { labels, value }))
+}
+
+/// Position of the `}` closing the labels, skipping quoted label values.
+fn label_end(text: &str) -> Option<usize> {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '}' if !quoted => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Samples of the metric in the scraped text that have all of the labels.
+fn samples(text: &str, metric: &str, labels: &BTreeMap<String, String>) -> Vec<Sample> {
+    text.lines()
+        .filter_map(parse_line)
+        .filter(|(name, _)| *name == metric)
+        .map(|(_, sample)| sample)
+        .filter(|sample| {
+            labels
+                .iter()
+                .all(|(key, value)| sample.labels.get(key) == Some(value))
+        })
+        .collect()
+}
+
+/// Checks the scraped text against the condition.
+fn check_text(condition: &MetricsCondition, text: &str) -> Result<(), Value> {
+    let samples = samples(text, &condition.metric, &condition.labels);
+    let fulfilled = if samples.is_empty() {
+        condition.allow_empty
+    } else {
+        samples
+            .iter()
+            .all(|sample| condition.value.matches(sample.value))
+    };
+    fulfilled.then_some(()).ok_or_else(|| {
+        let samples: Vec<Value> = samples
+            .iter()
+            .map(|sample| json!({"labels": sample.labels, "value": sample.value}))
+            .collect();
+        json!({"metric": condition.metric, "samples": samples})
+    })
+}
+
+/// Fetches the metrics, either from the URL or from the pod through the API server proxy.
+async fn scrape(client: Client, condition: &MetricsCondition) -> Result<String, String> {
+    match (&condition.url, &condition.pod) {
+        (Some(url), _) => CLIENT
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| err.to_string())?
+            .text()
+            .await
+            .map_err(|err| err.to_string()),
+        (None, Some(pod)) => {
+            let port = condition
+                .port
+                .map(|port| format!(":{port}"))
+                .unwrap_or_default();
+            let uri = format!(
+                "/api/v1/namespaces/{}/pods/{pod}{port}/proxy/{}",
+                condition.namespace,
+                condition.path.trim_start_matches('/')
+            );
+            let request = http::Request::get(uri)
+                .body(vec![])
+                .map_err(|err| err.to_string())?;
+            client
+                .request_text(request)
+                .await
+                .map_err(|err| err.to_string())
+        }
+        (None, None) => Err("neither url nor pod given".to_string()),
+    }
+}
+
+/// Scrapes the metrics once.
+/// On mismatch, returns a description of the samples for diagnostics.
+pub async fn scrape_metrics(client: Client, condition: &MetricsCondition) -> Result<(), Value> {
+    let source = condition.url.as_ref().or(condition.pod.as_ref());
+    let text = scrape(client, condition)
+        .await
+        .map_err(|err| json!({"source": source, "metric": condition.metric, "error": err}))?;
+    log::trace!("Scraped {} bytes of metrics from {:?}", text.len(), source);
+    check_text(condition, &text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_spec::Comparison;
+    use rstest::rstest;
+
+    const METRICS: &str = r#"# HELP reconcile_errors_total Total number of reconcile errors.
+# TYPE reconcile_errors_total counter
+reconcile_errors_total{controller="widget"} 0
+reconcile_errors_total{controller="gadget",reason="say \"hi\", }"} 3
+reconcile_duration_seconds_count 12 1730800000000
+"#;
+
+    #[rstest]
+    #[case("reconcile_errors_total 0", Some(("reconcile_errors_total", vec![], 0.0)))]
+    #[case(
+        r#"reconcile_errors_total{controller="widget",result="error"} 2.5"#,
+        Some(("reconcile_errors_total", vec![("controller", "widget"), ("result", "error")], 2.5))
+    )]
+    #[case(
+        r#"workqueue_depth{name="a\\b\"c"} +Inf"#,
+        Some(("workqueue_depth", vec![("name", r#"a\b"c"#)], f64::INFINITY))
+    )]
+    #[case("up 1 1730800000000", Some(("up", vec![], 1.0)))]
+    #[case("# TYPE up gauge", None)]
+    #[case(r#"up{job="a" 1"#, None)]
+    #[case("up", None)]
+    fn test_parse_line(
+        #[case] line: &str,
+        #[case] expected: Option<(&str, Vec<(&str, &str)>, f64)>,
+    ) {
+        let expected = expected.map(|(name, labels, value)| {
+            let labels = labels
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            (name, Sample { labels, value })
+        });
+        assert_eq!(parse_line(line), expected);
+    }
+
+    #[rstest]
+    #[case(vec![], 0.0, false, false)]
+    #[case(vec![("controller", "widget")], 0.0, false, true)]
+    #[case(vec![("controller", "gadget")], 0.0, false, false)]
+    #[case(vec![("reason", "say \"hi\", }")], 3.0, false, true)]
+    #[case(vec![("controller", "none")], 0.0, false, false)]
+    #[case(vec![("controller", "none")], 0.0, true, true)]
+    fn test_check_text(
+        #[case] labels: Vec<(&str, &str)>,
+        #[case] eq: f64,
+        #[case] allow_empty: bool,
+        #[case] expected: bool,
+    ) {
+        let condition = MetricsCondition {
+            url: Some("http://localhost:8080/metrics".to_string()),
+            pod: None,
+            namespace: "default".to_string(),
+            port: None,
+            path: "/metrics".to_string(),
+            metric: "reconcile_errors_total".to_string(),
+            labels: labels
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            value: Comparison {
+                eq: Some(eq),
+                ..Default::default()
+            },
+            allow_empty,
+        };
+        assert_eq!(check_text(&condition, METRICS).is_ok(), expected);
+    }
+}
@@ -6,7 +6,10 @@ use k8s_openapi::api::core::v1::Namespace;
 use kube::api::{DeleteParams, Patch, PatchParams, PostParams};
 use kube::{Api, Client};
 use serde_json::json;
-use tokio::time::{sleep, Duration};
+use tokio::time::{sleep, Duration, Instant};
+
+/// Seconds a namespace is given to be deleted gracefully, scaled by the timeout scaling.
+pub const DELETION_TIMEOUT: u16 = 30;
 
 pub struct NamespaceHandle {
     namespace: String,
@@ -22,6 +25,10 @@ impl NamespaceHandle {
         }
     }
 
+    pub fn name(&self) -> &str {
+        &self.namespace
+    }
+
     pub async fn create(&self) -> Result<()> {
         let ns = Namespace {
             metadata: kube::api::ObjectMeta {
@@ -38,9 +45,17 @@ impl NamespaceHandle {
         }
     }
 
-    pub async fn delete(&self) -> Result<()> {
+    /// Deletes the namespace, with `force` removing the finalizers
+    /// if it is not deleted gracefully within `timeout`.
+    pub async fn delete(&self, force: bool, timeout: Duration) -> Result<()> {
         log::debug!("Deleting namespace");
-        if self.try_delete().await? {
+        if self.try_delete(timeout).await? {
+            if !force {
+                return Err(Error::NamespaceNotDeleted(
+                    self.namespace.clone(),
+                    timeout.as_secs(),
+                ));
+            }
             self.force_delete().await?;
         } else {
             log::debug!("Namespace '{}' deleted gracefully.", self.namespace);
@@ -48,13 +63,13 @@ impl NamespaceHandle {
         Ok(())
     }
 
-    async fn try_delete(&self) -> Result<bool> {
+    async fn try_delete(&self, timeout: Duration) -> Result<bool> {
         let delete_params = DeleteParams::default();
 
         match self.api.delete(&self.namespace, &delete_params).await {
             Ok(delete_response) => {
                 if delete_response.left().is_some() {
-                    if self.wait_for_deletion(30).await? {
+                    if self.wait_for_deletion(timeout).await? {
                         Ok(false)
                     } else {
                         Ok(true)
@@ -68,9 +83,10 @@ impl NamespaceHandle {
         }
     }
 
-    async fn wait_for_deletion(&self, timeout_seconds: u64) -> Result<bool> {
+    async fn wait_for_deletion(&self, timeout: Duration) -> Result<bool> {
         log::debug!("Waiting for namespace deletion");
-        for _ in 0..timeout_seconds {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
             match self.api.get(&self.namespace).await {
                 Ok(_) => sleep(Duration::from_secs(1)).await,
                 Err(kube::Error::Api(ae)) if ae.code == 404 => {
@@ -106,7 +122,7 @@ impl NamespaceHandle {
 
         match self.api.delete(&self.namespace, &delete_params).await {
             Ok(_) => {
-                self.wait_for_deletion(10).await?;
+                self.wait_for_deletion(Duration::from_secs(10)).await?;
                 log::debug!("Namespace '{}' force deleted.", self.namespace);
                 Ok(())
             }
@@ -0,0 +1,91 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Crockford's base32 alphabet of ULIDs, in lower case to be valid in names of resources.
+const CROCKFORD: &[u8; 32] = b"0123456789abcdefghjkmnpqrstvwxyz";
+
+/// How the random suffixes of generated names, e.g. of the namespaces of tests, are made.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Naming {
+    /// Two random English words, e.g. `shifting-lanterns`
+    #[default]
+    Words,
+    /// 16 random hex digits
+    Hex,
+    /// A ULID in lower case, which sorts by the time it was made
+    Ulid,
+}
+
+fn word(len: usize) -> String {
+    random_word::gen_len(len, random_word::Lang::En)
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn hex(digits: usize) -> String {
+    let mut rng = rand::thread_rng();
+    (0..digits)
+        .map(|_| char::from_digit(rng.gen_range(0..16), 16).unwrap())
+        .collect()
+}
+
+/// Encodes 48 bits of milliseconds and 80 random bits as 26 base32 digits.
+fn ulid(millis: u64, random: u128) -> String {
+    let value = ((millis as u128 & 0xffff_ffff_ffff) << 80) | (random & ((1 << 80) - 1));
+    (0..26)
+        .rev()
+        .map(|digit| CROCKFORD[((value >> (digit * 5)) & 0x1f) as usize] as char)
+        .collect()
+}
+
+impl Naming {
+    /// A random suffix of a name with about `len` characters, valid in DNS labels.
+    /// ULIDs always have 26 characters.
+    pub fn suffix(&self, len: usize) -> String {
+        match self {
+            Naming::Words => format!("{}-{}", word(len / 2), word(len / 2)),
+            Naming::Hex => hex(len),
+            Naming::Ulid => {
+                let millis = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|duration| duration.as_millis() as u64)
+                    .unwrap_or_default();
+                ulid(millis, rand::thread_rng().gen())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(Naming::Words, 16)]
+    #[case(Naming::Hex, 16)]
+    #[case(Naming::Ulid, 16)]
+    fn test_suffix(#[case] naming: Naming, #[case] len: usize) {
+        let suffix = naming.suffix(len);
+        assert!(!suffix.is_empty());
+        assert!(suffix.len() <= 26);
+        assert!(suffix
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-'));
+        assert_ne!(suffix, naming.suffix(len));
+    }
+
+    #[rstest]
+    #[case(0, 0, "00000000000000000000000000")]
+    #[case(1, 0, "00000000010000000000000000")]
+    #[case(0, 31, "0000000000000000000000000z")]
+    #[case(1_469_922_850_259, 0, "01arz3ndek0000000000000000")]
+    fn test_ulid(#[case] millis: u64, #[case] random: u128, #[case] expected: &str) {
+        assert_eq!(ulid(millis, random), expected);
+    }
+}
@@ -0,0 +1,117 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::run;
+use crate::test_spec::ApplyRateSpec;
+use std::collections::HashMap;
+use tokio::time::{sleep, Duration, Instant};
+
+/// Token buckets pacing applies, by namespace.
+pub(crate) type Buckets = HashMap<String, TokenBucket>;
+
+pub(crate) struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    /// Tokens available, negative when applies are waiting for tokens.
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(spec: &ApplyRateSpec, now: Instant) -> Self {
+        let burst = spec.burst.max(1) as f64;
+        TokenBucket {
+            rate: spec.rate,
+            burst,
+            tokens: burst,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.updated = now;
+    }
+
+    /// Whether the bucket has refilled completely, so it paces no differently than a new one.
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.burst
+    }
+
+    /// Takes a token and returns how long to wait until it is available.
+    fn take(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+        self.tokens -= 1.0;
+        match self.tokens {
+            tokens if tokens >= 0.0 => Duration::ZERO,
+            tokens => Duration::from_secs_f64(-tokens / self.rate),
+        }
+    }
+}
+
+/// Waits until an object may be applied in the namespace, paced across the tests of the run.
+/// A rate that is not positive does not pace, neither are applies outside of a run.
+pub async fn acquire(namespace: &str, spec: &ApplyRateSpec) {
+    if spec.rate <= 0.0 || !spec.rate.is_finite() {
+        return;
+    }
+    let Some(run) = run::current() else {
+        return;
+    };
+    let delay = {
+        let mut buckets = run.buckets.lock().unwrap_or_else(|err| err.into_inner());
+        let now = Instant::now();
+        buckets.retain(|_, bucket| !bucket.is_full(now));
+        buckets
+            .entry(namespace.to_string())
+            .or_insert_with(|| TokenBucket::new(spec, now))
+            .take(now)
+    };
+    if !delay.is_zero() {
+        log::debug!("Pacing apply in namespace '{namespace}' by {delay:?}");
+        sleep(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(1.0, 1, vec![0, 1000, 2000])]
+    #[case(10.0, 1, vec![0, 100, 200])]
+    #[case(2.0, 2, vec![0, 0, 500, 1000])]
+    fn test_take(#[case] rate: f64, #[case] burst: u32, #[case] expected: Vec<u64>) {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(&ApplyRateSpec { rate, burst }, now);
+        let delays: Vec<u64> = expected
+            .iter()
+            .map(|_| bucket.take(now).as_millis() as u64)
+            .collect();
+        assert_eq!(delays, expected);
+    }
+
+    #[test]
+    fn test_refill() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(
+            &ApplyRateSpec {
+                rate: 2.0,
+                burst: 2,
+            },
+            now,
+        );
+        bucket.take(now);
+        bucket.take(now);
+        assert!(!bucket.is_full(now + Duration::from_millis(500)));
+        assert_eq!(
+            bucket.take(now + Duration::from_millis(500)),
+            Duration::ZERO
+        );
+        assert!(bucket.is_full(now + Duration::from_secs(10)));
+        assert_eq!(bucket.tokens, 2.0);
+    }
+}
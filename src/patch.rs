@@ -0,0 +1,84 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::error::{Error, Result};
+use crate::test_spec::{PatchSpec, PatchType};
+use kube::api::{Api, DynamicObject, Patch, PatchParams};
+use kube::core::discovery::Scope;
+use kube::core::GroupVersionKind;
+use kube::discovery::pinned_kind;
+use kube::Client;
+use serde_json::Value;
+
+fn patch_error(spec: &PatchSpec, message: impl std::fmt::Display) -> Error {
+    Error::PatchError(format!("{}/{}: {message}", spec.kind, spec.name))
+}
+
+pub(crate) fn make_patch(spec: &PatchSpec) -> Result<Patch<Value>> {
+    match spec.patch_type {
+        PatchType::Merge | PatchType::Strategic if !spec.patch.is_object() => Err(patch_error(
+            spec,
+            "patch must be an object for merge and strategic patches",
+        )),
+        PatchType::Merge => Ok(Patch::Merge(spec.patch.clone())),
+        PatchType::Strategic => Ok(Patch::Strategic(spec.patch.clone())),
+        PatchType::Json => Ok(Patch::Json(
+            serde_json::from_value(spec.patch.clone())
+                .map_err(|err| patch_error(spec, format!("invalid JSON patch: {err}")))?,
+        )),
+    }
+}
+
+async fn apply_patch(client: Client, spec: &PatchSpec) -> Result<()> {
+    let patch = make_patch(spec)?;
+    let gvk = GroupVersionKind::gvk(&spec.group, &spec.version, &spec.kind);
+    let (ar, caps) = pinned_kind(&client, &gvk)
+        .await
+        .map_err(|_| Error::DiscoveryError(Box::new(gvk)))?;
+    let api: Api<DynamicObject> = match caps.scope {
+        Scope::Namespaced => Api::namespaced_with(client, &spec.namespace, &ar),
+        Scope::Cluster => Api::all_with(client, &ar),
+    };
+    log::debug!("Patching {}/{}: {}", spec.kind, spec.name, spec.patch);
+    api.patch(&spec.name, &PatchParams::default(), &patch)
+        .await
+        .map_err(|err| patch_error(spec, err))?;
+    Ok(())
+}
+
+pub async fn apply_patches(client: Client, specs: Vec<PatchSpec>) -> Result<()> {
+    for spec in specs {
+        apply_patch(client.clone(), &spec).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serde_json::json;
+
+    fn spec(patch_type: PatchType, patch: Value) -> PatchSpec {
+        PatchSpec {
+            group: "apps".to_string(),
+            version: "v1".to_string(),
+            kind: "Deployment".to_string(),
+            name: "web".to_string(),
+            namespace: "test".to_string(),
+            patch_type,
+            patch,
+        }
+    }
+
+    #[rstest]
+    #[case(PatchType::Merge, json!({"spec": {"replicas": 3}}), true)]
+    #[case(PatchType::Strategic, json!({"spec": {"replicas": 3}}), true)]
+    #[case(PatchType::Merge, json!([{"op": "remove", "path": "/spec/replicas"}]), false)]
+    #[case(PatchType::Json, json!([{"op": "replace", "path": "/spec/replicas", "value": 3}]), true)]
+    #[case(PatchType::Json, json!({"spec": {"replicas": 3}}), false)]
+    #[case(PatchType::Json, json!([{"op": "frobnicate", "path": "/spec"}]), false)]
+    fn test_make_patch(#[case] patch_type: PatchType, #[case] patch: Value, #[case] valid: bool) {
+        assert_eq!(make_patch(&spec(patch_type, patch)).is_ok(), valid);
+    }
+}
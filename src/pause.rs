@@ -0,0 +1,84 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::events::{emit, EventKind};
+use crate::run::{self, Run};
+use crate::shutdown::Listener;
+
+/// Whether starting new tests of the current run is paused.
+pub fn is_paused() -> bool {
+    run::current().is_some_and(|run| *run.paused.borrow())
+}
+
+/// Pauses starting new tests of the run if running, resumes otherwise.
+/// Tests already running are not affected.
+pub fn toggle(run: &Run) {
+    let paused = !*run.paused.borrow();
+    run.paused.send_replace(paused);
+    if paused {
+        log::warn!("Paused, running tests will finish but no new tests are started");
+        emit(EventKind::SuitePaused);
+    } else {
+        log::warn!("Resumed");
+        emit(EventKind::SuiteResumed);
+    }
+}
+
+/// Waits until starting new tests of the current run is no longer paused.
+pub async fn resumed() {
+    let Some(run) = run::current() else {
+        return;
+    };
+    let mut receiver = run.paused.subscribe();
+    let _ = receiver.wait_for(|paused| !paused).await;
+}
+
+/// Toggles pausing the current run whenever the process receives SIGUSR1,
+/// until the returned listener is dropped.
+#[cfg(unix)]
+pub fn listen() -> std::io::Result<Listener> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut signal = signal(SignalKind::user_defined1())?;
+    let Some(run) = run::current() else {
+        return Ok(Listener(vec![]));
+    };
+    Ok(Listener(vec![run::spawn(async move {
+        while signal.recv().await.is_some() {
+            toggle(&run);
+        }
+    })]))
+}
+
+#[cfg(not(unix))]
+pub fn listen() -> std::io::Result<Listener> {
+    Ok(Listener(vec![]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_toggle() {
+        let run = Arc::new(Run::default());
+        run::scope(run.clone(), async {
+            assert!(!is_paused());
+            resumed().await;
+            toggle(&run);
+            assert!(is_paused());
+            let waiting = run::spawn(resumed());
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            assert!(!waiting.is_finished());
+            toggle(&run);
+            assert!(!is_paused());
+            tokio::time::timeout(Duration::from_secs(1), waiting)
+                .await
+                .unwrap()
+                .unwrap();
+        })
+        .await;
+        assert!(!is_paused());
+    }
+}
@@ -0,0 +1,159 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::error::{Error, Result};
+use crate::test_spec::PortForwardSpec;
+use k8s_openapi::api::core::v1::{Pod, Service};
+use k8s_openapi::apimachinery::pkg::util::intstr::IntOrString;
+use kube::api::ListParams;
+use kube::{Api, Client};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+pub struct PortForward {
+    token: CancellationToken,
+    task: JoinHandle<()>,
+}
+
+struct PortForwardBrief {
+    pods: Api<Pod>,
+    services: Api<Service>,
+    spec: PortForwardSpec,
+}
+
+fn is_ready(pod: &Pod) -> bool {
+    pod.status
+        .as_ref()
+        .and_then(|status| status.conditions.as_ref())
+        .is_some_and(|conditions| {
+            conditions
+                .iter()
+                .any(|c| c.type_ == "Ready" && c.status == "True")
+        })
+}
+
+fn container_port(pod: &Pod, name: &str) -> Option<u16> {
+    pod.spec
+        .as_ref()?
+        .containers
+        .iter()
+        .flat_map(|c| c.ports.iter().flatten())
+        .find(|p| p.name.as_deref() == Some(name))
+        .and_then(|p| u16::try_from(p.container_port).ok())
+}
+
+impl PortForwardBrief {
+    async fn resolve_service(&self, service: &str) -> Result<(String, u16)> {
+        let service = self.services.get(service).await?;
+        let spec = service.spec.unwrap_or_default();
+        let selector = spec
+            .selector
+            .unwrap_or_default()
+            .iter()
+            .map(|(k, v)| format!("{k}={v}"))
+            .collect::<Vec<_>>()
+            .join(",");
+        let service_port = spec
+            .ports
+            .unwrap_or_default()
+            .into_iter()
+            .find(|p| u16::try_from(p.port).ok() == Some(self.spec.port))
+            .ok_or_else(|| {
+                Error::PortForwardError(format!("service has no port {}", self.spec.port))
+            })?;
+        let pods = self
+            .pods
+            .list(&ListParams::default().labels(&selector))
+            .await?;
+        let pod = pods
+            .items
+            .into_iter()
+            .find(is_ready)
+            .ok_or_else(|| Error::PortForwardError("no ready pod for service".to_string()))?;
+        let port = match service_port.target_port {
+            Some(IntOrString::Int(port)) => u16::try_from(port).ok(),
+            Some(IntOrString::String(name)) => container_port(&pod, &name),
+            None => Some(self.spec.port),
+        }
+        .ok_or_else(|| Error::PortForwardError("unable to resolve target port".to_string()))?;
+        Ok((pod.metadata.name.unwrap_or_default(), port))
+    }
+
+    async fn resolve(&self) -> Result<(String, u16)> {
+        match (&self.spec.service, &self.spec.pod) {
+            (Some(service), _) => self.resolve_service(service).await,
+            (None, Some(pod)) => Ok((pod.clone(), self.spec.port)),
+            (None, None) => Err(Error::PortForwardError(
+                "neither pod nor service given".to_string(),
+            )),
+        }
+    }
+
+    async fn forward(&self, mut connection: TcpStream) -> Result<()> {
+        let (pod, port) = self.resolve().await?;
+        log::debug!("Forwarding connection to {pod}:{port}");
+        let mut forwarder = self.pods.portforward(&pod, &[port]).await?;
+        let mut upstream = forwarder
+            .take_stream(port)
+            .ok_or_else(|| Error::PortForwardError(format!("no stream for port {port}")))?;
+        tokio::io::copy_bidirectional(&mut connection, &mut upstream).await?;
+        drop(upstream);
+        forwarder
+            .join()
+            .await
+            .map_err(|err| Error::PortForwardError(err.to_string()))
+    }
+}
+
+impl PortForward {
+    /// Starts listening on a random local port and returns it.
+    /// Every accepted connection is forwarded to the target resolved at that time,
+    /// so the target does not need to be ready when the port forward is started.
+    pub async fn start(client: Client, spec: PortForwardSpec) -> Result<(Self, u16)> {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+        let local_port = listener.local_addr()?.port();
+        let brief = std::sync::Arc::new(PortForwardBrief {
+            pods: Api::namespaced(client.clone(), &spec.namespace),
+            services: Api::namespaced(client, &spec.namespace),
+            spec,
+        });
+        let token = CancellationToken::new();
+        let task_token = token.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                let connection = tokio::select! {
+                    _ = task_token.cancelled() => break,
+                    connection = listener.accept() => connection,
+                };
+                match connection {
+                    Ok((connection, _)) => {
+                        let brief = brief.clone();
+                        let token = task_token.clone();
+                        tokio::spawn(async move {
+                            tokio::select! {
+                                _ = token.cancelled() => {},
+                                result = brief.forward(connection) => {
+                                    if let Err(err) = result {
+                                        log::warn!(
+                                            "Port forward '{}' failed: {}",
+                                            brief.spec.name,
+                                            err
+                                        );
+                                    }
+                                }
+                            }
+                        });
+                    }
+                    Err(err) => log::warn!("Port forward failed to accept connection: {err}"),
+                }
+            }
+        });
+        Ok((PortForward { token, task }, local_port))
+    }
+
+    pub async fn stop(self) -> Result<()> {
+        self.token.cancel();
+        Ok(self.task.await?)
+    }
+}
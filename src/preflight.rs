@@ -0,0 +1,109 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::events::{emit, EventKind};
+use crate::run;
+use kube::Client;
+use std::collections::{BTreeMap, HashMap};
+
+/// API groups that minimal clusters, e.g. kind, might not serve, with a hint on what serves them.
+const OPTIONAL_GROUPS: [(&str, &str); 2] = [
+    ("metrics.k8s.io", "is metrics-server installed?"),
+    ("events.k8s.io", "is the events API enabled?"),
+];
+
+fn unavailable_groups(served: &[String]) -> BTreeMap<String, String> {
+    OPTIONAL_GROUPS
+        .iter()
+        .filter(|(group, _)| !served.iter().any(|served| served == group))
+        .map(|(group, hint)| {
+            let reason = format!("API group '{group}' is not served by the cluster, {hint}");
+            (group.to_string(), reason)
+        })
+        .collect()
+}
+
+/// Checks which of the optional API groups the cluster serves, once per run.
+/// If the API groups cannot be listed, all of them are assumed to be served.
+pub async fn check(client: &Client) {
+    let Some(run) = run::current() else {
+        return;
+    };
+    if run.unavailable.get().is_some() {
+        return;
+    }
+    let unavailable = match client.list_api_groups().await {
+        Ok(groups) => {
+            let served: Vec<String> = groups.groups.into_iter().map(|group| group.name).collect();
+            unavailable_groups(&served)
+        }
+        Err(err) => {
+            log::debug!("Unable to list API groups: {err}");
+            BTreeMap::new()
+        }
+    };
+    for reason in unavailable.values() {
+        log::warn!("{reason}; conditions on it are skipped");
+    }
+    let _ = run.unavailable.set(unavailable);
+}
+
+/// Why resources of `group` cannot be watched in the current run, if the cluster does not serve it.
+pub fn unavailable(group: &str) -> Option<String> {
+    run::current()?.unavailable.get()?.get(group).cloned()
+}
+
+/// Removes the conditions on buckets whose watches were not started as their API is not served,
+/// reporting them as skipped.
+pub fn skip_unavailable<T>(
+    test_name: &str,
+    step_name: &str,
+    conditions: Vec<T>,
+    target: impl Fn(&T) -> &str,
+    unavailable: &HashMap<String, String>,
+) -> Vec<T> {
+    conditions
+        .into_iter()
+        .filter(|condition| {
+            let Some(reason) = unavailable.get(target(condition)) else {
+                return true;
+            };
+            log::warn!(
+                "Skipping condition on bucket '{}': {reason}",
+                target(condition)
+            );
+            emit(EventKind::ConditionSkipped {
+                test: test_name.to_string(),
+                step: step_name.to_string(),
+                target: target(condition).to_string(),
+                reason: reason.clone(),
+            });
+            false
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unavailable_groups() {
+        let unavailable = unavailable_groups(&["apps".to_string(), "events.k8s.io".to_string()]);
+        assert_eq!(
+            unavailable.keys().collect::<Vec<_>>(),
+            vec!["metrics.k8s.io"]
+        );
+        assert!(unavailable["metrics.k8s.io"].contains("metrics-server"));
+    }
+
+    #[test]
+    fn test_skip_unavailable() {
+        let unavailable = HashMap::from([("pod-metrics".to_string(), "not served".to_string())]);
+        let conditions = vec!["pods", "pod-metrics", "events"];
+        assert_eq!(
+            skip_unavailable("test", "step", conditions, |c| c, &unavailable),
+            vec!["pods", "events"]
+        );
+    }
+}
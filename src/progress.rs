@@ -0,0 +1,237 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::events::EventKind;
+use colored::Colorize;
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::io::{IsTerminal, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::time::{interval, Duration, Instant};
+
+struct RunningTest {
+    started: Instant,
+    step: String,
+    pending: Vec<String>,
+}
+
+/// Live status of the suite, drawn below the log lines and redrawn in place.
+#[derive(Default)]
+pub(crate) struct Progress {
+    enabled: bool,
+    running: BTreeMap<String, RunningTest>,
+    /// Finished tests, by whether their last attempt passed.
+    finished: BTreeMap<String, bool>,
+    paused: bool,
+    /// Number of lines currently drawn, to be erased before the next redraw.
+    drawn: usize,
+}
+
+/// The progress of the run drawn on stderr, as stderr is shared by all runs of the process.
+static DRAWN: Lazy<Mutex<Option<Arc<Mutex<Progress>>>>> = Lazy::new(Default::default);
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+impl Progress {
+    fn update(&mut self, event: &EventKind) {
+        match event {
+            EventKind::TestStarted { test, .. } => {
+                self.running.insert(
+                    test.clone(),
+                    RunningTest {
+                        started: Instant::now(),
+                        step: String::new(),
+                        pending: vec![],
+                    },
+                );
+            }
+            EventKind::StepStarted { test, step } => {
+                if let Some(running) = self.running.get_mut(test) {
+                    running.step = step.clone();
+                    running.pending.clear();
+                }
+            }
+            EventKind::WaitPending { test, targets, .. } => {
+                if let Some(running) = self.running.get_mut(test) {
+                    running.pending = targets.clone();
+                }
+            }
+            EventKind::StepFinished { test, .. } => {
+                if let Some(running) = self.running.get_mut(test) {
+                    running.pending.clear();
+                }
+            }
+            EventKind::TestFinished { test, failure } => {
+                self.running.remove(test);
+                self.finished.insert(test.clone(), failure.is_none());
+            }
+            EventKind::SuitePaused => self.paused = true,
+            EventKind::SuiteResumed => self.paused = false,
+            _ => {}
+        }
+    }
+
+    fn lines(&self, now: Instant) -> Vec<String> {
+        let passed = self.finished.values().filter(|passed| **passed).count();
+        let mut lines = vec![format!(
+            "{} running, {} passed, {} failed",
+            self.running.len(),
+            passed,
+            self.finished.len() - passed
+        )];
+        if self.paused {
+            lines[0] += ", paused";
+        }
+        for (test, running) in &self.running {
+            let elapsed = now.duration_since(running.started).as_secs();
+            let mut line = format!(
+                "  {test}  {}  {}:{:02}",
+                running.step,
+                elapsed / 60,
+                elapsed % 60
+            );
+            if !running.pending.is_empty() {
+                line += &format!("  waiting for {}", running.pending.join(", "));
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    /// Erases the lines drawn last.
+    fn clear(&mut self, out: &mut impl Write) {
+        if self.drawn > 0 {
+            let _ = write!(out, "\x1b[{}A\x1b[J", self.drawn);
+            self.drawn = 0;
+        }
+    }
+
+    fn redraw(&mut self, out: &mut impl Write) {
+        self.clear(out);
+        if self.enabled {
+            let lines = self.lines(Instant::now());
+            let _ = writeln!(out, "{}", lines[0].bold());
+            for line in &lines[1..] {
+                let _ = writeln!(out, "{line}");
+            }
+            self.drawn = lines.len();
+        }
+        let _ = out.flush();
+    }
+}
+
+/// Whether the progress display can be drawn, which requires stderr to be a terminal.
+pub fn is_supported() -> bool {
+    std::io::stderr().is_terminal()
+}
+
+/// Starts drawing the progress display, refreshed every second until the suite has finished.
+pub(crate) fn start(progress: &Arc<Mutex<Progress>>) {
+    lock(progress).enabled = true;
+    *lock(&DRAWN) = Some(progress.clone());
+    let progress = progress.clone();
+    tokio::spawn(async move {
+        let mut ticks = interval(Duration::from_secs(1));
+        loop {
+            ticks.tick().await;
+            let mut progress = lock(&progress);
+            if !progress.enabled {
+                break;
+            }
+            progress.redraw(&mut std::io::stderr());
+        }
+    });
+}
+
+pub(crate) fn update(progress: &Mutex<Progress>, event: &EventKind) {
+    let mut progress = lock(progress);
+    if !progress.enabled {
+        return;
+    }
+    progress.update(event);
+    if let EventKind::SuiteFinished { .. } = event {
+        progress.enabled = false;
+    }
+    progress.redraw(&mut std::io::stderr());
+}
+
+/// Target of the log, which writes log lines above the progress display.
+pub struct LogWriter;
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let drawn = lock(&DRAWN);
+        let mut stderr = std::io::stderr();
+        let Some(progress) = drawn.as_ref() else {
+            stderr.write_all(buf)?;
+            return Ok(buf.len());
+        };
+        let mut progress = lock(progress);
+        progress.clear(&mut stderr);
+        stderr.write_all(buf)?;
+        if buf.ends_with(b"\n") {
+            progress.redraw(&mut stderr);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stderr().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Failure;
+    use crate::test_spec::TestType;
+
+    fn started(test: &str) -> EventKind {
+        EventKind::TestStarted {
+            test: test.to_string(),
+            test_type: TestType::User,
+            namespace: format!("{test}-ns"),
+        }
+    }
+
+    #[test]
+    fn test_lines() {
+        let mut progress = Progress::default();
+        for event in [
+            started("a"),
+            started("b"),
+            started("c"),
+            EventKind::StepStarted {
+                test: "a".to_string(),
+                step: "deploy".to_string(),
+            },
+            EventKind::WaitPending {
+                test: "a".to_string(),
+                step: "deploy".to_string(),
+                targets: vec!["pods".to_string(), "services".to_string()],
+            },
+            EventKind::StepStarted {
+                test: "b".to_string(),
+                step: "apply".to_string(),
+            },
+            EventKind::TestFinished {
+                test: "c".to_string(),
+                failure: Some(Failure {
+                    step: "apply".to_string(),
+                    error: "Timed out after 1 seconds".to_string(),
+                    failures: vec![],
+                }),
+            },
+        ] {
+            progress.update(&event);
+        }
+        let now = progress.running["a"].started + Duration::from_secs(75);
+        let lines = progress.lines(now);
+        assert_eq!(lines[0], "2 running, 0 passed, 1 failed");
+        assert_eq!(lines[1], "  a  deploy  1:15  waiting for pods, services");
+        assert!(lines[2].starts_with("  b  apply  "));
+        assert!(!lines[2].contains("waiting"));
+    }
+}
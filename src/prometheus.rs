@@ -0,0 +1,102 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::http_probe::CLIENT;
+use crate::test_spec::PrometheusCondition;
+use serde_json::{json, Value};
+
+/// Values of the samples in the `data` of a Prometheus query response.
+/// Vectors yield one value per series, scalars a single value.
+fn sample_values(data: &Value) -> Result<Vec<f64>, String> {
+    let parse = |value: &Value| {
+        value[1]
+            .as_str()
+            .and_then(|v| v.parse::<f64>().ok())
+            .ok_or_else(|| format!("invalid sample {value}"))
+    };
+    match data["resultType"].as_str() {
+        Some("vector") => data["result"]
+            .as_array()
+            .map(|result| {
+                result
+                    .iter()
+                    .map(|sample| parse(&sample["value"]))
+                    .collect()
+            })
+            .unwrap_or_else(|| Err("missing result".to_string())),
+        Some("scalar") => Ok(vec![parse(&data["result"])?]),
+        Some(result_type) => Err(format!("unsupported result type {result_type}")),
+        None => Err("missing result type".to_string()),
+    }
+}
+
+/// Checks the response of a Prometheus query against the condition.
+fn check_response(condition: &PrometheusCondition, response: &Value) -> Result<(), Value> {
+    let diagnostic = |error: String| json!({"url": condition.url, "query": condition.query, "error": error, "response": response});
+    if response["status"] != "success" {
+        return Err(diagnostic("query failed".to_string()));
+    }
+    let values = sample_values(&response["data"]).map_err(diagnostic)?;
+    let fulfilled = if values.is_empty() {
+        condition.allow_empty
+    } else {
+        values.iter().all(|value| condition.value.matches(*value))
+    };
+    fulfilled.then_some(()).ok_or_else(|| {
+        json!({"url": condition.url, "query": condition.query, "result": response["data"]["result"]})
+    })
+}
+
+/// Runs the query once.
+/// On mismatch, returns a description of the result for diagnostics.
+pub async fn query(condition: &PrometheusCondition) -> Result<(), Value> {
+    let url = format!("{}/api/v1/query", condition.url.trim_end_matches('/'));
+    let error = |err: String| json!({"url": url, "query": condition.query, "error": err});
+    let body = CLIENT
+        .get(&url)
+        .query(&[("query", &condition.query)])
+        .send()
+        .await
+        .map_err(|err| error(err.to_string()))?
+        .text()
+        .await
+        .map_err(|err| error(err.to_string()))?;
+    let response: Value = serde_json::from_str(&body).map_err(|err| error(err.to_string()))?;
+    log::trace!("Prometheus query {} returned {}", condition.query, response);
+    check_response(condition, &response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_spec::Comparison;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(json!({"resultType": "vector", "result": []}), false, false)]
+    #[case(json!({"resultType": "vector", "result": []}), true, true)]
+    #[case(json!({"resultType": "vector", "result": [
+        {"metric": {"pod": "a"}, "value": [1.0, "0"]},
+        {"metric": {"pod": "b"}, "value": [1.0, "0"]},
+    ]}), false, true)]
+    #[case(json!({"resultType": "vector", "result": [
+        {"metric": {"pod": "a"}, "value": [1.0, "0"]},
+        {"metric": {"pod": "b"}, "value": [1.0, "2"]},
+    ]}), false, false)]
+    #[case(json!({"resultType": "scalar", "result": [1.0, "0"]}), false, true)]
+    #[case(json!({"resultType": "scalar", "result": [1.0, "NaN"]}), false, false)]
+    #[case(json!({"resultType": "matrix", "result": []}), true, false)]
+    fn test_check_response(#[case] data: Value, #[case] allow_empty: bool, #[case] expected: bool) {
+        let condition = PrometheusCondition {
+            url: "http://prometheus".to_string(),
+            query: "increase(reconcile_errors_total[5m])".to_string(),
+            value: Comparison {
+                eq: Some(0.0),
+                ..Default::default()
+            },
+            allow_empty,
+        };
+        let response = json!({"status": "success", "data": data});
+        assert_eq!(check_response(&condition, &response).is_ok(), expected);
+    }
+}
@@ -0,0 +1,256 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::error::Result;
+use crate::run::Run;
+use futures::future::BoxFuture;
+use http::{Method, Request, Response, StatusCode, Uri};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll};
+use tokio::fs;
+use tower::{Layer, Service};
+
+/// Identity of requests that are not impersonating another user.
+const RUNNER: &str = "runner";
+
+/// A request that the API server denied.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Access {
+    identity: String,
+    group: String,
+    resource: String,
+    verb: String,
+}
+
+/// Verbs missing per identity, by group and resource.
+pub(crate) type Denied = BTreeMap<String, BTreeMap<(String, String), BTreeSet<String>>>;
+
+fn lock(denied: &Mutex<Denied>) -> MutexGuard<'_, Denied> {
+    denied.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn record(denied: &Mutex<Denied>, access: Access) {
+    log::debug!("Forbidden: {access:?}");
+    lock(denied)
+        .entry(access.identity)
+        .or_default()
+        .entry((access.group, access.resource))
+        .or_default()
+        .insert(access.verb);
+}
+
+/// Derives the RBAC verb, group and resource of a request from its method and URI,
+/// e.g. `GET /apis/apps/v1/namespaces/foo/deployments?watch=true` is a `watch` of `apps/deployments`.
+fn access(method: &Method, uri: &Uri, identity: &str) -> Option<Access> {
+    let segments: Vec<&str> = uri.path().trim_matches('/').split('/').collect();
+    let rest = match segments.as_slice() {
+        ["api", _version, rest @ ..] => rest,
+        ["apis", _group, _version, rest @ ..] => rest,
+        _ => return None,
+    };
+    let group = match segments[0] {
+        "apis" => segments[1],
+        _ => "",
+    };
+    let (resource, named) = match rest {
+        ["namespaces", _, subresource @ ("status" | "finalize")] => {
+            (format!("namespaces/{subresource}"), true)
+        }
+        ["namespaces", _, resource, name_and_subresource @ ..] => (
+            match name_and_subresource {
+                [_, subresource] => format!("{resource}/{subresource}"),
+                _ => resource.to_string(),
+            },
+            !name_and_subresource.is_empty(),
+        ),
+        ["namespaces", _] => ("namespaces".to_string(), true),
+        [resource, name_and_subresource @ ..] => (
+            match name_and_subresource {
+                [_, subresource] => format!("{resource}/{subresource}"),
+                _ => resource.to_string(),
+            },
+            !name_and_subresource.is_empty(),
+        ),
+        [] => return None,
+    };
+    let watch = uri
+        .query()
+        .is_some_and(|query| query.split('&').any(|param| param == "watch=true"));
+    let verb = match *method {
+        Method::GET if watch => "watch",
+        Method::GET if named => "get",
+        Method::GET => "list",
+        Method::POST => "create",
+        Method::PUT => "update",
+        Method::PATCH => "patch",
+        Method::DELETE if named => "delete",
+        Method::DELETE => "deletecollection",
+        _ => return None,
+    };
+    Some(Access {
+        identity: identity.to_string(),
+        group: group.to_string(),
+        resource,
+        verb: verb.to_string(),
+    })
+}
+
+/// Layer of the Kubernetes client recording the requests denied with 403 for the run.
+#[derive(Clone)]
+pub struct RecordForbidden(Arc<Mutex<Denied>>);
+
+impl RecordForbidden {
+    pub fn new(run: &Run) -> Self {
+        RecordForbidden(run.denied.clone())
+    }
+}
+
+impl<S> Layer<S> for RecordForbidden {
+    type Service = RecordForbiddenService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RecordForbiddenService {
+            inner,
+            denied: self.0.clone(),
+        }
+    }
+}
+
+pub struct RecordForbiddenService<S> {
+    inner: S,
+    denied: Arc<Mutex<Denied>>,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for RecordForbiddenService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, std::result::Result<S::Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::result::Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        let identity = request
+            .headers()
+            .get("Impersonate-User")
+            .and_then(|user| user.to_str().ok())
+            .unwrap_or(RUNNER);
+        let access = access(request.method(), request.uri(), identity);
+        let response = self.inner.call(request);
+        let denied = self.denied.clone();
+        Box::pin(async move {
+            let response = response.await?;
+            if let (StatusCode::FORBIDDEN, Some(access)) = (response.status(), access) {
+                record(&denied, access);
+            }
+            Ok(response)
+        })
+    }
+}
+
+/// The denied requests as rules of a ClusterRole per identity.
+fn report(denied: &Denied) -> Value {
+    let identities: Vec<Value> = denied
+        .iter()
+        .map(|(identity, resources)| {
+            let rules: Vec<Value> = resources
+                .iter()
+                .map(|((group, resource), verbs)| {
+                    json!({"apiGroups": [group], "resources": [resource], "verbs": verbs})
+                })
+                .collect();
+            json!({"identity": identity, "rules": rules})
+        })
+        .collect();
+    json!(identities)
+}
+
+/// Writes the requests denied during the run to `path`, as rules of a ClusterRole per identity
+/// that would have granted them.
+pub async fn write_report(run: &Run, path: &Path) -> Result<()> {
+    let denied = lock(&run.denied).clone();
+    for (identity, resources) in &denied {
+        log::warn!(
+            "Requests of {identity} to {} resources were forbidden",
+            resources.len()
+        );
+    }
+    fs::write(path, serde_yaml::to_string(&report(&denied))?).await?;
+    log::info!("Wrote missing RBAC to {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("GET", "/api/v1/namespaces/foo/pods", Some(("", "pods", "list")))]
+    #[case("GET", "/api/v1/namespaces/foo/pods?watch=true&resourceVersion=1", Some(("", "pods", "watch")))]
+    #[case("GET", "/api/v1/namespaces/foo/pods/web", Some(("", "pods", "get")))]
+    #[case("GET", "/api/v1/namespaces/foo/pods/web/log", Some(("", "pods/log", "get")))]
+    #[case("POST", "/api/v1/namespaces/foo/pods/web/portforward", Some(("", "pods/portforward", "create")))]
+    #[case("PATCH", "/apis/apps/v1/namespaces/foo/deployments/web", Some(("apps", "deployments", "patch")))]
+    #[case("PUT", "/apis/apps/v1/namespaces/foo/deployments/web/scale", Some(("apps", "deployments/scale", "update")))]
+    #[case("DELETE", "/api/v1/namespaces/foo", Some(("", "namespaces", "delete")))]
+    #[case("POST", "/api/v1/namespaces", Some(("", "namespaces", "create")))]
+    #[case("PUT", "/api/v1/namespaces/foo/finalize", Some(("", "namespaces/finalize", "update")))]
+    #[case("DELETE", "/apis/example.com/v1/namespaces/foo/widgets", Some(("example.com", "widgets", "deletecollection")))]
+    #[case("GET", "/apis/apiextensions.k8s.io/v1/customresourcedefinitions", Some(("apiextensions.k8s.io", "customresourcedefinitions", "list")))]
+    #[case("GET", "/version", None)]
+    #[case("GET", "/apis/apps/v1", None)]
+    fn test_access(
+        #[case] method: &str,
+        #[case] uri: &str,
+        #[case] expected: Option<(&str, &str, &str)>,
+    ) {
+        let method = Method::from_bytes(method.as_bytes()).unwrap();
+        let uri: Uri = uri.parse().unwrap();
+        let expected = expected.map(|(group, resource, verb)| Access {
+            identity: RUNNER.to_string(),
+            group: group.to_string(),
+            resource: resource.to_string(),
+            verb: verb.to_string(),
+        });
+        assert_eq!(access(&method, &uri, RUNNER), expected);
+    }
+
+    #[test]
+    fn test_report() {
+        let mut denied = Denied::new();
+        for (identity, group, resource, verb) in [
+            (RUNNER, "", "pods", "list"),
+            (RUNNER, "", "pods", "watch"),
+            (RUNNER, "apps", "deployments", "patch"),
+            ("system:serviceaccount:foo:operator", "", "secrets", "get"),
+        ] {
+            denied
+                .entry(identity.to_string())
+                .or_default()
+                .entry((group.to_string(), resource.to_string()))
+                .or_default()
+                .insert(verb.to_string());
+        }
+        assert_eq!(
+            report(&denied),
+            json!([
+                {"identity": RUNNER, "rules": [
+                    {"apiGroups": [""], "resources": ["pods"], "verbs": ["list", "watch"]},
+                    {"apiGroups": ["apps"], "resources": ["deployments"], "verbs": ["patch"]},
+                ]},
+                {"identity": "system:serviceaccount:foo:operator", "rules": [
+                    {"apiGroups": [""], "resources": ["secrets"], "verbs": ["get"]},
+                ]},
+            ])
+        );
+    }
+}
@@ -0,0 +1,221 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::config::Config;
+use crate::error::{AssertDiagnostic, Description, Error, ResourceDiff, TestFailure, TestFailures};
+use crate::match_object::Difference;
+use serde_json::Value;
+
+/// Removes noise and the data of Secrets, which must not end up in logs or artifacts.
+pub fn sanitize(mut object: Value) -> Value {
+    if let Some(items) = object.as_array_mut() {
+        for item in items {
+            *item = sanitize(item.take());
+        }
+        return object;
+    }
+    if let Some(metadata) = object.get_mut("metadata").and_then(Value::as_object_mut) {
+        metadata.remove("managedFields");
+    }
+    if object["kind"] == "Secret" {
+        for field in ["data", "stringData"] {
+            if let Some(data) = object.get_mut(field).and_then(Value::as_object_mut) {
+                data.values_mut()
+                    .for_each(|value| *value = Value::String("<redacted>".to_string()));
+            }
+        }
+    }
+    object
+}
+
+/// Replaces a value whose JSON is longer than `max_size` bytes by the beginning of its JSON.
+/// A `max_size` of 0 does not truncate.
+pub fn truncate(value: Value, max_size: usize) -> Value {
+    if max_size == 0 {
+        return value;
+    }
+    let json = value.to_string();
+    if json.len() <= max_size {
+        return value;
+    }
+    let mut end = max_size;
+    while !json.is_char_boundary(end) {
+        end -= 1;
+    }
+    Value::String(format!(
+        "{}… ({} more bytes)",
+        &json[..end],
+        json.len() - end
+    ))
+}
+
+/// Whether a difference might reveal the data of a Secret.
+fn is_secret_data(resource_diff: &ResourceDiff, difference: &Difference) -> bool {
+    resource_diff.kind.as_deref() == Some("Secret")
+        || [".data", ".stringData"].iter().any(|field| {
+            difference
+                .path
+                .strip_prefix(field)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with(['.', '[']))
+        })
+}
+
+fn redact_diagnostic(config: &Config, diagnostic: AssertDiagnostic) -> AssertDiagnostic {
+    let redact = |value: Value| match config.redact {
+        true => truncate(sanitize(value), config.max_object_size),
+        false => truncate(value, config.max_object_size),
+    };
+    AssertDiagnostic {
+        input: diagnostic.input.into_iter().map(redact).collect(),
+        diff: diagnostic
+            .diff
+            .into_iter()
+            .map(|mut resource_diff| {
+                let mut differences = std::mem::take(&mut resource_diff.differences);
+                for difference in &mut differences {
+                    let redacted = config.redact && is_secret_data(&resource_diff, difference);
+                    difference.actual = difference.actual.take().map(|actual| match redacted {
+                        true => Value::String("<redacted>".to_string()),
+                        false => truncate(actual, config.max_object_size),
+                    });
+                }
+                ResourceDiff {
+                    differences,
+                    ..resource_diff
+                }
+            })
+            .collect(),
+        ..diagnostic
+    }
+}
+
+fn redact_description(config: &Config, description: Description) -> Description {
+    Description {
+        spec: description
+            .spec
+            .map(|spec| truncate(spec, config.max_object_size)),
+        status: description
+            .status
+            .map(|status| truncate(status, config.max_object_size)),
+        ..description
+    }
+}
+
+/// Redacts and truncates the resources in the diagnostics of failed conditions,
+/// before the failure is logged and recorded in reports.
+pub fn redact_failure(config: &Config, failure: Error) -> Error {
+    match failure {
+        Error::ConditionsFailed(TestFailures(failures)) => Error::ConditionsFailed(TestFailures(
+            failures
+                .into_iter()
+                .map(|failure| TestFailure {
+                    assert_diagnostic: redact_diagnostic(config, failure.assert_diagnostic),
+                    describe: failure
+                        .describe
+                        .into_iter()
+                        .map(|description| redact_description(config, description))
+                        .collect(),
+                    ..failure
+                })
+                .collect(),
+        )),
+        failure => failure,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serde_json::json;
+
+    #[test]
+    fn test_sanitize() {
+        let secret = sanitize(json!({
+            "kind": "Secret",
+            "metadata": {"name": "credentials", "managedFields": [{"manager": "kubectl"}]},
+            "data": {"password": "c2VjcmV0"},
+        }));
+        assert_eq!(
+            secret,
+            json!({
+                "kind": "Secret",
+                "metadata": {"name": "credentials"},
+                "data": {"password": "<redacted>"},
+            })
+        );
+        let config_map = json!({"kind": "ConfigMap", "data": {"key": "value"}});
+        assert_eq!(sanitize(config_map.clone()), config_map);
+        assert_eq!(
+            sanitize(json!([{"kind": "Secret", "stringData": {"token": "abc"}}])),
+            json!([{"kind": "Secret", "stringData": {"token": "<redacted>"}}])
+        );
+    }
+
+    #[rstest]
+    #[case(json!({"a": "b"}), 0, json!({"a": "b"}))]
+    #[case(json!({"a": "b"}), 9, json!({"a": "b"}))]
+    #[case(json!({"a": "b"}), 5, json!("{\"a\":… (4 more bytes)"))]
+    #[case(json!("ää"), 2, json!("\"… (5 more bytes)"))]
+    fn test_truncate(#[case] value: Value, #[case] max_size: usize, #[case] expected: Value) {
+        assert_eq!(truncate(value, max_size), expected);
+    }
+
+    #[rstest]
+    #[case(true, json!({"kind": "Secret", "data": {"key": "<redacted>"}}))]
+    #[case(false, json!({"kind": "Secret", "data": {"key": "dmFsdWU="}}))]
+    fn test_redact_failure(#[case] redact: bool, #[case] expected: Value) {
+        let config = Config {
+            redact,
+            ..Default::default()
+        };
+        let failure = Error::ConditionsFailed(TestFailures(vec![TestFailure {
+            assert_diagnostic: AssertDiagnostic {
+                expr: serde_json::from_value(json!({"size": 0})).unwrap(),
+                input: vec![json!({"kind": "Secret", "data": {"key": "dmFsdWU="}})],
+                diff: vec![],
+            },
+            spec: serde_json::from_value(json!({
+                "target": "secrets",
+                "condition": {"size": 0},
+                "timeout": 1,
+            }))
+            .unwrap(),
+            describe: vec![],
+        }]));
+        let Error::ConditionsFailed(TestFailures(failures)) = redact_failure(&config, failure)
+        else {
+            panic!("not a condition failure");
+        };
+        assert_eq!(failures[0].assert_diagnostic.input, vec![expected]);
+    }
+
+    #[rstest]
+    #[case(Some("Secret"), ".metadata.labels.app", true)]
+    #[case(Some("ConfigMap"), ".data.password", true)]
+    #[case(None, ".stringData", true)]
+    #[case(Some("ConfigMap"), ".metadata.labels.app", false)]
+    #[case(Some("ConfigMap"), ".database", false)]
+    fn test_redact_diff(#[case] kind: Option<&str>, #[case] path: &str, #[case] redacted: bool) {
+        let diagnostic = AssertDiagnostic {
+            expr: serde_json::from_value(json!({"one": {}})).unwrap(),
+            input: vec![],
+            diff: vec![ResourceDiff {
+                resource: "credentials".to_string(),
+                kind: kind.map(str::to_string),
+                differences: vec![Difference {
+                    path: path.to_string(),
+                    expected: json!("a"),
+                    actual: Some(json!("c2VjcmV0")),
+                }],
+            }],
+        };
+        let config = Config::default();
+        let diagnostic = redact_diagnostic(&config, diagnostic);
+        let expected = match redacted {
+            true => json!("<redacted>"),
+            false => json!("c2VjcmV0"),
+        };
+        assert_eq!(diagnostic.diff[0].differences[0].actual, Some(expected));
+    }
+}
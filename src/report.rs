@@ -0,0 +1,827 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::error::{Error, Result};
+use crate::events::{read_events, BucketChange, Event, EventKind, Failure};
+use crate::test_spec::TestType;
+use chrono::{DateTime, Utc};
+#[cfg(feature = "report-template")]
+use handlebars::{handlebars_helper, Handlebars};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+
+#[derive(Clone, Serialize)]
+pub struct StepReport {
+    pub name: String,
+    pub start: DateTime<Utc>,
+    pub end: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+}
+
+/// Change of a bucket observed during an attempt.
+#[derive(Clone, Serialize)]
+pub struct BucketEventReport {
+    pub time: DateTime<Utc>,
+    pub bucket: String,
+    pub change: BucketChange,
+    pub object: String,
+}
+
+/// Warning returned by the API server on an apply during an attempt.
+#[derive(Clone, Serialize)]
+pub struct WarningReport {
+    pub time: DateTime<Utc>,
+    pub step: String,
+    pub object: String,
+    pub warning: String,
+}
+
+/// Condition skipped during an attempt, as its API is not served by the cluster.
+#[derive(Clone, Serialize)]
+pub struct SkippedConditionReport {
+    pub time: DateTime<Utc>,
+    pub step: String,
+    pub target: String,
+    pub reason: String,
+}
+
+#[derive(Clone, Default, Serialize)]
+pub struct AttemptReport {
+    pub start: Option<DateTime<Utc>>,
+    pub end: Option<DateTime<Utc>>,
+    pub namespace: Option<String>,
+    pub steps: Vec<StepReport>,
+    pub bucket_events: Vec<BucketEventReport>,
+    pub warnings: Vec<WarningReport>,
+    pub skipped_conditions: Vec<SkippedConditionReport>,
+    pub finished: bool,
+    pub failure: Option<Failure>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct TestReport {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub test_type: Option<TestType>,
+    pub skipped: bool,
+    pub attempts: Vec<AttemptReport>,
+}
+
+#[derive(Clone, Default, Serialize)]
+pub struct SuiteReport {
+    pub start: Option<DateTime<Utc>>,
+    pub end: Option<DateTime<Utc>>,
+    pub tests: Vec<TestReport>,
+}
+
+fn seconds(start: Option<DateTime<Utc>>, end: Option<DateTime<Utc>>) -> f64 {
+    match (start, end) {
+        (Some(start), Some(end)) => (end - start).num_milliseconds() as f64 / 1000.0,
+        _ => 0.0,
+    }
+}
+
+impl StepReport {
+    pub fn seconds(&self) -> f64 {
+        seconds(Some(self.start), self.end)
+    }
+}
+
+impl AttemptReport {
+    pub fn seconds(&self) -> f64 {
+        seconds(self.start, self.end)
+    }
+}
+
+impl TestReport {
+    fn new(name: &str) -> Self {
+        TestReport {
+            name: name.to_string(),
+            test_type: None,
+            skipped: false,
+            attempts: vec![],
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.attempts
+            .last()
+            .is_some_and(|attempt| attempt.finished && attempt.failure.is_none())
+    }
+
+    /// The failure of the last attempt, or of an attempt that never finished.
+    pub fn failure(&self) -> Option<Failure> {
+        let attempt = self.attempts.last()?;
+        match (&attempt.failure, attempt.finished) {
+            (Some(failure), _) => Some(failure.clone()),
+            (None, false) => Some(Failure {
+                step: attempt
+                    .steps
+                    .last()
+                    .map(|step| step.name.clone())
+                    .unwrap_or_default(),
+                error: "Test did not finish".to_string(),
+                failures: vec![],
+            }),
+            (None, true) => None,
+        }
+    }
+
+    pub fn seconds(&self) -> f64 {
+        self.attempts.iter().map(AttemptReport::seconds).sum()
+    }
+}
+
+impl SuiteReport {
+    pub fn from_events(events: &[Event]) -> Self {
+        let mut report = SuiteReport::default();
+        for event in events {
+            match &event.kind {
+                EventKind::SuiteStarted => report.start = Some(event.time),
+                EventKind::SuiteFinished { .. } => report.end = Some(event.time),
+                EventKind::SuitePaused
+                | EventKind::SuiteResumed
+                | EventKind::WaitPending { .. } => {}
+                EventKind::TestSkipped { test } => report.test(test).skipped = true,
+                EventKind::TestStarted {
+                    test,
+                    test_type,
+                    namespace,
+                } => {
+                    let test = report.test(test);
+                    test.test_type = Some(test_type.clone());
+                    test.attempts.push(AttemptReport {
+                        start: Some(event.time),
+                        namespace: Some(namespace.clone()),
+                        ..Default::default()
+                    });
+                }
+                EventKind::StepStarted { test, step } => {
+                    report.attempt(test).steps.push(StepReport {
+                        name: step.clone(),
+                        start: event.time,
+                        end: None,
+                        error: None,
+                    });
+                }
+                EventKind::StepFinished { test, step, error } => {
+                    if let Some(report) = report
+                        .attempt(test)
+                        .steps
+                        .iter_mut()
+                        .rev()
+                        .find(|s| s.name == *step)
+                    {
+                        report.end = Some(event.time);
+                        report.error = error.clone();
+                    }
+                }
+                EventKind::BucketChanged {
+                    test,
+                    bucket,
+                    change,
+                    object,
+                    ..
+                } => {
+                    // Deletions during cleanup are reported after the attempt has finished.
+                    if let Some(attempt) = report.test(test).attempts.last_mut() {
+                        attempt.bucket_events.push(BucketEventReport {
+                            time: event.time,
+                            bucket: bucket.clone(),
+                            change: *change,
+                            object: object.clone(),
+                        });
+                    }
+                }
+                EventKind::ApiWarning {
+                    test,
+                    step,
+                    object,
+                    warning,
+                } => {
+                    report.attempt(test).warnings.push(WarningReport {
+                        time: event.time,
+                        step: step.clone(),
+                        object: object.clone(),
+                        warning: warning.clone(),
+                    });
+                }
+                EventKind::ConditionSkipped {
+                    test,
+                    step,
+                    target,
+                    reason,
+                } => {
+                    report
+                        .attempt(test)
+                        .skipped_conditions
+                        .push(SkippedConditionReport {
+                            time: event.time,
+                            step: step.clone(),
+                            target: target.clone(),
+                            reason: reason.clone(),
+                        });
+                }
+                EventKind::TestFinished { test, failure } => {
+                    let attempt = report.attempt(test);
+                    attempt.end = Some(event.time);
+                    attempt.finished = true;
+                    attempt.failure = failure.clone();
+                }
+            }
+        }
+        report.end = report.end.or(events.last().map(|event| event.time));
+        report
+    }
+
+    fn test(&mut self, name: &str) -> &mut TestReport {
+        match self.tests.iter().position(|test| test.name == name) {
+            Some(index) => &mut self.tests[index],
+            None => {
+                self.tests.push(TestReport::new(name));
+                self.tests.last_mut().unwrap()
+            }
+        }
+    }
+
+    /// The current attempt of the test, started implicitly for tests that were never started.
+    fn attempt(&mut self, name: &str) -> &mut AttemptReport {
+        let test = self.test(name);
+        if test.attempts.last().is_none_or(|attempt| attempt.finished) {
+            test.attempts.push(AttemptReport::default());
+        }
+        test.attempts.last_mut().unwrap()
+    }
+
+    pub fn seconds(&self) -> f64 {
+        seconds(self.start, self.end)
+    }
+
+    pub fn count(&self) -> (usize, usize, usize) {
+        let skipped = self.tests.iter().filter(|test| test.skipped).count();
+        let passed = self.tests.iter().filter(|test| test.passed()).count();
+        (passed, self.tests.len() - passed - skipped, skipped)
+    }
+}
+
+pub fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn failure_details(failure: &Failure) -> String {
+    std::iter::once(failure.error.clone())
+        .chain(failure.failures.iter().map(|f| f.to_string()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn render_junit(report: &SuiteReport) -> String {
+    let (_, failed, skipped) = report.count();
+    let mut out = String::new();
+    let _ = writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(
+        out,
+        r#"<testsuites name="blackjack" tests="{}" failures="{failed}" skipped="{skipped}" time="{:.3}">"#,
+        report.tests.len(),
+        report.seconds()
+    );
+    let _ = writeln!(
+        out,
+        r#"  <testsuite name="blackjack" tests="{}" failures="{failed}" skipped="{skipped}" time="{:.3}">"#,
+        report.tests.len(),
+        report.seconds()
+    );
+    for test in &report.tests {
+        let classname = match &test.test_type {
+            Some(TestType::Cluster) => "cluster",
+            _ => "user",
+        };
+        let _ = write!(
+            out,
+            r#"    <testcase name="{}" classname="{classname}" time="{:.3}""#,
+            escape_xml(&test.name),
+            test.seconds()
+        );
+        match test.failure() {
+            _ if test.skipped => {
+                let _ = writeln!(out, ">\n      <skipped/>\n    </testcase>");
+            }
+            Some(failure) => {
+                let _ = writeln!(
+                    out,
+                    ">\n      <failure message=\"{}: {}\">{}</failure>\n    </testcase>",
+                    escape_xml(&failure.step),
+                    escape_xml(&failure.error),
+                    escape_xml(&failure_details(&failure))
+                );
+            }
+            None => {
+                let _ = writeln!(out, "/>");
+            }
+        }
+    }
+    let _ = writeln!(out, "  </testsuite>\n</testsuites>");
+    out
+}
+
+fn status(test: &TestReport) -> &'static str {
+    if test.skipped {
+        "skipped"
+    } else if test.passed() {
+        "passed"
+    } else {
+        "failed"
+    }
+}
+
+pub fn render_markdown(report: &SuiteReport) -> String {
+    let (passed, failed, skipped) = report.count();
+    let mut out = String::new();
+    let _ = writeln!(out, "# Test Report\n");
+    let _ = writeln!(
+        out,
+        "{passed} passed, {failed} failed, {skipped} skipped in {:.1}s\n",
+        report.seconds()
+    );
+    let _ = writeln!(out, "| Test | Status | Attempts | Duration |");
+    let _ = writeln!(out, "| --- | --- | --- | --- |");
+    for test in &report.tests {
+        let _ = writeln!(
+            out,
+            "| {} | {} | {} | {:.1}s |",
+            test.name,
+            status(test),
+            test.attempts.len(),
+            test.seconds()
+        );
+    }
+    for test in report.tests.iter().filter(|test| !test.skipped) {
+        if let Some(failure) = test.failure() {
+            let _ = writeln!(out, "\n## {}\n", test.name);
+            let _ = writeln!(out, "Failed in step `{}`:\n", failure.step);
+            let _ = writeln!(out, "```\n{}\n```", failure_details(&failure));
+        }
+    }
+    let warnings: Vec<(&str, &WarningReport)> = report
+        .tests
+        .iter()
+        .filter_map(|test| Some((test.name.as_str(), test.attempts.last()?)))
+        .flat_map(|(test, attempt)| attempt.warnings.iter().map(move |warning| (test, warning)))
+        .collect();
+    if !warnings.is_empty() {
+        let _ = writeln!(out, "\n## Warnings\n");
+        for (test, warning) in warnings {
+            let _ = writeln!(
+                out,
+                "- {test}/{}: `{}`: {}",
+                warning.step, warning.object, warning.warning
+            );
+        }
+    }
+    let skipped_conditions: Vec<(&str, &SkippedConditionReport)> = report
+        .tests
+        .iter()
+        .filter_map(|test| Some((test.name.as_str(), test.attempts.last()?)))
+        .flat_map(|(test, attempt)| {
+            attempt
+                .skipped_conditions
+                .iter()
+                .map(move |skipped| (test, skipped))
+        })
+        .collect();
+    if !skipped_conditions.is_empty() {
+        let _ = writeln!(out, "\n## Skipped Conditions\n");
+        for (test, skipped) in skipped_conditions {
+            let _ = writeln!(
+                out,
+                "- {test}/{}: `{}`: {}",
+                skipped.step, skipped.target, skipped.reason
+            );
+        }
+    }
+    out
+}
+
+const HTML_TEMPLATE: &str = include_str!("report.html");
+
+/// The report as JSON, with the status, duration and failure of each test filled in.
+fn report_data(report: &SuiteReport) -> serde_json::Value {
+    let tests: Vec<serde_json::Value> = report
+        .tests
+        .iter()
+        .map(|test| {
+            let mut value = serde_json::to_value(test).unwrap_or_default();
+            value["status"] = status(test).into();
+            value["seconds"] = test.seconds().into();
+            value["failure"] = serde_json::to_value(test.failure()).unwrap_or_default();
+            value
+        })
+        .collect();
+    serde_json::json!({
+        "start": report.start,
+        "end": report.end,
+        "tests": tests,
+    })
+}
+
+pub fn render_json(report: &SuiteReport) -> String {
+    serde_json::to_string_pretty(&report_data(report)).unwrap_or_default()
+}
+
+/// Renders a single self-contained HTML file, with the report embedded as JSON
+/// and rendered by embedded JavaScript.
+pub fn render_html(report: &SuiteReport) -> String {
+    // `</` would end the embedding script element early.
+    let data = report_data(report).to_string().replace("</", "<\\/");
+    HTML_TEMPLATE.replace("/*REPORT_DATA*/", &data)
+}
+
+#[cfg(feature = "report-template")]
+handlebars_helper!(json: |value: Json| value.to_string());
+
+/// Renders the Handlebars template at `path` with the data of the JSON report,
+/// plus the `passed`, `failed` and `skipped` counts and the `seconds` of the suite.
+/// Values are not HTML-escaped, as reports are mostly plain text or Markdown.
+#[cfg(feature = "report-template")]
+pub fn render_template(report: &SuiteReport, path: &Path) -> Result<String> {
+    let template = std::fs::read_to_string(path)?;
+    let mut handlebars = Handlebars::new();
+    handlebars.register_escape_fn(handlebars::no_escape);
+    handlebars.register_helper("json", Box::new(json));
+    let mut data = report_data(report);
+    let (passed, failed, skipped) = report.count();
+    data["passed"] = passed.into();
+    data["failed"] = failed.into();
+    data["skipped"] = skipped.into();
+    data["seconds"] = report.seconds().into();
+    handlebars
+        .render_template(&template, &data)
+        .map_err(|err| Error::ReportTemplateError(path.to_path_buf(), err.to_string()))
+}
+
+#[cfg(not(feature = "report-template"))]
+pub fn render_template(_report: &SuiteReport, _path: &Path) -> Result<String> {
+    Err(Error::FeatureDisabled("report-template"))
+}
+
+/// Outcome and duration of a test, as contained in JSON reports.
+#[derive(Clone, Deserialize)]
+pub struct TestSummary {
+    pub name: String,
+    pub status: String,
+    pub seconds: f64,
+}
+
+#[derive(Deserialize)]
+struct SuiteSummary {
+    tests: Vec<TestSummary>,
+}
+
+impl SuiteReport {
+    pub fn summaries(&self) -> Vec<TestSummary> {
+        self.tests
+            .iter()
+            .map(|test| TestSummary {
+                name: test.name.clone(),
+                status: status(test).to_string(),
+                seconds: test.seconds(),
+            })
+            .collect()
+    }
+}
+
+/// Reads the tests of a JSON report, or of an event log.
+pub fn read_summaries(path: &Path) -> Result<Vec<TestSummary>> {
+    let content = std::fs::read_to_string(path)?;
+    match serde_json::from_str::<SuiteSummary>(&content) {
+        Ok(suite) => Ok(suite.tests),
+        Err(_) => Ok(SuiteReport::from_events(&read_events(path)?).summaries()),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Regression {
+    pub name: String,
+    pub old_seconds: f64,
+    pub new_seconds: f64,
+}
+
+/// Differences between two runs of a suite.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ReportDiff {
+    /// Tests that failed in the new run, but not in the old one.
+    pub newly_failing: Vec<String>,
+    /// Tests that failed in the old run and passed in the new one.
+    pub fixed: Vec<String>,
+    pub regressions: Vec<Regression>,
+    /// Tests that are only part of the new run.
+    pub added: Vec<String>,
+    /// Tests that are only part of the old run.
+    pub removed: Vec<String>,
+}
+
+/// Compares two runs. A test that passed in both runs has regressed when it
+/// took at least `min_seconds` longer and `slowdown` percent longer than before.
+pub fn diff_reports(
+    old: &[TestSummary],
+    new: &[TestSummary],
+    slowdown: f64,
+    min_seconds: f64,
+) -> ReportDiff {
+    let old_tests: HashMap<&str, &TestSummary> =
+        old.iter().map(|test| (test.name.as_str(), test)).collect();
+    let new_names: Vec<&str> = new.iter().map(|test| test.name.as_str()).collect();
+    let mut diff = ReportDiff::default();
+    for test in new {
+        let old_test = old_tests.get(test.name.as_str());
+        let old_status = old_test.map(|old_test| old_test.status.as_str());
+        match (old_status, test.status.as_str()) {
+            (Some("failed"), "failed") => {}
+            (_, "failed") => diff.newly_failing.push(test.name.clone()),
+            (Some("failed"), "passed") => diff.fixed.push(test.name.clone()),
+            (Some("passed"), "passed") => {
+                let old_seconds = old_test.unwrap().seconds;
+                if test.seconds - old_seconds >= min_seconds
+                    && test.seconds >= old_seconds * (1.0 + slowdown / 100.0)
+                {
+                    diff.regressions.push(Regression {
+                        name: test.name.clone(),
+                        old_seconds,
+                        new_seconds: test.seconds,
+                    });
+                }
+            }
+            _ => {}
+        }
+        if old_test.is_none() {
+            diff.added.push(test.name.clone());
+        }
+    }
+    diff.removed = old
+        .iter()
+        .filter(|test| !new_names.contains(&test.name.as_str()))
+        .map(|test| test.name.clone())
+        .collect();
+    diff
+}
+
+pub fn render_diff_markdown(diff: &ReportDiff) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "# Test Report Comparison\n");
+    let _ = writeln!(
+        out,
+        "{} newly failing, {} fixed, {} slower\n",
+        diff.newly_failing.len(),
+        diff.fixed.len(),
+        diff.regressions.len()
+    );
+    for (title, tests) in [
+        ("Newly Failing", &diff.newly_failing),
+        ("Fixed", &diff.fixed),
+        ("Added", &diff.added),
+        ("Removed", &diff.removed),
+    ] {
+        if !tests.is_empty() {
+            let _ = writeln!(out, "## {title}\n");
+            for test in tests {
+                let _ = writeln!(out, "- {test}");
+            }
+            let _ = writeln!(out);
+        }
+    }
+    if !diff.regressions.is_empty() {
+        let _ = writeln!(out, "## Slower\n");
+        let _ = writeln!(out, "| Test | Before | After |");
+        let _ = writeln!(out, "| --- | --- | --- |");
+        for regression in &diff.regressions {
+            let _ = writeln!(
+                out,
+                "| {} | {:.1}s | {:.1}s |",
+                regression.name, regression.old_seconds, regression.new_seconds
+            );
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn events() -> Vec<Event> {
+        serde_json::from_str(
+            r#"[
+            {"time": "2024-01-01T00:00:00Z", "event": "suite-started"},
+            {"time": "2024-01-01T00:00:00Z", "event": "test-skipped", "test": "c"},
+            {"time": "2024-01-01T00:00:01Z", "event": "test-started", "test": "a", "type": "user", "namespace": "a-ns"},
+            {"time": "2024-01-01T00:00:01Z", "event": "step-started", "test": "a", "step": "one"},
+            {"time": "2024-01-01T00:00:02Z", "event": "bucket-changed", "test": "a", "bucket": "pods", "change": "create", "object": "Pod/p1"},
+            {"time": "2024-01-01T00:00:03Z", "event": "step-finished", "test": "a", "step": "one", "error": "Timed out after 2 seconds"},
+            {"time": "2024-01-01T00:00:03Z", "event": "test-finished", "test": "a", "failure": {"step": "one", "error": "Timed out after 2 seconds"}},
+            {"time": "2024-01-01T00:00:04Z", "event": "bucket-changed", "test": "a", "bucket": "pods", "change": "delete", "object": "Pod/p1"},
+            {"time": "2024-01-01T00:00:04Z", "event": "test-started", "test": "a", "type": "user", "namespace": "a-ns2"},
+            {"time": "2024-01-01T00:00:04Z", "event": "step-started", "test": "a", "step": "one"},
+            {"time": "2024-01-01T00:00:04Z", "event": "api-warning", "test": "a", "step": "one", "object": "Widget/w1", "warning": "unknown field \"spec.sise\""},
+            {"time": "2024-01-01T00:00:04Z", "event": "condition-skipped", "test": "a", "step": "one", "target": "pod-metrics", "reason": "API group 'metrics.k8s.io' is not served"},
+            {"time": "2024-01-01T00:00:05Z", "event": "step-finished", "test": "a", "step": "one"},
+            {"time": "2024-01-01T00:00:05Z", "event": "test-finished", "test": "a"},
+            {"time": "2024-01-01T00:00:05Z", "event": "test-started", "test": "b", "type": "user", "namespace": "b-ns"},
+            {"time": "2024-01-01T00:00:06Z", "event": "test-finished", "test": "b", "failure": {"step": "", "error": "Interrupted"}},
+            {"time": "2024-01-01T00:00:06Z", "event": "suite-finished", "success": false}
+        ]"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_suite_report() {
+        let report = SuiteReport::from_events(&events());
+        assert_eq!(report.count(), (1, 1, 1));
+        assert_eq!(report.seconds(), 6.0);
+        let a = &report.tests[1];
+        assert_eq!(a.name, "a");
+        assert_eq!(a.attempts.len(), 2);
+        assert_eq!(a.seconds(), 3.0);
+        assert_eq!(a.attempts[0].steps[0].seconds(), 2.0);
+        let changes: Vec<BucketChange> = a.attempts[0]
+            .bucket_events
+            .iter()
+            .map(|event| event.change)
+            .collect();
+        assert_eq!(changes, vec![BucketChange::Create, BucketChange::Delete]);
+        assert!(a.attempts[1].bucket_events.is_empty());
+        assert!(a.attempts[0].warnings.is_empty());
+        assert_eq!(a.attempts[1].warnings[0].object, "Widget/w1");
+        assert!(
+            render_markdown(&report).contains("- a/one: `Widget/w1`: unknown field \"spec.sise\"")
+        );
+        assert_eq!(a.attempts[1].skipped_conditions[0].target, "pod-metrics");
+        assert!(render_markdown(&report)
+            .contains("- a/one: `pod-metrics`: API group 'metrics.k8s.io' is not served"));
+        assert!(a.passed());
+        assert_eq!(report.tests[2].failure().unwrap().error, "Interrupted");
+    }
+
+    #[test]
+    fn test_render_markdown_describe() {
+        let mut events = events();
+        events.push(
+            serde_json::from_str(
+                r#"{"time": "2024-01-01T00:00:06Z", "event": "test-started", "test": "d", "type": "user", "namespace": "d-ns"}"#,
+            )
+            .unwrap(),
+        );
+        events.push(
+            serde_json::from_str(
+                r#"{"time": "2024-01-01T00:00:07Z", "event": "test-finished", "test": "d", "failure": {
+                    "step": "one",
+                    "error": "Conditions failed",
+                    "failures": [{
+                        "assert_diagnostic": {"expr": {"one": {"metadata": {"name": "web-0"}}}, "input": []},
+                        "spec": {"target": "pods", "condition": {"one": {"metadata": {"name": "web-0"}}}, "timeout": 5},
+                        "describe": [{"resource": "Pod/web-0", "status": {"phase": "Pending"}, "events": ["Warning BackOff (x5): Back-off restarting failed container"]}]
+                    }]
+                }}"#,
+            )
+            .unwrap(),
+        );
+        let markdown = render_markdown(&SuiteReport::from_events(&events));
+        assert!(markdown.contains("Describe Pod/web-0:\n  Status: {\"phase\":\"Pending\"}\n"));
+        assert!(markdown.contains("    Warning BackOff (x5): Back-off restarting failed container"));
+        assert_eq!(markdown.matches("\"describe\"").count(), 0);
+    }
+
+    #[test]
+    fn test_render_junit() {
+        let junit = render_junit(&SuiteReport::from_events(&events()));
+        assert!(junit.contains(r#"tests="3" failures="1" skipped="1""#));
+        assert!(junit.contains(r#"<testcase name="a" classname="user" time="3.000"/>"#));
+        assert!(junit.contains(r#"<failure message=": Interrupted">Interrupted</failure>"#));
+    }
+
+    #[test]
+    fn test_render_html() {
+        let mut events = events();
+        events.push(
+            serde_json::from_str(
+                r#"{"time": "2024-01-01T00:00:06Z", "event": "test-skipped", "test": "</script>"}"#,
+            )
+            .unwrap(),
+        );
+        let html = render_html(&SuiteReport::from_events(&events));
+        assert!(!html.contains("/*REPORT_DATA*/"));
+        assert_eq!(html.matches("</script>").count(), 2);
+        let start = html.find(r#"id="report-data">"#).unwrap() + 17;
+        let end = start + html[start..].find("</script>").unwrap();
+        let data: serde_json::Value = serde_json::from_str(&html[start..end]).unwrap();
+        assert_eq!(data["tests"][1]["status"], "passed");
+        assert_eq!(data["tests"][1]["attempts"][0]["steps"][0]["name"], "one");
+        assert_eq!(data["tests"][3]["name"], "</script>");
+    }
+
+    #[test]
+    fn test_render_json() {
+        let data: serde_json::Value =
+            serde_json::from_str(&render_json(&SuiteReport::from_events(&events()))).unwrap();
+        assert_eq!(data["tests"][2]["status"], "failed");
+        let bucket_events = &data["tests"][1]["attempts"][0]["bucket_events"];
+        assert_eq!(bucket_events[0]["object"], "Pod/p1");
+        assert_eq!(bucket_events[1]["change"], "delete");
+    }
+
+    #[cfg(feature = "report-template")]
+    #[test]
+    fn test_render_template() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.hbs");
+        std::fs::write(
+            &path,
+            "{{passed}}/{{failed}}/{{skipped}}\n{{#each tests}}{{name}}: {{status}}{{#if failure}}: {{failure.error}}{{/if}}\n{{/each}}{{json tests.1.type}}\n{{tests.1.attempts.1.warnings.0.warning}}",
+        )
+        .unwrap();
+        let output = render_template(&SuiteReport::from_events(&events()), &path).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[0], "1/1/1");
+        assert_eq!(
+            lines[1..],
+            [
+                "c: skipped",
+                "a: passed",
+                "b: failed: Interrupted",
+                "\"user\"",
+                "unknown field \"spec.sise\""
+            ]
+        );
+        std::fs::write(&path, "{{#each tests}}").unwrap();
+        assert!(matches!(
+            render_template(&SuiteReport::from_events(&events()), &path),
+            Err(Error::ReportTemplateError(_, _))
+        ));
+    }
+
+    fn summary(name: &str, status: &str, seconds: f64) -> TestSummary {
+        TestSummary {
+            name: name.to_string(),
+            status: status.to_string(),
+            seconds,
+        }
+    }
+
+    #[test]
+    fn test_diff_reports() {
+        let old = vec![
+            summary("a", "failed", 1.0),
+            summary("b", "passed", 1.0),
+            summary("c", "failed", 1.0),
+            summary("d", "passed", 1.0),
+        ];
+        let new = vec![
+            summary("a", "passed", 1.0),
+            summary("b", "failed", 1.0),
+            summary("c", "failed", 1.0),
+            summary("e", "failed", 1.0),
+        ];
+        let diff = diff_reports(&old, &new, 50.0, 10.0);
+        assert_eq!(diff.newly_failing, vec!["b", "e"]);
+        assert_eq!(diff.fixed, vec!["a"]);
+        assert_eq!(diff.added, vec!["e"]);
+        assert_eq!(diff.removed, vec!["d"]);
+        assert!(diff.regressions.is_empty());
+    }
+
+    #[rstest]
+    #[case(10.0, 20.0, true)]
+    #[case(10.0, 14.0, false)]
+    #[case(100.0, 140.0, false)]
+    #[case(100.0, 150.0, true)]
+    fn test_diff_regressions(#[case] old: f64, #[case] new: f64, #[case] expected: bool) {
+        let diff = diff_reports(
+            &[summary("a", "passed", old)],
+            &[summary("a", "passed", new)],
+            50.0,
+            10.0,
+        );
+        assert_eq!(!diff.regressions.is_empty(), expected);
+    }
+
+    #[test]
+    fn test_read_summaries() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(
+            file.path(),
+            render_json(&SuiteReport::from_events(&events())),
+        )
+        .unwrap();
+        let summaries = read_summaries(file.path()).unwrap();
+        let statuses: Vec<&str> = summaries.iter().map(|t| t.status.as_str()).collect();
+        assert_eq!(statuses, vec!["skipped", "passed", "failed"]);
+        assert_eq!(summaries[1].seconds, 3.0);
+    }
+}
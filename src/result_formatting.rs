@@ -1,8 +1,9 @@
 // Copyright 2024 Ole Kliemann
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::error::{FailedTest, TestResult};
+use crate::error::{Error, FailedTest, ScriptFailure, TestResult};
 use colored::Colorize;
+use std::path::Path;
 
 pub fn log_result(result: &TestResult) {
     match result {
@@ -20,7 +21,65 @@ pub fn log_result(result: &TestResult) {
                 test_name,
                 step_name
             );
-            log::info!("{}", failure);
+            match failure {
+                Error::ScriptFailed(script_failure) => log_script_failure(script_failure),
+                failure => log::info!("{}", failure),
+            }
         }
     }
 }
+
+fn log_script_failure(failure: &ScriptFailure) {
+    log::info!(
+        "{}  {} ({})",
+        "Script failed".red(),
+        failure.script,
+        failure.exit()
+    );
+    for (title, lines) in [("stdout", &failure.stdout), ("stderr", &failure.stderr)] {
+        if !lines.is_empty() {
+            log::info!("{}", format!("Last lines of {title}:").bold());
+            for line in lines {
+                log::info!("  {}", line.dimmed());
+            }
+        }
+    }
+    log::info!("{}", "Environment:".bold());
+    for (name, value) in &failure.env {
+        log::info!("  {}={}", name, value);
+    }
+}
+
+pub fn log_skipped(test_name: &str) {
+    log::info!("{}  {}", "Test skipped".yellow().bold(), test_name);
+}
+
+pub fn log_validation(test_name: &str, path: &Path, problems: &[String]) {
+    if problems.is_empty() {
+        log::info!("{}  {}", "Test valid".green().bold(), test_name);
+    } else {
+        log::info!(
+            "{}  {} ({})",
+            "Test invalid".red().bold(),
+            test_name,
+            path.display()
+        );
+        for problem in problems {
+            log::info!("  {}", problem);
+        }
+    }
+}
+
+pub fn log_format(path: &Path, check: bool, result: std::result::Result<bool, String>) {
+    match result {
+        Ok(false) => log::info!("{}  {}", "Formatted".green().bold(), path.display()),
+        Ok(true) if check => log::info!("{}  {}", "Not formatted".red().bold(), path.display()),
+        Ok(true) => log::info!("{}  {}", "Reformatted".yellow().bold(), path.display()),
+        Err(err) => log::info!(
+            "{}  {}: {}",
+            "Unable to format".red().bold(),
+            path.display(),
+            err
+        ),
+    }
+}
@@ -0,0 +1,250 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::error::{Error, Result, TestResult};
+use chrono::Utc;
+use k8s_openapi::api::core::v1::{ConfigMap, Event, EventSource, Namespace, ObjectReference};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+use kube::api::{ObjectMeta, Patch, PatchParams, PostParams};
+use kube::{Api, Client};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+pub const RESULTS_CONFIGMAP: &str = "blackjack-results";
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Passed,
+    Failed,
+    Skipped,
+}
+
+/// Outcome of a single test, as published in the results ConfigMap.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TestOutcome {
+    pub test: String,
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub step: Option<String>,
+    /// First line of the error, the full diagnostics are only logged.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Summary {
+    pub success: bool,
+    pub passed: usize,
+    pub failed: usize,
+    pub skipped: usize,
+}
+
+pub fn outcomes(results: &[TestResult], skipped: &[String]) -> Vec<TestOutcome> {
+    results
+        .iter()
+        .map(|result| match result {
+            Ok(test) => TestOutcome {
+                test: test.clone(),
+                status: Status::Passed,
+                step: None,
+                error: None,
+            },
+            Err(failed_test) => TestOutcome {
+                test: failed_test.test_name.clone(),
+                status: Status::Failed,
+                step: Some(failed_test.step_name.clone()),
+                error: failed_test
+                    .failure
+                    .to_string()
+                    .lines()
+                    .next()
+                    .map(String::from),
+            },
+        })
+        .chain(skipped.iter().map(|test| TestOutcome {
+            test: test.clone(),
+            status: Status::Skipped,
+            step: None,
+            error: None,
+        }))
+        .collect()
+}
+
+pub fn summary(outcomes: &[TestOutcome]) -> Summary {
+    let count = |status: Status| outcomes.iter().filter(|o| o.status == status).count();
+    let failed = count(Status::Failed);
+    Summary {
+        success: failed == 0,
+        passed: count(Status::Passed),
+        failed,
+        skipped: count(Status::Skipped),
+    }
+}
+
+fn event_message(outcome: &TestOutcome) -> (&'static str, &'static str, String) {
+    match outcome.status {
+        Status::Passed => (
+            "Normal",
+            "TestPassed",
+            format!("Test '{}' passed", outcome.test),
+        ),
+        Status::Failed => (
+            "Warning",
+            "TestFailed",
+            format!(
+                "Test '{}' failed in step '{}': {}",
+                outcome.test,
+                outcome.step.clone().unwrap_or_default(),
+                outcome.error.clone().unwrap_or_default()
+            ),
+        ),
+        Status::Skipped => (
+            "Normal",
+            "TestSkipped",
+            format!("Test '{}' skipped", outcome.test),
+        ),
+    }
+}
+
+fn suite_event_message(summary: &Summary) -> (&'static str, &'static str, String) {
+    let message = format!(
+        "{} passed, {} failed, {} skipped",
+        summary.passed, summary.failed, summary.skipped
+    );
+    match summary.success {
+        true => ("Normal", "SuitePassed", message),
+        false => ("Warning", "SuiteFailed", message),
+    }
+}
+
+/// Publishes the outcomes of a test run into `namespace`: the ConfigMap
+/// `blackjack-results` holds the summary and the outcome of every test,
+/// and an Event referring to the ConfigMap is created for every test and for the suite.
+pub async fn publish_results(
+    client: Client,
+    namespace: &str,
+    results: &[TestResult],
+    skipped: &[String],
+) -> Result<()> {
+    let outcomes = outcomes(results, skipped);
+    let summary = summary(&outcomes);
+
+    let namespaces: Api<Namespace> = Api::all(client.clone());
+    let ns = Namespace {
+        metadata: ObjectMeta {
+            name: Some(namespace.to_string()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    match namespaces.create(&PostParams::default(), &ns).await {
+        Ok(_) => log::debug!("Created results namespace '{namespace}'"),
+        Err(kube::Error::Api(ae)) if ae.code == 409 => {}
+        Err(e) => return Err(Error::from(e)),
+    }
+
+    let config_maps: Api<ConfigMap> = Api::namespaced(client.clone(), namespace);
+    let config_map = ConfigMap {
+        metadata: ObjectMeta {
+            name: Some(RESULTS_CONFIGMAP.to_string()),
+            namespace: Some(namespace.to_string()),
+            labels: Some(BTreeMap::from([(
+                "app.kubernetes.io/managed-by".to_string(),
+                "blackjack".to_string(),
+            )])),
+            ..Default::default()
+        },
+        data: Some(BTreeMap::from([
+            ("summary".to_string(), serde_json::to_string(&summary)?),
+            ("results".to_string(), serde_json::to_string(&outcomes)?),
+            ("finished".to_string(), Utc::now().to_rfc3339()),
+        ])),
+        ..Default::default()
+    };
+    let config_map = config_maps
+        .patch(
+            RESULTS_CONFIGMAP,
+            &PatchParams::apply("blackjack").force(),
+            &Patch::Apply(&config_map),
+        )
+        .await?;
+
+    let involved_object = ObjectReference {
+        api_version: Some("v1".to_string()),
+        kind: Some("ConfigMap".to_string()),
+        name: Some(RESULTS_CONFIGMAP.to_string()),
+        namespace: Some(namespace.to_string()),
+        uid: config_map.metadata.uid.clone(),
+        resource_version: config_map.metadata.resource_version.clone(),
+        ..Default::default()
+    };
+    let events: Api<Event> = Api::namespaced(client, namespace);
+    for (event_type, reason, message) in outcomes
+        .iter()
+        .map(event_message)
+        .chain(std::iter::once(suite_event_message(&summary)))
+    {
+        let now = Time(Utc::now());
+        let event = Event {
+            metadata: ObjectMeta {
+                generate_name: Some(format!("{RESULTS_CONFIGMAP}.")),
+                namespace: Some(namespace.to_string()),
+                ..Default::default()
+            },
+            involved_object: involved_object.clone(),
+            type_: Some(event_type.to_string()),
+            reason: Some(reason.to_string()),
+            message: Some(message),
+            source: Some(EventSource {
+                component: Some("blackjack".to_string()),
+                ..Default::default()
+            }),
+            first_timestamp: Some(now.clone()),
+            last_timestamp: Some(now),
+            count: Some(1),
+            ..Default::default()
+        };
+        events.create(&PostParams::default(), &event).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::FailedTest;
+
+    #[test]
+    fn test_outcomes() {
+        let results = vec![
+            Ok("a".to_string()),
+            Err(FailedTest {
+                test_name: "b".to_string(),
+                step_name: "one".to_string(),
+                failure: Error::Other("first\nsecond".to_string()),
+            }),
+        ];
+        let outcomes = outcomes(&results, &["c".to_string()]);
+        assert_eq!(outcomes[1].step.as_deref(), Some("one"));
+        assert_eq!(outcomes[1].error.as_deref(), Some("Other error: first"));
+        assert_eq!(outcomes[2].status, Status::Skipped);
+        assert_eq!(
+            summary(&outcomes),
+            Summary {
+                success: false,
+                passed: 1,
+                failed: 1,
+                skipped: 1,
+            }
+        );
+        assert_eq!(
+            event_message(&outcomes[1]),
+            (
+                "Warning",
+                "TestFailed",
+                "Test 'b' failed in step 'one': Other error: first".to_string()
+            )
+        );
+    }
+}
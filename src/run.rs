@@ -0,0 +1,151 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::collector::SharedWatches;
+use crate::error::Result;
+use crate::events::EventLog;
+use crate::log_prefix;
+use crate::pacing::Buckets;
+use crate::progress::Progress;
+use crate::rbac::Denied;
+use chrono::Utc;
+use kube::Discovery;
+use once_cell::sync::OnceCell;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tempfile::NamedTempFile;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+tokio::task_local! {
+    static RUN: Arc<Run>;
+}
+
+/// State shared by the tests of a run, owned by `run_test_suite` or a `TestRunner`.
+/// It is only visible to the tasks of the run, so runs in the same process do not share it.
+pub struct Run {
+    pub id: String,
+    pub(crate) events: Option<EventLog>,
+    pub(crate) progress: Option<Arc<Mutex<Progress>>>,
+    pub(crate) paused: watch::Sender<bool>,
+    pub(crate) denied: Arc<Mutex<Denied>>,
+    /// Discovery of the API resources of the cluster, run again once invalidated.
+    pub(crate) discovery: tokio::sync::Mutex<Option<Arc<Discovery>>>,
+    pub(crate) shared_watches: Arc<Mutex<SharedWatches>>,
+    pub(crate) buckets: Mutex<Buckets>,
+    /// Optional API groups not served by the cluster, with the reason to skip conditions on them.
+    pub(crate) unavailable: OnceCell<BTreeMap<String, String>>,
+    /// Kubeconfig pinned to the context of the config, exported to scripts as `KUBECONFIG`.
+    pub(crate) kubeconfig: OnceCell<Option<NamedTempFile>>,
+}
+
+impl Default for Run {
+    fn default() -> Self {
+        Run {
+            id: new_id(),
+            events: None,
+            progress: None,
+            paused: watch::channel(false).0,
+            denied: Default::default(),
+            discovery: Default::default(),
+            shared_watches: Default::default(),
+            buckets: Default::default(),
+            unavailable: OnceCell::new(),
+            kubeconfig: OnceCell::new(),
+        }
+    }
+}
+
+/// Identifies a run by when it started, e.g. `20240101-120000-abc123`.
+fn new_id() -> String {
+    let suffix: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(6)
+        .map(|c| char::from(c).to_ascii_lowercase())
+        .collect();
+    format!("{}-{suffix}", Utc::now().format("%Y%m%d-%H%M%S"))
+}
+
+impl Run {
+    /// Writes the events of the run to the file at `path`, if set.
+    /// Without it, events are discarded.
+    pub fn with_events(self, path: Option<&Path>) -> Result<Self> {
+        match path {
+            Some(path) => Ok(Run {
+                events: Some(EventLog::create(path)?),
+                ..self
+            }),
+            None => Ok(self),
+        }
+    }
+
+    /// Draws the progress of the run below the log lines, if `progress` is set.
+    pub fn with_progress(self, progress: bool) -> Self {
+        if progress {
+            Run {
+                progress: Some(Default::default()),
+                ..self
+            }
+        } else {
+            self
+        }
+    }
+}
+
+/// Runs the future as part of `run`, including the tasks it spawns via `spawn`.
+pub async fn scope<F: Future>(run: Arc<Run>, future: F) -> F::Output {
+    RUN.scope(run, future).await
+}
+
+/// The run the current task is part of, if any.
+pub fn current() -> Option<Arc<Run>> {
+    RUN.try_with(Clone::clone).ok()
+}
+
+/// Identifies the run the current task is part of, empty outside of a run.
+pub fn id() -> String {
+    current().map(|run| run.id.clone()).unwrap_or_default()
+}
+
+/// Wraps the future to be part of the run of the current task, when spawned as a task.
+pub(crate) fn inherit<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    let run = current();
+    async move {
+        match run {
+            Some(run) => RUN.scope(run, future).await,
+            None => future.await,
+        }
+    }
+}
+
+/// Spawns the future as a task which keeps the run and the log prefix of the current task.
+pub(crate) fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    log_prefix::spawn(inherit(future))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_scope() {
+        assert!(current().is_none());
+        let run = Arc::new(Run::default());
+        let (id, spawned) = scope(run.clone(), async {
+            let spawned = spawn(async { current().map(|run| run.id.clone()) });
+            (current().unwrap().id.clone(), spawned.await.unwrap())
+        })
+        .await;
+        assert_eq!(id, run.id);
+        assert_eq!(spawned, Some(run.id.clone()));
+        assert_ne!(Run::default().id, run.id);
+    }
+}
@@ -1,52 +1,166 @@
 // Copyright 2024 Ole Kliemann
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::audit::Audit;
+use crate::certificate::wait_for_certificates;
+use crate::chaos::run_all_chaos;
 use crate::collector::{Bucket, CollectedDataContainer, Collector};
 use crate::config::Config;
+use crate::container::ScriptContainer;
+use crate::conversion::expand_conversion;
+use crate::dns::wait_for_dns;
+use crate::dump::dump_failed_test;
 use crate::error::{Error, FailedTest, Result, TestResult};
+use crate::events::{emit, emit_test_finished, EventKind};
+use crate::export::{export_manifests, export_values};
 use crate::file::{list_directories, list_files};
+use crate::fixtures::Fixtures;
+use crate::gherkin::FEATURE_FILE;
+use crate::locks::Locks;
+use crate::log_prefix;
+use crate::logs::wait_for_logs;
 use crate::manifest::ManifestHandle;
-use crate::namespace::NamespaceHandle;
-use crate::result_formatting::log_result;
-use crate::script::execute_script;
-use crate::test_spec::{EnvSubst, StepSpec, TestSpec, TestType, WaitSpec};
-use crate::wait::wait_for_all;
+use crate::messages::{subscribe, wait_for_messages};
+use crate::mqtt::{publish, subscribe_to_buckets};
+use crate::namespace::{NamespaceHandle, DELETION_TIMEOUT};
+use crate::naming::Naming;
+use crate::patch::apply_patches;
+use crate::pause;
+use crate::port_forward::PortForward;
+use crate::preflight::{self, skip_unavailable};
+use crate::progress;
+use crate::rbac;
+use crate::redact::redact_failure;
+use crate::result_formatting::{log_result, log_skipped};
+use crate::results::publish_results;
+use crate::run::{self, Run};
+use crate::s3::wait_for_s3;
+use crate::schedule::{configured_durations, sort_tests};
+use crate::script::{execute_script, script_dir, script_failure};
+use crate::shutdown::Shutdown;
+use crate::sql::wait_for_sql;
+use crate::state::State;
+use crate::test_filter::TestFilter;
+use crate::test_spec::{
+    AssertSpec, CertificateSpec, DnsSpec, EnvSubst, ExportManifestsSpec, ExportSpec, LogsSpec,
+    PortForwardScope, S3Spec, SqlSpec, StepSpec, SuiteSpec, TestSpec, TestType, WaitSpec,
+};
+use crate::wait::{assert_all, load_assert_file, wait_for_all, wait_for_quiescence};
+use crate::warnings;
+use crate::webhook::start_receivers;
+use chrono::Utc;
+use futures::future::join_all;
 use kube::Client;
-use std::cmp;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tokio::task::{JoinHandle, JoinSet};
 use tokio::time::{sleep, Duration};
 
-fn make_namespace(name: &String) -> String {
-    let mut truncated_name = name.clone();
+/// How often another name is tried if the namespace of a test already exists.
+const NAMESPACE_ATTEMPTS: usize = 5;
+
+fn make_namespace(name: &str, naming: Naming) -> String {
+    let mut truncated_name = name.to_string();
     truncated_name.truncate(32);
-    format!(
-        "{}-{}-{}",
-        truncated_name,
-        random_word::gen_len(8, random_word::Lang::En)
-            .or_else(|| Some(""))
-            .unwrap(),
-        random_word::gen_len(8, random_word::Lang::En)
-            .or_else(|| Some(""))
-            .unwrap()
-    )
+    format!("{}-{}", truncated_name, naming.suffix(16))
+}
+
+/// Creates a namespace with a unique name for the test,
+/// trying another name if the namespace already exists.
+async fn create_namespace(
+    client: Client,
+    config: &Config,
+    test_name: &str,
+) -> (NamespaceHandle, Result<()>) {
+    let mut attempts = 1;
+    loop {
+        let namespace = make_namespace(test_name, config.naming);
+        let namespace_handle = NamespaceHandle::new(client.clone(), &namespace);
+        match namespace_handle.create().await {
+            Err(Error::NamespaceExists) if attempts < NAMESPACE_ATTEMPTS => {
+                log::debug!("Namespace '{namespace}' already exists, trying another name");
+                attempts += 1;
+            }
+            result => return (namespace_handle, result),
+        }
+    }
+}
+
+/// What the steps of a test create and its cleanup releases.
+struct TestResources {
+    manifests: Vec<ManifestHandle>,
+    collectors: Vec<Collector>,
+    port_forwards: Vec<(PortForwardScope, PortForward)>,
+    collected_data: CollectedDataContainer,
+    /// Client of the cleanup, which does not inject the faults of the test.
+    cleanup_client: Client,
+}
+
+impl TestResources {
+    fn new(collected_data: CollectedDataContainer, cleanup_client: Client) -> Self {
+        TestResources {
+            manifests: vec![],
+            collectors: vec![],
+            port_forwards: vec![],
+            collected_data,
+            cleanup_client,
+        }
+    }
 }
 
 async fn run_step(
     client: Client,
+    config: &Config,
     dirname: PathBuf,
     test_name: &str,
     step: StepSpec,
-    manifests: &mut Vec<ManifestHandle>,
-    collectors: &mut Vec<Collector>,
-    collected_data: &CollectedDataContainer,
+    resources: &mut TestResources,
     inherited_env: HashMap<String, String>,
 ) -> Result<HashMap<String, String>> {
+    let started = Utc::now();
+    let TestResources {
+        manifests,
+        collectors,
+        port_forwards,
+        collected_data,
+        cleanup_client,
+    } = resources;
+    let collected_data = &*collected_data;
     let mut env: HashMap<String, String> = inherited_env;
+    let mut step = step;
+    log::debug!("Expanding conversion checks");
+    for conversion in std::mem::take(&mut step.conversion) {
+        let (watch, apply, wait) = expand_conversion(conversion.subst_env(&env), &dirname).await?;
+        step.watch.push(watch);
+        step.apply.push(apply);
+        step.wait.push(wait);
+    }
+
     log::debug!("Creating collector");
-    let watches: Vec<_> = step.watch.into_iter().map(|w| w.subst_env(&env)).collect();
-    collectors.push(Collector::new(client.clone(), watches, collected_data.clone()).await?);
+    let mut unavailable = vec![];
+    let watches: Vec<_> = step
+        .watch
+        .into_iter()
+        .map(|w| config.apply_watch_defaults(w.subst_env(&env)))
+        .filter(|watch| match preflight::unavailable(&watch.group) {
+            Some(reason) => {
+                log::warn!("Not watching bucket '{}': {reason}", watch.name);
+                unavailable.push((watch.name.clone(), reason));
+                false
+            }
+            None => true,
+        })
+        .collect();
+    collected_data.lock().await.unavailable.extend(unavailable);
+    let collector = Collector::new(
+        client.clone(),
+        test_name,
+        watches,
+        collected_data.clone(),
+        config.share_watches,
+    );
+    collectors.push(collector.await?);
 
     log::debug!("Setting buckets");
     for bucket_spec in &step.bucket {
@@ -54,17 +168,33 @@ async fn run_step(
         (*data)
             .buckets
             .entry(bucket_spec.name.clone())
-            .and_modify(|bucket| bucket.allowed_operations = bucket_spec.operations.clone())
-            .or_insert_with(|| Bucket::new(bucket_spec.operations.clone()));
+            .and_modify(|bucket| {
+                bucket.allowed_operations = bucket_spec.operations.clone();
+                bucket.tombstones.clear();
+            })
+            .or_insert_with(|| Bucket::new(bucket_spec.operations.clone()))
+            .keep_history |= bucket_spec.history;
     }
 
     log::debug!("Applying manifests");
+    let audit = Audit {
+        run_id: run::id(),
+        test: test_name.to_string(),
+        step: step.name.clone(),
+        spec: TestSpec::spec_path(&dirname),
+    };
     for apply in step.apply {
         let apply = apply.subst_env(&env);
         log::debug!("Creating manifest: {:?}", apply);
-        let handle = ManifestHandle::new(apply, dirname.clone(), client.clone()).await?;
+        let handle = ManifestHandle::new(apply, dirname.clone(), client.clone())
+            .await?
+            .with_audit(&audit)
+            .with_apply_rate(config.apply_rate.clone())
+            .with_cleanup_client(cleanup_client.clone());
         log::debug!("Applying manifest");
-        handle.apply().await?;
+        let warnings = handle.apply(!config.strict_cluster).await?;
+        warnings::check(config, test_name, &step.name, warnings)?;
+        handle.wait_established(client.clone(), config).await?;
         manifests.push(handle);
     }
 
@@ -78,14 +208,120 @@ async fn run_step(
             .await?;
     }
 
+    log::debug!("Patching resources");
+    let patch: Vec<_> = step.patch.into_iter().map(|p| p.subst_env(&env)).collect();
+    if !patch.is_empty() {
+        apply_patches(client.clone(), patch).await?;
+    }
+
+    log::debug!("Running chaos actions");
+    let chaos: Vec<_> = step.chaos.into_iter().map(|c| c.subst_env(&env)).collect();
+    if !chaos.is_empty() {
+        run_all_chaos(client.clone(), chaos).await?;
+    }
+
+    log::debug!("Starting port forwards");
+    for port_forward in step.port_forward {
+        let port_forward = port_forward.subst_env(&env);
+        let (scope, env_name) = (port_forward.scope, port_forward.env_name());
+        let (handle, port) = PortForward::start(client.clone(), port_forward).await?;
+        log::debug!("Forwarding local port {port} as {env_name}");
+        env.insert(env_name, port.to_string());
+        port_forwards.push((scope, handle));
+    }
+
+    log::debug!("Starting webhook receivers");
+    let receivers: Vec<_> = step
+        .webhook_receiver
+        .into_iter()
+        .map(|r| r.subst_env(&env))
+        .collect();
+    if !receivers.is_empty() {
+        let (collector, urls) = start_receivers(
+            client.clone(),
+            config,
+            test_name,
+            receivers,
+            collected_data.clone(),
+        )
+        .await?;
+        collectors.push(collector);
+        env.extend(urls);
+    }
+
+    log::debug!("Subscribing to messages");
+    let mut subscriptions = Vec::new();
+    for messages in step.messages {
+        subscriptions.push(subscribe(messages.subst_env(&env)).await?);
+    }
+
+    log::debug!("Subscribing to MQTT topics");
+    let mqtt_subscriptions: Vec<_> = step
+        .mqtt_subscribe_to_bucket
+        .into_iter()
+        .map(|s| s.subst_env(&env))
+        .collect();
+    if !mqtt_subscriptions.is_empty() {
+        let collector =
+            subscribe_to_buckets(test_name, mqtt_subscriptions, collected_data.clone()).await?;
+        collectors.push(collector);
+    }
+
+    log::debug!("Publishing MQTT messages");
+    for mqtt_publish in step.mqtt_publish {
+        publish(mqtt_publish.subst_env(&env)).await?;
+    }
+
     log::debug!("Running scripts");
+    let workdir = script_dir(&dirname, step.workdir.as_deref(), &env);
+    let container = match step.script_container {
+        Some(spec) if !step.script.is_empty() => Some(
+            ScriptContainer::start(
+                client.clone(),
+                config,
+                spec.subst_env(&env),
+                workdir.clone(),
+            )
+            .await?,
+        ),
+        _ => None,
+    };
+    let mut script_result = Ok(());
     for script in step.script {
-        let (status, stdout, stderr) = execute_script(&script, dirname.clone(), &mut env).await?;
-        status
-            .success()
-            .then_some(())
-            .ok_or(Error::ScriptFailed(stdout, stderr))?;
+        let execution = async {
+            match &container {
+                Some(container) => container.execute(script.command(), &mut env).await,
+                None => {
+                    let shell = script.shell().unwrap_or(config.shell);
+                    execute_script(config, script.command(), shell, workdir.clone(), &mut env).await
+                }
+            }
+        };
+        // Dropping the execution on timeout kills a local script or removes its docker or podman
+        // container. The command keeps running in a script pod until the pod is deleted below.
+        let result = match script.timeout() {
+            Some(timeout) => tokio::time::timeout(config.scaled_timeout(timeout), execution)
+                .await
+                .unwrap_or(Err(Error::TimeoutError(timeout))),
+            None => execution.await,
+        };
+        script_result = result.and_then(|(status, stdout, stderr)| {
+            (status.success() != script.expect_failure())
+                .then_some(())
+                .ok_or_else(|| script_failure(&script, status, &stdout, &stderr, &env))?;
+            if let Some(name) = script.capture_stdout() {
+                env.insert(name.to_string(), stdout.trim().to_string());
+            }
+            Ok(())
+        });
+        if script_result.is_err() {
+            break;
+        }
     }
+    if let Some(container) = container {
+        container.stop().await?;
+    }
+    script_result?;
     log::debug!(
         "{}/{} environment after script: {:?}",
         test_name,
@@ -96,31 +332,194 @@ async fn run_step(
     log::debug!("Sleeping");
     if step.sleep > 0 {
         sleep(Duration::from_secs(
-            (step.sleep * Config::get().timeout_scaling.ceil() as u16).into(),
+            (step.sleep * config.timeout_scaling.ceil() as u16).into(),
         ))
         .await;
     }
 
+    if let Some(quiesce) = step.quiesce {
+        log::debug!("Waiting for quiescence");
+        wait_for_quiescence(config, quiesce, collected_data.clone()).await?;
+    }
+
     log::debug!("Waiting");
-    let wait: Vec<WaitSpec> = step.wait.into_iter().map(|w| w.subst_env(&env)).collect();
+    let unavailable = collected_data.lock().await.unavailable.clone();
+    let mut wait: Vec<WaitSpec> = vec![];
+    for w in step.wait {
+        wait.push(load_assert_file(w.subst_env(&env), &dirname, &env).await?);
+    }
+    let wait = skip_unavailable(test_name, &step.name, wait, |w| &w.target, &unavailable);
     if wait.len() > 0 {
-        wait_for_all(wait, collected_data.clone()).await?;
+        wait_for_all(
+            client.clone(),
+            config,
+            test_name,
+            &step.name,
+            env.get("BLACKJACK_NAMESPACE").map_or("", String::as_str),
+            wait,
+            collected_data.clone(),
+        )
+        .await?;
+    }
+
+    log::debug!("Waiting for messages");
+    if !subscriptions.is_empty() {
+        wait_for_messages(config, subscriptions).await?;
+    }
+
+    log::debug!("Waiting for queries");
+    let sql: Vec<SqlSpec> = step.sql.into_iter().map(|s| s.subst_env(&env)).collect();
+    if !sql.is_empty() {
+        wait_for_sql(config, sql).await?;
+    }
+
+    log::debug!("Waiting for objects");
+    let s3: Vec<S3Spec> = step.s3.into_iter().map(|s| s.subst_env(&env)).collect();
+    if !s3.is_empty() {
+        wait_for_s3(config, s3, &env).await?;
+    }
+
+    log::debug!("Waiting for DNS records");
+    let dns: Vec<DnsSpec> = step.dns.into_iter().map(|d| d.subst_env(&env)).collect();
+    if !dns.is_empty() {
+        wait_for_dns(config, dns).await?;
+    }
+
+    log::debug!("Waiting for certificates");
+    let certificates: Vec<CertificateSpec> = step
+        .certificate
+        .into_iter()
+        .map(|c| c.subst_env(&env))
+        .collect();
+    if !certificates.is_empty() {
+        wait_for_certificates(client.clone(), config, certificates).await?;
+    }
+
+    log::debug!("Waiting for logs");
+    let logs: Vec<LogsSpec> = step.logs.into_iter().map(|l| l.subst_env(&env)).collect();
+    if !logs.is_empty() {
+        wait_for_logs(client.clone(), config, logs, started).await?;
+    }
+
+    log::debug!("Asserting");
+    let assert: Vec<AssertSpec> = step.assert.into_iter().map(|a| a.subst_env(&env)).collect();
+    let assert = skip_unavailable(test_name, &step.name, assert, |a| &a.target, &unavailable);
+    if !assert.is_empty() {
+        assert_all(assert, collected_data.clone()).await?;
+    }
+
+    log::debug!("Exporting values");
+    let export: Vec<ExportSpec> = step.export.into_iter().map(|e| e.subst_env(&env)).collect();
+    if !export.is_empty() {
+        env.extend(export_values(config, export, collected_data).await?);
+    }
+
+    log::debug!("Exporting manifests");
+    let manifest_exports: Vec<ExportManifestsSpec> = step
+        .export_manifests
+        .into_iter()
+        .map(|e| e.subst_env(&env))
+        .collect();
+    if !manifest_exports.is_empty() {
+        export_manifests(manifest_exports, &dirname, collected_data).await?;
+    }
+
+    log::debug!("Running parallel substeps");
+    if !step.parallel.is_empty() {
+        let substeps = step.parallel.into_iter().map(|substep| {
+            let (client, dirname, env) = (client.clone(), dirname.clone(), env.clone());
+            let cleanup_client = cleanup_client.clone();
+            let config = config.clone().with_timeout_scaling(substep.timeout_scaling);
+            let prefix = format!("{test_name}/{}", substep.name);
+            log_prefix::scope(prefix, async move {
+                let mut resources = TestResources::new(collected_data.clone(), cleanup_client);
+                let result = Box::pin(run_step(
+                    client,
+                    &config,
+                    dirname,
+                    test_name,
+                    substep,
+                    &mut resources,
+                    env,
+                ))
+                .await;
+                (result, resources)
+            })
+        });
+        let mut errors = vec![];
+        for (result, substep_resources) in join_all(substeps).await {
+            manifests.extend(substep_resources.manifests);
+            collectors.extend(substep_resources.collectors);
+            port_forwards.extend(substep_resources.port_forwards);
+            match result {
+                Ok(substep_env) => env.extend(substep_env),
+                Err(err) => errors.push(err),
+            }
+        }
+        match errors.len() {
+            0 => {}
+            1 => return Err(errors.remove(0)),
+            _ => return Err(Error::MultipleErrors(errors)),
+        }
+    }
+
+    log::debug!("Stopping port forwards of step");
+    let (step_forwards, test_forwards) = std::mem::take(port_forwards)
+        .into_iter()
+        .partition(|(scope, _)| *scope == PortForwardScope::Step);
+    *port_forwards = test_forwards;
+    for (_, port_forward) in step_forwards {
+        port_forward.stop().await?;
     }
 
     log::debug!("Done");
     Ok(env)
 }
 
+fn initial_env(
+    config: &Config,
+    namespace: &str,
+    matrix_env: &HashMap<String, String>,
+) -> HashMap<String, String> {
+    let mut env: HashMap<String, String> = matrix_env.clone();
+    env.insert("BLACKJACK_NAMESPACE".to_string(), namespace.to_string());
+    env.insert("BLACKJACK_RUN_ID".to_string(), run::id());
+    if let Ok(root) = std::env::current_dir() {
+        env.insert("BLACKJACK_ROOT".to_string(), root.display().to_string());
+    }
+    if let Some(kubeconfig) = &config.kubeconfig {
+        env.insert("KUBECONFIG".to_string(), kubeconfig.clone());
+        env.insert("BLACKJACK_KUBECONFIG".to_string(), kubeconfig.clone());
+    }
+    if let Some(context) = &config.context {
+        env.insert("BLACKJACK_CONTEXT".to_string(), context.clone());
+        if let Some(kubeconfig) = pinned_kubeconfig(config) {
+            env.insert("KUBECONFIG".to_string(), kubeconfig);
+        }
+    }
+    env
+}
+
+/// The path of the kubeconfig of the current run pinned to the context of the config,
+/// written on first use.
+fn pinned_kubeconfig(config: &Config) -> Option<String> {
+    let run = run::current()?;
+    let file = run.kubeconfig.get_or_init(|| {
+        config.pinned_kubeconfig().unwrap_or_else(|err| {
+            log::warn!("Not pinning the context of scripts: {err}");
+            None
+        })
+    });
+    file.as_ref().map(|file| file.path().display().to_string())
+}
+
 async fn run_steps(
     client: Client,
-    namespace: &String,
+    config: &Config,
     test_spec: TestSpec,
-    manifests: &mut Vec<ManifestHandle>,
-    collectors: &mut Vec<Collector>,
-    collected_data: &CollectedDataContainer,
+    resources: &mut TestResources,
+    env: &mut HashMap<String, String>,
 ) -> TestResult {
-    let mut env: HashMap<String, String> = HashMap::new();
-    env.insert("BLACKJACK_NAMESPACE".to_string(), namespace.to_string());
     for step in test_spec.steps {
         log::info!("Running step {}/{}", test_spec.name, step.name);
         log::debug!(
@@ -130,22 +529,41 @@ async fn run_steps(
             env
         );
         let step_name = step.name.clone();
-        env = run_step(
-            client.clone(),
-            test_spec.dir.clone(),
-            &test_spec.name,
-            step,
-            manifests,
-            collectors,
-            collected_data,
-            env,
-        )
-        .await
-        .map_err(|err| {
+        let step_timeout = step.timeout;
+        let config = &config.clone().with_timeout_scaling(step.timeout_scaling);
+        let step_task = log_prefix::scope(
+            format!("{}/{}", test_spec.name, step_name),
+            run_step(
+                client.clone(),
+                config,
+                test_spec.dir.clone(),
+                &test_spec.name,
+                step,
+                resources,
+                env.clone(),
+            ),
+        );
+        emit(EventKind::StepStarted {
+            test: test_spec.name.clone(),
+            step: step_name.clone(),
+        });
+        let step_result = match step_timeout {
+            Some(timeout) => tokio::time::timeout(config.scaled_timeout(timeout), step_task)
+                .await
+                .unwrap_or(Err(Error::TimeoutError(timeout))),
+            None => step_task.await,
+        }
+        .map_err(|err| redact_failure(config, err));
+        emit(EventKind::StepFinished {
+            test: test_spec.name.clone(),
+            step: step_name.clone(),
+            error: step_result.as_ref().err().map(|err| err.to_string()),
+        });
+        *env = step_result.map_err(|err| {
             log::error!("Test step {}/{} failed", test_spec.name, step_name);
             FailedTest {
                 test_name: test_spec.name.clone(),
-                step_name: step_name,
+                step_name,
                 failure: err,
             }
         })?;
@@ -154,15 +572,31 @@ async fn run_steps(
     Ok(test_spec.name.clone())
 }
 
-async fn run_test(client: Client, test_spec: TestSpec) -> (TestResult, TestSpec, Option<JoinHandle<()>>) {
-    let namespace = make_namespace(&test_spec.name);
+/// Runs the test in a namespace of its own. Its steps send their requests with `step_client`,
+/// e.g. to inject faults into them, while the namespace and cleanup use `client`.
+pub(crate) async fn run_test(
+    client: Client,
+    step_client: Client,
+    config: &Config,
+    shutdown: &Shutdown,
+    test_spec: TestSpec,
+) -> (TestResult, TestSpec, Option<JoinHandle<()>>) {
+    let config = &config
+        .clone()
+        .with_timeout_scaling(test_spec.timeout_scaling);
+    let (namespace_handle, ns) = create_namespace(client.clone(), config, &test_spec.name).await;
+    let namespace = namespace_handle.name().to_string();
     log::info!(
         "Running test '{}' with unique namespace '{}'",
         test_spec.name,
         namespace
     );
-    let namespace_handle = NamespaceHandle::new(client.clone(), &namespace);
-    let ns = namespace_handle.create().await.map_err(|err| FailedTest {
+    emit(EventKind::TestStarted {
+        test: test_spec.name.clone(),
+        test_type: test_spec.test_type.clone(),
+        namespace: namespace.clone(),
+    });
+    let ns = ns.map_err(|err| FailedTest {
         test_name: test_spec.name.clone(),
         step_name: "".to_string(),
         failure: err,
@@ -171,26 +605,43 @@ async fn run_test(client: Client, test_spec: TestSpec) -> (TestResult, TestSpec,
         return (Err(ns.unwrap_err()), test_spec, None);
     }
 
-    let mut manifests = Vec::<ManifestHandle>::new();
-    let collected_data = Collector::new_data();
-    let mut collectors = Vec::<Collector>::new();
+    let mut resources = TestResources::new(Collector::new_data(), client.clone());
 
     let test_name = test_spec.name.clone();
+    let test_timeout = test_spec.timeout;
+    let mut env = initial_env(config, &namespace, &test_spec.env);
+    let steps = test_spec.setup.iter().chain(&test_spec.steps);
     let test_task = run_steps(
-        client.clone(),
-        &namespace,
-        test_spec.clone(),
-        &mut manifests,
-        &mut collectors,
-        &collected_data,
+        step_client.clone(),
+        config,
+        TestSpec {
+            steps: steps.cloned().collect(),
+            ..test_spec.clone()
+        },
+        &mut resources,
+        &mut env,
     );
-    let sigint = tokio::signal::ctrl_c();
+    let test_task = async {
+        match test_timeout {
+            Some(timeout) => tokio::time::timeout(config.scaled_timeout(timeout), test_task)
+                .await
+                .unwrap_or_else(|_| {
+                    log::error!("Test {} timed out", test_name);
+                    Err(FailedTest {
+                        test_name: test_name.clone(),
+                        step_name: "".to_string(),
+                        failure: Error::TimeoutError(timeout),
+                    })
+                }),
+            None => test_task.await,
+        }
+    };
     let result = tokio::select! {
         test_result = test_task => test_result,
-        _ = sigint => {
-            log::info!("Received SIGINT, exiting...");
+        _ = shutdown.requested() => {
+            log::info!("Cancelling test '{test_name}'");
             Err(FailedTest {
-                test_name: test_name,
+                test_name: test_name.clone(),
                 step_name: "".to_string(),
                 failure: Error::SIGINT,
             })
@@ -199,91 +650,314 @@ async fn run_test(client: Client, test_spec: TestSpec) -> (TestResult, TestSpec,
 
     log::debug!("step returned with success: {}", result.is_ok());
 
+    let result = if test_spec.teardown.is_empty() {
+        result
+    } else {
+        log::info!("Running teardown of test '{}'", test_name);
+        // Teardown is not bound by the test timeout, so its steps are, unless they have their own.
+        let teardown = test_spec.teardown.iter().map(|step| StepSpec {
+            timeout: step.timeout.or(test_timeout),
+            ..step.clone()
+        });
+        let teardown_task = run_steps(
+            step_client,
+            config,
+            TestSpec {
+                steps: teardown.collect(),
+                ..test_spec.clone()
+            },
+            &mut resources,
+            &mut env,
+        );
+        let grace_period = Duration::from_secs(config.grace_period.into());
+        let teardown_result = tokio::select! {
+            teardown_result = teardown_task => teardown_result,
+            _ = shutdown.forced() => {
+                log::info!("Skipping teardown of test '{test_name}'");
+                Ok(test_name.clone())
+            }
+            _ = shutdown.expired(grace_period) => {
+                log::info!("Grace period expired, skipping teardown of test '{test_name}'");
+                Ok(test_name.clone())
+            }
+        };
+        match (result, teardown_result) {
+            (Ok(_), Err(err)) => Err(err),
+            (Err(err), Err(teardown_err)) => {
+                log::warn!(
+                    "Teardown of failed test '{}' failed, too: {}",
+                    test_name,
+                    teardown_err.failure
+                );
+                Err(err)
+            }
+            (result, Ok(_)) => result,
+        }
+    };
+
+    if let (Err(failed_test), Some(artifacts_dir)) = (&result, &config.artifacts_dir) {
+        let data = resources.collected_data.lock().await;
+        let run = run::current().filter(|_| config.bucket_events);
+        let event_log = run.as_ref().and_then(|run| run.events.as_ref());
+        match dump_failed_test(
+            client.clone(),
+            artifacts_dir,
+            event_log.map(|events| events.path()),
+            &namespace,
+            failed_test,
+            &data,
+            &resources.manifests,
+        )
+        .await
+        {
+            Ok(dir) => log::info!(
+                "Dumped state of test '{}' into {}",
+                test_name,
+                dir.display()
+            ),
+            Err(err) => log::warn!("Unable to dump state of test '{}': {}", test_name, err),
+        }
+    }
+
     log::debug!("initiating cleanup");
-    let cleanup_task = tokio::task::spawn(async move {
+    let strict_cluster = config.strict_cluster;
+    let deletion_timeout = config.scaled_timeout(DELETION_TIMEOUT);
+    let cleanup = async move {
         let mut results: Vec<Result<()>> = vec![];
-        for mut collector in collectors {
+        for (_, port_forward) in resources.port_forwards {
+            results.push(port_forward.stop().await);
+        }
+        for mut collector in resources.collectors {
             results.push(collector.stop().await);
         }
         {
-            let data = collected_data.lock().await;
+            let data = resources.collected_data.lock().await;
             results.push((*data).cleanup(client).await);
         }
-        for manifest in manifests {
-            results.push(manifest.delete().await);
+        for manifest in resources.manifests {
+            if !manifest.preserve() {
+                results.push(manifest.delete().await);
+            }
         }
-        results.push(namespace_handle.delete().await);
         for error in results.into_iter().filter(|r| r.is_err()) {
             log::warn!("Errors during cleanup: {:?}", error.unwrap_err());
         }
+        namespace_handle
+            .delete(!strict_cluster, deletion_timeout)
+            .await
+    };
+
+    // In strict mode, a namespace stuck on finalizers fails the test, so cleanup is awaited.
+    if strict_cluster {
+        let result = match (result, cleanup.await) {
+            (Ok(_), Err(err)) => Err(FailedTest {
+                test_name: test_name.clone(),
+                step_name: "cleanup".to_string(),
+                failure: err,
+            }),
+            (result, _) => result,
+        };
+        log::debug!("cleanup done");
+        return (result, test_spec, None);
+    }
+    let cleanup_task = run::spawn(async move {
+        if let Err(err) = cleanup.await {
+            log::warn!("Errors during cleanup: {:?}", err);
+        }
     });
 
     log::debug!("cleanup done");
     (result, test_spec, Some(cleanup_task))
 }
 
-async fn run_all_tests(
+/// Runs an attempt of a test, creating its fixtures unless they are reused
+/// from a previous attempt. The fixtures are returned to be reused by the next attempt.
+async fn run_attempt(
+    client: Client,
+    config: &Config,
+    shutdown: &Shutdown,
+    test_spec: TestSpec,
+    fixtures: Option<Fixtures>,
+) -> (
+    TestResult,
+    TestSpec,
+    Option<JoinHandle<()>>,
+    Option<Fixtures>,
+) {
+    let step_client = match config.http_trace || test_spec.faults.is_some() {
+        true => config.test_client(&test_spec).await.unwrap_or_else(|err| {
+            log::warn!(
+                "Not tracing or injecting faults into HTTP requests of test '{}': {err}",
+                test_spec.name
+            );
+            client.clone()
+        }),
+        false => client.clone(),
+    };
+    // Shared watches outlive the test and would use its traced or faulty client for other tests.
+    let config = &Config {
+        share_watches: config.share_watches && !config.http_trace && test_spec.faults.is_none(),
+        ..config.clone()
+    }
+    .with_apply_rate(test_spec.apply_rate.clone());
+    let fixtures = match (fixtures, &test_spec.fixtures) {
+        (Some(fixtures), _) => Ok(Some(fixtures)),
+        (None, Some(spec)) => {
+            log::info!("Creating fixtures of test '{}'", test_spec.name);
+            Fixtures::create(
+                client.clone(),
+                config,
+                &test_spec.name,
+                spec,
+                test_spec.dir.clone(),
+            )
+            .await
+            .map(Some)
+        }
+        (None, None) => Ok(None),
+    };
+    match fixtures {
+        Ok(fixtures) => {
+            let (result, test_spec, cleanup_task) =
+                run_test(client, step_client, config, shutdown, test_spec).await;
+            (result, test_spec, cleanup_task, fixtures)
+        }
+        Err(err) => {
+            let failed_test = FailedTest {
+                test_name: test_spec.name.clone(),
+                step_name: "fixtures".to_string(),
+                failure: err,
+            };
+            (Err(failed_test), test_spec, None, None)
+        }
+    }
+}
+
+/// Deletes the fixtures of a test after its last attempt has been cleaned up,
+/// as its resources might depend on them.
+fn delete_fixtures(cleanup_task: Option<JoinHandle<()>>, fixtures: Fixtures) -> JoinHandle<()> {
+    run::spawn(async move {
+        if let Some(cleanup_task) = cleanup_task {
+            let _ = cleanup_task.await;
+        }
+        if let Err(err) = fixtures.delete().await {
+            log::warn!("Errors during cleanup of fixtures: {:?}", err);
+        }
+    })
+}
+
+/// Reports the tests still queued as not executed.
+fn skip_queued(queue: &mut VecDeque<TestSpec>, results: &mut Vec<TestResult>) {
+    for test_spec in queue.drain(..) {
+        let not_executed = Err(FailedTest {
+            test_name: test_spec.name,
+            step_name: "".to_string(),
+            failure: Error::NotExecuted,
+        });
+        emit_test_finished(&not_executed);
+        results.push(not_executed);
+    }
+}
+
+pub(crate) async fn run_all_tests(
     client: Client,
+    config: Arc<Config>,
+    shutdown: &Shutdown,
     test_specs: Vec<TestSpec>,
     parallel: u16,
     attempts: u16,
 ) -> Result<Vec<TestResult>> {
     let mut results: Vec<TestResult> = vec![];
     let mut tasks = JoinSet::new();
-    let mut it = test_specs.into_iter();
+    let mut queue: VecDeque<TestSpec> = test_specs.into();
+    let mut locks = Locks::default();
     let mut cleanup_tasks: Vec<JoinHandle<()>> = vec![];
     let mut attempt_counter: HashMap<String, u16> = HashMap::new();
+    let mut reused_fixtures: HashMap<String, Fixtures> = HashMap::new();
+    let failure_limit = config.failure_limit();
+    let mut failures = 0;
 
-    let mut next = it.next();
     loop {
-        while next.is_some() && (tasks.len() < parallel.into()) {
-            let client = client.clone();
-            tasks.spawn(async move { run_test(client, next.unwrap()).await });
-            next = it.next();
+        while !pause::is_paused() && !shutdown.is_requested() && tasks.len() < parallel.into() {
+            let Some(index) = queue.iter().position(|test_spec| locks.is_free(test_spec)) else {
+                break;
+            };
+            let test_spec = queue.remove(index).unwrap();
+            locks.acquire(&test_spec);
+            let (client, config, shutdown) = (client.clone(), config.clone(), shutdown.clone());
+            let fixtures = reused_fixtures.remove(&test_spec.name);
+            let prefix = test_spec.name.clone();
+            tasks.spawn(run::inherit(log_prefix::scope(prefix, async move {
+                run_attempt(client, &config, &shutdown, test_spec, fixtures).await
+            })));
+        }
+        if tasks.is_empty() && shutdown.is_requested() {
+            skip_queued(&mut queue, &mut results);
         }
-        if let Some(result) = tasks.join_next().await {
-            let (test_result, test_spec, cleanup_task) =
+        if tasks.is_empty() && !queue.is_empty() && pause::is_paused() {
+            tokio::select! {
+                _ = pause::resumed() => {},
+                _ = shutdown.requested() => {},
+            };
+            continue;
+        }
+        let result = tokio::select! {
+            result = tasks.join_next() => result,
+            _ = pause::resumed(), if pause::is_paused() => continue,
+        };
+        if let Some(result) = result {
+            let (test_result, test_spec, cleanup_task, fixtures) =
                 result.map_err(|err| Error::JoinError(err))?;
             attempt_counter
                 .entry(test_spec.name.clone())
                 .and_modify(|i| *i += 1)
                 .or_insert(1);
-            if let Some(ct) = cleanup_task {
-                cleanup_tasks.push(ct);
+            locks.release(&test_spec.name);
+            let attempts = test_spec.attempts.or(Some(attempts)).unwrap();
+            let retry = test_result.is_err()
+                && !shutdown.is_requested()
+                && attempt_counter.get(&test_spec.name).unwrap() < &attempts;
+            match fixtures {
+                Some(fixtures) if retry => {
+                    reused_fixtures.insert(test_spec.name.clone(), fixtures);
+                    cleanup_tasks.extend(cleanup_task);
+                }
+                Some(fixtures) => cleanup_tasks.push(delete_fixtures(cleanup_task, fixtures)),
+                None => cleanup_tasks.extend(cleanup_task),
             }
+            emit_test_finished(&test_result);
             if test_result.is_ok() {
                 results.push(test_result);
+            } else if retry {
+                queue.push_back(test_spec);
             } else {
-                let attempts = test_spec.attempts.or(Some(attempts)).unwrap();
-                if attempt_counter.get(&test_spec.name).unwrap() < &attempts {
-                    it = it.chain(std::iter::once(test_spec)).collect::<Vec<_>>().into_iter();
-                    if next.is_none() {
-                        next = it.next();
-                    }
-                } else {
-                    results.push(test_result);
-                    while next.is_some() {
-                        let test_spec = next.unwrap();
-                        results.push(Err(FailedTest {
-                            test_name: test_spec.name,
-                            step_name: "".to_string(),
-                            failure: Error::NotExecuted,
-                        }));
-                        next = it.next();
+                results.push(test_result);
+                failures += 1;
+                if failure_limit.is_some_and(|limit| failures >= limit) {
+                    if !queue.is_empty() {
+                        log::error!("Not starting further tests after {failures} failed");
                     }
+                    skip_queued(&mut queue, &mut results);
                 }
             }
         } else {
             break;
         }
     }
+    for (_, fixtures) in reused_fixtures {
+        cleanup_tasks.push(delete_fixtures(None, fixtures));
+    }
     log::info!("Waiting for all cleanup tasks");
+    let grace_period = Duration::from_secs(config.grace_period.into());
     for task in cleanup_tasks {
-        let sigint = tokio::signal::ctrl_c();
         tokio::select! {
             _ = task => {},
-            _ = sigint => {
-                log::info!("Received another SIGINT, exiting without cleanup");
+            _ = shutdown.forced() => {
+                log::info!("Exiting without cleanup");
+                break;
+            }
+            _ = shutdown.expired(grace_period) => {
+                log::info!("Grace period expired, exiting without cleanup");
                 break;
             }
         };
@@ -292,11 +966,134 @@ async fn run_all_tests(
     Ok(results)
 }
 
-pub async fn run_test_suite(dirname: &Path) -> Result<()> {
-    let client = Client::try_default().await?;
-    let test_specs = discover_tests(&dirname.to_path_buf()).await?;
+/// Name under which failures of the assertions of `suite.yaml` are reported.
+const SUITE_NAME: &str = "suite";
+
+/// Starts collecting the buckets of the suite for the duration of the run.
+async fn start_suite_collector(
+    client: Client,
+    config: &Config,
+    suite_spec: &SuiteSpec,
+) -> Result<(Collector, CollectedDataContainer)> {
+    log::info!("Collecting {} suite buckets", suite_spec.watch.len());
+    let collected_data = Collector::new_data();
+    let watches = suite_spec
+        .watches()
+        .into_iter()
+        .map(|watch| config.apply_watch_defaults(watch))
+        .collect();
+    let collector =
+        Collector::new(client, SUITE_NAME, watches, collected_data.clone(), false).await?;
+    Ok((collector, collected_data))
+}
+
+/// Stops collecting the buckets of the suite and checks its assertions on them.
+async fn assert_suite(
+    config: &Config,
+    mut collector: Collector,
+    collected_data: CollectedDataContainer,
+    suite_spec: SuiteSpec,
+) -> TestResult {
+    log::info!("Checking suite assertions");
+    let result = match collector.stop().await {
+        Ok(()) => assert_all(suite_spec.assert, collected_data).await,
+        Err(err) => Err(err),
+    };
+    result
+        .map(|()| SUITE_NAME.to_string())
+        .map_err(|failure| FailedTest {
+            test_name: SUITE_NAME.to_string(),
+            step_name: "assert".to_string(),
+            failure: redact_failure(config, failure),
+        })
+}
+
+pub async fn run_test_suite(config: Config, dirname: &Path) -> Result<()> {
+    // Durations are read before the event log of the previous run is overwritten.
+    let durations = configured_durations(&config);
+    let state = State::read(dirname).unwrap_or_else(|err| {
+        log::warn!("Ignoring state of previous runs: {err}");
+        State::default()
+    });
+    if config.rerun_failed && state.failed.is_empty() {
+        log::info!("All tests passed last run, nothing to rerun");
+        return Ok(());
+    }
+    let suite_spec = SuiteSpec::new_from_dir(dirname).await?;
+    let show_progress = config.progress && progress::is_supported();
+    if config.progress && !show_progress {
+        log::warn!("Not showing progress, as stderr is not a terminal");
+    }
+    let run = Run::default()
+        .with_events(event_log(&config)?.as_deref())?
+        .with_progress(show_progress);
+    let run = Arc::new(run);
+    let suite = run_suite(run.clone(), config, dirname, state, durations, suite_spec);
+    run::scope(run, suite).await
+}
+
+/// Path of the event log of the run: `events` of the config, or, as the changes of buckets
+/// are taken from it, a log in `artifacts_dir` if only `bucket_events` is set.
+fn event_log(config: &Config) -> Result<Option<PathBuf>> {
+    match (&config.events, &config.artifacts_dir) {
+        (Some(events), _) => Ok(Some(PathBuf::from(events))),
+        (None, Some(artifacts_dir)) if config.bucket_events => {
+            std::fs::create_dir_all(artifacts_dir)?;
+            Ok(Some(Path::new(artifacts_dir).join("events.jsonl")))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Runs the suite in `dirname` as `run`.
+async fn run_suite(
+    run: Arc<Run>,
+    config: Config,
+    dirname: &Path,
+    state: State,
+    durations: HashMap<String, f64>,
+    suite_spec: Option<SuiteSpec>,
+) -> Result<()> {
+    if let Some(progress) = &run.progress {
+        progress::start(progress);
+    }
+    if config.http_trace && config.artifacts_dir.is_none() {
+        log::warn!("Not tracing HTTP requests, as no artifacts dir is set");
+    }
+    emit(EventKind::SuiteStarted);
+    log::info!("Starting run {}", run.id);
+    let _pause_listener = pause::listen()?;
+    let shutdown = Shutdown::default();
+    let _shutdown_listener = shutdown.listen()?;
+    let client = config.client().await?;
+    if config.skip_unavailable_apis {
+        preflight::check(&client).await;
+    }
+    let filter = TestFilter::from_config(&config)?;
+    let config = Arc::new(config);
+    let mut skipped: Vec<String> = vec![];
+    let test_specs = discover_tests(&dirname.to_path_buf(), &filter, &mut skipped).await?;
+    let suite = match suite_spec {
+        Some(suite_spec) => {
+            let (collector, collected_data) =
+                start_suite_collector(client.clone(), &config, &suite_spec).await?;
+            Some((collector, collected_data, suite_spec))
+        }
+        None => None,
+    };
+    if config.rerun_failed {
+        log::info!("Rerunning {} failed tests", state.failed.len());
+    }
     let mut sorted_test_specs = test_specs
         .into_iter()
+        .flat_map(TestSpec::expand_matrix)
+        .filter(|test_spec| {
+            let selected = !config.rerun_failed || state.failed.contains(&test_spec.name);
+            if !selected {
+                skipped.push(test_spec.name.clone());
+            }
+            selected
+        })
         .fold(HashMap::new(), |mut map, item| {
             map.entry(item.test_type.clone())
                 .or_insert(Vec::new())
@@ -304,12 +1101,7 @@ pub async fn run_test_suite(dirname: &Path) -> Result<()> {
             map
         });
     for (_, tests) in &mut sorted_test_specs {
-        tests.sort_by(|lhs, rhs| match (&lhs.ordering, &rhs.ordering) {
-            (Some(ref l), Some(ref r)) => l.cmp(r),
-            (Some(_), None) => cmp::Ordering::Greater,
-            (None, Some(_)) => cmp::Ordering::Less,
-            (None, None) => cmp::Ordering::Equal,
-        });
+        sort_tests(tests, config.schedule, &durations);
     }
     let mut results: Vec<TestResult> = vec![];
     log::info!("Running cluster tests");
@@ -317,9 +1109,11 @@ pub async fn run_test_suite(dirname: &Path) -> Result<()> {
         results.append(
             &mut run_all_tests(
                 client.clone(),
+                config.clone(),
+                &shutdown,
                 cluster_tests,
-                Config::get().cluster.parallel,
-                Config::get().cluster.attempts,
+                config.cluster.parallel,
+                config.cluster.attempts,
             )
             .await?,
         );
@@ -330,9 +1124,11 @@ pub async fn run_test_suite(dirname: &Path) -> Result<()> {
             results.append(
                 &mut run_all_tests(
                     client.clone(),
+                    config.clone(),
+                    &shutdown,
                     user_tests,
-                    Config::get().user.parallel,
-                    Config::get().user.attempts,
+                    config.user.parallel,
+                    config.user.attempts,
                 )
                 .await?,
             );
@@ -340,9 +1136,40 @@ pub async fn run_test_suite(dirname: &Path) -> Result<()> {
     } else {
         log::error!("Skipping user tests after cluster test failed");
     }
+    for test_name in &skipped {
+        log_skipped(test_name);
+        emit(EventKind::TestSkipped {
+            test: test_name.clone(),
+        });
+    }
     if results.is_empty() {
+        if let Some((mut collector, _, _)) = suite {
+            if let Err(err) = collector.stop().await {
+                log::warn!("Errors stopping suite collector: {err}");
+            }
+        }
         return Err(Error::NoTestsFoundError);
     }
+    if let Err(err) = state.update(&run.id, &results).write(dirname) {
+        log::warn!(
+            "Unable to write state to {}: {err}",
+            State::path(dirname).display()
+        );
+    }
+    if let Some((collector, collected_data, suite_spec)) = suite {
+        results.push(assert_suite(&config, collector, collected_data, suite_spec).await);
+    }
+    if let Some(namespace) = &config.results_namespace {
+        match publish_results(client.clone(), namespace, &results, &skipped).await {
+            Ok(()) => log::info!("Published results to namespace '{namespace}'"),
+            Err(err) => log::warn!("Unable to publish results to namespace '{namespace}': {err}"),
+        }
+    }
+    if let Some(path) = &config.rbac_report {
+        if let Err(err) = rbac::write_report(&run, Path::new(path)).await {
+            log::warn!("Unable to write RBAC report to {path}: {err}");
+        }
+    }
     let mut success = true;
     for result in results {
         log_result(&result);
@@ -350,25 +1177,59 @@ pub async fn run_test_suite(dirname: &Path) -> Result<()> {
             success = false;
         }
     }
+    emit(EventKind::SuiteFinished { success });
     success.then_some(()).ok_or(Error::SomeTestsFailedError)
 }
 
-async fn discover_tests(dirname: &PathBuf) -> Result<Vec<TestSpec>> {
+pub(crate) async fn discover_tests(
+    dirname: &PathBuf,
+    filter: &TestFilter,
+    skipped: &mut Vec<String>,
+) -> Result<Vec<TestSpec>> {
+    let mut invalid = vec![];
+    let test_specs = discover_all_tests(dirname, filter, skipped, &mut invalid).await?;
+    match invalid.into_iter().next() {
+        Some((_, err)) => Err(err),
+        None => Ok(test_specs),
+    }
+}
+
+/// Discovers tests like `discover_tests`, but collects the directories of tests
+/// that cannot be read into `invalid` with their error instead of failing on the first.
+pub(crate) async fn discover_all_tests(
+    dirname: &PathBuf,
+    filter: &TestFilter,
+    skipped: &mut Vec<String>,
+    invalid: &mut Vec<(PathBuf, Error)>,
+) -> Result<Vec<TestSpec>> {
     log::trace!("Discovering tests: {dirname:?}");
     let mut result: Vec<TestSpec> = vec![];
     let files = list_files(dirname).await?;
     if files
         .iter()
         .filter_map(|e| e.file_name())
-        .find(|&x| x == "test.yaml")
+        .find(|&x| x == "test.yaml" || x == FEATURE_FILE)
         .is_some()
     {
-        result.push(TestSpec::new_from_file(dirname.clone()).await?);
+        let test_spec = match TestSpec::new_from_file(dirname.clone()).await {
+            Ok(test_spec) => test_spec,
+            Err(err) => {
+                invalid.push((dirname.clone(), err));
+                return Ok(vec![]);
+            }
+        };
+        if filter.matches(&test_spec) {
+            result.push(test_spec);
+        } else {
+            log::debug!("Skipping test '{}'", test_spec.name);
+            skipped.push(test_spec.name);
+        }
     } else {
         let dirs: Vec<PathBuf> = list_directories(dirname).await?;
         log::trace!("Descending into {dirs:?}");
         for dir in dirs {
-            result.append(&mut Box::pin(discover_tests(&dir)).await?);
+            let tests = Box::pin(discover_all_tests(&dir, filter, skipped, invalid)).await?;
+            result.extend(tests);
         }
     }
     Ok(result)
@@ -0,0 +1,91 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::config::Config;
+use crate::error::{Result, TestResult};
+use crate::preflight;
+use crate::run::{self, Run};
+use crate::run_test::run_all_tests;
+use crate::shutdown::Shutdown;
+use crate::test_spec::{TestSpec, TestType};
+use kube::Client;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Runs tests from Rust, e.g. in `#[tokio::test]` functions, instead of via the binary.
+/// Every runner has its own config and run, so runners with different configs can be used
+/// in the same process.
+pub struct TestRunner {
+    client: Client,
+    config: Arc<Config>,
+    run: Arc<Run>,
+}
+
+impl TestRunner {
+    /// Creates a runner for the cluster of `client`, with the default config.
+    pub fn new(client: Client) -> Self {
+        TestRunner {
+            client,
+            config: Arc::new(Config::default()),
+            run: Arc::new(Run::default()),
+        }
+    }
+
+    pub fn with_config(self, config: Config) -> Self {
+        TestRunner {
+            config: Arc::new(config),
+            ..self
+        }
+    }
+
+    /// Loads the test spec from `test.yaml` in `dir`,
+    /// whose manifests and scripts are resolved relative to `dir`.
+    pub async fn load(dir: impl AsRef<Path>) -> Result<TestSpec> {
+        TestSpec::new_from_file(dir.as_ref().to_path_buf()).await
+    }
+
+    /// Runs the test, expanding its matrix and retrying failed attempts
+    /// as configured for user tests. Returns the result of every test of the matrix.
+    pub async fn run(&self, test_spec: TestSpec) -> Result<Vec<TestResult>> {
+        self.run_all(vec![test_spec]).await
+    }
+
+    /// Runs the tests like `run`, concurrently as configured for their type.
+    /// Cluster tests are run before user tests.
+    pub async fn run_all(&self, test_specs: Vec<TestSpec>) -> Result<Vec<TestResult>> {
+        run::scope(self.run.clone(), self.run_tests(test_specs)).await
+    }
+
+    async fn run_tests(&self, test_specs: Vec<TestSpec>) -> Result<Vec<TestResult>> {
+        let shutdown = Shutdown::default();
+        let _listener = shutdown.listen()?;
+        if self.config.skip_unavailable_apis {
+            preflight::check(&self.client).await;
+        }
+        let (cluster_tests, user_tests): (Vec<TestSpec>, Vec<TestSpec>) = test_specs
+            .into_iter()
+            .flat_map(TestSpec::expand_matrix)
+            .partition(|test_spec| test_spec.test_type == TestType::Cluster);
+        let mut results = vec![];
+        for (tests, type_config) in [
+            (cluster_tests, &self.config.cluster),
+            (user_tests, &self.config.user),
+        ] {
+            if tests.is_empty() {
+                continue;
+            }
+            results.extend(
+                run_all_tests(
+                    self.client.clone(),
+                    self.config.clone(),
+                    &shutdown,
+                    tests,
+                    type_config.parallel,
+                    type_config.attempts,
+                )
+                .await?,
+            );
+        }
+        Ok(results)
+    }
+}
@@ -0,0 +1,177 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::check::{assert_expr, CheckOptions, History};
+use crate::config::Config;
+use crate::error::{AssertDiagnostic, Error, Result, TestFailure, TestFailures};
+use crate::messages::payload;
+use crate::test_spec::{process_env, Env, S3Spec, WaitSpec};
+use crate::wait::wait_until;
+use futures::TryStreamExt;
+use object_store::aws::{AmazonS3, AmazonS3Builder};
+use object_store::path::Path;
+use object_store::{ObjectMeta, ObjectStore};
+use serde_json::{json, Value};
+use tokio::time::{Duration, Instant};
+
+fn s3_error(err: impl std::fmt::Display) -> Error {
+    Error::S3Error(err.to_string())
+}
+
+/// Resolves a reference to a variable, from the environment of the test
+/// or else from the environment of `blackjack`.
+/// The resolved value is never stored in the spec, so it does not show up in logs.
+fn resolve(reference: &str, env: &Env) -> Result<String> {
+    let value = envsubst::substitute(reference, env)?;
+    let value = envsubst::substitute(value, &process_env())?;
+    if envsubst::is_templated(&value) {
+        return Err(s3_error(format!("unable to resolve '{reference}'")));
+    }
+    Ok(value)
+}
+
+fn store(spec: &S3Spec, env: &Env) -> Result<AmazonS3> {
+    let mut builder = AmazonS3Builder::from_env().with_bucket_name(&spec.bucket);
+    if let Some(endpoint) = &spec.endpoint {
+        builder = builder
+            .with_allow_http(endpoint.starts_with("http://"))
+            .with_endpoint(endpoint);
+    }
+    if let Some(region) = &spec.region {
+        builder = builder.with_region(region);
+    }
+    if let Some(access_key_id) = &spec.access_key_id {
+        builder = builder.with_access_key_id(resolve(access_key_id, env)?);
+    }
+    if let Some(secret_access_key) = &spec.secret_access_key {
+        builder = builder.with_secret_access_key(resolve(secret_access_key, env)?);
+    }
+    builder.build().map_err(s3_error)
+}
+
+fn object_value(meta: &ObjectMeta) -> Value {
+    json!({
+        "key": meta.location.to_string(),
+        "size": meta.size,
+        "lastModified": meta.last_modified.to_rfc3339(),
+        "etag": meta.e_tag.as_deref().map(|etag| etag.trim_matches('"')),
+    })
+}
+
+async fn list_objects(store: &AmazonS3, spec: &S3Spec) -> Result<Vec<Value>> {
+    let prefix = (!spec.prefix.is_empty()).then(|| Path::from(spec.prefix.as_str()));
+    let mut metas: Vec<ObjectMeta> = store
+        .list(prefix.as_ref())
+        .try_collect()
+        .await
+        .map_err(s3_error)?;
+    metas.sort_by(|a, b| a.location.cmp(&b.location));
+    let mut objects = vec![];
+    for meta in metas {
+        let mut object = object_value(&meta);
+        if spec.content {
+            let bytes = store
+                .get(&meta.location)
+                .await
+                .map_err(s3_error)?
+                .bytes()
+                .await
+                .map_err(s3_error)?;
+            object["content"] = payload(&bytes);
+        }
+        objects.push(object);
+    }
+    Ok(objects)
+}
+
+fn check_objects(spec: &S3Spec, objects: &[Value]) -> std::result::Result<(), AssertDiagnostic> {
+    let objects: Vec<&Value> = objects.iter().collect();
+    assert_expr(
+        &objects,
+        &vec![],
+        &History::new(),
+        &spec.condition,
+        CheckOptions {
+            coerce: spec.coerce,
+        },
+    )
+}
+
+/// Lists the objects repeatedly until they fulfill the condition.
+/// Errors are retried as well, as the bucket may not exist yet.
+async fn wait_for_objects(
+    config: &Config,
+    spec: &S3Spec,
+    env: &Env,
+) -> Result<std::result::Result<(), AssertDiagnostic>> {
+    let deadline = Instant::now() + config.scaled_timeout(spec.timeout);
+    let store = store(spec, env)?;
+    wait_until(
+        deadline,
+        Duration::from_secs(1),
+        || list_objects(&store, spec),
+        |objects| check_objects(spec, objects),
+    )
+    .await
+}
+
+/// Waits until the objects of all buckets fulfill their conditions.
+pub async fn wait_for_s3(config: &Config, specs: Vec<S3Spec>, env: &Env) -> Result<()> {
+    log::debug!("Waiting for objects in {} buckets", specs.len());
+    let mut errors: Vec<TestFailure> = Vec::new();
+    for spec in specs {
+        if let Err(assert_diagnostic) = wait_for_objects(config, &spec, env).await? {
+            let target = format!("s3:{}/{}", spec.bucket, spec.prefix);
+            let spec = WaitSpec {
+                condition: spec.condition,
+                coerce: spec.coerce,
+                ..WaitSpec::synthetic(target, spec.timeout)
+            };
+            errors.push(TestFailure::new(spec, assert_diagnostic));
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::ConditionsFailed(TestFailures(errors)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use rstest::rstest;
+
+    #[test]
+    fn test_object_value() {
+        let meta = ObjectMeta {
+            location: Path::from("backups/db.tar.gz"),
+            last_modified: Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap(),
+            size: 1024,
+            e_tag: Some("\"abc\"".to_string()),
+            version: None,
+        };
+        assert_eq!(
+            object_value(&meta),
+            json!({
+                "key": "backups/db.tar.gz",
+                "size": 1024,
+                "lastModified": "2024-05-01T12:00:00+00:00",
+                "etag": "abc",
+            })
+        );
+    }
+
+    #[rstest]
+    #[case("${SECRET}", Ok("from-test"))]
+    #[case("literal", Ok("literal"))]
+    #[case("${BLACKJACK_TEST_UNSET_CREDENTIAL}", Err(()))]
+    fn test_resolve(#[case] reference: &str, #[case] expected: std::result::Result<&str, ()>) {
+        let env = Env::from([("SECRET".to_string(), "from-test".to_string())]);
+        assert_eq!(
+            resolve(reference, &env).map_err(|_| ()),
+            expected.map(String::from)
+        );
+    }
+}
@@ -0,0 +1,106 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::config::Config;
+use crate::report::read_summaries;
+use crate::test_spec::TestSpec;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The order in which tests of the same type are started.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Schedule {
+    /// In the order of discovery
+    #[default]
+    Naive,
+    /// Longest tests first, according to the durations of a previous run
+    Lpt,
+}
+
+/// Durations in seconds of the tests of a previous run,
+/// from a JSON report or an event log.
+pub fn read_durations(path: &Path) -> HashMap<String, f64> {
+    if !path.exists() {
+        return HashMap::new();
+    }
+    match read_summaries(path) {
+        Ok(summaries) => summaries
+            .into_iter()
+            .map(|summary| (summary.name, summary.seconds))
+            .collect(),
+        Err(err) => {
+            log::warn!("Unable to read durations from {}: {}", path.display(), err);
+            HashMap::new()
+        }
+    }
+}
+
+/// Durations of the previous run for the configured schedule, read from `history`,
+/// or else from the event log, which is yet to be overwritten.
+/// Only `lpt` needs them.
+pub fn configured_durations(config: &Config) -> HashMap<String, f64> {
+    let history = config.history.as_ref().or(config.events.as_ref());
+    match (config.schedule, history) {
+        (Schedule::Lpt, Some(path)) => read_durations(Path::new(path)),
+        _ => HashMap::new(),
+    }
+}
+
+fn by_ordering(lhs: &TestSpec, rhs: &TestSpec) -> Ordering {
+    match (&lhs.ordering, &rhs.ordering) {
+        (Some(l), Some(r)) => l.cmp(r),
+        (Some(_), None) => Ordering::Greater,
+        (None, Some(_)) => Ordering::Less,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// Sorts tests by their `ordering`, and with `lpt`, tests of the same ordering
+/// by their durations, longest first. Tests without a known duration are started first,
+/// as they might be the longest.
+pub fn sort_tests(tests: &mut [TestSpec], schedule: Schedule, durations: &HashMap<String, f64>) {
+    let duration = |test: &TestSpec| durations.get(&test.name).copied().unwrap_or(f64::INFINITY);
+    tests.sort_by(|lhs, rhs| {
+        by_ordering(lhs, rhs).then_with(|| match schedule {
+            Schedule::Naive => Ordering::Equal,
+            Schedule::Lpt => duration(rhs).total_cmp(&duration(lhs)),
+        })
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn test_spec(name: &str, ordering: Option<&str>) -> TestSpec {
+        TestSpec {
+            name: name.to_string(),
+            ordering: ordering.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    #[rstest]
+    #[case(Schedule::Naive, vec!["short", "long", "new", "late"])]
+    #[case(Schedule::Lpt, vec!["new", "long", "short", "late"])]
+    fn test_sort_tests(#[case] schedule: Schedule, #[case] expected: Vec<&str>) {
+        let mut tests = vec![
+            test_spec("late", Some("z")),
+            test_spec("short", None),
+            test_spec("long", None),
+            test_spec("new", None),
+        ];
+        let durations = HashMap::from([
+            ("short".to_string(), 1.0),
+            ("long".to_string(), 60.0),
+            ("late".to_string(), 120.0),
+        ]);
+        sort_tests(&mut tests, schedule, &durations);
+        let names: Vec<&str> = tests.iter().map(|test| test.name.as_str()).collect();
+        assert_eq!(names, expected);
+    }
+}
@@ -0,0 +1,227 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::test_spec::TestSpec;
+use serde_json::{json, Map, Value};
+
+pub const DEFAULT_SCHEMA_URL: &str =
+    "https://raw.githubusercontent.com/olekli/mrblackjack/main/schema/test_spec.yaml";
+
+pub fn schema() -> Value {
+    serde_json::to_value(TestSpec::schema()).unwrap_or_default()
+}
+
+/// Header line for `test.yaml` files that makes yaml-language-server pick up the schema.
+pub fn yaml_language_server_header(url: &str) -> String {
+    format!("# yaml-language-server: $schema={url}")
+}
+
+fn resolve<'a>(property: &'a Value, definitions: &'a Value) -> Option<&'a Value> {
+    property
+        .get("$ref")
+        .or_else(|| property.pointer("/allOf/0/$ref"))
+        .and_then(|r| r.as_str())
+        .and_then(|r| r.strip_prefix("#/definitions/"))
+        .and_then(|name| definitions.get(name))
+}
+
+fn type_of<'a>(property: &'a Value, definitions: &'a Value) -> &'a str {
+    match property.get("type") {
+        Some(Value::String(t)) => t,
+        Some(Value::Array(types)) => types
+            .iter()
+            .filter_map(|t| t.as_str())
+            .find(|t| *t != "null")
+            .unwrap_or("object"),
+        _ => match resolve(property, definitions) {
+            Some(definition) if definition.get("enum").is_some() => "string",
+            Some(definition) => type_of(definition, definitions),
+            None => "object",
+        },
+    }
+}
+
+/// Properties that make up the snippet of a definition:
+/// the required ones, or all with a non-null default if none are required.
+fn snippet_properties(definition: &Value) -> Vec<(&String, &Value)> {
+    let properties = match definition.get("properties").and_then(|p| p.as_object()) {
+        Some(properties) => properties,
+        None => return vec![],
+    };
+    let required: Vec<&str> = definition
+        .get("required")
+        .and_then(|r| r.as_array())
+        .map(|r| r.iter().filter_map(|r| r.as_str()).collect())
+        .unwrap_or_default();
+    properties
+        .iter()
+        .filter(|(name, property)| {
+            if required.is_empty() {
+                property.get("default").is_some_and(|d| !d.is_null())
+                    && property.get("readOnly").is_none()
+            } else {
+                required.contains(&name.as_str())
+            }
+        })
+        .collect()
+}
+
+/// Value of a property in a yaml-language-server `defaultSnippets` body.
+/// Strings prefixed with `^` are inserted without quotes.
+fn snippet_value(name: &str, property: &Value, definitions: &Value, index: usize) -> Value {
+    if let Some(default) = property.get("default").filter(|d| !d.is_null()) {
+        return default.clone();
+    }
+    match type_of(property, definitions) {
+        "string" => json!(format!("${{{index}:{name}}}")),
+        "integer" | "number" => json!(format!("^${{{index}:0}}")),
+        "boolean" => json!(format!("^${{{index}:false}}")),
+        "array" => json!([]),
+        _ => json!({}),
+    }
+}
+
+/// Value of a property in a VS Code snippet body.
+fn snippet_text(name: &str, property: &Value, definitions: &Value, index: usize) -> String {
+    match property.get("default").filter(|d| !d.is_null()) {
+        Some(Value::String(default)) => format!("${{{index}:{default}}}"),
+        Some(default) => default.to_string(),
+        None => match type_of(property, definitions) {
+            "string" => format!("${{{index}:{name}}}"),
+            "integer" | "number" => format!("${{{index}:0}}"),
+            "boolean" => format!("${{{index}:false}}"),
+            "array" => "[]".to_string(),
+            _ => "{}".to_string(),
+        },
+    }
+}
+
+fn label(name: &str, schema: &Value) -> String {
+    schema
+        .get("title")
+        .and_then(|t| t.as_str())
+        .unwrap_or(name)
+        .to_string()
+}
+
+fn description(schema: &Value) -> String {
+    schema
+        .get("description")
+        .and_then(|d| d.as_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn add_default_snippets(name: &str, definition: &mut Value, definitions: &Value) {
+    let body: Map<String, Value> = snippet_properties(definition)
+        .into_iter()
+        .enumerate()
+        .map(|(i, (key, property))| {
+            (
+                key.clone(),
+                snippet_value(key, property, definitions, i + 1),
+            )
+        })
+        .collect();
+    if let Some(properties) = definition
+        .get_mut("properties")
+        .and_then(|p| p.as_object_mut())
+    {
+        for (key, property) in properties.iter_mut() {
+            let snippet = json!({
+                "label": key,
+                "description": description(property),
+                "body": snippet_value(key, property, definitions, 1),
+            });
+            if let Some(property) = property.as_object_mut() {
+                property.insert("defaultSnippets".to_string(), json!([snippet]));
+            }
+        }
+    }
+    if !body.is_empty() {
+        let snippet = json!({
+            "label": label(name, definition),
+            "description": description(definition),
+            "body": body,
+        });
+        if let Some(definition) = definition.as_object_mut() {
+            definition.insert("defaultSnippets".to_string(), json!([snippet]));
+        }
+    }
+}
+
+/// The schema with `defaultSnippets` added for every definition and field,
+/// which yaml-language-server offers as completions.
+pub fn editor_schema() -> Value {
+    let mut schema = schema();
+    let definitions = schema.get("definitions").cloned().unwrap_or_default();
+    if let Some(entries) = schema
+        .get_mut("definitions")
+        .and_then(|d| d.as_object_mut())
+    {
+        for (name, definition) in entries.iter_mut() {
+            add_default_snippets(name, definition, &definitions);
+        }
+    }
+    add_default_snippets("TestSpec", &mut schema, &definitions);
+    schema
+}
+
+/// Snippets in the format of VS Code snippet files, one for the test and each definition.
+pub fn vscode_snippets() -> Value {
+    let schema = schema();
+    let definitions = schema.get("definitions").cloned().unwrap_or_default();
+    let entries = definitions.as_object().cloned().unwrap_or_default();
+    std::iter::once(("TestSpec".to_string(), schema.clone()))
+        .chain(entries)
+        .filter_map(|(name, definition)| {
+            let body: Vec<String> = snippet_properties(&definition)
+                .into_iter()
+                .enumerate()
+                .map(|(i, (key, property))| {
+                    format!(
+                        "{key}: {}",
+                        snippet_text(key, property, &definitions, i + 1)
+                    )
+                })
+                .collect();
+            (!body.is_empty()).then(|| {
+                let snippet = json!({
+                    "prefix": format!("blackjack-{}", name.to_lowercase()),
+                    "description": description(&definition),
+                    "body": body,
+                });
+                (label(&name, &definition), snippet)
+            })
+        })
+        .collect::<Map<String, Value>>()
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("/definitions/WaitSpec/defaultSnippets/0/body", json!({"timeout": "^${1:0}"}))]
+    #[case("/definitions/BucketSpec/defaultSnippets/0/body", json!({"name": "${1:name}", "operations": []}))]
+    #[case("/defaultSnippets/0/body", json!({"locks": [], "matrix": {}, "name": "", "setup": [], "steps": [], "tags": [], "teardown": [], "type": "user"}))]
+    #[case("/properties/attempts/defaultSnippets/0/body", json!("^${1:0}"))]
+    fn test_editor_schema(#[case] pointer: &str, #[case] expected: Value) {
+        assert_eq!(editor_schema().pointer(pointer), Some(&expected));
+    }
+
+    #[test]
+    fn test_vscode_snippets() {
+        let snippets = vscode_snippets();
+        assert_eq!(
+            snippets.pointer("/PortForwardSpec/body"),
+            Some(&json!(["name: ${1:name}", "port: ${2:0}"]))
+        );
+        assert_eq!(
+            snippets.pointer("/PortForwardSpec/prefix"),
+            Some(&json!("blackjack-portforwardspec"))
+        );
+    }
+}
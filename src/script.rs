@@ -1,37 +1,204 @@
 // Copyright 2024 Ole Kliemann
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::error::{Error, Result};
+use crate::config::Config;
+use crate::error::{Error, Result, ScriptFailure};
+use crate::log_prefix;
+use crate::test_spec::ScriptSpec;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
 use std::process::{ExitStatus, Stdio};
 use tempfile::NamedTempFile;
 use tokio::fs;
-use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
 use tokio::process::Command;
 use colored::Colorize;
 
-pub async fn execute_script(
-    command_line: &str,
-    wd: PathBuf,
+/// The shell scripts are sourced by, so that the variables they export can be captured.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Shell {
+    /// POSIX `sh`, the default on Unix
+    Sh,
+    /// `bash`
+    Bash,
+    /// `fish`
+    Fish,
+    /// Nushell (`nu`)
+    Nu,
+    /// PowerShell 7 (`pwsh`)
+    Pwsh,
+    /// Windows PowerShell, the default on Windows
+    Powershell,
+    /// Windows `cmd.exe`
+    Cmd,
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        if cfg!(windows) {
+            Shell::Powershell
+        } else {
+            Shell::Sh
+        }
+    }
+}
+
+impl Shell {
+    fn program(&self) -> &'static str {
+        match self {
+            Shell::Sh => "sh",
+            Shell::Bash => "bash",
+            Shell::Fish => "fish",
+            Shell::Nu => "nu",
+            Shell::Pwsh => "pwsh",
+            Shell::Powershell => "powershell",
+            Shell::Cmd => "cmd",
+        }
+    }
+
+    /// Arguments that source `command_line` and, if it succeeds,
+    /// write the resulting environment into `env_file`.
+    fn args(&self, command_line: &str, env_file: &Path) -> Vec<String> {
+        let env_file = env_file.display();
+        match self {
+            Shell::Sh | Shell::Bash => vec![
+                "-c".to_string(),
+                format!(
+                    ". {command_line} && {{ env -0 2>/dev/null || env; }} > '{}'",
+                    env_file.to_string().replace('\'', "'\\''")
+                ),
+            ],
+            Shell::Fish => vec![
+                "-c".to_string(),
+                format!(
+                    "source {command_line}; and begin; env -0 2>/dev/null; or env; end > '{}'",
+                    env_file.to_string().replace('\\', "\\\\").replace('\'', "\\'")
+                ),
+            ],
+            // The variables are written from `$env`, as nushell runs on Windows
+            // without an external `env`.
+            Shell::Nu => vec![
+                "-c".to_string(),
+                format!(
+                    "source {command_line}; $env | transpose name value \
+                     | where name starts-with BLACKJACK_ \
+                     | each {{|row| $\"($row.name)=($row.value)\"}} \
+                     | str join (char nul) | save --force `{env_file}`"
+                ),
+            ],
+            Shell::Pwsh | Shell::Powershell => vec![
+                "-NoProfile".to_string(),
+                "-NonInteractive".to_string(),
+                "-Command".to_string(),
+                format!(
+                    "$ErrorActionPreference = 'Stop'; . {command_line}; if (-not $?) {{ exit 1 }}; \
+                     [IO.File]::WriteAllText('{}', ((Get-ChildItem env: | ForEach-Object {{ \"$($_.Name)=$($_.Value)\" }}) -join [char]0))",
+                    env_file.to_string().replace('\'', "''")
+                ),
+            ],
+            Shell::Cmd => vec![
+                "/d".to_string(),
+                "/c".to_string(),
+                format!("call {command_line} && set > \"{env_file}\""),
+            ],
+        }
+    }
+}
+
+/// Parses the captured environment, separated by NUL characters if available,
+/// or else by lines, and returns all variables starting with `BLACKJACK_`.
+pub(crate) fn parse_env(contents: &str) -> Vec<(String, String)> {
+    let entries: Vec<&str> = if contents.contains('\0') {
+        contents.split('\0').collect()
+    } else {
+        contents.lines().collect()
+    };
+    entries
+        .into_iter()
+        .filter_map(|line| {
+            log::trace!("captured env: {line}");
+            let (var_name, value) = line.split_once('=')?;
+            var_name.starts_with("BLACKJACK_").then(|| {
+                let value = value.trim_end_matches('\r').trim_matches('\'');
+                (var_name.to_string(), value.to_string())
+            })
+        })
+        .collect()
+}
+
+/// Logs and collects the lines read from a stream of a script.
+pub(crate) fn read_lines<R>(
+    reader: R,
+) -> tokio::task::JoinHandle<std::result::Result<Vec<String>, futures::io::Error>>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    log_prefix::spawn(async move {
+        let mut buf = BufReader::new(reader);
+        let mut result: Vec<String> = Vec::new();
+        loop {
+            let mut s = String::new();
+            let size = buf.read_line(&mut s).await?;
+            if size == 0 {
+                break Ok(result);
+            }
+            log::info!("{}", s.strip_suffix("\n").or(Some(&s)).unwrap().dimmed());
+            result.push(s);
+        }
+    })
+}
+
+/// Kills the process group of a script if dropped before the script finished,
+/// e.g. when the test times out or is interrupted,
+/// so that processes started by the script do not outlive the run.
+struct ProcessGroup(Option<u32>);
+
+impl ProcessGroup {
+    fn finished(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(pid) = self.0 {
+            log::debug!("Killing process group {pid} of aborted script");
+            // SAFETY: killpg has no memory safety preconditions.
+            unsafe {
+                libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+            }
+        }
+    }
+}
+
+/// Runs the command, which is expected to write the environment into `env_file`,
+/// and captures the `BLACKJACK_` variables from it into `env`.
+pub(crate) async fn run_command(
+    mut command: Command,
+    env_file: &Path,
     env: &mut HashMap<String, String>,
 ) -> Result<(ExitStatus, String, String)> {
-    let env_file = NamedTempFile::new()?;
-    let env_file_path = env_file.path().to_owned();
-    let shell_command = format!(
-        ". {} && env -0 > {}",
-        command_line,
-        env_file_path.display()
-    );
-
-    let mut child = Command::new("sh")
-        .arg("-c")
-        .arg(shell_command)
+    #[cfg(unix)]
+    // SAFETY: setsid is async-signal-safe.
+    unsafe {
+        command.pre_exec(|| {
+            libc::setsid();
+            Ok(())
+        });
+    }
+    let mut child = command
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
-        .current_dir(wd)
-        .envs(env.clone())
+        .kill_on_drop(true)
         .spawn()?;
+    let process_group = ProcessGroup(child.id());
 
     let stdout = child
         .stdout
@@ -42,52 +209,233 @@ pub async fn execute_script(
         .take()
         .ok_or(Error::Other("unable to capture script stderr".to_string()))?;
 
-    let stdout_future: tokio::task::JoinHandle<std::result::Result<_, futures::io::Error>> =
-        tokio::spawn(async move {
-            let mut buf = BufReader::new(stdout);
-            let mut result: Vec<String> = Vec::new();
-            loop {
-                let mut s = String::new();
-                let size = buf.read_line(&mut s).await?;
-                if size == 0 {
-                    break Ok(result);
-                }
-                log::info!("{}", s.strip_suffix("\n").or(Some(&s)).unwrap().dimmed());
-                result.push(s);
-            }
-        });
-    let stderr_future: tokio::task::JoinHandle<std::result::Result<_, futures::io::Error>> =
-        tokio::spawn(async move {
-            let mut buf = BufReader::new(stderr);
-            let mut result: Vec<String> = Vec::new();
-            loop {
-                let mut s = String::new();
-                let size = buf.read_line(&mut s).await?;
-                if size == 0 {
-                    break Ok(result);
-                }
-                log::info!("{}", s.strip_suffix("\n").or(Some(&s)).unwrap().dimmed());
-                result.push(s);
-            }
-        });
+    let stdout_future = read_lines(stdout);
+    let stderr_future = read_lines(stderr);
 
     let status = child.wait().await?;
     let stdout_result = stdout_future.await??;
     let stderr_result = stderr_future.await??;
+    process_group.finished();
 
-    let env_contents = fs::read_to_string(env_file_path).await?;
-    for line in env_contents.split('\0') {
-        log::trace!("captured env: {line}");
-        if let Some(eq_pos) = line.find('=') {
-            let var_name = &line[..eq_pos];
-            if var_name.starts_with("BLACKJACK_") {
-                let value = &line[eq_pos + 1..];
-                let value = value.trim_matches('\'');
-                env.insert(var_name.to_string(), value.to_string());
-            }
-        }
-    }
+    let env_contents = fs::read_to_string(env_file).await?;
+    env.extend(parse_env(&env_contents));
     log::trace!("exit code: {status}");
 
-    Ok((status, stdout_result.join("\n"), stderr_result.join("\n")))
+    Ok((status, stdout_result.concat(), stderr_result.concat()))
+}
+
+/// Resolves the working directory of scripts, relative to the directory of the test.
+pub fn script_dir(
+    test_dir: &Path,
+    workdir: Option<&str>,
+    env: &HashMap<String, String>,
+) -> PathBuf {
+    match workdir {
+        Some(workdir) => test_dir
+            .join(envsubst::substitute(workdir, env).unwrap_or_else(|_| workdir.to_string())),
+        None => test_dir.to_path_buf(),
+    }
+}
+
+/// Prepends the directories from `script_path` to `path`.
+/// Relative directories are resolved against the directory Blackjack was started in.
+fn search_path(script_path: &[String], path: Option<OsString>) -> Result<OsString> {
+    let extra = script_path
+        .iter()
+        .map(std::path::absolute)
+        .collect::<std::io::Result<Vec<PathBuf>>>()?;
+    let existing = path
+        .as_ref()
+        .map(std::env::split_paths)
+        .into_iter()
+        .flatten();
+    std::env::join_paths(extra.into_iter().chain(existing))
+        .map_err(|err| Error::Other(format!("invalid script path: {err}")))
+}
+
+/// Number of lines of the output of a failed script included in the error.
+const FAILURE_OUTPUT_LINES: usize = 20;
+
+fn last_lines(output: &str, count: usize) -> Vec<String> {
+    let lines: Vec<&str> = output.lines().filter(|line| !line.is_empty()).collect();
+    lines[lines.len().saturating_sub(count)..]
+        .iter()
+        .map(|line| line.to_string())
+        .collect()
+}
+
+fn is_secret(name: &str) -> bool {
+    let name = name.to_uppercase();
+    ["PASSWORD", "SECRET", "TOKEN", "KEY", "CREDENTIAL"]
+        .iter()
+        .any(|word| name.contains(word))
+}
+
+pub fn script_failure(
+    script: &ScriptSpec,
+    status: ExitStatus,
+    stdout: &str,
+    stderr: &str,
+    env: &HashMap<String, String>,
+) -> Error {
+    let env = env
+        .iter()
+        .map(|(name, value)| {
+            let value = if is_secret(name) { "***" } else { value };
+            (name.clone(), value.to_string())
+        })
+        .collect();
+    Error::ScriptFailed(Box::new(ScriptFailure {
+        script: script.command().to_string(),
+        exit_code: status.code(),
+        expected_failure: script.expect_failure(),
+        stdout: last_lines(stdout, FAILURE_OUTPUT_LINES),
+        stderr: last_lines(stderr, FAILURE_OUTPUT_LINES),
+        env,
+    }))
+}
+
+pub async fn execute_script(
+    config: &Config,
+    command_line: &str,
+    shell: Shell,
+    wd: PathBuf,
+    env: &mut HashMap<String, String>,
+) -> Result<(ExitStatus, String, String)> {
+    let env_file = NamedTempFile::new()?;
+    let mut command = Command::new(shell.program());
+    command
+        .args(shell.args(command_line, env_file.path()))
+        .current_dir(wd)
+        .envs(env.clone());
+    let script_path = &config.script_path;
+    if !script_path.is_empty() {
+        let path = env
+            .get("PATH")
+            .map(OsString::from)
+            .or_else(|| std::env::var_os("PATH"));
+        command.env("PATH", search_path(script_path, path)?);
+    }
+    run_command(command, env_file.path(), env).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use std::time::Duration;
+
+    #[rstest]
+    #[case("BLACKJACK_A=1\0PATH=/bin\0BLACKJACK_B=x\ny\0", vec![("BLACKJACK_A", "1"), ("BLACKJACK_B", "x\ny")])]
+    #[case("BLACKJACK_A=1\nHOME=/root\nBLACKJACK_B='quoted'\n", vec![("BLACKJACK_A", "1"), ("BLACKJACK_B", "quoted")])]
+    #[case("BLACKJACK_A=1\r\nBLACKJACK_B=a=b\r\n", vec![("BLACKJACK_A", "1"), ("BLACKJACK_B", "a=b")])]
+    #[case("", vec![])]
+    fn test_parse_env(#[case] contents: &str, #[case] expected: Vec<(&str, &str)>) {
+        let expected: Vec<(String, String)> = expected
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        assert_eq!(parse_env(contents), expected);
+    }
+
+    #[rstest]
+    #[case(None, "/repo/tests/a")]
+    #[case(Some("fixtures"), "/repo/tests/a/fixtures")]
+    #[case(Some("${BLACKJACK_ROOT}/tools"), "/repo/tools")]
+    #[case(Some("/opt/tools"), "/opt/tools")]
+    fn test_script_dir(#[case] workdir: Option<&str>, #[case] expected: &str) {
+        let env = HashMap::from([("BLACKJACK_ROOT".to_string(), "/repo".to_string())]);
+        assert_eq!(
+            script_dir(Path::new("/repo/tests/a"), workdir, &env),
+            PathBuf::from(expected)
+        );
+    }
+
+    #[rstest]
+    #[case("a\n\nb\nc\n", 2, vec!["b", "c"])]
+    #[case("a\nb\n", 5, vec!["a", "b"])]
+    #[case("", 5, vec![])]
+    fn test_last_lines(#[case] output: &str, #[case] count: usize, #[case] expected: Vec<&str>) {
+        assert_eq!(last_lines(output, count), expected);
+    }
+
+    #[rstest]
+    #[case("BLACKJACK_DB_PASSWORD", true)]
+    #[case("AWS_SECRET_ACCESS_KEY", true)]
+    #[case("BLACKJACK_api_token", true)]
+    #[case("BLACKJACK_NAMESPACE", false)]
+    #[case("KUBECONFIG", false)]
+    fn test_is_secret(#[case] name: &str, #[case] expected: bool) {
+        assert_eq!(is_secret(name), expected);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_abort_kills_process_group() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("pid");
+        let mut command = Command::new("sh");
+        command.args([
+            "-c",
+            &format!("sleep 60 & echo $! > '{}'; wait", pid_file.display()),
+        ]);
+        let env_file = dir.path().join("env");
+        let mut env = HashMap::new();
+        let run = run_command(command, &env_file, &mut env);
+        assert!(tokio::time::timeout(Duration::from_millis(500), run)
+            .await
+            .is_err());
+        let pid: u32 = fs::read_to_string(&pid_file)
+            .await
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        // The killed process is either gone or a zombie not yet reaped.
+        let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).unwrap_or_default();
+        assert!(stat.is_empty() || stat.contains(") Z "), "{stat}");
+    }
+
+    #[cfg(unix)]
+    #[rstest]
+    #[case(Shell::Sh)]
+    #[case(Shell::Bash)]
+    #[tokio::test]
+    async fn test_capture_env(#[case] shell: Shell) {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("setup.sh"),
+            "export BLACKJACK_TOKEN='a b'\nexport OTHER=x\n",
+        )
+        .unwrap();
+        let env_file = dir.path().join("env");
+        let mut command = Command::new(shell.program());
+        command
+            .args(shell.args("./setup.sh", &env_file))
+            .current_dir(dir.path());
+        let mut env = HashMap::new();
+        let (status, _, _) = run_command(command, &env_file, &mut env).await.unwrap();
+        assert!(status.success());
+        assert_eq!(
+            env,
+            HashMap::from([("BLACKJACK_TOKEN".to_string(), "a b".to_string())])
+        );
+    }
+
+    #[cfg(unix)]
+    #[rstest]
+    #[case(vec!["/opt/tools"], Some("/usr/bin:/bin"), "/opt/tools:/usr/bin:/bin")]
+    #[case(vec!["/opt/a", "/opt/b"], None, "/opt/a:/opt/b")]
+    fn test_search_path(
+        #[case] script_path: Vec<&str>,
+        #[case] path: Option<&str>,
+        #[case] expected: &str,
+    ) {
+        let script_path: Vec<String> = script_path.into_iter().map(String::from).collect();
+        assert_eq!(
+            search_path(&script_path, path.map(OsString::from)).unwrap(),
+            OsString::from(expected)
+        );
+    }
 }
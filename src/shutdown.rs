@@ -0,0 +1,145 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use once_cell::sync::OnceCell;
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep_until, Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+/// Termination signals received during a run.
+/// The first cancels running tests, the second skips their cleanup.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    requested: CancellationToken,
+    forced: CancellationToken,
+    /// When the first signal was received.
+    requested_at: Arc<OnceCell<Instant>>,
+}
+
+/// Tasks recording signals for a run, which stop when dropped.
+pub struct Listener(pub(crate) Vec<JoinHandle<()>>);
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        for task in &self.0 {
+            task.abort();
+        }
+    }
+}
+
+impl Shutdown {
+    /// Whether the run is shutting down, as a termination signal has been received.
+    pub fn is_requested(&self) -> bool {
+        self.requested.is_cancelled()
+    }
+
+    /// Records a termination signal.
+    pub fn signal(&self, name: &str) {
+        if !self.is_requested() {
+            let _ = self.requested_at.set(Instant::now());
+            self.requested.cancel();
+            log::warn!("Received {name}, cancelling running tests and cleaning up");
+        } else {
+            self.forced.cancel();
+            log::warn!("Received another {name}, skipping cleanup");
+        }
+    }
+
+    /// Waits for the first termination signal, returning at once if it has been received.
+    pub async fn requested(&self) {
+        self.requested.cancelled().await
+    }
+
+    /// Waits for the second termination signal, after which cleanup is skipped.
+    pub async fn forced(&self) {
+        self.forced.cancelled().await
+    }
+
+    /// Waits until `grace_period` has passed since the first termination signal,
+    /// after which cleanup is abandoned.
+    pub async fn expired(&self, grace_period: Duration) {
+        self.requested().await;
+        let requested_at = self
+            .requested_at
+            .get()
+            .copied()
+            .unwrap_or_else(Instant::now);
+        sleep_until(requested_at + grace_period).await;
+    }
+
+    /// Records SIGINT and SIGTERM for the run, instead of terminating the process,
+    /// until the returned listener is dropped.
+    pub fn listen(&self) -> std::io::Result<Listener> {
+        let mut tasks = self.listen_sigterm()?;
+        let shutdown = self.clone();
+        tasks.push(tokio::task::spawn(async move {
+            while tokio::signal::ctrl_c().await.is_ok() {
+                shutdown.signal("SIGINT");
+            }
+        }));
+        Ok(Listener(tasks))
+    }
+
+    #[cfg(unix)]
+    fn listen_sigterm(&self) -> std::io::Result<Vec<JoinHandle<()>>> {
+        use tokio::signal::unix::SignalKind;
+        let mut sigterm = tokio::signal::unix::signal(SignalKind::terminate())?;
+        let shutdown = self.clone();
+        Ok(vec![tokio::task::spawn(async move {
+            while sigterm.recv().await.is_some() {
+                shutdown.signal("SIGTERM");
+            }
+        })])
+    }
+
+    #[cfg(not(unix))]
+    fn listen_sigterm(&self) -> std::io::Result<Vec<JoinHandle<()>>> {
+        Ok(vec![])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_signal() {
+        let shutdown = Shutdown::default();
+        assert!(!shutdown.is_requested());
+        let first = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move { shutdown.requested().await }
+        });
+        let second = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move { shutdown.forced().await }
+        });
+        let grace = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move { shutdown.expired(Duration::from_millis(50)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!first.is_finished());
+        shutdown.signal("SIGTERM");
+        assert!(shutdown.is_requested());
+        tokio::time::timeout(Duration::from_secs(1), first)
+            .await
+            .unwrap()
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!second.is_finished());
+        assert!(!grace.is_finished());
+        shutdown.signal("SIGTERM");
+        tokio::time::timeout(Duration::from_secs(1), second)
+            .await
+            .unwrap()
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(1), grace)
+            .await
+            .unwrap()
+            .unwrap();
+        shutdown.requested().await;
+        assert!(!Shutdown::default().is_requested());
+    }
+}
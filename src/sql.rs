@@ -0,0 +1,155 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::check::{assert_expr, CheckOptions, History};
+use crate::config::Config;
+use crate::error::{AssertDiagnostic, Error, Result, TestFailure, TestFailures};
+use crate::test_spec::{subst_process_env, SqlSpec, WaitSpec};
+use crate::wait::wait_until;
+use serde_json::{Map, Value};
+use sqlx::any::{AnyRow, AnyTypeInfoKind};
+use sqlx::{AnyConnection, Column, Connection, Row};
+use std::sync::Once;
+use tokio::time::{Duration, Instant};
+
+static INSTALL_DRIVERS: Once = Once::new();
+
+fn sql_error(err: impl std::fmt::Display) -> Error {
+    Error::SqlError(err.to_string())
+}
+
+fn column_value(row: &AnyRow, index: usize) -> Result<Value> {
+    let column = row.column(index);
+    let value = match column.type_info().kind() {
+        AnyTypeInfoKind::Null => Value::Null,
+        AnyTypeInfoKind::Bool => row
+            .try_get::<Option<bool>, _>(index)
+            .map_err(sql_error)?
+            .into(),
+        AnyTypeInfoKind::SmallInt | AnyTypeInfoKind::Integer | AnyTypeInfoKind::BigInt => row
+            .try_get::<Option<i64>, _>(index)
+            .map_err(sql_error)?
+            .into(),
+        AnyTypeInfoKind::Real | AnyTypeInfoKind::Double => row
+            .try_get::<Option<f64>, _>(index)
+            .map_err(sql_error)?
+            .into(),
+        AnyTypeInfoKind::Text => row
+            .try_get::<Option<String>, _>(index)
+            .map_err(sql_error)?
+            .into(),
+        AnyTypeInfoKind::Blob => row
+            .try_get::<Option<Vec<u8>>, _>(index)
+            .map_err(sql_error)?
+            .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+            .into(),
+    };
+    Ok(value)
+}
+
+/// The row as object of its columns by name.
+fn row_value(row: &AnyRow) -> Result<Value> {
+    let mut columns = Map::new();
+    for (index, column) in row.columns().iter().enumerate() {
+        columns.insert(column.name().to_string(), column_value(row, index)?);
+    }
+    Ok(Value::Object(columns))
+}
+
+async fn query(connection: &mut AnyConnection, query: &str) -> Result<Vec<Value>> {
+    sqlx::query(query)
+        .fetch_all(connection)
+        .await
+        .map_err(sql_error)?
+        .iter()
+        .map(row_value)
+        .collect()
+}
+
+fn check_rows(spec: &SqlSpec, rows: &[Value]) -> std::result::Result<(), AssertDiagnostic> {
+    let rows: Vec<&Value> = rows.iter().collect();
+    assert_expr(
+        &rows,
+        &vec![],
+        &History::new(),
+        &spec.condition,
+        CheckOptions {
+            coerce: spec.coerce,
+        },
+    )
+}
+
+async fn connect_and_query(url: &str, query_str: &str) -> Result<Vec<Value>> {
+    let mut connection = AnyConnection::connect(url).await.map_err(sql_error)?;
+    let rows = query(&mut connection, query_str).await;
+    connection.close().await.map_err(sql_error)?;
+    rows
+}
+
+/// Runs the query repeatedly until the returned rows fulfill the condition.
+/// Connection and query errors are retried as well, as the database
+/// or its tables may not exist yet.
+async fn wait_for_rows(
+    config: &Config,
+    spec: &SqlSpec,
+) -> Result<std::result::Result<(), AssertDiagnostic>> {
+    let deadline = Instant::now() + config.scaled_timeout(spec.timeout);
+    let url = subst_process_env(spec.url.clone());
+    wait_until(
+        deadline,
+        Duration::from_millis(500),
+        || connect_and_query(&url, &spec.query),
+        |rows| check_rows(spec, rows),
+    )
+    .await
+}
+
+/// Waits until the results of all queries fulfill their conditions.
+pub async fn wait_for_sql(config: &Config, specs: Vec<SqlSpec>) -> Result<()> {
+    INSTALL_DRIVERS.call_once(sqlx::any::install_default_drivers);
+    log::debug!("Waiting for {} queries", specs.len());
+    let mut errors: Vec<TestFailure> = Vec::new();
+    for spec in specs {
+        if let Err(assert_diagnostic) = wait_for_rows(config, &spec).await? {
+            let target = format!("sql:{}", spec.query);
+            let spec = WaitSpec {
+                condition: spec.condition,
+                coerce: spec.coerce,
+                ..WaitSpec::synthetic(target, spec.timeout)
+            };
+            errors.push(TestFailure::new(spec, assert_diagnostic));
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::ConditionsFailed(TestFailures(errors)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serde_json::json;
+
+    #[rstest]
+    #[case(json!({"size": 2}), true)]
+    #[case(json!({"size": 0}), false)]
+    #[case(json!({"one": {"name": "alice", "active": true}}), true)]
+    #[case(json!({"all": {"active": true}}), false)]
+    fn test_check_rows(#[case] condition: Value, #[case] expected: bool) {
+        let spec: SqlSpec = serde_json::from_value(json!({
+            "query": "SELECT name, active FROM users",
+            "condition": condition,
+            "timeout": 1,
+        }))
+        .unwrap();
+        assert_eq!(spec.url, "${BLACKJACK_DATABASE_URL}");
+        let rows = vec![
+            json!({"name": "alice", "active": true}),
+            json!({"name": "bob", "active": false}),
+        ];
+        assert_eq!(check_rows(&spec, &rows).is_ok(), expected);
+    }
+}
@@ -0,0 +1,90 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::error::{Error, Result, TestResult};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// File in the directory of the test suite recording the outcome of previous runs.
+pub const STATE_FILE: &str = ".blackjack-state.json";
+
+/// Tests that failed when they were last run, for `--rerun-failed`.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct State {
+    pub run_id: String,
+    pub failed: BTreeSet<String>,
+}
+
+impl State {
+    pub fn path(dirname: &Path) -> PathBuf {
+        dirname.join(STATE_FILE)
+    }
+
+    /// Reads the state of the test suite in `dirname`, which is empty before its first run.
+    pub fn read(dirname: &Path) -> Result<State> {
+        let path = State::path(dirname);
+        if !path.exists() {
+            return Ok(State::default());
+        }
+        let data = std::fs::read_to_string(&path)?;
+        serde_json::from_str(&data)
+            .map_err(|err| Error::Other(format!("Invalid state {}: {err}", path.display())))
+    }
+
+    /// Updates the state with the results of a run.
+    /// Tests that were not run keep their previous state.
+    pub fn update(self, run_id: &str, results: &[TestResult]) -> State {
+        let mut failed = self.failed;
+        for result in results {
+            match result {
+                Ok(test_name) => failed.remove(test_name),
+                Err(failed_test) => failed.insert(failed_test.test_name.clone()),
+            };
+        }
+        State {
+            run_id: run_id.to_string(),
+            failed,
+        }
+    }
+
+    pub fn write(&self, dirname: &Path) -> Result<()> {
+        std::fs::write(State::path(dirname), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::FailedTest;
+
+    #[test]
+    fn test_update() {
+        let failed = |test_name: &str| FailedTest {
+            test_name: test_name.to_string(),
+            step_name: "".to_string(),
+            failure: Error::NotExecuted,
+        };
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(State::read(dir.path()).unwrap(), State::default());
+        State::default()
+            .update(
+                "first",
+                &[Err(failed("a")), Err(failed("b")), Ok("c".to_string())],
+            )
+            .write(dir.path())
+            .unwrap();
+        let state = State::read(dir.path()).unwrap();
+        assert_eq!(state.run_id, "first");
+        assert_eq!(
+            state.failed,
+            BTreeSet::from(["a".to_string(), "b".to_string()])
+        );
+        let state = state.update("second", &[Ok("a".to_string()), Err(failed("c"))]);
+        assert_eq!(
+            state.failed,
+            BTreeSet::from(["b".to_string(), "c".to_string()])
+        );
+    }
+}
@@ -0,0 +1,95 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::test_spec::TestSpec;
+use regex::Regex;
+
+pub struct TestFilter {
+    name: Option<Regex>,
+    tags: Vec<String>,
+    skip_tags: Vec<String>,
+}
+
+pub(crate) fn glob_to_regex(glob: &str) -> String {
+    let escaped = regex::escape(glob)
+        .replace("\\*", ".*")
+        .replace("\\?", ".");
+    format!("^{escaped}$")
+}
+
+impl TestFilter {
+    /// Patterns enclosed in slashes (`/.../`) are regular expressions,
+    /// all others are globs matched against the whole test name.
+    pub fn new(pattern: Option<&str>, tags: Vec<String>, skip_tags: Vec<String>) -> Result<Self> {
+        let name = pattern
+            .map(|pattern| {
+                let expr = match pattern
+                    .strip_prefix('/')
+                    .and_then(|p| p.strip_suffix('/'))
+                {
+                    Some(expr) => expr.to_string(),
+                    None => glob_to_regex(pattern),
+                };
+                Regex::new(&expr).map_err(|err| Error::Other(format!("invalid filter: {err}")))
+            })
+            .transpose()?;
+        Ok(TestFilter {
+            name,
+            tags,
+            skip_tags,
+        })
+    }
+
+    pub fn from_config(config: &Config) -> Result<Self> {
+        TestFilter::new(
+            config.filter.as_deref(),
+            config.tags.clone(),
+            config.skip_tags.clone(),
+        )
+    }
+
+    pub fn matches(&self, test_spec: &TestSpec) -> bool {
+        self.name
+            .as_ref()
+            .is_none_or(|name| name.is_match(&test_spec.name))
+            && (self.tags.is_empty() || self.tags.iter().any(|t| test_spec.tags.contains(t)))
+            && !self.skip_tags.iter().any(|t| test_spec.tags.contains(t))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(Some("test-*"), vec![], vec![], "test-foo", vec![], true)]
+    #[case(Some("test-*"), vec![], vec![], "other-foo", vec![], false)]
+    #[case(Some("test-?oo"), vec![], vec![], "test-foo", vec![], true)]
+    #[case(Some("/^test-(foo|bar)$/"), vec![], vec![], "test-bar", vec![], true)]
+    #[case(Some("/^test-(foo|bar)$/"), vec![], vec![], "test-baz", vec![], false)]
+    #[case(None, vec!["slow"], vec![], "test-foo", vec!["slow", "mqtt"], true)]
+    #[case(None, vec!["slow"], vec![], "test-foo", vec!["mqtt"], false)]
+    #[case(None, vec![], vec!["slow"], "test-foo", vec!["slow"], false)]
+    #[case(None, vec![], vec!["slow"], "test-foo", vec![], true)]
+    #[case(Some("test-*"), vec!["mqtt"], vec!["slow"], "test-foo", vec!["mqtt", "slow"], false)]
+    fn test_matches(
+        #[case] pattern: Option<&str>,
+        #[case] tags: Vec<&str>,
+        #[case] skip_tags: Vec<&str>,
+        #[case] name: &str,
+        #[case] test_tags: Vec<&str>,
+        #[case] expected: bool,
+    ) {
+        let to_strings = |v: Vec<&str>| v.into_iter().map(String::from).collect::<Vec<_>>();
+        let filter = TestFilter::new(pattern, to_strings(tags), to_strings(skip_tags)).unwrap();
+        let test_spec = TestSpec {
+            name: name.to_string(),
+            tags: to_strings(test_tags),
+            ..Default::default()
+        };
+        assert_eq!(filter.matches(&test_spec), expected);
+    }
+}
@@ -0,0 +1,888 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::check::path_conditions;
+use crate::config::Config;
+use crate::crd_schema::{schemas_from_cluster, schemas_from_manifests, validate_conditions};
+use crate::error::{Error, Result};
+use crate::json_path;
+use crate::patch::make_patch;
+use crate::result_formatting::{log_skipped, log_validation};
+use crate::run_test::discover_all_tests;
+use crate::test_filter::TestFilter;
+use crate::test_spec::{ApplySpec, StepSpec, SuiteSpec, TestSpec, WatchSpec};
+use kube::core::GroupVersionKind;
+use kube::Client;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+
+static LABEL_NAME: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[A-Za-z0-9]([-A-Za-z0-9_.]{0,61}[A-Za-z0-9])?$").unwrap());
+static LABEL_PREFIX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^[a-z0-9]([-a-z0-9]*[a-z0-9])?(\.[a-z0-9]([-a-z0-9]*[a-z0-9])?)*$").unwrap()
+});
+static DNS_LABEL: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[a-z]([-a-z0-9]{0,61}[a-z0-9])?$").unwrap());
+static FIELD_PATH: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[A-Za-z][A-Za-z0-9]*(\.[A-Za-z][A-Za-z0-9]*)*$").unwrap());
+
+fn contains_env(s: &str) -> bool {
+    s.contains('$')
+}
+
+fn validate_apply(test_spec: &TestSpec, step_name: &str, apply: &ApplySpec) -> Option<String> {
+    let path = test_spec.dir.join(&apply.path);
+    (!contains_env(&apply.path) && !path.exists())
+        .then(|| format!("{step_name}: manifest path does not exist: {}", path.display()))
+}
+
+fn validate_script(test_spec: &TestSpec, step_name: &str, script: &str) -> Option<String> {
+    match shell_words::split(script) {
+        Ok(words) => words.first().and_then(|command| {
+            let path = test_spec.dir.join(command);
+            (!contains_env(command) && !path.exists())
+                .then(|| format!("{step_name}: script does not exist: {}", path.display()))
+        }),
+        Err(err) => Some(format!("{step_name}: unable to parse script '{script}': {err}")),
+    }
+}
+
+fn is_label_key(key: &str) -> bool {
+    match key.split_once('/') {
+        Some((prefix, name)) => {
+            prefix.len() <= 253 && LABEL_PREFIX.is_match(prefix) && LABEL_NAME.is_match(name)
+        }
+        None => LABEL_NAME.is_match(key),
+    }
+}
+
+fn is_label_value(value: &str) -> bool {
+    value.is_empty() || LABEL_NAME.is_match(value)
+}
+
+fn is_field_value(value: &str) -> bool {
+    !value.contains([',', '=', '!'])
+}
+
+fn validate_selector(
+    step_name: &str,
+    watch: &WatchSpec,
+    what: &str,
+    selector: &Option<BTreeMap<String, String>>,
+    is_key: fn(&str) -> bool,
+    is_value: fn(&str) -> bool,
+) -> Vec<String> {
+    selector
+        .iter()
+        .flatten()
+        .filter(|(key, value)| !contains_env(key) && !contains_env(value))
+        .filter(|(key, value)| !is_key(key) || !is_value(value))
+        .map(|(key, value)| {
+            format!(
+                "{step_name}: watch '{}': invalid {what} selector '{key}={value}'",
+                watch.name
+            )
+        })
+        .collect()
+}
+
+fn validate_watch(step_name: &str, watch: &WatchSpec) -> Vec<String> {
+    let mut problems = vec![];
+    if watch.kind.is_empty() {
+        problems.push(format!(
+            "{step_name}: watch '{}': kind is required",
+            watch.name
+        ));
+    }
+    if !watch.version.is_empty() && !watch.versions.is_empty() {
+        problems.push(format!(
+            "{step_name}: watch '{}': version and versions cannot be combined",
+            watch.name
+        ));
+    }
+    if watch.namespace.patterns().is_empty() {
+        problems.push(format!(
+            "{step_name}: watch '{}': namespace list is empty",
+            watch.name
+        ));
+    }
+    problems.extend(validate_selector(
+        step_name,
+        watch,
+        "label",
+        &watch.labels,
+        is_label_key,
+        is_label_value,
+    ));
+    problems.extend(validate_selector(
+        step_name,
+        watch,
+        "field",
+        &watch.fields,
+        |key| FIELD_PATH.is_match(key),
+        is_field_value,
+    ));
+    problems
+}
+
+/// Whether the kind is served in the version,
+/// or in the preferred version of the group if no version is given.
+async fn kind_exists(client: &Client, group: &str, version: &str, kind: &str) -> bool {
+    match version {
+        "" => kube::discovery::group(client, group)
+            .await
+            .is_ok_and(|group| group.recommended_kind(kind).is_some()),
+        version => {
+            let gvk = GroupVersionKind::gvk(group, version, kind);
+            kube::discovery::pinned_kind(client, &gvk).await.is_ok()
+        }
+    }
+}
+
+/// Resolves the resource kinds of all watches via discovery.
+/// Results are cached in `known` across tests.
+async fn validate_kinds(
+    client: &Client,
+    test_spec: &TestSpec,
+    known: &mut HashMap<(String, String, String), bool>,
+) -> Vec<String> {
+    let mut problems = vec![];
+    for step in test_spec.all_steps() {
+        for watch in &step.watch {
+            if watch.kind.is_empty() {
+                continue;
+            }
+            let versions = match watch.versions.is_empty() {
+                true => vec![watch.version.clone()],
+                false => watch.versions.clone(),
+            };
+            for version in versions {
+                let key = (watch.group.clone(), version, watch.kind.clone());
+                let exists = match known.get(&key) {
+                    Some(exists) => *exists,
+                    None => {
+                        let exists = kind_exists(client, &key.0, &key.1, &key.2).await;
+                        known.insert(key.clone(), exists);
+                        exists
+                    }
+                };
+                if !exists {
+                    let api_version = match (watch.group.as_str(), key.1.as_str()) {
+                        ("", "") => "core".to_string(),
+                        ("", version) => version.to_string(),
+                        (group, "") => group.to_string(),
+                        (group, version) => format!("{group}/{version}"),
+                    };
+                    problems.push(format!(
+                        "{}: watch '{}': unknown resource kind '{}' in '{api_version}'",
+                        step.name, watch.name, watch.kind
+                    ));
+                }
+            }
+        }
+    }
+    problems
+}
+
+/// Names of the buckets fed by a step, by watches, conversion checks, MQTT subscriptions
+/// or webhook receivers.
+fn bucket_names(step: &StepSpec) -> impl Iterator<Item = &String> {
+    step.watch
+        .iter()
+        .map(|w| &w.name)
+        .chain(step.conversion.iter().map(|c| &c.name))
+        .chain(step.mqtt_subscribe_to_bucket.iter().map(|s| &s.name))
+        .chain(step.webhook_receiver.iter().map(|r| &r.name))
+}
+
+pub fn validate_test_spec(test_spec: &TestSpec) -> Vec<String> {
+    let mut problems: Vec<String> = vec![];
+    let all_watches: HashSet<&String> = test_spec.all_steps().flat_map(bucket_names).collect();
+    let mut declared_watches: HashSet<&String> = HashSet::new();
+
+    for (key, values) in &test_spec.matrix {
+        if values.is_empty() {
+            problems.push(format!("matrix variable '{key}' has no values"));
+        }
+    }
+    if test_spec
+        .timeout_scaling
+        .is_some_and(|scaling| scaling <= 0.0)
+    {
+        problems.push("timeout-scaling must be positive".to_string());
+    }
+    if let Some(faults) = &test_spec.faults {
+        if !(0.0..=1.0).contains(&faults.error_rate) {
+            problems.push("faults: error-rate must be between 0 and 1".to_string());
+        }
+        if faults.status != 429 && !(500..600).contains(&faults.status) {
+            problems.push(format!(
+                "faults: status {} is neither 429 nor a 5xx",
+                faults.status
+            ));
+        }
+    }
+    if let Some(apply_rate) = &test_spec.apply_rate {
+        if !(apply_rate.rate > 0.0 && apply_rate.rate.is_finite()) {
+            problems.push("apply-rate: rate must be positive".to_string());
+        }
+        if apply_rate.burst == 0 {
+            problems.push("apply-rate: burst must be at least 1".to_string());
+        }
+    }
+
+    for step in test_spec.all_steps() {
+        let step_name = &step.name;
+        declared_watches.extend(bucket_names(step));
+
+        problems.extend(
+            step.watch
+                .iter()
+                .flat_map(|watch| validate_watch(step_name, watch)),
+        );
+
+        for bucket in &step.bucket {
+            if !all_watches.contains(&bucket.name) {
+                problems.push(format!(
+                    "{step_name}: bucket operation on '{}' which is never watched",
+                    bucket.name
+                ));
+            }
+        }
+        if let Some(quiesce) = &step.quiesce {
+            for bucket in quiesce.buckets.iter().filter(|b| !all_watches.contains(b)) {
+                problems.push(format!(
+                    "{step_name}: quiesce on '{bucket}' which is never watched"
+                ));
+            }
+            if quiesce.period >= quiesce.timeout {
+                problems.push(format!(
+                    "{step_name}: quiesce period must be shorter than its timeout"
+                ));
+            }
+        }
+        problems.extend(
+            step.apply
+                .iter()
+                .chain(step.delete.iter())
+                .filter_map(|apply| validate_apply(test_spec, step_name, apply)),
+        );
+        for port_forward in &step.port_forward {
+            if port_forward.pod.is_none() && port_forward.service.is_none() {
+                problems.push(format!(
+                    "{step_name}: port forward '{}' needs either pod or service",
+                    port_forward.name
+                ));
+            }
+        }
+        problems.extend(
+            step.script
+                .iter()
+                .filter_map(|script| validate_script(test_spec, step_name, script.command())),
+        );
+        if step.timeout_scaling.is_some_and(|scaling| scaling <= 0.0) {
+            problems.push(format!("{step_name}: timeout-scaling must be positive"));
+        }
+        if step.script_container.is_some() && step.script.iter().any(|s| s.shell().is_some()) {
+            problems.push(format!(
+                "{step_name}: shell of scripts cannot be chosen with a script container"
+            ));
+        }
+        for wait in &step.wait {
+            if wait.target.is_empty()
+                && wait.http.is_none()
+                && wait.prometheus.is_none()
+                && wait.metrics.is_none()
+            {
+                problems.push(format!(
+                    "{step_name}: wait needs either target, http, prometheus or metrics"
+                ));
+            }
+            if let Some(metrics) = &wait.metrics {
+                if metrics.url.is_some() == metrics.pod.is_some() {
+                    problems.push(format!("{step_name}: metrics needs either url or pod"));
+                }
+                if metrics.pod.is_some() && metrics.port.is_none() {
+                    problems.push(format!("{step_name}: metrics of a pod need a port"));
+                }
+            }
+            if let Some(path) = &wait.assert_file {
+                if wait.target.is_empty() {
+                    problems.push(format!("{step_name}: assert-file '{path}' needs a target"));
+                }
+                if !contains_env(path) && !test_spec.dir.join(path).exists() {
+                    problems.push(format!(
+                        "{step_name}: assert file does not exist: {}",
+                        test_spec.dir.join(path).display()
+                    ));
+                }
+            }
+        }
+        for messages in &step.messages {
+            if messages.kafka.is_some() == messages.nats.is_some() {
+                problems.push(format!("{step_name}: messages needs either kafka or nats"));
+            }
+        }
+        for sql in &step.sql {
+            if sql.query.trim().is_empty() {
+                problems.push(format!("{step_name}: sql query must not be empty"));
+            }
+        }
+        for s3 in &step.s3 {
+            let credentials = [&s3.access_key_id, &s3.secret_access_key];
+            if credentials.into_iter().flatten().any(|c| !contains_env(c)) {
+                problems.push(format!(
+                    "{step_name}: s3 credentials must reference variables instead of literal values"
+                ));
+            }
+        }
+        for certificate in &step.certificate {
+            if certificate.secret.is_some() == certificate.endpoint.is_some() {
+                problems.push(format!(
+                    "{step_name}: certificate needs either secret or endpoint"
+                ));
+            }
+        }
+        for logs in step.logs.iter().filter(|l| !contains_env(&l.regex)) {
+            if let Err(err) = Regex::new(&logs.regex) {
+                problems.push(format!("{step_name}: logs: invalid regex: {err}"));
+            }
+        }
+        for patch in &step.patch {
+            if let Err(err) = make_patch(patch) {
+                problems.push(format!("{step_name}: {err}"));
+            }
+        }
+        if step.chaos.iter().any(|chaos| chaos.count == 0) {
+            problems.push(format!("{step_name}: chaos count must be at least 1"));
+        }
+        let mut qos = step
+            .mqtt_subscribe_to_bucket
+            .iter()
+            .map(|s| s.qos)
+            .chain(step.mqtt_publish.iter().map(|p| p.qos));
+        if qos.any(|qos| qos > 2) {
+            problems.push(format!("{step_name}: mqtt qos must be 0, 1 or 2"));
+        }
+        for receiver in step.webhook_receiver.iter().filter(|r| r.relay) {
+            if !DNS_LABEL.is_match(&format!("blackjack-webhook-{}", receiver.name)) {
+                problems.push(format!(
+                    "{step_name}: webhook receiver '{}': relay needs a lower case name of at most 45 characters",
+                    receiver.name
+                ));
+            }
+        }
+        for export in step.export.iter().filter(|e| !contains_env(&e.path)) {
+            if let Err(err) = json_path::parse(&export.path) {
+                problems.push(format!("{step_name}: export '{}': {err}", export.name));
+            }
+        }
+        let conditions = step
+            .wait
+            .iter()
+            .map(|w| &w.condition)
+            .chain(step.assert.iter().map(|a| &a.condition))
+            .flat_map(path_conditions)
+            .filter(|c| !contains_env(&c.path))
+            .filter(|c| !c.matches.as_deref().is_some_and(|m| m.contains("${")));
+        for condition in conditions {
+            if let Err(err) = condition.compiled() {
+                problems.push(format!(
+                    "{step_name}: condition on path '{}': {err}",
+                    condition.path
+                ));
+            }
+        }
+        let targets = step
+            .wait
+            .iter()
+            .filter(|w| {
+                !w.target.is_empty()
+                    || (w.http.is_none() && w.prometheus.is_none() && w.metrics.is_none())
+            })
+            .map(|w| &w.target)
+            .chain(step.assert.iter().map(|a| &a.target))
+            .chain(step.export.iter().map(|e| &e.bucket))
+            .chain(step.export_manifests.iter().map(|e| &e.bucket));
+        for target in targets {
+            if !declared_watches.contains(target) {
+                problems.push(format!(
+                    "{step_name}: condition on bucket '{target}' which is not watched in this or any previous step"
+                ));
+            }
+        }
+    }
+
+    problems
+}
+
+/// Checks the watches of `suite.yaml` and that its assertions target watched buckets.
+pub fn validate_suite_spec(suite_spec: &SuiteSpec) -> Vec<String> {
+    let mut problems: Vec<String> = suite_spec
+        .watch
+        .iter()
+        .flat_map(|watch| validate_watch("suite", watch))
+        .collect();
+    for assert in &suite_spec.assert {
+        if !suite_spec
+            .watch
+            .iter()
+            .any(|watch| watch.name == assert.target)
+        {
+            problems.push(format!(
+                "suite: condition on bucket '{}' which is never watched",
+                assert.target
+            ));
+        }
+    }
+    problems
+}
+
+pub async fn validate_test_suite(config: &Config, dirname: &Path) -> Result<()> {
+    let filter = TestFilter::from_config(config)?;
+    let suite_spec = SuiteSpec::new_from_dir(dirname).await?;
+    let mut skipped: Vec<String> = vec![];
+    let mut invalid = vec![];
+    let test_specs =
+        discover_all_tests(&dirname.to_path_buf(), &filter, &mut skipped, &mut invalid).await?;
+    for test_name in &skipped {
+        log_skipped(test_name);
+    }
+    if test_specs.is_empty() && invalid.is_empty() {
+        return Err(Error::NoTestsFoundError);
+    }
+
+    let client = match config.client().await {
+        Ok(client) if client.apiserver_version().await.is_ok() => Some(client),
+        _ => {
+            log::warn!("Cluster not reachable, skipping checks of resource kinds");
+            None
+        }
+    };
+    let mut known_kinds = HashMap::new();
+    let mut schemas = HashMap::new();
+    if let Some(client) = &client {
+        match schemas_from_cluster(client).await {
+            Ok(cluster_schemas) => schemas = cluster_schemas,
+            Err(err) => {
+                log::warn!("Failed to fetch CRDs, skipping checks against their schemas: {err}")
+            }
+        }
+    }
+
+    // Tests that cannot be read are reported along with the problems of the others.
+    let mut success = invalid.is_empty();
+    for (dir, err) in &invalid {
+        let name = dir.file_name().unwrap_or_default().to_string_lossy();
+        log_validation(&name, &TestSpec::spec_path(dir), &[err.to_string()]);
+    }
+    if let Some(suite_spec) = suite_spec {
+        let problems = validate_suite_spec(&suite_spec);
+        log_validation("suite", &dirname.join("suite.yaml"), &problems);
+        success = success && problems.is_empty();
+    }
+    for test_spec in &test_specs {
+        let mut problems = validate_test_spec(test_spec);
+        if let Some(client) = &client {
+            problems.extend(validate_kinds(client, test_spec, &mut known_kinds).await);
+        }
+        let mut schemas = schemas.clone();
+        schemas.extend(schemas_from_manifests(test_spec).await);
+        problems.extend(validate_conditions(test_spec, &schemas));
+        let path = TestSpec::spec_path(&test_spec.dir);
+        log_validation(&test_spec.name, &path, &problems);
+        success = success && problems.is_empty();
+    }
+    success.then_some(()).ok_or(Error::ValidationFailedError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_spec::{AssertSpec, BucketSpec, MqttSubscribeSpec, WaitSpec};
+    use rstest::rstest;
+    use serde_json::json;
+
+    fn watch(name: &str) -> WatchSpec {
+        WatchSpec {
+            name: name.to_string(),
+            version: "v1".to_string(),
+            kind: "Pod".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn wait(target: &str) -> WaitSpec {
+        WaitSpec {
+            target: target.to_string(),
+            condition: serde_json::from_value(json!({"size": 1})).unwrap(),
+            assert_file: None,
+            http: None,
+            prometheus: None,
+            metrics: None,
+            coerce: false,
+            never: false,
+            timeout: 1,
+        }
+    }
+
+    #[test]
+    fn test_validate_bucket_references() {
+        let test_spec = TestSpec {
+            steps: vec![
+                StepSpec {
+                    name: "first".to_string(),
+                    bucket: vec![BucketSpec {
+                        name: "pods".to_string(),
+                        operations: HashSet::new(),
+                        history: false,
+                    }],
+                    wait: vec![wait("pods")],
+                    ..Default::default()
+                },
+                StepSpec {
+                    name: "second".to_string(),
+                    watch: vec![watch("pods")],
+                    bucket: vec![BucketSpec {
+                        name: "services".to_string(),
+                        operations: HashSet::new(),
+                        history: false,
+                    }],
+                    wait: vec![wait("pods")],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let problems = validate_test_spec(&test_spec);
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].starts_with("first: condition on bucket 'pods'"));
+        assert!(problems[1].starts_with("second: bucket operation on 'services'"));
+    }
+
+    #[rstest]
+    #[case("events", 0)]
+    #[case("pods", 1)]
+    fn test_validate_suite_spec(#[case] target: &str, #[case] expected: usize) {
+        let suite_spec = SuiteSpec {
+            min_blackjack_version: None,
+            watch: vec![watch("events")],
+            assert: vec![AssertSpec {
+                target: target.to_string(),
+                condition: serde_json::from_value(json!({"size": 0})).unwrap(),
+                coerce: false,
+            }],
+        };
+        assert_eq!(validate_suite_spec(&suite_spec).len(), expected);
+    }
+
+    #[rstest]
+    #[case(vec![("app", "nginx")], vec![], 0)]
+    #[case(vec![("app.kubernetes.io/name", "nginx")], vec![], 0)]
+    #[case(vec![("app", "")], vec![], 0)]
+    #[case(vec![("app", "${BLACKJACK_APP}")], vec![], 0)]
+    #[case(vec![("my app", "nginx")], vec![], 1)]
+    #[case(vec![("app", "nginx,foo")], vec![], 1)]
+    #[case(vec![("Example.com/app", "nginx")], vec![], 1)]
+    #[case(vec![], vec![("metadata.name", "nginx")], 0)]
+    #[case(vec![], vec![("status.phase", "Running,Pending")], 1)]
+    #[case(vec![], vec![("metadata/name", "nginx")], 1)]
+    fn test_validate_selectors(
+        #[case] labels: Vec<(&str, &str)>,
+        #[case] fields: Vec<(&str, &str)>,
+        #[case] expected: usize,
+    ) {
+        let to_map = |v: Vec<(&str, &str)>| {
+            Some(
+                v.into_iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+            )
+        };
+        let watch = WatchSpec {
+            labels: to_map(labels),
+            fields: to_map(fields),
+            ..watch("pods")
+        };
+        assert_eq!(validate_watch("step", &watch).len(), expected);
+    }
+
+    #[rstest]
+    #[case("v1", vec![], 0)]
+    #[case("", vec![], 0)]
+    #[case("", vec!["v1alpha1", "v1"], 0)]
+    #[case("v1", vec!["v1alpha1", "v1"], 1)]
+    fn test_validate_versions(
+        #[case] version: &str,
+        #[case] versions: Vec<&str>,
+        #[case] expected: usize,
+    ) {
+        let watch = WatchSpec {
+            version: version.to_string(),
+            versions: versions.into_iter().map(String::from).collect(),
+            ..watch("widgets")
+        };
+        assert_eq!(validate_watch("step", &watch).len(), expected);
+    }
+
+    #[test]
+    fn test_validate_mqtt_buckets() {
+        let test_spec = TestSpec {
+            steps: vec![StepSpec {
+                name: "subscribe".to_string(),
+                mqtt_subscribe_to_bucket: vec![MqttSubscribeSpec {
+                    name: "sensors".to_string(),
+                    url: "mqtt://localhost".to_string(),
+                    topic: "sensors/#".to_string(),
+                    qos: 3,
+                }],
+                wait: vec![wait("sensors")],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert_eq!(
+            validate_test_spec(&test_spec),
+            vec!["subscribe: mqtt qos must be 0, 1 or 2"]
+        );
+    }
+
+    #[rstest]
+    #[case("alerts", true, 0)]
+    #[case("Alerts", true, 1)]
+    #[case("Alerts", false, 0)]
+    fn test_validate_webhook_relay(
+        #[case] name: &str,
+        #[case] relay: bool,
+        #[case] expected: usize,
+    ) {
+        let test_spec = TestSpec {
+            steps: vec![StepSpec {
+                name: "receive".to_string(),
+                webhook_receiver: vec![serde_json::from_value(
+                    json!({"name": name, "relay": relay}),
+                )
+                .unwrap()],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert_eq!(validate_test_spec(&test_spec).len(), expected);
+    }
+
+    #[rstest]
+    #[case(json!({"secret": "tls"}), 0)]
+    #[case(json!({"endpoint": "127.0.0.1:8443"}), 0)]
+    #[case(json!({"secret": "tls", "endpoint": "127.0.0.1:8443"}), 1)]
+    #[case(json!({}), 1)]
+    fn test_validate_certificate(#[case] source: serde_json::Value, #[case] expected: usize) {
+        let mut certificate = json!({"condition": {"size": 1}, "timeout": 10});
+        certificate
+            .as_object_mut()
+            .unwrap()
+            .extend(source.as_object().unwrap().clone());
+        let test_spec = TestSpec {
+            steps: vec![StepSpec {
+                name: "certificate".to_string(),
+                certificate: vec![serde_json::from_value(certificate).unwrap()],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert_eq!(validate_test_spec(&test_spec).len(), expected);
+    }
+
+    #[rstest]
+    #[case("secrets", "{.data.password}", 0)]
+    #[case("pods", "{.data.password}", 1)]
+    #[case("secrets", ".data[*]", 1)]
+    #[case("secrets", "${BLACKJACK_PATH}", 0)]
+    fn test_validate_export(#[case] bucket: &str, #[case] path: &str, #[case] expected: usize) {
+        let test_spec = TestSpec {
+            steps: vec![StepSpec {
+                name: "export".to_string(),
+                watch: vec![watch("secrets")],
+                export: vec![serde_json::from_value(
+                    json!({"name": "password", "bucket": bucket, "path": path}),
+                )
+                .unwrap()],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert_eq!(validate_test_spec(&test_spec).len(), expected);
+    }
+
+    #[rstest]
+    #[case(json!({"path": ".status.phase", "matches": "^Running$"}), 0)]
+    #[case(json!({"path": ".spec.ports[*]", "exists": true}), 1)]
+    #[case(json!({"not": {"path": ".status.phase", "matches": "(Running"}}), 1)]
+    #[case(json!({"path": ".status.phase", "matches": "${PHASE}"}), 0)]
+    fn test_validate_path_condition(#[case] condition: serde_json::Value, #[case] expected: usize) {
+        let test_spec = TestSpec {
+            steps: vec![StepSpec {
+                name: "wait".to_string(),
+                watch: vec![watch("pods")],
+                wait: vec![serde_json::from_value(
+                    json!({"target": "pods", "condition": condition, "timeout": 10}),
+                )
+                .unwrap()],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert_eq!(validate_test_spec(&test_spec).len(), expected);
+    }
+
+    #[test]
+    fn test_validate_matrix() {
+        let test_spec = TestSpec {
+            matrix: BTreeMap::from([
+                ("IMAGE".to_string(), vec!["nginx".to_string()]),
+                ("VERSION".to_string(), vec![]),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            validate_test_spec(&test_spec),
+            vec!["matrix variable 'VERSION' has no values"]
+        );
+    }
+
+    #[rstest]
+    #[case(None, None, 0)]
+    #[case(Some(2.0), Some(0.5), 0)]
+    #[case(Some(0.0), None, 1)]
+    #[case(None, Some(-1.0), 1)]
+    fn test_validate_timeout_scaling(
+        #[case] test_scaling: Option<f32>,
+        #[case] step_scaling: Option<f32>,
+        #[case] expected: usize,
+    ) {
+        let test_spec = TestSpec {
+            timeout_scaling: test_scaling,
+            steps: vec![StepSpec {
+                name: "slow".to_string(),
+                timeout_scaling: step_scaling,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert_eq!(validate_test_spec(&test_spec).len(), expected);
+    }
+
+    #[rstest]
+    #[case(json!({"latency": 200}), 0)]
+    #[case(json!({"error-rate": 0.1, "status": 429}), 0)]
+    #[case(json!({"error-rate": 1.5}), 1)]
+    #[case(json!({"error-rate": 0.1, "status": 404}), 1)]
+    fn test_validate_faults(#[case] faults: serde_json::Value, #[case] expected: usize) {
+        let test_spec = TestSpec {
+            faults: Some(serde_json::from_value(faults).unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(validate_test_spec(&test_spec).len(), expected);
+    }
+
+    #[rstest]
+    #[case(json!({"rate": 20}), 0)]
+    #[case(json!({"rate": 0.5, "burst": 10}), 0)]
+    #[case(json!({"rate": 0}), 1)]
+    #[case(json!({"rate": 5, "burst": 0}), 1)]
+    fn test_validate_apply_rate(#[case] apply_rate: serde_json::Value, #[case] expected: usize) {
+        let test_spec = TestSpec {
+            apply_rate: Some(serde_json::from_value(apply_rate).unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(validate_test_spec(&test_spec).len(), expected);
+    }
+
+    #[rstest]
+    #[case("reconciled widget", 0)]
+    #[case("reconciled (widget", 1)]
+    #[case("reconciled ${BLACKJACK_WIDGET}", 0)]
+    fn test_validate_logs(#[case] regex: &str, #[case] expected: usize) {
+        let test_spec = TestSpec {
+            steps: vec![StepSpec {
+                name: "logs".to_string(),
+                logs: vec![serde_json::from_value(json!({
+                    "labels": {"app": "operator"},
+                    "regex": regex,
+                    "timeout": 10,
+                }))
+                .unwrap()],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert_eq!(validate_test_spec(&test_spec).len(), expected);
+    }
+
+    #[rstest]
+    #[case(json!({"url": "http://localhost:8080/metrics"}), 0)]
+    #[case(json!({"pod": "operator", "port": 8080}), 0)]
+    #[case(json!({"pod": "operator"}), 1)]
+    #[case(json!({}), 1)]
+    #[case(json!({"url": "http://localhost:8080/metrics", "pod": "operator", "port": 8080}), 1)]
+    fn test_validate_metrics(#[case] source: serde_json::Value, #[case] expected: usize) {
+        let mut metrics = json!({"metric": "reconcile_errors_total", "value": {"eq": 0}});
+        metrics
+            .as_object_mut()
+            .unwrap()
+            .extend(source.as_object().unwrap().clone());
+        let test_spec = TestSpec {
+            steps: vec![StepSpec {
+                name: "metrics".to_string(),
+                wait: vec![
+                    serde_json::from_value(json!({"metrics": metrics, "timeout": 10})).unwrap(),
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert_eq!(validate_test_spec(&test_spec).len(), expected);
+    }
+
+    #[rstest]
+    #[case(json!({"period": 5, "timeout": 60}), 0)]
+    #[case(json!({"period": 5, "buckets": ["pods"], "timeout": 60}), 0)]
+    #[case(json!({"period": 5, "buckets": ["deployments"], "timeout": 60}), 1)]
+    #[case(json!({"period": 60, "timeout": 60}), 1)]
+    fn test_validate_quiesce(#[case] quiesce: serde_json::Value, #[case] expected: usize) {
+        let test_spec = TestSpec {
+            steps: vec![StepSpec {
+                name: "settle".to_string(),
+                watch: vec![watch("pods")],
+                quiesce: Some(serde_json::from_value(quiesce).unwrap()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert_eq!(validate_test_spec(&test_spec).len(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_discover_invalid_tests() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, spec) in [("a", "name: a\n"), ("b", "steps: 1\n"), ("c", "name: [\n")] {
+            std::fs::create_dir(dir.path().join(name)).unwrap();
+            std::fs::write(dir.path().join(name).join("test.yaml"), spec).unwrap();
+        }
+        let filter = TestFilter::new(None, vec![], vec![]).unwrap();
+        let mut invalid = vec![];
+        let test_specs = discover_all_tests(
+            &dir.path().to_path_buf(),
+            &filter,
+            &mut vec![],
+            &mut invalid,
+        )
+        .await
+        .unwrap();
+        assert_eq!(test_specs.len(), 1);
+        let mut dirs: Vec<std::path::PathBuf> = invalid.into_iter().map(|(dir, _)| dir).collect();
+        dirs.sort();
+        assert_eq!(dirs, vec![dir.path().join("b"), dir.path().join("c")]);
+    }
+}
@@ -1,74 +1,565 @@
 // Copyright 2024 Ole Kliemann
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::check::assert_expr;
-use crate::collector::{Bucket, CollectedData, CollectedDataContainer};
+use crate::check::{assert_expr, path_conditions, pod_reasons, unfulfillable, CheckOptions};
+use crate::collector::{Bucket, Buckets, CollectedData, CollectedDataContainer};
 use crate::config::Config;
+use crate::describe::{describe, job_pods};
 use crate::error::{AssertDiagnostic, Error, Result, TestFailure, TestFailures};
-use crate::test_spec::WaitSpec;
-use tokio::time::{sleep, Duration};
+use crate::events::{emit, EventKind};
+use crate::http_probe::probe;
+use crate::metrics::scrape_metrics;
+use crate::prometheus::query;
+use crate::test_spec::{AssertSpec, Env, EnvSubst, Expr, QuiesceSpec, WaitSpec};
+use kube::Client;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::future::Future;
+use std::path::Path;
+use tokio::fs;
+use tokio::time::{sleep, Duration, Instant};
 
 fn check_spec_against_data(
     wait_spec: &WaitSpec,
     collected_data: &CollectedData,
 ) -> std::result::Result<(), AssertDiagnostic> {
     let default: Bucket = Default::default();
-    let data = collected_data
+    let bucket = collected_data
         .buckets
         .get(&wait_spec.target)
-        .or_else(|| Some(&default))
-        .unwrap()
-        .data
-        .iter()
-        .map(|(_, value)| value)
-        .collect::<Vec<&serde_json::Value>>();
+        .unwrap_or(&default);
+    let data = bucket.data.values().collect::<Vec<&serde_json::Value>>();
     let expr = &wait_spec.condition;
-    assert_expr(&data, &expr)
+    let tombstones = bucket
+        .tombstones
+        .values()
+        .collect::<Vec<&serde_json::Value>>();
+    assert_expr(
+        &data,
+        &tombstones,
+        &bucket.history,
+        expr,
+        CheckOptions {
+            coerce: wait_spec.coerce,
+        },
+    )
+}
+
+/// Fetches repeatedly, every `interval`, until the fetched value fulfills `check`
+/// or `deadline` has passed, returning the last result of `check`.
+/// Errors of `fetch` are retried until the deadline as well, as what is fetched
+/// may not be available yet, like a database that is still starting.
+pub async fn wait_until<T, F, Fut>(
+    deadline: Instant,
+    interval: Duration,
+    mut fetch: F,
+    check: impl Fn(&T) -> std::result::Result<(), AssertDiagnostic>,
+) -> Result<std::result::Result<(), AssertDiagnostic>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    loop {
+        match fetch().await {
+            Ok(value) => {
+                let result = check(&value);
+                if result.is_ok() || Instant::now() >= deadline {
+                    return Ok(result);
+                }
+            }
+            Err(err) if Instant::now() < deadline => log::debug!("Retrying: {err}"),
+            Err(err) => return Err(err),
+        }
+        sleep(interval).await;
+    }
+}
+
+fn collect_failures(wait_specs: Vec<WaitSpec>, collected_data: &CollectedData) -> Result<()> {
+    let mut errors: Vec<TestFailure> = Vec::new();
+    for spec in wait_specs {
+        if let Err(assert_diagnostic) = check_spec_against_data(&spec, collected_data) {
+            errors.push(TestFailure::new(spec, assert_diagnostic));
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::ConditionsFailed(TestFailures(errors)))
+    }
+}
+
+pub async fn assert_all(
+    assert_specs: Vec<AssertSpec>,
+    collected_data: CollectedDataContainer,
+) -> Result<()> {
+    log::debug!("Asserting {} conditions", assert_specs.len());
+    check_conditions(assert_specs.iter().map(|spec| &spec.condition))?;
+    let wait_specs: Vec<WaitSpec> = assert_specs.into_iter().map(WaitSpec::from).collect();
+    let data = collected_data.lock().await;
+    let result = collect_failures(wait_specs, &data);
+    log::debug!("Assert concluded with {result:?}");
+    result
+}
+
+/// Fails on path conditions with an invalid path or regular expression,
+/// which validation misses when they reference variables or come from an assert file.
+fn check_conditions<'a>(conditions: impl Iterator<Item = &'a Expr>) -> Result<()> {
+    for condition in conditions.flat_map(path_conditions) {
+        condition
+            .compiled()
+            .map_err(|err| Error::ConditionError(format!("path '{}': {err}", condition.path)))?;
+    }
+    Ok(())
+}
+
+async fn check_spec(
+    client: &Client,
+    wait_spec: &WaitSpec,
+    collected_data: &CollectedDataContainer,
+) -> std::result::Result<(), AssertDiagnostic> {
+    {
+        let data = collected_data.lock().await;
+        check_spec_against_data(wait_spec, &data)?;
+    }
+    if let Some(http) = &wait_spec.http {
+        probe(http).await.map_err(|response| AssertDiagnostic {
+            expr: wait_spec.condition.clone(),
+            input: vec![response],
+            diff: vec![],
+        })?;
+    }
+    if let Some(prometheus) = &wait_spec.prometheus {
+        query(prometheus).await.map_err(|result| AssertDiagnostic {
+            expr: wait_spec.condition.clone(),
+            input: vec![result],
+            diff: vec![],
+        })?;
+    }
+    if let Some(metrics) = &wait_spec.metrics {
+        scrape_metrics(client.clone(), metrics)
+            .await
+            .map_err(|result| AssertDiagnostic {
+                expr: wait_spec.condition.clone(),
+                input: vec![result],
+                diff: vec![],
+            })?;
+    }
+    Ok(())
+}
+
+/// Adds a `one` condition for every document of the assert file of the wait to its condition.
+fn add_documents(wait_spec: WaitSpec, documents: Vec<Value>) -> WaitSpec {
+    let mut conditions = match wait_spec.condition {
+        Expr::AndExpr { and } => and,
+        condition => vec![condition],
+    };
+    conditions.extend(documents.into_iter().map(|one| Expr::OneExpr { one }));
+    WaitSpec {
+        condition: Expr::AndExpr { and: conditions },
+        assert_file: None,
+        ..wait_spec
+    }
+}
+
+/// Reads the assert file of the wait, relative to `dirname`, into its condition.
+/// Variables in the documents are substituted from `env`.
+pub async fn load_assert_file(wait_spec: WaitSpec, dirname: &Path, env: &Env) -> Result<WaitSpec> {
+    let Some(path) = &wait_spec.assert_file else {
+        return Ok(wait_spec);
+    };
+    let data = fs::read_to_string(dirname.join(path)).await?;
+    let mut documents = vec![];
+    for document in serde_yaml::Deserializer::from_str(&data) {
+        let document = Value::deserialize(document)?;
+        if !document.is_null() {
+            documents.push(document);
+        }
+    }
+    let wait_spec = add_documents(wait_spec, documents);
+    Ok(WaitSpec {
+        condition: wait_spec.condition.subst_env(env),
+        ..wait_spec
+    })
+}
+
+async fn bucket_objects(collected_data: &CollectedDataContainer, name: &str) -> Vec<Value> {
+    let data = collected_data.lock().await;
+    data.buckets
+        .get(name)
+        .map(|bucket| bucket.data.values().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Describes what a wait is waiting for, e.g. in the progress display.
+fn wait_target(wait_spec: &WaitSpec) -> String {
+    match (&wait_spec.http, &wait_spec.prometheus, &wait_spec.metrics) {
+        _ if !wait_spec.target.is_empty() => wait_spec.target.clone(),
+        (Some(http), _, _) => http.url.clone(),
+        (None, Some(_), _) => "prometheus".to_string(),
+        (None, None, _) => "metrics".to_string(),
+    }
+}
+
+/// Checks a wait. A `never` wait passes while its condition is not fulfilled.
+async fn check_wait(
+    client: &Client,
+    wait_spec: &WaitSpec,
+    collected_data: &CollectedDataContainer,
+) -> std::result::Result<(), AssertDiagnostic> {
+    match (
+        wait_spec.never,
+        check_spec(client, wait_spec, collected_data).await,
+    ) {
+        (false, result) => result,
+        (true, Ok(())) => Err(AssertDiagnostic {
+            expr: Expr::NotExpr {
+                not: Box::new(wait_spec.condition.clone()),
+            },
+            input: bucket_objects(collected_data, &wait_spec.target).await,
+            diff: vec![],
+        }),
+        (true, Err(_)) => Ok(()),
+    }
 }
 
+/// Whether the condition of a wait can no longer be fulfilled, e.g. as a Job has failed.
+async fn wait_unfulfillable(wait_spec: &WaitSpec, collected_data: &CollectedDataContainer) -> bool {
+    let data = collected_data.lock().await;
+    let input = data
+        .buckets
+        .get(&wait_spec.target)
+        .map(|bucket| bucket.data.values().collect::<Vec<_>>())
+        .unwrap_or_default();
+    !wait_spec.never && unfulfillable(&input, &wait_spec.condition)
+}
+
+/// Adds the reasons the containers of the pods of the Jobs in the diagnostic of
+/// a `jobComplete` condition have failed or are waiting with.
+async fn add_pod_reasons(client: &Client, mut diagnostic: AssertDiagnostic) -> AssertDiagnostic {
+    if !matches!(diagnostic.expr, Expr::JobCompleteExpr { .. }) {
+        return diagnostic;
+    }
+    for job in &mut diagnostic.input {
+        let (Some(namespace), Some(name)) = (job["namespace"].as_str(), job["name"].as_str())
+        else {
+            continue;
+        };
+        let pods = job_pods(client.clone(), namespace, name).await;
+        job["pods"] = json!(pod_reasons(&pods));
+    }
+    diagnostic
+}
+
+/// The failure of a wait, describing the resources of its bucket and the pods in `namespace`.
+async fn wait_failure(
+    client: &Client,
+    namespace: &str,
+    spec: WaitSpec,
+    assert_diagnostic: AssertDiagnostic,
+    collected_data: &CollectedDataContainer,
+) -> TestFailure {
+    let descriptions = match spec.target.is_empty() {
+        true => vec![],
+        false => {
+            let objects = bucket_objects(collected_data, &spec.target).await;
+            describe(client.clone(), namespace, objects).await
+        }
+    };
+    TestFailure {
+        assert_diagnostic: add_pod_reasons(client, assert_diagnostic).await,
+        spec,
+        describe: descriptions,
+    }
+}
+
+/// Waits until all conditions are fulfilled and `never` waits have not been fulfilled
+/// until their timeout. Fails early on conditions that can no longer be fulfilled.
+/// The diagnostics of the failed conditions on buckets describe
+/// the resources of the bucket and the pods in `namespace`.
 pub async fn wait_for_all(
+    client: Client,
+    config: &Config,
+    test_name: &str,
+    step_name: &str,
+    namespace: &str,
     wait_specs: Vec<WaitSpec>,
     collected_data: CollectedDataContainer,
 ) -> Result<()> {
-    let mut timeout = wait_specs.iter().map(|spec| spec.timeout).max().unwrap() * 10;
-    timeout = timeout * Config::get().timeout_scaling.ceil() as u16;
-    log::debug!("Found max timeout cycles: {timeout}");
+    check_conditions(wait_specs.iter().map(|spec| &spec.condition))?;
+    let timeout = wait_specs.iter().map(|spec| spec.timeout).max().unwrap();
+    let start = Instant::now();
+    let deadline = start + config.scaled_timeout(timeout);
+    log::debug!("Found max timeout: {timeout}");
 
     log::debug!("Waiting for {} conditions", wait_specs.len());
+    let emit_pending = |wait_specs: &[WaitSpec]| {
+        emit(EventKind::WaitPending {
+            test: test_name.to_string(),
+            step: step_name.to_string(),
+            targets: wait_specs.iter().map(wait_target).collect(),
+        })
+    };
+    emit_pending(&wait_specs);
     let mut wait_specs = wait_specs;
-    while timeout > 0 && wait_specs.len() > 0 {
-        log::trace!("trying to lock mutex");
-        let data = collected_data.lock().await;
-        log::trace!("mutex locked");
-        wait_specs = wait_specs
-            .into_iter()
-            .filter(|w| check_spec_against_data(w, &*data).is_err())
-            .collect();
-        drop(data);
-        timeout = timeout - 1;
+    let mut errors: Vec<TestFailure> = Vec::new();
+    while Instant::now() < deadline && !wait_specs.is_empty() && errors.is_empty() {
+        let pending = wait_specs.len();
+        let mut unfulfilled: Vec<WaitSpec> = vec![];
+        for wait_spec in wait_specs {
+            match check_wait(&client, &wait_spec, &collected_data).await {
+                Err(assert_diagnostic) if wait_spec.never => {
+                    log::debug!("Condition on '{}' was fulfilled", wait_spec.target);
+                    errors.push(
+                        wait_failure(
+                            &client,
+                            namespace,
+                            wait_spec,
+                            assert_diagnostic,
+                            &collected_data,
+                        )
+                        .await,
+                    );
+                }
+                Err(assert_diagnostic) if wait_unfulfillable(&wait_spec, &collected_data).await => {
+                    log::debug!(
+                        "Condition on '{}' can no longer be fulfilled",
+                        wait_spec.target
+                    );
+                    errors.push(
+                        wait_failure(
+                            &client,
+                            namespace,
+                            wait_spec,
+                            assert_diagnostic,
+                            &collected_data,
+                        )
+                        .await,
+                    );
+                }
+                Err(_) => unfulfilled.push(wait_spec),
+                Ok(())
+                    if wait_spec.never
+                        && Instant::now() < start + config.scaled_timeout(wait_spec.timeout) =>
+                {
+                    unfulfilled.push(wait_spec)
+                }
+                Ok(()) => {}
+            }
+        }
+        if unfulfilled.len() < pending {
+            emit_pending(&unfulfilled);
+        }
+        wait_specs = unfulfilled;
         log::trace!("Still {} conditions unfulfilled", wait_specs.len());
         log::trace!("sleeping");
         sleep(Duration::from_millis(100)).await;
     }
-    let result = if wait_specs.len() == 0 {
-        Ok(())
-    } else {
-        let data = collected_data.lock().await;
-        let mut errors: Vec<TestFailure> = Vec::new();
+    // Conditions still pending after a `never` wait or an unfulfillable condition failed
+    // have not timed out yet.
+    if errors.is_empty() {
         for spec in wait_specs {
-            if let Err(assert_diagnostic) = check_spec_against_data(&spec, &*data) {
-                errors.push(TestFailure {
-                    assert_diagnostic,
-                    spec,
-                });
+            if let Err(assert_diagnostic) = check_wait(&client, &spec, &collected_data).await {
+                errors.push(
+                    wait_failure(&client, namespace, spec, assert_diagnostic, &collected_data)
+                        .await,
+                );
             }
         }
-        if errors.len() == 0 {
-            Ok(())
-        } else {
-            Err(Error::ConditionsFailed(TestFailures(errors)))
-        }
+    }
+    let result = if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::ConditionsFailed(TestFailures(errors)))
     };
     log::debug!("Wait concluded with {result:?}");
     result
 }
+
+/// Buckets among `names`, or all buckets if `names` is empty, that have changed
+/// within `period` before `now`, with the time since their last change.
+fn unsettled(
+    buckets: &Buckets,
+    names: &[String],
+    now: Instant,
+    period: Duration,
+) -> Vec<(String, Duration)> {
+    let mut unsettled: Vec<(String, Duration)> = buckets
+        .iter()
+        .filter(|(name, _)| names.is_empty() || names.contains(name))
+        .map(|(name, bucket)| (name.clone(), now.saturating_duration_since(bucket.changed)))
+        .filter(|(_, since)| *since < period)
+        .collect();
+    unsettled.sort();
+    unsettled
+}
+
+/// Waits until none of the buckets of `spec` has changed for its period.
+pub async fn wait_for_quiescence(
+    config: &Config,
+    spec: QuiesceSpec,
+    collected_data: CollectedDataContainer,
+) -> Result<()> {
+    let period = Duration::from_secs(spec.period.into());
+    let deadline = Instant::now() + config.scaled_timeout(spec.timeout);
+    log::debug!("Waiting for buckets to be unchanged for {period:?}");
+    loop {
+        let now = Instant::now();
+        let unsettled = {
+            let data = collected_data.lock().await;
+            unsettled(&data.buckets, &spec.buckets, now, period)
+        };
+        let Some(until) = unsettled.iter().map(|(_, since)| period - *since).max() else {
+            log::debug!("Buckets are quiescent");
+            return Ok(());
+        };
+        if now + until > deadline {
+            let changed: Vec<Value> = unsettled
+                .iter()
+                .map(|(bucket, since)| json!({"bucket": bucket, "unchangedFor": since.as_secs()}))
+                .collect();
+            return Err(Error::ConditionsFailed(TestFailures(vec![
+                TestFailure::new(
+                    WaitSpec::synthetic("quiesce".to_string(), spec.timeout),
+                    AssertDiagnostic {
+                        expr: Expr::AndExpr { and: vec![] },
+                        input: changed,
+                        diff: vec![],
+                    },
+                ),
+            ])));
+        }
+        log::trace!("{} buckets changed recently", unsettled.len());
+        sleep(until.max(Duration::from_millis(100))).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(json!({"and": []}), json!({"and": [{"one": {"kind": "Pod"}}]}))]
+    #[case(
+        json!({"size": 1}),
+        json!({"and": [{"size": 1}, {"one": {"kind": "Pod"}}]})
+    )]
+    #[case(
+        json!({"and": [{"size": 1}]}),
+        json!({"and": [{"size": 1}, {"one": {"kind": "Pod"}}]})
+    )]
+    fn test_add_documents(#[case] condition: Value, #[case] expected: Value) {
+        let wait_spec: WaitSpec = serde_json::from_value(json!({
+            "target": "pods",
+            "condition": condition,
+            "assert-file": "expected.yaml",
+            "timeout": 10,
+        }))
+        .unwrap();
+        let wait_spec = add_documents(wait_spec, vec![json!({"kind": "Pod"})]);
+        assert!(wait_spec.assert_file.is_none());
+        assert_eq!(
+            serde_json::to_value(&wait_spec.condition).unwrap(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case(vec![], vec!["deployments", "pods"])]
+    #[case(vec!["pods"], vec!["pods"])]
+    #[case(vec!["configmaps"], vec![])]
+    fn test_unsettled(#[case] names: Vec<&str>, #[case] expected: Vec<&str>) {
+        let now = Instant::now();
+        let changed = |secs| Bucket {
+            changed: now - Duration::from_secs(secs),
+            ..Default::default()
+        };
+        let buckets = Buckets::from([
+            ("configmaps".to_string(), changed(30)),
+            ("deployments".to_string(), changed(2)),
+            ("pods".to_string(), changed(0)),
+        ]);
+        let names: Vec<String> = names.into_iter().map(String::from).collect();
+        let unsettled: Vec<String> = unsettled(&buckets, &names, now, Duration::from_secs(5))
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(unsettled, expected);
+    }
+
+    #[tokio::test]
+    async fn test_wait_until() {
+        let deadline = Instant::now() + Duration::from_secs(1);
+        let mut attempts = 0;
+        let result = wait_until(
+            deadline,
+            Duration::from_millis(1),
+            || {
+                attempts += 1;
+                let attempt = attempts;
+                async move {
+                    match attempt {
+                        1 => Err(Error::Other("not ready".to_string())),
+                        _ => Ok(attempt),
+                    }
+                }
+            },
+            |attempt| match attempt {
+                3 => Ok(()),
+                _ => Err(AssertDiagnostic {
+                    expr: Expr::default(),
+                    input: vec![],
+                    diff: vec![],
+                }),
+            },
+        )
+        .await;
+        assert!(matches!(result, Ok(Ok(()))));
+        assert_eq!(attempts, 3);
+        let result = wait_until(
+            Instant::now(),
+            Duration::from_millis(1),
+            || async { Err::<(), _>(Error::Other("not ready".to_string())) },
+            |_| Ok(()),
+        )
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[rstest]
+    #[case(false, json!({"size": 1}), true)]
+    #[case(false, json!({"size": 0}), false)]
+    #[case(true, json!({"size": 1}), false)]
+    #[case(true, json!({"size": 0}), true)]
+    #[tokio::test]
+    async fn test_check_wait(
+        #[case] never: bool,
+        #[case] condition: Value,
+        #[case] expected: bool,
+    ) {
+        let client =
+            Client::try_from(kube::Config::new("http://127.0.0.1:1".parse().unwrap())).unwrap();
+        let mut bucket = Bucket::default();
+        bucket
+            .data
+            .insert("a".to_string(), json!({"metadata": {"name": "web-0"}}));
+        let mut collected_data = CollectedData::new();
+        collected_data.buckets.insert("pods".to_string(), bucket);
+        let collected_data = std::sync::Arc::new(tokio::sync::Mutex::new(collected_data));
+        let wait_spec: WaitSpec = serde_json::from_value(json!({
+            "target": "pods",
+            "condition": condition,
+            "never": never,
+            "timeout": 1,
+        }))
+        .unwrap();
+        let result = check_wait(&client, &wait_spec, &collected_data).await;
+        assert_eq!(result.is_ok(), expected);
+        if let (true, Err(diagnostic)) = (never, result) {
+            assert_eq!(
+                diagnostic.input,
+                vec![json!({"metadata": {"name": "web-0"}})]
+            );
+        }
+    }
+}
@@ -0,0 +1,143 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::events::{emit, EventKind};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// A warning returned by the API server on an apply, e.g. of a deprecated API version
+/// or from an admission webhook.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ApiWarning {
+    /// Kind and name of the applied object.
+    pub object: String,
+    pub text: String,
+}
+
+/// Extracts the text of a `Warning` header, e.g. `299 - "unknown field \"spec.size\""`.
+/// Headers not in this format are taken as they are.
+pub fn parse_header(header: &str) -> String {
+    let text = header.splitn(3, ' ').nth(2).unwrap_or(header).trim();
+    match text
+        .strip_prefix('"')
+        .and_then(|text| text.strip_suffix('"'))
+    {
+        Some(quoted) => {
+            let mut text = String::new();
+            let mut chars = quoted.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => text.extend(chars.next()),
+                    c => text.push(c),
+                }
+            }
+            text
+        }
+        None => header.to_string(),
+    }
+}
+
+/// A regex of `fail_on_warning`, compiled when the config or command line is read,
+/// so that invalid patterns are rejected on startup.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct WarningPattern(Regex);
+
+impl FromStr for WarningPattern {
+    type Err = regex::Error;
+
+    fn from_str(pattern: &str) -> std::result::Result<Self, Self::Err> {
+        Regex::new(pattern).map(WarningPattern)
+    }
+}
+
+impl TryFrom<String> for WarningPattern {
+    type Error = regex::Error;
+
+    fn try_from(pattern: String) -> std::result::Result<Self, Self::Error> {
+        pattern.parse()
+    }
+}
+
+impl From<WarningPattern> for String {
+    fn from(pattern: WarningPattern) -> Self {
+        pattern.0.as_str().to_string()
+    }
+}
+
+/// The first pattern of `fail_on_warning` the warning matches.
+fn failing_pattern<'a>(patterns: &'a [WarningPattern], warning: &ApiWarning) -> Option<&'a str> {
+    patterns
+        .iter()
+        .find(|pattern| pattern.0.is_match(&warning.text))
+        .map(|pattern| pattern.0.as_str())
+}
+
+/// Logs the warnings and records them in the event log for the report.
+/// Fails if a warning matches one of the patterns of `fail_on_warning`.
+pub fn check(config: &Config, test: &str, step: &str, warnings: Vec<ApiWarning>) -> Result<()> {
+    for warning in warnings {
+        log::warn!("Warning on {}: {}", warning.object, warning.text);
+        emit(EventKind::ApiWarning {
+            test: test.to_string(),
+            step: step.to_string(),
+            object: warning.object.clone(),
+            warning: warning.text.clone(),
+        });
+        if let Some(pattern) = failing_pattern(&config.fail_on_warning, &warning) {
+            return Err(Error::ApiWarning(format!(
+                "{}: {} (matches '{pattern}')",
+                warning.object, warning.text
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(
+        r#"299 - "unknown field \"spec.sise\"""#,
+        r#"unknown field "spec.sise""#
+    )]
+    #[case(
+        r#"299 - "policy/v1beta1 PodDisruptionBudget is deprecated in v1.21+""#,
+        "policy/v1beta1 PodDisruptionBudget is deprecated in v1.21+"
+    )]
+    #[case("no warning format", "no warning format")]
+    fn test_parse_header(#[case] header: &str, #[case] expected: &str) {
+        assert_eq!(parse_header(header), expected);
+    }
+
+    #[rstest]
+    #[case(vec![], None)]
+    #[case(vec!["deprecated"], Some("deprecated"))]
+    #[case(vec!["unknown field", "deprecated"], Some("deprecated"))]
+    #[case(vec!["^unknown"], None)]
+    fn test_failing_pattern(#[case] patterns: Vec<&str>, #[case] expected: Option<&str>) {
+        let patterns: Vec<WarningPattern> = patterns
+            .into_iter()
+            .map(|pattern| pattern.parse().unwrap())
+            .collect();
+        let warning = ApiWarning {
+            object: "PodDisruptionBudget/widget".to_string(),
+            text: "policy/v1beta1 PodDisruptionBudget is deprecated in v1.21+".to_string(),
+        };
+        assert_eq!(failing_pattern(&patterns, &warning), expected);
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        assert!("deprecated(".parse::<WarningPattern>().is_err());
+        let config: std::result::Result<Config, _> =
+            serde_yaml::from_str("fail_on_warning: ['deprecated(']");
+        assert!(config.is_err());
+    }
+}
@@ -0,0 +1,399 @@
+// Copyright 2024 Ole Kliemann
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::collector::{CollectedDataContainer, Collector};
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::events::{emit, BucketChange, EventKind};
+use crate::messages::payload;
+use crate::run;
+use crate::test_spec::WebhookReceiverSpec;
+use futures::{AsyncBufReadExt, StreamExt};
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Bytes, Incoming};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use k8s_openapi::api::core::v1::{Pod, Service};
+use kube::api::{LogParams, PostParams};
+use kube::runtime::wait::await_condition;
+use kube::{Api, Client};
+use serde_json::{json, Map, Value};
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+const RELAY_PORT: u16 = 8080;
+const RELAY_READY_TIMEOUT: u16 = 120;
+/// Prefix of the log lines of the relay that hold a received request.
+const RELAY_LOG_PREFIX: &str = "blackjack-webhook ";
+
+/// Answers every request with the given status and prints it as JSON line.
+const RELAY_SCRIPT: &str = r#"
+import http.server, json, sys
+STATUS = int(sys.argv[1])
+class Handler(http.server.BaseHTTPRequestHandler):
+    def handle_request(self):
+        body = self.rfile.read(int(self.headers.get("Content-Length") or 0))
+        request = {
+            "method": self.command,
+            "uri": self.path,
+            "headers": {k: v for k, v in self.headers.items()},
+            "body": body.decode("utf-8", "replace"),
+        }
+        self.send_response(STATUS)
+        self.send_header("Content-Length", "0")
+        self.end_headers()
+        print("blackjack-webhook " + json.dumps(request), flush=True)
+    do_GET = do_POST = do_PUT = do_PATCH = do_DELETE = do_HEAD = handle_request
+    def log_message(self, *args):
+        pass
+http.server.ThreadingHTTPServer(("", 8080), Handler).serve_forever()
+"#;
+
+fn webhook_error(err: impl std::fmt::Display) -> Error {
+    Error::WebhookError(err.to_string())
+}
+
+/// A received request, with the query parameters split from the path
+/// and the header names in lower case.
+fn request_value<'a>(
+    method: &str,
+    uri: &str,
+    headers: impl Iterator<Item = (&'a str, &'a str)>,
+    body: &[u8],
+) -> Value {
+    let (path, query) = uri.split_once('?').unwrap_or((uri, ""));
+    let query: Map<String, Value> = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (key.to_string(), Value::String(value.to_string()))
+        })
+        .collect();
+    let headers: Map<String, Value> = headers
+        .map(|(name, value)| (name.to_lowercase(), Value::String(value.to_string())))
+        .collect();
+    json!({
+        "method": method,
+        "path": path,
+        "query": query,
+        "headers": headers,
+        "body": payload(body),
+    })
+}
+
+/// Parses a log line of the relay, ignoring other output.
+fn parse_relay_line(line: &str) -> Option<Value> {
+    let request: Value = serde_json::from_str(line.strip_prefix(RELAY_LOG_PREFIX)?).ok()?;
+    let headers = request["headers"].as_object()?;
+    Some(request_value(
+        request["method"].as_str()?,
+        request["uri"].as_str()?,
+        headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.as_str(), value.as_str()?))),
+        request["body"].as_str()?.as_bytes(),
+    ))
+}
+
+struct Receiver {
+    test_name: String,
+    spec: WebhookReceiverSpec,
+    collected_data: CollectedDataContainer,
+    count: AtomicUsize,
+}
+
+impl Receiver {
+    async fn insert(&self, request: Value) {
+        let count = self.count.fetch_add(1, Ordering::SeqCst) + 1;
+        let object = format!(
+            "Request/{} {}",
+            request["method"].as_str().unwrap_or_default(),
+            request["path"].as_str().unwrap_or_default()
+        );
+        let key = format!("webhook:{}:{count}", self.spec.name);
+        let mut data = self.collected_data.lock().await;
+        if data.insert_received(&self.spec.name, key, request) {
+            emit(EventKind::BucketChanged {
+                test: self.test_name.clone(),
+                bucket: self.spec.name.clone(),
+                change: BucketChange::Create,
+                object,
+                resource_version: None,
+            });
+        }
+    }
+
+    async fn handle(
+        &self,
+        request: Request<Incoming>,
+    ) -> std::result::Result<Response<Full<Bytes>>, Infallible> {
+        let (parts, body) = request.into_parts();
+        let body = body
+            .collect()
+            .await
+            .map(|collected| collected.to_bytes())
+            .unwrap_or_default();
+        let headers = parts
+            .headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?)));
+        let uri = parts
+            .uri
+            .path_and_query()
+            .map(|pq| pq.as_str())
+            .unwrap_or("/");
+        self.insert(request_value(parts.method.as_str(), uri, headers, &body))
+            .await;
+        let mut response = Response::new(Full::new(Bytes::new()));
+        *response.status_mut() = StatusCode::from_u16(self.spec.status).unwrap_or(StatusCode::OK);
+        Ok(response)
+    }
+
+    /// Serves requests on a local port until cancelled.
+    async fn serve(self: Arc<Self>, listener: TcpListener, token: CancellationToken) -> Result<()> {
+        loop {
+            let (stream, _) = tokio::select! {
+                biased;
+                _ = token.cancelled() => break,
+                accepted = listener.accept() => accepted?,
+            };
+            let receiver = self.clone();
+            run::spawn(async move {
+                let service = service_fn(|request| {
+                    let receiver = receiver.clone();
+                    async move { receiver.handle(request).await }
+                });
+                if let Err(err) = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await
+                {
+                    log::debug!("Error serving webhook request: {err}");
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Follows the log of the relay pod until cancelled.
+    async fn follow_relay(
+        self: Arc<Self>,
+        pods: Api<Pod>,
+        name: String,
+        token: CancellationToken,
+    ) -> Result<()> {
+        let params = LogParams {
+            follow: true,
+            ..Default::default()
+        };
+        let mut lines = pods.log_stream(&name, &params).await?.lines();
+        loop {
+            let line = tokio::select! {
+                biased;
+                _ = token.cancelled() => break,
+                line = lines.next() => line,
+            };
+            match line {
+                Some(Ok(line)) => {
+                    if let Some(request) = parse_relay_line(&line) {
+                        self.insert(request).await;
+                    }
+                }
+                Some(Err(err)) => return Err(webhook_error(err)),
+                None => {
+                    log::warn!("Log of webhook relay '{name}' ended");
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn relay_objects(name: &str, spec: &WebhookReceiverSpec) -> (Pod, Service) {
+    let labels =
+        json!({"app.kubernetes.io/name": "blackjack-webhook", "blackjack.io/webhook": spec.name});
+    let pod = serde_json::from_value(json!({
+        "metadata": {"name": name, "labels": labels},
+        "spec": {
+            "containers": [{
+                "name": "relay",
+                "image": spec.image,
+                "command": ["python3", "-u", "-c", RELAY_SCRIPT, spec.status.to_string()],
+                "ports": [{"containerPort": RELAY_PORT}],
+                "readinessProbe": {"tcpSocket": {"port": RELAY_PORT}, "periodSeconds": 1},
+            }],
+        },
+    }))
+    .expect("valid pod");
+    let service = serde_json::from_value(json!({
+        "metadata": {"name": name, "labels": labels},
+        "spec": {
+            "selector": labels,
+            "ports": [{"port": RELAY_PORT, "targetPort": RELAY_PORT}],
+        },
+    }))
+    .expect("valid service");
+    (pod, service)
+}
+
+fn is_pod_ready(pod: Option<&Pod>) -> bool {
+    pod.and_then(|pod| pod.status.as_ref())
+        .and_then(|status| status.conditions.as_ref())
+        .is_some_and(|conditions| {
+            conditions
+                .iter()
+                .any(|c| c.type_ == "Ready" && c.status == "True")
+        })
+}
+
+/// Creates the relay pod and service, and waits until the pod is ready.
+/// Both are deleted along with the namespace.
+async fn start_relay(
+    client: Client,
+    config: &Config,
+    spec: &WebhookReceiverSpec,
+) -> Result<(Api<Pod>, String)> {
+    let name = format!("blackjack-webhook-{}", spec.name);
+    let (pod, service) = relay_objects(&name, spec);
+    let pods: Api<Pod> = Api::namespaced(client.clone(), &spec.namespace);
+    let services: Api<Service> = Api::namespaced(client, &spec.namespace);
+    pods.create(&PostParams::default(), &pod).await?;
+    services.create(&PostParams::default(), &service).await?;
+    tokio::time::timeout(
+        config.scaled_timeout(RELAY_READY_TIMEOUT),
+        await_condition(pods.clone(), &name, is_pod_ready),
+    )
+    .await
+    .map_err(|_| webhook_error(format!("relay '{name}' did not become ready")))?
+    .map_err(webhook_error)?;
+    Ok((pods, name))
+}
+
+/// Starts the receivers, which store received requests in their buckets until
+/// the returned collector is stopped. Returns the environment variables
+/// holding the URLs of the receivers.
+pub async fn start_receivers(
+    client: Client,
+    config: &Config,
+    test_name: &str,
+    specs: Vec<WebhookReceiverSpec>,
+    collected_data: CollectedDataContainer,
+) -> Result<(Collector, Vec<(String, String)>)> {
+    let token = CancellationToken::new();
+    let mut tasks = JoinSet::new();
+    let mut env = vec![];
+    for spec in specs {
+        let env_name = spec.env_name();
+        let receiver = Arc::new(Receiver {
+            test_name: test_name.to_string(),
+            spec: spec.clone(),
+            collected_data: collected_data.clone(),
+            count: AtomicUsize::new(0),
+        });
+        let url = if spec.relay {
+            let (pods, name) = start_relay(client.clone(), config, &spec).await?;
+            tasks.spawn(run::inherit(receiver.follow_relay(
+                pods,
+                name.clone(),
+                token.clone(),
+            )));
+            format!("http://{name}.{}.svc:{RELAY_PORT}", spec.namespace)
+        } else {
+            let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+            let port = listener.local_addr()?.port();
+            tasks.spawn(run::inherit(receiver.serve(listener, token.clone())));
+            format!("http://localhost:{port}")
+        };
+        log::debug!("Receiving webhooks for bucket '{}' at {url}", spec.name);
+        env.push((env_name, url));
+    }
+    Ok((Collector::from_tasks(token, tasks), env))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(
+        "/alerts?severity=critical&team=",
+        b"{\"status\": \"firing\"}",
+        json!({
+            "method": "POST",
+            "path": "/alerts",
+            "query": {"severity": "critical", "team": ""},
+            "headers": {"content-type": "application/json"},
+            "body": {"status": "firing"},
+        })
+    )]
+    #[case(
+        "/",
+        b"ping",
+        json!({
+            "method": "POST",
+            "path": "/",
+            "query": {},
+            "headers": {"content-type": "application/json"},
+            "body": "ping",
+        })
+    )]
+    fn test_request_value(#[case] uri: &str, #[case] body: &[u8], #[case] expected: Value) {
+        let headers = [("Content-Type", "application/json")].into_iter();
+        assert_eq!(request_value("POST", uri, headers, body), expected);
+    }
+
+    #[rstest]
+    #[case(
+        r#"blackjack-webhook {"method": "PUT", "uri": "/hook", "headers": {"X-Token": "abc"}, "body": ""}"#,
+        Some(json!({
+            "method": "PUT",
+            "path": "/hook",
+            "query": {},
+            "headers": {"x-token": "abc"},
+            "body": "",
+        }))
+    )]
+    #[case("Serving HTTP on 0.0.0.0 port 8080", None)]
+    #[case("blackjack-webhook not json", None)]
+    fn test_parse_relay_line(#[case] line: &str, #[case] expected: Option<Value>) {
+        assert_eq!(parse_relay_line(line), expected);
+    }
+
+    #[tokio::test]
+    async fn test_local_receiver() {
+        let collected_data = Collector::new_data();
+        let spec: WebhookReceiverSpec =
+            serde_json::from_value(json!({"name": "alerts", "status": 202})).unwrap();
+        let receiver = Arc::new(Receiver {
+            test_name: "test".to_string(),
+            spec,
+            collected_data: collected_data.clone(),
+            count: AtomicUsize::new(0),
+        });
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let token = CancellationToken::new();
+        let server = tokio::spawn(receiver.serve(listener, token.clone()));
+        let response = reqwest::Client::new()
+            .post(format!("http://localhost:{port}/alerts"))
+            .body("{\"status\": \"firing\"}")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 202);
+        token.cancel();
+        server.await.unwrap().unwrap();
+        let data = collected_data.lock().await;
+        let requests: Vec<&Value> = data.buckets["alerts"].data.values().collect();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0]["body"], json!({"status": "firing"}));
+    }
+}
@@ -0,0 +1,3 @@
+#!/bin/sh
+
+kubectl create deployment nginx --image=nginx:${NGINX_VERSION} -n ${BLACKJACK_NAMESPACE}
@@ -0,0 +1,3 @@
+#!/bin/sh
+
+curl -sf "http://127.0.0.1:${BLACKJACK_PORT_NGINX}/" > /dev/null